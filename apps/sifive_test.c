@@ -0,0 +1,27 @@
+#include <stdint.h>
+#include "sifive_test.h"
+
+#define FINISHER_FAIL 0x3333
+#define FINISHER_PASS 0x5555
+#define FINISHER_RESET 0x7777
+
+static void sifive_test_write(uint32_t value)
+{
+    volatile uint32_t *sifive_test = (volatile uint32_t *)SIFIVE_TEST_BASE_ADDR;
+    *sifive_test = value;
+}
+
+void sifive_test_pass()
+{
+    sifive_test_write(FINISHER_PASS);
+}
+
+void sifive_test_fail(uint16_t code)
+{
+    sifive_test_write((uint32_t)code << 16 | FINISHER_FAIL);
+}
+
+void sifive_test_reset()
+{
+    sifive_test_write(FINISHER_RESET);
+}
@@ -0,0 +1,7 @@
+#include <stdint.h>
+// left out (with a warning) when it would hide the memory of the program, e.g. its stack
+#define SIFIVE_TEST_BASE_ADDR 0x100000
+
+void sifive_test_pass();
+void sifive_test_fail(uint16_t code);
+void sifive_test_reset();
@@ -1,23 +1,170 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use frisc::{
     emulator::Emulator,
-    mmio_device::{debug_exit::DebugExit, simple_uart::SimpleUart},
+    mmio_device::{
+        clint::Clint,
+        debug_exit::DebugExit,
+        dma::Dma,
+        framebuffer::{Framebuffer, PixelFormat},
+        goldfish_rtc::{GoldfishRtc, RtcClock},
+        gpio::{Gpio, GpioSchedule},
+        htif::Htif,
+        i2c::{
+            eeprom::{self, Eeprom},
+            lm75::{self, Lm75},
+            I2c,
+        },
+        plic::Plic,
+        sifive_test::SifiveTest,
+        simple_uart::SimpleUart,
+        spi::{sd_card::SdCard, Spi},
+        virtio::{
+            blk::{DiskImage, DiskMode, VirtioBlk},
+            console::VirtioConsole,
+            net::{VirtioNet, DEFAULT_MAC},
+            p9::Virtio9p,
+            rng::VirtioRng,
+            VirtioDevice, VirtioMmio,
+        },
+        watchdog::{self, Watchdog, WatchdogAction},
+        RequestFromDevice,
+    },
+    net::{DropBackend, NetBackend, PcapBackend, ReflectBackend},
+    sbi::Sbi,
+    semihosting::{HeapInfo, Semihosting},
+    serial::{SerialBackend, SharedBackend, StdoutBackend},
+    syscall::{self, Syscalls},
+    timing::{self, Timing},
 };
+use serial::{PtyBackend, SocketBackend, StdioBackend};
 use std::{
+    fmt::Write as _,
     fs::{self, File},
     io::Write,
+    path::PathBuf,
 };
 use xmas_elf::{
     header,
     program::{self, ProgramHeader, SegmentData},
+    sections::SectionData,
+    symbol_table::Entry,
     ElfFile,
 };
 
+mod serial;
+
+// images linked at or above this address are placed in RAM starting from here
+const DRAM_BASE: u32 = 0x8000_0000;
+// FW_JUMP_ADDR of OpenSBI's generic platform (rv32)
+const DEFAULT_KERNEL_ADDR: u32 = 0x8040_0000;
+const DEFAULT_INITRD_ADDR: u32 = 0x8400_0000;
+
+// struct fw_dynamic_info of OpenSBI (version 2, unsigned long is 4 bytes)
+const FW_DYNAMIC_INFO_MAGIC: u32 = 0x4942_534f; // "OSBI"
+const FW_DYNAMIC_INFO_VERSION: u32 = 2;
+const FW_DYNAMIC_INFO_NEXT_MODE_S: u32 = 1;
+const FW_DYNAMIC_INFO_LEN: u32 = 24;
+
+// heap and mmap space of a user mode program (unless --ram-size is given)
+const DEFAULT_USER_HEAP_SIZE: usize = 16 * 1024 * 1024;
+
+// the hart threads only wait for each other (and the devices) at the end of a quantum
+const DEFAULT_PARALLEL_QUANTUM: usize = 10_000;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum BootMode {
+    /// Run the program on its own
+    Bare,
+    /// The program is OpenSBI fw_jump (the kernel must be at the compiled-in FW_JUMP_ADDR)
+    FwJump,
+    /// The program is OpenSBI fw_dynamic (the kernel address is passed in a2)
+    FwDynamic,
+    /// No program, the kernel starts in S-mode and the emulator implements the SBI
+    Sbi,
+    /// The program is a static Linux executable run in U-mode, the ecalls are Linux syscalls
+    User,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum UartMode {
+    /// Output only
+    Stdout,
+    /// Raw mode stdin + stdout (Ctrl-A x to quit)
+    Stdio,
+    /// Allocate a pseudo-terminal
+    Pty,
+    /// Listen on a local TCP port (--uart-addr <HOST:PORT>)
+    Tcp,
+    /// Listen on a Unix domain socket (--uart-addr <PATH>)
+    Unix,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ConsoleInput {
+    /// The UART (and the SBI and semihosting console calls)
+    Uart,
+    /// virtio-console (hvc0), the UART only writes
+    VirtioConsole,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum BlkMode {
+    /// Writes go to the image file
+    Rw,
+    /// The device is read-only
+    Ro,
+    /// Writes are kept in memory (the image file is not modified)
+    Cow,
+}
+
+impl From<BlkMode> for DiskMode {
+    fn from(mode: BlkMode) -> Self {
+        match mode {
+            BlkMode::Rw => DiskMode::ReadWrite,
+            BlkMode::Ro => DiskMode::ReadOnly,
+            BlkMode::Cow => DiskMode::CopyOnWrite,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum NetMode {
+    /// Discard transmitted frames
+    Drop,
+    /// Send transmitted frames back to the guest
+    Reflect,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum RtcMode {
+    /// Host wall-clock time
+    Host,
+    /// Derived from the step count (--rtc-start + steps * --rtc-ns-per-step)
+    Virtual,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum WatchdogMode {
+    /// Reset the machine
+    Reset,
+    /// Stop with --watchdog-exit-code
+    Exit,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum FramebufferFormat {
+    R5g6b5,
+    X8r8g8b8,
+    A8r8g8b8,
+    A8b8g8r8,
+}
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// ELF executable (not used with --boot sbi)
     #[arg(long, short)]
-    program_path: String,
+    program_path: Option<String>,
     #[arg(long, short)]
     step_log_path: Option<String>,
     #[arg(long, short)]
@@ -26,14 +173,197 @@ struct Args {
     default_sp: Option<u32>,
     #[arg(long, short)]
     instruction_log: bool,
+    #[arg(long, short)]
+    max_steps: Option<usize>,
+    /// Number of harts sharing the ram and the devices
+    #[arg(long, default_value_t = 1)]
+    harts: usize,
+    /// Steps a hart runs before the next one takes its turn
+    /// (before the devices catch up in parallel mode) [default: 1, parallel: 10000]
+    #[arg(long)]
+    quantum: Option<usize>,
+    /// Run each hart on its own host thread (faster, but not deterministic)
+    #[arg(long)]
+    parallel: bool,
+    /// Dump the memory between begin_signature and end_signature (riscv-arch-test)
+    #[arg(long)]
+    signature_path: Option<String>,
+    #[arg(long, short, value_enum, default_value_t = UartMode::Stdout)]
+    uart: UartMode,
+    #[arg(long, required_if_eq_any([("uart", "tcp"), ("uart", "unix")]))]
+    uart_addr: Option<String>,
+    /// Don't start execution until a client is connected to the UART socket
+    #[arg(long)]
+    uart_wait: bool,
+    /// Raw disk image for the virtio-blk device
+    #[arg(long)]
+    virtio_blk_path: Option<String>,
+    #[arg(long, value_enum, default_value_t = BlkMode::Rw)]
+    virtio_blk_mode: BlkMode,
+    /// Raw disk image for the SD card on the SPI controller
+    #[arg(long)]
+    sd_card_path: Option<String>,
+    #[arg(long, value_enum, default_value_t = BlkMode::Rw)]
+    sd_card_mode: BlkMode,
+    /// Add a virtio-console device sharing the host side of the UART
+    #[arg(long)]
+    virtio_console: bool,
+    /// Device that receives the input of the UART host side (only one of them reads it)
+    #[arg(long, value_enum, default_value_t = ConsoleInput::Uart)]
+    console_input: ConsoleInput,
+    /// Add a virtio-rng device (host entropy)
+    #[arg(long)]
+    virtio_rng: bool,
+    /// Add a virtio-rng device with a deterministic sequence
+    #[arg(long)]
+    virtio_rng_seed: Option<u64>,
+    /// Add a virtio-net device
+    #[arg(long, value_enum)]
+    virtio_net: Option<NetMode>,
+    /// Write all virtio-net traffic to a pcap file
+    #[arg(long, requires = "virtio_net")]
+    virtio_net_pcap_path: Option<String>,
+    /// Share a host directory with virtio-9p (read-only unless --virtio-9p-writable)
+    #[arg(long)]
+    virtio_9p_path: Option<String>,
+    /// Mount tag of the shared directory
+    #[arg(long, default_value = "frisc")]
+    virtio_9p_tag: String,
+    #[arg(long, requires = "virtio_9p_path")]
+    virtio_9p_writable: bool,
+    #[arg(long, value_enum, default_value_t = RtcMode::Host)]
+    rtc: RtcMode,
+    /// Initial RTC time in seconds since the unix epoch (virtual mode)
+    #[arg(long, default_value_t = 0)]
+    rtc_start: u64,
+    /// Nanoseconds per step, 0 freezes the RTC (virtual mode)
+    #[arg(long, default_value_t = 10)]
+    rtc_ns_per_step: u64,
+    /// Add a 24Cxx EEPROM of the given size in bytes to the I2C bus
+    #[arg(long)]
+    i2c_eeprom_size: Option<usize>,
+    /// Add an LM75 temperature sensor reporting the given millidegrees Celsius to the I2C bus
+    #[arg(long, allow_negative_numbers = true)]
+    i2c_lm75_temp: Option<i32>,
+    /// Drive the GPIO input pins from a schedule file ("<step> <pin> <0|1>" per line)
+    #[arg(long)]
+    gpio_schedule_path: Option<String>,
+    /// Start the watchdog at power on with a timeout in steps
+    #[arg(long)]
+    watchdog_timeout: Option<u32>,
+    /// What happens when the watchdog expires
+    #[arg(long, value_enum, default_value_t = WatchdogMode::Reset)]
+    watchdog: WatchdogMode,
+    #[arg(long, default_value_t = watchdog::DEFAULT_EXIT_CODE)]
+    watchdog_exit_code: u32,
+    /// Add a linear framebuffer (<WIDTH>x<HEIGHT>)
+    #[arg(long, value_parser = parse_framebuffer_size)]
+    framebuffer: Option<(u32, u32)>,
+    #[arg(long, value_enum, default_value_t = FramebufferFormat::X8r8g8b8)]
+    framebuffer_format: FramebufferFormat,
+    /// Save the framebuffer as a PNG image at exit
+    #[arg(long, requires = "framebuffer")]
+    framebuffer_png_path: Option<String>,
+    /// Also save "<stem>-<step>.png" every N steps
+    #[arg(long, requires = "framebuffer_png_path")]
+    framebuffer_png_interval: Option<usize>,
+    /// Place a device tree blob describing the machine at the end of RAM and pass it in a1
+    /// (always done in the OpenSBI boot modes)
+    #[arg(long)]
+    fdt: bool,
+    /// Kernel command line (/chosen/bootargs of the device tree)
+    #[arg(long)]
+    bootargs: Option<String>,
+    /// Write the device tree (source if the extension is .dts, blob otherwise)
+    #[arg(long)]
+    fdt_dump_path: Option<String>,
+    /// Boot chain, OpenSBI modes start the firmware in M-mode with a0 = hart id and a1 = DTB
+    #[arg(long, value_enum, default_value_t = BootMode::Bare)]
+    boot: BootMode,
+    /// Raw kernel image (e.g. Linux Image) jumped to by the firmware
+    #[arg(long)]
+    kernel_path: Option<String>,
+    #[arg(long, default_value_t = DEFAULT_KERNEL_ADDR)]
+    kernel_addr: u32,
+    #[arg(long)]
+    initrd_path: Option<String>,
+    #[arg(long, default_value_t = DEFAULT_INITRD_ADDR)]
+    initrd_addr: u32,
+    /// Environment variable of the program ("NAME=VALUE", --boot user)
+    #[arg(long = "env")]
+    envs: Vec<String>,
+    /// Arguments of the program after "--" (--boot user, --semihosting)
+    #[arg(last = true)]
+    program_args: Vec<String>,
+    /// Handle the semihosting calls (slli/ebreak/srai), the console is the UART backend
+    #[arg(long)]
+    semihosting: bool,
+    /// Restrict the files of the semihosting calls to this directory
+    #[arg(long, requires = "semihosting")]
+    semihosting_sandbox: Option<String>,
+    /// Cycles per instruction class of the timing model (JSON object, e.g. {"div": 20},
+    /// the other classes keep their defaults)
+    #[arg(long)]
+    timing_path: Option<String>,
+    /// Print the instructions, cycles and CPI of each hart at exit
+    #[arg(long)]
+    timing_report: bool,
 }
 
-fn main() -> anyhow::Result<()> {
-    let default_stack_size = 1024 * 1024;
+fn parse_framebuffer_size(s: &str) -> Result<(u32, u32), String> {
+    let (width, height) = s
+        .split_once('x')
+        .ok_or_else(|| String::from("expected <WIDTH>x<HEIGHT>"))?;
+    let width = width.parse::<u32>().map_err(|e| e.to_string())?;
+    let height = height.parse::<u32>().map_err(|e| e.to_string())?;
 
-    let args = Args::parse();
-    let bin = fs::read(args.program_path)?;
-    let elf = ElfFile::new(&bin).unwrap();
+    if width == 0 || height == 0 {
+        return Err(String::from("width and height must not be 0"));
+    }
+
+    Ok((width, height))
+}
+
+// a fixed-address device would hide the memory used by the program (its image, heap and
+// stack, e.g. the test finisher and the RTC below the stack of a program linked at 0x10000),
+// every such device is left out with a warning
+fn remove_devices_over_program(emulator: &mut Emulator, program_start: u32) {
+    let start = program_start as u64;
+    let end = emulator.ram.base_addr as u64 + emulator.ram.size() as u64;
+
+    emulator.mmio_devices.retain(|device| {
+        let device_start = device.base_addr() as u64;
+        let device_end = device_start + device.used_mem_bytes_len() as u64;
+        let overlaps = device_start < end && device_end > start;
+        if overlaps {
+            eprintln!(
+                "Warning: {} at 0x{:x}..0x{:x} is left out, the program uses 0x{:x}..0x{:x}",
+                device.device_name(),
+                device_start,
+                device_end,
+                start,
+                end
+            );
+        }
+
+        !overlaps
+    });
+}
+
+fn find_symbol(elf: &ElfFile, name: &str) -> Option<u32> {
+    let symtab = elf.find_section_by_name(".symtab")?;
+
+    match symtab.get_data(elf).ok()? {
+        SectionData::SymbolTable32(entries) => entries
+            .iter()
+            .find(|entry| entry.get_name(elf) == Ok(name))
+            .map(|entry| entry.value() as u32),
+        _ => None,
+    }
+}
+
+fn parse_elf(bin: &[u8]) -> anyhow::Result<ElfFile<'_>> {
+    let elf = ElfFile::new(bin).map_err(anyhow::Error::msg)?;
     let elf_header = &elf.header;
 
     if elf_header.pt1.magic != header::MAGIC {
@@ -48,12 +378,99 @@ fn main() -> anyhow::Result<()> {
         return Err(anyhow::anyhow!("Not executable"));
     }
 
-    let loadable_phs: Vec<ProgramHeader> = elf.program_iter().filter(|p|p.get_type().unwrap() == program::Type::Load).collect();
-    let max_ram_size = loadable_phs
+    Ok(elf)
+}
+
+fn main() -> anyhow::Result<()> {
+    let default_stack_size = 1024 * 1024;
+
+    let args = Args::parse();
+    let bin = match (&args.program_path, args.boot) {
+        (Some(_), BootMode::Sbi) => {
+            return Err(anyhow::anyhow!(
+                "--boot sbi starts the kernel without a program"
+            ))
+        }
+        (Some(program_path), _) => fs::read(program_path)?,
+        (None, BootMode::Sbi) => Vec::new(),
+        (None, _) => return Err(anyhow::anyhow!("--program-path is required")),
+    };
+    let elf = match args.program_path {
+        Some(_) => Some(parse_elf(&bin)?),
+        None => None,
+    };
+
+    let loadable_phs: Vec<ProgramHeader> = elf
+        .iter()
+        .flat_map(|elf| elf.program_iter())
+        .filter(|p| p.get_type().unwrap() == program::Type::Load)
+        .collect();
+    if elf.is_some() && loadable_phs.is_empty() {
+        return Err(anyhow::anyhow!("No loadable segments"));
+    }
+    let program_end = loadable_phs
         .iter()
         .map(|ph| ph.virtual_addr() + ph.mem_size())
         .max()
-        .expect("Failed to calcurate RAM size") as usize;
+        .map_or(0, |end| end as u32);
+    let min_addr = loadable_phs
+        .iter()
+        .map(|ph| ph.virtual_addr())
+        .min()
+        .unwrap_or(DRAM_BASE as u64) as u32;
+    let ram_base = if min_addr >= DRAM_BASE { DRAM_BASE } else { 0 };
+    let mut max_ram_size = program_end.saturating_sub(ram_base) as usize;
+
+    // kernel and initrd for the firmware (address, data)
+    let mut payloads = Vec::new();
+    if matches!(args.boot, BootMode::Bare | BootMode::User)
+        && (args.kernel_path.is_some() || args.initrd_path.is_some())
+    {
+        return Err(anyhow::anyhow!("Payloads are not used by this boot mode"));
+    }
+    if args.boot == BootMode::Sbi && args.kernel_path.is_none() {
+        return Err(anyhow::anyhow!("--boot sbi needs --kernel-path"));
+    }
+    if let Some(kernel_path) = &args.kernel_path {
+        payloads.push((args.kernel_addr, fs::read(kernel_path)?));
+    }
+    let payload_end = |addr: u32, data: &[u8]| {
+        u32::try_from(data.len())
+            .ok()
+            .and_then(|len| addr.checked_add(len))
+            .ok_or_else(|| anyhow::anyhow!("Payload at 0x{:x} doesn't fit in memory", addr))
+    };
+    let mut initrd = None;
+    if let Some(initrd_path) = &args.initrd_path {
+        let data = fs::read(initrd_path)?;
+        initrd = Some((args.initrd_addr, payload_end(args.initrd_addr, &data)?));
+        payloads.push((args.initrd_addr, data));
+    }
+    // the payloads are copied over the program, nothing may be overwritten
+    let mut used_ranges: Vec<(u64, u64)> = loadable_phs
+        .iter()
+        .map(|ph| (ph.virtual_addr(), ph.virtual_addr() + ph.mem_size()))
+        .collect();
+    for (addr, data) in &payloads {
+        if *addr < ram_base {
+            return Err(anyhow::anyhow!("Payload at 0x{:x} is not in RAM", addr));
+        }
+        let (begin, end) = (*addr as u64, payload_end(*addr, data)? as u64);
+        if let Some((used_begin, used_end)) = used_ranges
+            .iter()
+            .find(|(used_begin, used_end)| begin < *used_end && *used_begin < end)
+        {
+            return Err(anyhow::anyhow!(
+                "Payload at 0x{:x}..0x{:x} overlaps 0x{:x}..0x{:x}",
+                begin,
+                end,
+                used_begin,
+                used_end
+            ));
+        }
+        used_ranges.push((begin, end));
+        max_ram_size = max_ram_size.max((addr - ram_base) as usize + data.len());
+    }
 
     if let Some(ram_size) = args.ram_size {
         if ram_size < max_ram_size {
@@ -61,34 +478,385 @@ fn main() -> anyhow::Result<()> {
         }
     }
 
+    let default_ram_size = match args.boot {
+        BootMode::User => max_ram_size + DEFAULT_USER_HEAP_SIZE,
+        _ => max_ram_size,
+    };
     // 4 bytes alignment
-    let mut ram = vec![0u8; (args.ram_size.unwrap_or(max_ram_size) + default_stack_size + 3) & !3];
-    for ph in loadable_phs {
-        let offset = ph.virtual_addr() as usize;
-        let file_size = ph.file_size() as usize;
-        let mem_size = ph.mem_size() as usize;
-        let data = match ph.get_data(&elf).unwrap() {
-            SegmentData::Undefined(data) => data,
-            _ => return Err(anyhow::anyhow!("Unsupported segment type")),
-        };
-        ram[offset..offset + file_size].copy_from_slice(&data[..file_size]);
+    let mut ram =
+        vec![0u8; (args.ram_size.unwrap_or(default_ram_size) + default_stack_size + 3) & !3];
+    let stack_bottom = ram_base + (ram.len() - default_stack_size) as u32;
+    if let Some(elf) = &elf {
+        for ph in &loadable_phs {
+            let offset = ph.virtual_addr() as usize - ram_base as usize;
+            let file_size = ph.file_size() as usize;
+            let mem_size = ph.mem_size() as usize;
+            let data = match ph.get_data(elf).unwrap() {
+                SegmentData::Undefined(data) => data,
+                _ => return Err(anyhow::anyhow!("Unsupported segment type")),
+            };
+            ram[offset..offset + file_size].copy_from_slice(&data[..file_size]);
 
-        if mem_size > file_size {
-            ram[offset + file_size..offset + mem_size].fill(0);
+            if mem_size > file_size {
+                ram[offset + file_size..offset + mem_size].fill(0);
+            }
         }
     }
+    let find_elf_symbol = |name| elf.as_ref().and_then(|elf| find_symbol(elf, name));
+    for (addr, data) in &payloads {
+        let offset = (addr - ram_base) as usize;
+        ram[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    let signature_range = match args.signature_path {
+        Some(_) => match (
+            find_elf_symbol("begin_signature"),
+            find_elf_symbol("end_signature"),
+        ) {
+            // the range is dumped from the ram at the end of the run
+            (Some(begin), Some(end)) => {
+                let len = end.checked_sub(begin).and_then(|len| {
+                    let offset = begin.checked_sub(ram_base)? as usize;
+                    ram.get(offset..offset + len as usize).map(|_| len as usize)
+                });
+                match len {
+                    Some(len) => Some((begin, len)),
+                    None => {
+                        return Err(anyhow::anyhow!(
+                            "Signature range 0x{:x}..0x{:x} is not in RAM",
+                            begin,
+                            end
+                        ))
+                    }
+                }
+            }
+            _ => return Err(anyhow::anyhow!("Signature symbols were not found")),
+        },
+        None => None,
+    };
+
+    let default_pc = elf
+        .as_ref()
+        .map_or(args.kernel_addr, |elf| elf.header.pt2.entry_point() as u32);
+    let default_sp = args.default_sp.unwrap_or(ram_base + ram.len() as u32);
+
+    let uart_backend: Box<dyn SerialBackend> = match args.uart {
+        UartMode::Stdout => Box::new(StdoutBackend),
+        UartMode::Stdio => Box::new(StdioBackend::new()?),
+        UartMode::Pty => {
+            let backend = PtyBackend::new()?;
+            println!("UART is connected to {}", backend.path());
+            Box::new(backend)
+        }
+        UartMode::Tcp | UartMode::Unix => {
+            let addr = args.uart_addr.as_deref().unwrap();
+            let backend = if args.uart == UartMode::Tcp {
+                SocketBackend::new_tcp(addr)?
+            } else {
+                SocketBackend::new_unix(addr)?
+            };
+            println!("UART is listening on {}", addr);
+
+            if args.uart_wait {
+                backend.wait_for_client();
+            }
+            Box::new(backend)
+        }
+    };
 
-    let default_pc = elf_header.pt2.entry_point() as u32;
-    let default_sp = args.default_sp.unwrap_or(ram.len() as u32);
+    // the input goes to exactly one device, the others only write
+    let uart_backend = SharedBackend::new(uart_backend);
+    let (uart_backend, console_backend) = match args.console_input {
+        ConsoleInput::Uart => (uart_backend.clone(), uart_backend.output_only()),
+        ConsoleInput::VirtioConsole if args.virtio_console => {
+            (uart_backend.output_only(), uart_backend)
+        }
+        ConsoleInput::VirtioConsole => {
+            return Err(anyhow::anyhow!(
+                "--console-input virtio-console needs --virtio-console"
+            ))
+        }
+    };
 
-    let mut emulator = Emulator::new(ram);
+    // virtio-mmio slots are assigned in this order
+    let mut virtio_devices: Vec<Box<dyn VirtioDevice>> = Vec::new();
+    if let Some(virtio_blk_path) = &args.virtio_blk_path {
+        let disk = DiskImage::open(virtio_blk_path, args.virtio_blk_mode.into())?;
+        virtio_devices.push(Box::new(VirtioBlk::new(disk)));
+    }
+    if args.virtio_console {
+        virtio_devices.push(Box::new(VirtioConsole::new(Box::new(console_backend))));
+    }
+    if let Some(seed) = args.virtio_rng_seed {
+        virtio_devices.push(Box::new(VirtioRng::new_with_seed(seed)));
+    } else if args.virtio_rng {
+        virtio_devices.push(Box::new(VirtioRng::new_with_host_entropy()?));
+    }
+    if let Some(net_mode) = args.virtio_net {
+        let mut net_backend: Box<dyn NetBackend> = match net_mode {
+            NetMode::Drop => Box::new(DropBackend),
+            NetMode::Reflect => Box::new(ReflectBackend::default()),
+        };
+        if let Some(pcap_path) = &args.virtio_net_pcap_path {
+            net_backend = Box::new(PcapBackend::new(pcap_path, net_backend)?);
+        }
+        virtio_devices.push(Box::new(VirtioNet::new(DEFAULT_MAC, net_backend)));
+    }
+    if let Some(virtio_9p_path) = &args.virtio_9p_path {
+        virtio_devices.push(Box::new(Virtio9p::new(
+            virtio_9p_path,
+            &args.virtio_9p_tag,
+            !args.virtio_9p_writable,
+        )?));
+    }
+
+    if args.harts == 0 {
+        return Err(anyhow::anyhow!("At least one hart is required"));
+    }
+    let mut emulator = Emulator::new_with_harts(ram_base, ram, args.harts);
+    emulator.parallel = args.parallel;
+    emulator.quantum = match args.quantum {
+        Some(quantum) => quantum,
+        None if args.parallel => DEFAULT_PARALLEL_QUANTUM,
+        None => 1,
+    };
+    emulator.max_steps = args.max_steps;
+    emulator.bootargs = args.bootargs;
+    emulator.initrd = initrd;
+    emulator.discard_steps = args.step_log_path.is_none();
     emulator.register_mmio_device(Box::new(DebugExit::default()));
-    emulator.register_mmio_device(Box::new(SimpleUart::default()));
+    emulator.register_mmio_device(Box::new(SifiveTest::default()));
+    emulator.register_mmio_device(Box::new(SimpleUart::new_with_backend(Box::new(
+        uart_backend.clone(),
+    ))));
+    let clint = Clint::new_with_harts(args.harts);
+    emulator.mtime = Some(clint.mtime());
+    emulator.register_mmio_device(Box::new(clint));
+    emulator.register_mmio_device(Box::new(Plic::new_with_harts(args.harts)));
+    emulator.register_mmio_device(Box::new(Dma::default()));
+
+    let rtc_clock = match args.rtc {
+        RtcMode::Host => RtcClock::Host,
+        RtcMode::Virtual => RtcClock::Virtual {
+            start_ns: args.rtc_start.saturating_mul(1_000_000_000),
+            ns_per_step: args.rtc_ns_per_step,
+        },
+    };
+    emulator.register_mmio_device(Box::new(GoldfishRtc::new_with_clock(rtc_clock)));
+
+    let mut spi = Spi::default();
+    if let Some(sd_card_path) = &args.sd_card_path {
+        let disk = DiskImage::open(sd_card_path, args.sd_card_mode.into())?;
+        spi.attach(0, Box::new(SdCard::new(disk)));
+    }
+    emulator.register_mmio_device(Box::new(spi));
+
+    let mut i2c = I2c::default();
+    if let Some(size) = args.i2c_eeprom_size {
+        if !size.is_power_of_two() || !(128..=0x10000).contains(&size) {
+            return Err(anyhow::anyhow!("Unsupported EEPROM size"));
+        }
+        i2c.attach(Box::new(Eeprom::new(eeprom::DEFAULT_ADDRESS, size)));
+    }
+    if let Some(millicelsius) = args.i2c_lm75_temp {
+        i2c.attach(Box::new(Lm75::new(lm75::DEFAULT_ADDRESS, millicelsius)));
+    }
+    emulator.register_mmio_device(Box::new(i2c));
+
+    let watchdog_action = match args.watchdog {
+        WatchdogMode::Reset => WatchdogAction::Reset,
+        WatchdogMode::Exit => WatchdogAction::Exit(args.watchdog_exit_code),
+    };
+    let mut watchdog = Watchdog::new_with_action(watchdog_action);
+    if let Some(timeout) = args.watchdog_timeout {
+        watchdog.arm(timeout);
+    }
+    emulator.register_mmio_device(Box::new(watchdog));
+
+    let gpio_schedule = match &args.gpio_schedule_path {
+        Some(gpio_schedule_path) => GpioSchedule::load(gpio_schedule_path)?,
+        None => GpioSchedule::default(),
+    };
+    emulator.register_mmio_device(Box::new(Gpio::new_with_schedule(gpio_schedule)));
+
+    for (slot, virtio_device) in virtio_devices.into_iter().enumerate() {
+        emulator.register_mmio_device(Box::new(VirtioMmio::new_with_slot(
+            slot as u32,
+            virtio_device,
+        )));
+    }
+
+    let screen = match args.framebuffer {
+        Some((width, height)) => {
+            let format = match args.framebuffer_format {
+                FramebufferFormat::R5g6b5 => PixelFormat::R5g6b5,
+                FramebufferFormat::X8r8g8b8 => PixelFormat::X8r8g8b8,
+                FramebufferFormat::A8r8g8b8 => PixelFormat::A8r8g8b8,
+                FramebufferFormat::A8b8g8r8 => PixelFormat::A8b8g8r8,
+            };
+            let mut framebuffer = Framebuffer::new_with_mode(width, height, format);
+            if let (Some(png_path), Some(interval)) =
+                (&args.framebuffer_png_path, args.framebuffer_png_interval)
+            {
+                framebuffer.set_png_dump(png_path, interval);
+            }
+            let screen = framebuffer.screen();
+            emulator.register_mmio_device(Box::new(framebuffer));
+            Some(screen)
+        }
+        None => None,
+    };
+
+    let program_start = payloads
+        .iter()
+        .map(|(addr, _)| *addr)
+        .fold(min_addr, u32::min);
+    remove_devices_over_program(&mut emulator, program_start);
+
+    // tohost and fromhost are in the image of the program
+    if let (Some(tohost), Some(fromhost)) = (find_elf_symbol("tohost"), find_elf_symbol("fromhost"))
+    {
+        emulator.register_mmio_device(Box::new(Htif::new(
+            tohost,
+            fromhost,
+            Box::new(StdoutBackend),
+        )));
+    }
+    let timing = match &args.timing_path {
+        Some(timing_path) => serde_json::from_str(&fs::read_to_string(timing_path)?)?,
+        None => Timing::default(),
+    };
+
     emulator.reset();
-    emulator.cpu.pc.store(default_pc); // pc
-    emulator.cpu.x_regs[2].store(default_sp); // sp
+    for cpu in &mut emulator.harts {
+        cpu.pc.store(default_pc); // pc
+        cpu.x_regs[2].store(default_sp); // sp
+        cpu.timing = timing;
+    }
+
+    // the stack starts below the device tree blob (and fw_dynamic_info)
+    if args.fdt || !matches!(args.boot, BootMode::Bare | BootMode::User) {
+        let mut stack_top = emulator.load_fdt()?;
+        if args.boot == BootMode::FwDynamic {
+            stack_top -= FW_DYNAMIC_INFO_LEN;
+            let info = [
+                FW_DYNAMIC_INFO_MAGIC,
+                FW_DYNAMIC_INFO_VERSION,
+                args.kernel_addr, // next_addr
+                FW_DYNAMIC_INFO_NEXT_MODE_S,
+                0,        // options
+                u32::MAX, // boot_hart (any)
+            ];
+            for (i, value) in info.into_iter().enumerate() {
+                emulator.ram.store32(stack_top + i as u32 * 4, value);
+            }
+            for cpu in &mut emulator.harts {
+                cpu.x_regs[12].store(stack_top); // a2
+            }
+        }
+        if args.default_sp.is_none() {
+            for cpu in &mut emulator.harts {
+                cpu.x_regs[2].store(stack_top & !0xf); // sp
+            }
+        }
+    }
+
+    if args.semihosting {
+        let cmdline: Vec<&str> = args
+            .program_path
+            .iter()
+            .chain(&args.program_args)
+            .map(String::as_str)
+            .collect();
+        let heap_info = HeapInfo {
+            heap_base: program_end,
+            heap_limit: stack_bottom,
+            stack_base: default_sp,
+            stack_limit: stack_bottom,
+        };
+        emulator.enable_semihosting(Semihosting::new(
+            Box::new(uart_backend.clone()),
+            &cmdline.join(" "),
+            heap_info,
+            args.semihosting_sandbox.as_ref().map(PathBuf::from),
+        ));
+    }
+
+    if args.boot == BootMode::Sbi {
+        emulator.boot_supervisor(args.kernel_addr, Sbi::new(Box::new(uart_backend)));
+    }
+
+    if let (BootMode::User, Some(elf)) = (args.boot, &elf) {
+        // the program headers are found through the segment that contains them
+        let ph_offset = elf.header.pt2.ph_offset();
+        let phdr = loadable_phs
+            .iter()
+            .find(|ph| (ph.offset()..ph.offset() + ph.file_size()).contains(&ph_offset))
+            .map_or(0, |ph| (ph.virtual_addr() + ph_offset - ph.offset()) as u32);
+        let auxv = [
+            (syscall::AT_PHDR, phdr),
+            (syscall::AT_PHENT, elf.header.pt2.ph_entry_size() as u32),
+            (syscall::AT_PHNUM, elf.header.pt2.ph_count() as u32),
+            (syscall::AT_ENTRY, default_pc),
+        ];
+        let argv: Vec<String> = args
+            .program_path
+            .iter()
+            .chain(&args.program_args)
+            .cloned()
+            .collect();
+
+        emulator.boot_user(
+            default_pc,
+            default_sp,
+            Syscalls::new(program_end, stack_bottom),
+            &argv,
+            &args.envs,
+            &auxv,
+        )?;
+    }
+
+    if let Some(fdt_dump_path) = &args.fdt_dump_path {
+        let fdt = emulator.fdt();
+        if fdt_dump_path.ends_with(".dts") {
+            fs::write(fdt_dump_path, fdt.to_dts())?;
+        } else {
+            fs::write(fdt_dump_path, fdt.to_dtb()?)?;
+        }
+    }
+
     let (exit_code, log) = emulator.run(args.instruction_log)?;
+    // the run also ends when the pc leaves ram
+    if !log
+        .dev_reqs
+        .iter()
+        .any(|dev_req| matches!(dev_req.req, RequestFromDevice::Exit(_)))
+    {
+        println!("Stopped without an exit request");
+    }
     println!("Exited with 0x{:x}", exit_code);
+    if args.timing_report {
+        print!("{}", timing::report(&emulator.harts));
+    }
+
+    if let (Some(screen), Some(png_path)) = (screen, args.framebuffer_png_path) {
+        screen.save_png(png_path)?;
+    }
+
+    // one 32-bit word per line
+    if let (Some(signature_path), Some((begin, len))) = (args.signature_path, signature_range) {
+        let signature = emulator
+            .ram
+            .slice(begin, len)
+            .ok_or_else(|| anyhow::anyhow!("Signature range is not in RAM"))?;
+        let mut s = String::new();
+        for word in signature.chunks(4) {
+            let mut bytes = [0; 4];
+            bytes[..word.len()].copy_from_slice(word);
+            writeln!(s, "{:08x}", u32::from_le_bytes(bytes))?;
+        }
+        fs::write(signature_path, s)?;
+    }
 
     if let Some(step_log_path) = args.step_log_path {
         let s = serde_json::to_string(&log)?;
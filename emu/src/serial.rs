@@ -0,0 +1,405 @@
+use frisc::serial::SerialBackend;
+use std::{
+    ffi::CStr,
+    fs::{self, File},
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    os::{
+        fd::{AsRawFd, FromRawFd},
+        unix::{
+            fs::FileTypeExt,
+            net::{UnixListener, UnixStream},
+        },
+    },
+    sync::{
+        mpsc::{self, Receiver, SyncSender},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+// Ctrl-A x quits, Ctrl-A Ctrl-A sends a literal Ctrl-A (same as QEMU)
+const ESCAPE_CHAR: u8 = 0x01;
+const ESCAPE_QUIT_CHAR: u8 = b'x';
+
+fn spawn_reader<R: Read + Send + 'static>(mut reader: R, retry_on_error: bool) -> Receiver<u8> {
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut buf = [0u8; 256];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(len) => {
+                    for value in &buf[..len] {
+                        if tx.send(*value).is_err() {
+                            return;
+                        }
+                    }
+                }
+                // EIO is returned from a pty master while no client is connected
+                Err(_) if retry_on_error => thread::sleep(Duration::from_millis(100)),
+                Err(_) => break,
+            }
+        }
+    });
+
+    rx
+}
+
+fn make_raw(fd: i32) -> anyhow::Result<libc::termios> {
+    let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+    if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    let orig_termios = termios;
+    unsafe { libc::cfmakeraw(&mut termios) };
+    // keep output processing so that '\n' still returns the carriage
+    termios.c_oflag |= libc::OPOST;
+
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    Ok(orig_termios)
+}
+
+// stdin in raw mode + stdout
+#[derive(Debug)]
+pub struct StdioBackend {
+    rx: Receiver<u8>,
+    orig_termios: Option<libc::termios>,
+    escaped: bool,
+    quit: bool,
+}
+
+impl StdioBackend {
+    pub fn new() -> anyhow::Result<Self> {
+        let fd = io::stdin().as_raw_fd();
+        let orig_termios = if unsafe { libc::isatty(fd) } == 1 {
+            Some(make_raw(fd)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            rx: spawn_reader(io::stdin(), false),
+            orig_termios,
+            escaped: false,
+            quit: false,
+        })
+    }
+}
+
+impl SerialBackend for StdioBackend {
+    fn write(&mut self, value: u8) {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(&[value]);
+        let _ = stdout.flush();
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        while let Ok(value) = self.rx.try_recv() {
+            if self.escaped {
+                self.escaped = false;
+
+                match value {
+                    ESCAPE_CHAR => return Some(ESCAPE_CHAR),
+                    ESCAPE_QUIT_CHAR => {
+                        self.quit = true;
+                        return None;
+                    }
+                    _ => continue,
+                }
+            }
+
+            if value == ESCAPE_CHAR {
+                self.escaped = true;
+                continue;
+            }
+
+            return Some(value);
+        }
+
+        None
+    }
+
+    fn quit_requested(&mut self) -> bool {
+        self.quit
+    }
+}
+
+impl Drop for StdioBackend {
+    fn drop(&mut self) {
+        if let Some(termios) = self.orig_termios {
+            unsafe { libc::tcsetattr(io::stdin().as_raw_fd(), libc::TCSANOW, &termios) };
+        }
+    }
+}
+
+// pseudo-terminal, connect with screen/picocom
+#[derive(Debug)]
+pub struct PtyBackend {
+    master: File,
+    rx: Receiver<u8>,
+    path: String,
+}
+
+impl PtyBackend {
+    pub fn new() -> anyhow::Result<Self> {
+        let fd = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let master = unsafe { File::from_raw_fd(fd) };
+
+        if unsafe { libc::grantpt(fd) } != 0 || unsafe { libc::unlockpt(fd) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let mut buf = [0 as libc::c_char; 128];
+        if unsafe { libc::ptsname_r(fd, buf.as_mut_ptr(), buf.len()) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let path = unsafe { CStr::from_ptr(buf.as_ptr()) }
+            .to_string_lossy()
+            .into_owned();
+
+        make_raw(fd)?;
+
+        // don't block the guest while nobody is reading the output
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+        }
+
+        let reader = BlockingPtyReader(master.try_clone()?);
+        Ok(Self {
+            master,
+            rx: spawn_reader(reader, true),
+            path,
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl SerialBackend for PtyBackend {
+    fn write(&mut self, value: u8) {
+        // dropped if the client isn't draining the output
+        let _ = self.master.write_all(&[value]);
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        self.rx.try_recv().ok()
+    }
+}
+
+// the master fd is non-blocking, so wait for input with poll() before reading
+struct BlockingPtyReader(File);
+
+impl Read for BlockingPtyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut pollfd = libc::pollfd {
+            fd: self.0.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        loop {
+            if unsafe { libc::poll(&mut pollfd, 1, -1) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            match self.0.read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                res => return res,
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nodelay(true)?;
+                Ok(Stream::Tcp(stream))
+            }
+            Self::Unix(listener) => Ok(Stream::Unix(listener.accept()?.0)),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Stream {
+    fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Self::Tcp(stream) => Ok(Self::Tcp(stream.try_clone()?)),
+            Self::Unix(stream) => Ok(Self::Unix(stream.try_clone()?)),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.read(buf),
+            Self::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.write(buf),
+            Self::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.flush(),
+            Self::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+type Client = Arc<(Mutex<Option<Stream>>, Condvar)>;
+
+// bytes of guest output buffered for a slow client
+const OUTPUT_BUFFER_LEN: usize = 4096;
+
+// local TCP port or Unix domain socket, serves one client at a time
+#[derive(Debug)]
+pub struct SocketBackend {
+    rx: Receiver<u8>,
+    output: SyncSender<u8>,
+    client: Client,
+    unix_path: Option<String>,
+}
+
+impl SocketBackend {
+    pub fn new_tcp(addr: &str) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        Ok(Self::new(Listener::Tcp(listener), None))
+    }
+
+    pub fn new_unix(path: &str) -> anyhow::Result<Self> {
+        // remove a stale socket left by a previous run
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if metadata.file_type().is_socket() {
+                fs::remove_file(path)?;
+            }
+        }
+
+        let listener = UnixListener::bind(path)?;
+        Ok(Self::new(Listener::Unix(listener), Some(path.to_string())))
+    }
+
+    fn new(listener: Listener, unix_path: Option<String>) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let client: Client = Arc::new((Mutex::new(None), Condvar::new()));
+        let client_ = client.clone();
+
+        thread::spawn(move || loop {
+            let mut stream = match listener.accept() {
+                Ok(stream) => stream,
+                // e.g. EMFILE, retried after a while instead of spinning
+                Err(_) => {
+                    thread::sleep(Duration::from_millis(100));
+                    continue;
+                }
+            };
+
+            let (lock, cvar) = &*client_;
+            match stream.try_clone() {
+                Ok(writer) => *lock.lock().unwrap() = Some(writer),
+                Err(_) => continue,
+            }
+            cvar.notify_all();
+
+            let mut buf = [0u8; 256];
+            while let Ok(len) = stream.read(&mut buf) {
+                if len == 0 {
+                    break;
+                }
+
+                for value in &buf[..len] {
+                    if tx.send(*value).is_err() {
+                        return;
+                    }
+                }
+            }
+
+            *lock.lock().unwrap() = None;
+        });
+
+        // a client that doesn't read blocks this thread instead of the guest
+        let (output, output_rx) = mpsc::sync_channel::<u8>(OUTPUT_BUFFER_LEN);
+        let client_ = client.clone();
+        thread::spawn(move || {
+            let mut buf = Vec::with_capacity(OUTPUT_BUFFER_LEN);
+            while let Ok(value) = output_rx.recv() {
+                buf.push(value);
+                buf.extend(output_rx.try_iter().take(OUTPUT_BUFFER_LEN));
+
+                let (lock, _) = &*client_;
+                if let Some(stream) = lock.lock().unwrap().as_mut() {
+                    let _ = stream.write_all(&buf);
+                }
+                buf.clear();
+            }
+        });
+
+        Self {
+            rx,
+            output,
+            client,
+            unix_path,
+        }
+    }
+
+    pub fn wait_for_client(&self) {
+        let (lock, cvar) = &*self.client;
+        let _guard = cvar
+            .wait_while(lock.lock().unwrap(), |client| client.is_none())
+            .unwrap();
+    }
+}
+
+impl SerialBackend for SocketBackend {
+    fn write(&mut self, value: u8) {
+        // dropped while no client is connected or if the client isn't draining the output
+        let _ = self.output.try_send(value);
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        self.rx.try_recv().ok()
+    }
+}
+
+impl Drop for SocketBackend {
+    fn drop(&mut self) {
+        if let Some(path) = &self.unix_path {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
@@ -0,0 +1,78 @@
+// Helpers shared by the integration tests.
+
+use std::path::Path;
+
+const CODE_OFFSET: usize = 0x1000;
+
+// a minimal RV32 executable: one loadable segment with the code at base (also the entry point)
+// and a symbol table with the given symbols
+pub fn write_elf(path: &Path, base: u32, code: &[u8], symbols: &[(&str, u32)]) {
+    let mut strtab = vec![0u8];
+    let mut symtab = vec![0u8; 16];
+    for (name, value) in symbols {
+        symtab.extend_from_slice(&(strtab.len() as u32).to_le_bytes()); // st_name
+        symtab.extend_from_slice(&value.to_le_bytes()); // st_value
+        symtab.extend_from_slice(&0u32.to_le_bytes()); // st_size
+        symtab.extend_from_slice(&[0x10, 0]); // st_info (global), st_other
+        symtab.extend_from_slice(&1u16.to_le_bytes()); // st_shndx (.text)
+        strtab.extend_from_slice(name.as_bytes());
+        strtab.push(0);
+    }
+    let shstrtab = b"\0.text\0.symtab\0.strtab\0.shstrtab\0";
+
+    let symtab_offset = CODE_OFFSET + code.len().next_multiple_of(4);
+    let strtab_offset = symtab_offset + symtab.len();
+    let shstrtab_offset = strtab_offset + strtab.len();
+    let sh_offset = (shstrtab_offset + shstrtab.len()).next_multiple_of(4);
+
+    let mut data = Vec::new();
+    // ELF header (ELFCLASS32, little endian, ET_EXEC, EM_RISCV)
+    data.extend_from_slice(b"\x7fELF\x01\x01\x01\0\0\0\0\0\0\0\0\0");
+    for half in [2u16, 0xf3] {
+        data.extend_from_slice(&half.to_le_bytes());
+    }
+    for word in [1, base, 52, sh_offset as u32, 0] {
+        data.extend_from_slice(&word.to_le_bytes());
+    }
+    for half in [52u16, 32, 1, 40, 5, 4] {
+        data.extend_from_slice(&half.to_le_bytes());
+    }
+    // PT_LOAD (read, execute)
+    let len = code.len() as u32;
+    for word in [1, CODE_OFFSET as u32, base, base, len, len, 5, 0x1000] {
+        data.extend_from_slice(&word.to_le_bytes());
+    }
+
+    data.resize(CODE_OFFSET, 0);
+    data.extend_from_slice(code);
+    data.resize(symtab_offset, 0);
+    data.extend_from_slice(&symtab);
+    data.extend_from_slice(&strtab);
+    data.extend_from_slice(shstrtab);
+    data.resize(sh_offset, 0);
+
+    // [name, type, flags, addr, offset, size, link, info, addralign, entsize]
+    let [symtab_offset, strtab_offset, shstrtab_offset, symtab_len, strtab_len, shstrtab_len] = [
+        symtab_offset,
+        strtab_offset,
+        shstrtab_offset,
+        symtab.len(),
+        strtab.len(),
+        shstrtab.len(),
+    ]
+    .map(|value| value as u32);
+    let sections: [[u32; 10]; 5] = [
+        [0; 10],
+        [1, 1, 6, base, CODE_OFFSET as u32, len, 0, 0, 4, 0],
+        [7, 2, 0, 0, symtab_offset, symtab_len, 3, 1, 4, 16],
+        [15, 3, 0, 0, strtab_offset, strtab_len, 0, 0, 1, 0],
+        [23, 3, 0, 0, shstrtab_offset, shstrtab_len, 0, 0, 1, 0],
+    ];
+    for section in sections {
+        for word in section {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+    }
+
+    std::fs::write(path, data).unwrap();
+}
@@ -0,0 +1,71 @@
+// Boots a 32-bit Linux Image with a built-in initramfs (built by `python3 task.py task_build_linux`,
+// or set FRISC_LINUX_IMAGE) in S-mode with the SBI of the emulator, and waits for the BusyBox prompt
+// on the UART. The shell waits for input forever, so the emulator is killed once the prompt shows up.
+// Ignored by default as it needs the built kernel and runs for minutes, run it with
+// `python3 task.py task_linux_test` (or `cargo test --release -p emu --test linux -- --ignored`).
+
+use std::{
+    io::Read,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+const MAX_STEPS: usize = 2_000_000_000;
+const RAM_SIZE: usize = 128 * 1024 * 1024;
+// the init process is the shell itself, there is no getty
+const BOOTARGS: &str = "console=ttyS0 earlycon=sbi rdinit=/bin/sh";
+const PROMPT: &str = "# ";
+
+#[test]
+#[ignore]
+fn linux() {
+    let image_path = match std::env::var_os("FRISC_LINUX_IMAGE") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../buildroot/output/images/Image"),
+    };
+
+    assert!(
+        image_path.exists(),
+        "Linux is not built ({})",
+        image_path.display()
+    );
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_emu"))
+        .arg("--boot")
+        .arg("sbi")
+        .arg("--kernel-path")
+        .arg(&image_path)
+        .arg("--ram-size")
+        .arg(RAM_SIZE.to_string())
+        .arg("--bootargs")
+        .arg(BOOTARGS)
+        .arg("--max-steps")
+        .arg(MAX_STEPS.to_string())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut stdout = child.stdout.take().unwrap();
+    let mut output = Vec::new();
+    let mut buf = [0; 4096];
+    let found = loop {
+        match stdout.read(&mut buf) {
+            Ok(0) | Err(_) => break false,
+            Ok(len) => output.extend_from_slice(&buf[..len]),
+        }
+
+        // the prompt is the last thing printed before the shell reads the console
+        if String::from_utf8_lossy(&output).ends_with(PROMPT) {
+            break true;
+        }
+    };
+
+    let _ = child.kill();
+    let _ = child.wait();
+
+    let output = String::from_utf8_lossy(&output);
+    println!("{}", output);
+
+    assert!(output.contains("Linux version"), "no kernel banner");
+    assert!(found, "no shell prompt");
+}
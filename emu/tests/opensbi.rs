@@ -0,0 +1,49 @@
+// Boots OpenSBI (built by `python3 task.py task_build_opensbi`) with its test payload through the emu binary.
+// The payload prints a message through the SBI console and then waits forever, so the run ends at the step limit.
+// Ignored by default as it needs the built firmware, run it with `python3 task.py task_opensbi_test`
+// (or `cargo test -p emu --test opensbi -- --ignored --nocapture`).
+
+use std::{path::Path, process::Command};
+
+const MAX_STEPS: usize = 20_000_000;
+const PAYLOAD_MESSAGE: &str = "Test payload running";
+
+fn boot(firmware_dir: &Path, mode: &str, firmware: &str) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_emu"))
+        .arg("--program-path")
+        .arg(firmware_dir.join(firmware))
+        .arg("--boot")
+        .arg(mode)
+        .arg("--kernel-path")
+        .arg(firmware_dir.join("payloads/test.bin"))
+        .arg("--max-steps")
+        .arg(MAX_STEPS.to_string())
+        .output()
+        .unwrap();
+
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+#[test]
+#[ignore]
+fn opensbi() {
+    let firmware_dir =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("../opensbi/build/platform/generic/firmware");
+    assert!(
+        firmware_dir.join("payloads/test.bin").exists(),
+        "OpenSBI is not built ({})",
+        firmware_dir.display()
+    );
+
+    for (mode, firmware) in [("fw-jump", "fw_jump.elf"), ("fw-dynamic", "fw_dynamic.elf")] {
+        let stdout = boot(&firmware_dir, mode, firmware);
+        println!("{}:\n{}", firmware, stdout);
+
+        assert!(stdout.contains("OpenSBI"), "{}: no banner", firmware);
+        assert!(
+            stdout.contains(PAYLOAD_MESSAGE),
+            "{}: payload did not run",
+            firmware
+        );
+    }
+}
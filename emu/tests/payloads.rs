@@ -0,0 +1,69 @@
+// Loads a kernel and an initrd next to a small firmware through the emu binary.
+
+mod common;
+
+use std::{path::Path, process::Command};
+
+// exits through the debug exit port
+const FIRMWARE: [u8; 4] = [
+    0x23, 0x0a, 0x00, 0x0e, // SB x0, 0xf4(x0) (debug exit)
+];
+const FIRMWARE_LEN: usize = 0x1000;
+
+fn run(dir: &Path, kernel_addr: u32, initrd_addr: u32, initrd_len: usize) -> (bool, String) {
+    let elf_path = dir.join("firmware.elf");
+    let kernel_path = dir.join("kernel.bin");
+    let initrd_path = dir.join("initrd.bin");
+    let mut firmware = FIRMWARE.to_vec();
+    firmware.resize(FIRMWARE_LEN, 0);
+    common::write_elf(&elf_path, 0x8000_0000, &firmware, &[]);
+    std::fs::write(&kernel_path, [0; 0x100]).unwrap();
+    std::fs::write(&initrd_path, vec![0; initrd_len]).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_emu"))
+        .arg("--program-path")
+        .arg(&elf_path)
+        .arg("--boot")
+        .arg("fw-dynamic")
+        .arg("--kernel-path")
+        .arg(&kernel_path)
+        .arg("--kernel-addr")
+        .arg(kernel_addr.to_string())
+        .arg("--initrd-path")
+        .arg(&initrd_path)
+        .arg("--initrd-addr")
+        .arg(initrd_addr.to_string())
+        .output()
+        .unwrap();
+
+    (
+        output.status.success(),
+        String::from_utf8_lossy(&output.stderr).to_string(),
+    )
+}
+
+#[test]
+fn payloads() {
+    let dir = std::env::temp_dir().join(format!("frisc-payloads-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let (success, stderr) = run(&dir, 0x8000_1000, 0x8000_2000, 0x100);
+    assert!(success, "{}", stderr);
+
+    // over the firmware, over the kernel and past the end of the address space
+    for (kernel_addr, initrd_addr, initrd_len) in [
+        (0x8000_0f00, 0x8000_2000, 0x100),
+        (0x8000_1000, 0x8000_10f0, 0x100),
+        (0x8000_1000, 0xffff_ff00, 0x200),
+    ] {
+        let (success, stderr) = run(&dir, kernel_addr, initrd_addr, initrd_len);
+        assert!(!success);
+        assert!(
+            stderr.contains("overlaps") || stderr.contains("doesn't fit"),
+            "{}",
+            stderr
+        );
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
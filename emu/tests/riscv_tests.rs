@@ -0,0 +1,114 @@
+// Runs the riscv-tests ISA tests (built by `python3 task.py task_build`) through the emu binary.
+// Tests of suites listed in SUPPORTED_SUITES must pass, the others are only reported.
+// Ignored by default as it needs the built tests, run it with `python3 task.py task_riscv_tests`
+// (or `cargo test -p emu --test riscv_tests -- --ignored --nocapture`).
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const MAX_STEPS: usize = 1_000_000;
+const SUPPORTED_SUITES: &[&str] = &["rv32ui", "rv32um", "rv32ua", "rv32mi", "rv32si"];
+
+#[derive(Debug)]
+enum Outcome {
+    Pass,
+    Fail(u32), // failed test case number
+    Error(String),
+}
+
+fn discover(isa_dir: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = match fs::read_dir(isa_dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| {
+                let name = path.file_name().unwrap().to_string_lossy();
+                name.starts_with("rv32") && name.contains("-p-") && path.extension().is_none()
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    paths.sort();
+    paths
+}
+
+fn run(path: &Path) -> Outcome {
+    let output = match Command::new(env!("CARGO_BIN_EXE_emu"))
+        .arg("--program-path")
+        .arg(path)
+        .arg("--max-steps")
+        .arg(MAX_STEPS.to_string())
+        .output()
+    {
+        Ok(output) => output,
+        Err(e) => return Outcome::Error(e.to_string()),
+    };
+
+    // a pass is reported through tohost, not by running off the end of ram
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if stdout.contains("Stopped without an exit request") {
+        return Outcome::Error(String::from("no tohost exit"));
+    }
+    let exit_code = stdout
+        .lines()
+        .find_map(|line| line.strip_prefix("Exited with 0x"))
+        .and_then(|code| u32::from_str_radix(code, 16).ok());
+
+    match exit_code {
+        Some(0) => Outcome::Pass,
+        Some(test_num) => Outcome::Fail(test_num),
+        None => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Outcome::Error(stderr.lines().next().unwrap_or("unknown error").to_string())
+        }
+    }
+}
+
+#[test]
+#[ignore]
+fn riscv_tests() {
+    let isa_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../riscv-tests/isa");
+    let paths = discover(&isa_dir);
+
+    assert!(
+        !paths.is_empty(),
+        "riscv-tests are not built ({})",
+        isa_dir.display()
+    );
+
+    let mut regressions = Vec::new();
+    let mut passed = 0;
+
+    println!("{:<32} {:<10} result", "test", "supported");
+    for path in &paths {
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        let suite = name.split('-').next().unwrap();
+        let supported = SUPPORTED_SUITES.contains(&suite);
+        let outcome = run(path);
+
+        let result = match &outcome {
+            Outcome::Pass => String::from("pass"),
+            Outcome::Fail(test_num) => format!("FAIL (test #{})", test_num),
+            Outcome::Error(e) => format!("ERROR ({})", e),
+        };
+        println!("{:<32} {:<10} {}", name, supported, result);
+
+        match outcome {
+            Outcome::Pass => passed += 1,
+            _ if supported => regressions.push(name),
+            _ => (),
+        }
+    }
+
+    println!(
+        "{} tests, {} passed, {} failed ({} regressions)",
+        paths.len(),
+        passed,
+        paths.len() - passed,
+        regressions.len()
+    );
+
+    assert!(regressions.is_empty(), "regressions: {:?}", regressions);
+}
@@ -0,0 +1,59 @@
+// Dumps the signature of a small program (the riscv-arch-test convention) through the emu binary.
+
+mod common;
+
+use std::{path::Path, process::Command};
+
+// writes two words to the signature at 0x80000040 and exits through the debug exit port
+const PROGRAM: [u8; 24] = [
+    0xb7, 0x02, 0x00, 0x80, // LUI x5, 0x80000
+    0x13, 0x03, 0x30, 0x12, // ADDI x6, x0, 0x123
+    0x23, 0xa0, 0x62, 0x04, // SW x6, 0x40(x5) (begin_signature)
+    0x13, 0x03, 0xf0, 0xff, // ADDI x6, x0, -1
+    0x23, 0xa2, 0x62, 0x04, // SW x6, 0x44(x5)
+    0x23, 0x0a, 0x00, 0x0e, // SB x0, 0xf4(x0) (debug exit)
+];
+const PROGRAM_LEN: usize = 0x48;
+
+fn run(dir: &Path, begin: u32, end: u32) -> (bool, Option<String>) {
+    let elf_path = dir.join("signature.elf");
+    let signature_path = dir.join("signature.txt");
+    let _ = std::fs::remove_file(&signature_path);
+    let symbols = [("begin_signature", begin), ("end_signature", end)];
+    let mut program = PROGRAM.to_vec();
+    program.resize(PROGRAM_LEN, 0);
+    common::write_elf(&elf_path, 0x8000_0000, &program, &symbols);
+
+    let status = Command::new(env!("CARGO_BIN_EXE_emu"))
+        .arg("--program-path")
+        .arg(&elf_path)
+        .arg("--signature-path")
+        .arg(&signature_path)
+        .output()
+        .unwrap()
+        .status;
+
+    (
+        status.success(),
+        std::fs::read_to_string(&signature_path).ok(),
+    )
+}
+
+#[test]
+fn signature() {
+    let dir = std::env::temp_dir().join(format!("frisc-signature-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let (success, signature) = run(&dir, 0x8000_0040, 0x8000_0048);
+    assert!(success);
+    assert_eq!(signature.as_deref(), Some("00000123\nffffffff\n"));
+
+    // a range that isn't in the ram is an error, not a panic after the run
+    for (begin, end) in [(0x8000_0040, 0x9000_0000), (0x8000_0048, 0x8000_0040)] {
+        let (success, signature) = run(&dir, begin, end);
+        assert!(!success);
+        assert_eq!(signature, None);
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,86 @@
+// Bridges the UART of the emu binary to a Unix domain socket and talks to the guest through it.
+
+mod common;
+
+use std::{
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+// prints "ok", then echoes the input until 'q' and exits with 42
+const ECHO_PROGRAM: [u8; 60] = [
+    0x93, 0x02, 0x80, 0x3f, // ADDI x5, x0, 0x3f8 (UART)
+    0x13, 0x03, 0xf0, 0x06, // ADDI x6, x0, 'o'
+    0x23, 0x80, 0x62, 0x00, // SB x6, 0(x5)
+    0x13, 0x03, 0xb0, 0x06, // ADDI x6, x0, 'k'
+    0x23, 0x80, 0x62, 0x00, // SB x6, 0(x5)
+    0x93, 0x03, 0x10, 0x07, // ADDI x7, x0, 'q'
+    0x03, 0xc4, 0x52, 0x00, // LBU x8, 5(x5) (LSR)
+    0x13, 0x74, 0x14, 0x00, // ANDI x8, x8, 1 (data ready)
+    0xe3, 0x0c, 0x04, 0xfe, // BEQ x8, x0, -8
+    0x03, 0xc3, 0x02, 0x00, // LBU x6, 0(x5)
+    0x63, 0x06, 0x73, 0x00, // BEQ x6, x7, 12
+    0x23, 0x80, 0x62, 0x00, // SB x6, 0(x5)
+    0x6f, 0xf0, 0x9f, 0xfe, // JAL x0, -24
+    0x13, 0x03, 0xa0, 0x02, // ADDI x6, x0, 42
+    0x23, 0x0a, 0x60, 0x0e, // SB x6, 0xf4(x0) (debug exit)
+];
+
+fn connect(path: &Path) -> UnixStream {
+    let start = Instant::now();
+    loop {
+        match UnixStream::connect(path) {
+            Ok(stream) => return stream,
+            Err(e) if start.elapsed() > Duration::from_secs(10) => panic!("{}", e),
+            Err(_) => thread::sleep(Duration::from_millis(10)),
+        }
+    }
+}
+
+#[test]
+fn uart_socket() {
+    let dir = std::env::temp_dir().join(format!("frisc-uart-socket-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let elf_path = dir.join("echo.elf");
+    let socket_path = dir.join("uart.sock");
+    common::write_elf(&elf_path, 0x8000_0000, &ECHO_PROGRAM, &[]);
+
+    let child = Command::new(env!("CARGO_BIN_EXE_emu"))
+        .arg("--program-path")
+        .arg(&elf_path)
+        .arg("--uart")
+        .arg("unix")
+        .arg("--uart-addr")
+        .arg(&socket_path)
+        .arg("--uart-wait")
+        .arg("--max-steps")
+        .arg("100000000")
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // the guest waits for the client, so the first output isn't lost
+    let mut stream = connect(&socket_path);
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let mut buf = [0u8; 2];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"ok");
+
+    stream.write_all(b"hi").unwrap();
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hi");
+
+    stream.write_all(b"q").unwrap();
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Exited with 0x2a"), "{}", stdout);
+    assert!(!socket_path.exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
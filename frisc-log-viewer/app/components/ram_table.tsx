@@ -20,6 +20,7 @@ export default function RamTable(props: Readonly<RamTableProps>)
     const [u32Ram, setU32Ram] = useState<Uint32Array | undefined>(undefined);
     const [rows, setRows] = useState<any[]>([]);
     const [activeRamAddress, setActiveRamAddress] = useState<number[]>([]);
+    const [baseAddr, setBaseAddr] = useState<number>(0);
     const activeRowRef = useRef<HTMLTableRowElement>(null);
 
     useEffect(() =>
@@ -38,6 +39,7 @@ export default function RamTable(props: Readonly<RamTableProps>)
         }
 
         const ram = [...props.stepLog.init_ram];
+        const baseAddr = props.stepLog.init_ram_base_addr;
         const activeRamAddress: number[] = [];
 
         for (let i = 0; i < props.step; i++)
@@ -45,17 +47,18 @@ export default function RamTable(props: Readonly<RamTableProps>)
             const cpuStep: CpuStep | undefined = props.stepLog.steps[i];
             cpuStep?.ram_writes.forEach(v =>
             {
-                ram[v.addr] = v.value;
+                ram[v.addr - baseAddr] = v.value;
 
                 if (props.step === cpuStep.step + 1)
                 {
-                    activeRamAddress.push(v.addr);
+                    activeRamAddress.push(v.addr - baseAddr);
                 }
             });
         }
 
         setU32Ram(numArrayTou32Array(ram));
         setActiveRamAddress(activeRamAddress);
+        setBaseAddr(baseAddr);
     }, [props.step, props.stepLog]);
 
     useEffect(() =>
@@ -96,7 +99,7 @@ export default function RamTable(props: Readonly<RamTableProps>)
                 {rows.map((row, index) => (
                     <TableRow key={index.toString()} sx={{ backgroundColor: row.isActive ? "lightyellow" : "white" }} ref={row.isActive ? activeRowRef : null}>
                         <TableCell align="right">
-                            0x{row.start.toString(16).padStart(8, "0")} - 0x{row.end.toString(16).padStart(8, "0")}
+                            0x{(baseAddr + row.start).toString(16).padStart(8, "0")} - 0x{(baseAddr + row.end).toString(16).padStart(8, "0")}
                         </TableCell>
                         <TableCell align="right">0x{row.value.toString(16).padStart(8, "0")}</TableCell>
                     </TableRow>
@@ -1,9 +1,11 @@
 export interface StepLog
 {
     init_cpu_state: CpuState;
+    init_ram_base_addr: number;
     init_ram: number[];
     steps: CpuStep[];
     dev_reqs: DeviceRequest[];
+    dev_writes: DeviceWrite[];
 }
 
 export interface DeviceRequest
@@ -12,11 +14,27 @@ export interface DeviceRequest
     req: RequestFromDevice;
 }
 
-export interface RequestFromDevice
+// writes by bus masters other than the cpu (one entry per burst)
+export interface DeviceWrite
 {
-    Exit: number;
+    step: number;
+    device_name: string;
+    ram_writes: RamWrite[];
 }
 
+export type RequestFromDevice =
+    | { Exit: number }
+    | "Reset"
+    | { GpioOutput: { pin: number; level: boolean } }
+    | { I2c: I2cEvent }
+    | "WatchdogExpired";
+
+export type I2cEvent =
+    | { Start: { address: number; read: boolean; ack: boolean } }
+    | { Write: { value: number; ack: boolean } }
+    | { Read: { value: number; ack: boolean } }
+    | "Stop";
+
 export interface CpuState
 {
     zero: number;
@@ -66,6 +84,8 @@ export enum State
 export interface CpuStep
 {
     step: number;
+    cycle: number;
+    hart: number;
     fetched_instruction: number;
     decoded_instruction: Instruction;
     cpu_state: CpuState;
@@ -109,9 +129,39 @@ export type Bltu = { Bltu: { rs1: number; rs2: number; offset: number } };
 export type Bgeu = { Bgeu: { rs1: number; rs2: number; offset: number } };
 export type Lui = { Lui: { rd: number; imm: number } };
 export type Auipc = { Auipc: { rd: number; imm: number } };
+export type Mul = { Mul: { rd: number; rs1: number; rs2: number } };
+export type Mulh = { Mulh: { rd: number; rs1: number; rs2: number } };
+export type Mulhsu = { Mulhsu: { rd: number; rs1: number; rs2: number } };
+export type Mulhu = { Mulhu: { rd: number; rs1: number; rs2: number } };
+export type Div = { Div: { rd: number; rs1: number; rs2: number } };
+export type Divu = { Divu: { rd: number; rs1: number; rs2: number } };
+export type Rem = { Rem: { rd: number; rs1: number; rs2: number } };
+export type Remu = { Remu: { rd: number; rs1: number; rs2: number } };
+export type LrW = { LrW: { rd: number; rs1: number } };
+export type ScW = { ScW: { rd: number; rs1: number; rs2: number } };
+export type AmoswapW = { AmoswapW: { rd: number; rs1: number; rs2: number } };
+export type AmoaddW = { AmoaddW: { rd: number; rs1: number; rs2: number } };
+export type AmoxorW = { AmoxorW: { rd: number; rs1: number; rs2: number } };
+export type AmoandW = { AmoandW: { rd: number; rs1: number; rs2: number } };
+export type AmoorW = { AmoorW: { rd: number; rs1: number; rs2: number } };
+export type AmominW = { AmominW: { rd: number; rs1: number; rs2: number } };
+export type AmomaxW = { AmomaxW: { rd: number; rs1: number; rs2: number } };
+export type AmominuW = { AmominuW: { rd: number; rs1: number; rs2: number } };
+export type AmomaxuW = { AmomaxuW: { rd: number; rs1: number; rs2: number } };
 export type Fence = { Fence: { pred: number; succ: number } };
+export type FenceI = { FenceI: {} };
 export type Ecall = { Ecall: {} };
 export type Ebreak = { Ebreak: {} };
+export type Sret = { Sret: {} };
+export type Mret = { Mret: {} };
+export type Wfi = { Wfi: {} };
+export type SfenceVma = { SfenceVma: { rs1: number; rs2: number } };
+export type Csrrw = { Csrrw: { rd: number; rs1: number; csr: number } };
+export type Csrrs = { Csrrs: { rd: number; rs1: number; csr: number } };
+export type Csrrc = { Csrrc: { rd: number; rs1: number; csr: number } };
+export type Csrrwi = { Csrrwi: { rd: number; uimm: number; csr: number } };
+export type Csrrsi = { Csrrsi: { rd: number; uimm: number; csr: number } };
+export type Csrrci = { Csrrci: { rd: number; uimm: number; csr: number } };
 
 export type Instruction =
     | Add
@@ -151,9 +201,39 @@ export type Instruction =
     | Bgeu
     | Lui
     | Auipc
+    | Mul
+    | Mulh
+    | Mulhsu
+    | Mulhu
+    | Div
+    | Divu
+    | Rem
+    | Remu
+    | LrW
+    | ScW
+    | AmoswapW
+    | AmoaddW
+    | AmoxorW
+    | AmoandW
+    | AmoorW
+    | AmominW
+    | AmomaxW
+    | AmominuW
+    | AmomaxuW
     | Fence
+    | FenceI
     | Ecall
-    | Ebreak;
+    | Ebreak
+    | Sret
+    | Mret
+    | Wfi
+    | SfenceVma
+    | Csrrw
+    | Csrrs
+    | Csrrc
+    | Csrrwi
+    | Csrrsi
+    | Csrrci;
 
 export interface RamWrite
 {
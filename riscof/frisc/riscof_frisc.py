@@ -0,0 +1,84 @@
+import os
+import logging
+
+import riscof.utils as utils
+from riscof.pluginTemplate import pluginTemplate
+
+logger = logging.getLogger()
+
+# riscv-arch-test tests are small, this only guards against hangs
+MAX_STEPS = 10_000_000
+
+
+class frisc(pluginTemplate):
+    __model__ = "frisc"
+    __version__ = "0.1.0"
+
+    def __init__(self, *args, **kwargs):
+        super().__init__(*args, **kwargs)
+
+        config = kwargs.get("config")
+        if config is None:
+            print("Please enter input file paths in configuration.")
+            raise SystemExit(1)
+
+        self.num_jobs = str(config["jobs"] if "jobs" in config else 1)
+        self.pluginpath = os.path.abspath(config["pluginpath"])
+        self.isa_spec = os.path.abspath(config["ispec"])
+        self.platform_spec = os.path.abspath(config["pspec"])
+        self.dut_exe = os.path.join(
+            os.path.abspath(config["PATH"]) if "PATH" in config else "", "emu"
+        )
+        self.target_run = config.get("target_run", "1") != "0"
+
+    def initialise(self, suite, work_dir, archtest_env):
+        self.work_dir = work_dir
+        self.suite_dir = suite
+        self.compile_cmd = (
+            "riscv{1}-unknown-elf-gcc -march={0} -static -mcmodel=medany"
+            " -fvisibility=hidden -nostdlib -nostartfiles -g"
+            f" -T {self.pluginpath}/env/link.ld"
+            f" -I {self.pluginpath}/env/"
+            f" -I {archtest_env}"
+            " {2} -o {3} {4}"
+        )
+
+    def build(self, isa_yaml, platform_yaml):
+        ispec = utils.load_yaml(isa_yaml)["hart0"]
+        self.xlen = "64" if 64 in ispec["supported_xlen"] else "32"
+        self.compile_cmd += " -mabi=" + ("lp64" if self.xlen == "64" else "ilp32")
+
+    def runTests(self, testList):
+        makefile = os.path.join(self.work_dir, f"Makefile.{self.name[:-1]}")
+        if os.path.exists(makefile):
+            os.remove(makefile)
+
+        make = utils.makeUtil(makefilePath=makefile)
+        make.makeCommand = f"make -k -j{self.num_jobs}"
+
+        for testname in testList:
+            testentry = testList[testname]
+            test = testentry["test_path"]
+            test_dir = testentry["work_dir"]
+
+            elf = "my.elf"
+            sig_file = os.path.join(test_dir, self.name[:-1] + ".signature")
+            compile_macros = " -D" + " -D".join(testentry["macros"])
+            cmd = self.compile_cmd.format(
+                testentry["isa"].lower(), self.xlen, test, elf, compile_macros
+            )
+
+            if self.target_run:
+                simcmd = (
+                    f"{self.dut_exe} --program-path {elf}"
+                    f" --signature-path {sig_file} --max-steps {MAX_STEPS}"
+                )
+            else:
+                simcmd = 'echo "NO RUN"'
+
+            make.add_target(f"@cd {test_dir}; {cmd}; {simcmd};")
+
+        make.execute_all(self.work_dir)
+
+        if not self.target_run:
+            raise SystemExit(0)
@@ -1,11 +1,18 @@
 use serde::Serialize;
+use std::{
+    fmt,
+    sync::atomic::{fence, Ordering},
+};
 
 use crate::{
+    csr::{self, Csrs},
     instruction::{Instruction, InstructionFormat},
     mmio_device::MmioDeviceInterface,
-    ram::Ram,
+    mmu::{self, AccessType},
+    ram::Memory,
     register::{ProgramCounter, Register},
-    step_log,
+    semihosting, step_log,
+    timing::Timing,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
@@ -16,12 +23,120 @@ pub enum CpuState {
     Execute,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum PrivilegeMode {
+    User = 0,
+    Supervisor = 1,
+    Machine = 3,
+}
+
+impl PrivilegeMode {
+    // mstatus.MPP encoding (the reserved value 2 is treated as user mode)
+    pub fn from_bits(bits: u32) -> Self {
+        match bits & 0b11 {
+            0b01 => Self::Supervisor,
+            0b11 => Self::Machine,
+            _ => Self::User,
+        }
+    }
+}
+
+// synchronous exceptions (the value is the faulting virtual address, the illegal instruction or
+// the address of the ebreak), returned as errors and turned into traps by fetch_decode_execute
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    IllegalInstruction(u32),
+    Breakpoint(u32),
+    EnvironmentCall(PrivilegeMode),
+    InstructionAccessFault(u32),
+    LoadAccessFault(u32),
+    StoreAccessFault(u32),
+    InstructionPageFault(u32),
+    LoadPageFault(u32),
+    StorePageFault(u32),
+}
+
+impl Exception {
+    pub fn cause(&self) -> u32 {
+        match self {
+            Self::IllegalInstruction(_) => csr::CAUSE_ILLEGAL_INSTRUCTION,
+            Self::Breakpoint(_) => csr::CAUSE_BREAKPOINT,
+            Self::EnvironmentCall(PrivilegeMode::User) => csr::CAUSE_ECALL_FROM_U,
+            Self::EnvironmentCall(PrivilegeMode::Supervisor) => csr::CAUSE_ECALL_FROM_S,
+            Self::EnvironmentCall(PrivilegeMode::Machine) => csr::CAUSE_ECALL_FROM_M,
+            Self::InstructionAccessFault(_) => csr::CAUSE_INSTRUCTION_ACCESS_FAULT,
+            Self::LoadAccessFault(_) => csr::CAUSE_LOAD_ACCESS_FAULT,
+            Self::StoreAccessFault(_) => csr::CAUSE_STORE_ACCESS_FAULT,
+            Self::InstructionPageFault(_) => csr::CAUSE_INSTRUCTION_PAGE_FAULT,
+            Self::LoadPageFault(_) => csr::CAUSE_LOAD_PAGE_FAULT,
+            Self::StorePageFault(_) => csr::CAUSE_STORE_PAGE_FAULT,
+        }
+    }
+
+    pub fn tval(&self) -> u32 {
+        match *self {
+            Self::IllegalInstruction(instruction) => instruction,
+            Self::EnvironmentCall(_) => 0,
+            Self::Breakpoint(addr)
+            | Self::InstructionAccessFault(addr)
+            | Self::LoadAccessFault(addr)
+            | Self::StoreAccessFault(addr)
+            | Self::InstructionPageFault(addr)
+            | Self::LoadPageFault(addr)
+            | Self::StorePageFault(addr) => addr,
+        }
+    }
+}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::IllegalInstruction(instruction) => {
+                return write!(f, "Illegal instruction 0x{:08x}", instruction);
+            }
+            Self::EnvironmentCall(mode) => {
+                return write!(f, "Environment call from {:?} mode", mode);
+            }
+            Self::Breakpoint(_) => "Breakpoint",
+            Self::InstructionAccessFault(_) => "Instruction access fault",
+            Self::LoadAccessFault(_) => "Load access fault",
+            Self::StoreAccessFault(_) => "Store access fault",
+            Self::InstructionPageFault(_) => "Instruction page fault",
+            Self::LoadPageFault(_) => "Load page fault",
+            Self::StorePageFault(_) => "Store page fault",
+        };
+        write!(f, "{} at 0x{:08x}", name, self.tval())
+    }
+}
+
+impl std::error::Error for Exception {}
+
+#[derive(Debug, Clone)]
 pub struct Cpu {
     pub x_regs: [Register; 32],
     pub pc: ProgramCounter,
+    pub csrs: Csrs,
+    pub mode: PrivilegeMode,
     pub state: CpuState,
     pub step: usize,
+    // cycles of the steps according to the timing model (mcycle)
+    pub cycle: u64,
+    // instructions retired without a trap (minstret)
+    pub instret: u64,
+    // latencies of the instruction classes, kept across resets
+    pub timing: Timing,
+    // physical address reserved by lr.w and the tag of its reservation set
+    pub reservation: Option<(u32, u32)>,
+    // ecalls from this mode are handled by the host (e.g. SBI) instead of trapping
+    pub host_ecall: Option<PrivilegeMode>,
+    // set by such an ecall until the host has handled it
+    pub ecall_pending: bool,
+    // ebreaks in the semihosting sequence are handled by the host
+    pub semihosting: bool,
+    // set by such an ebreak until the host has handled it
+    pub semihosting_pending: bool,
+    // not scheduled until it is started again (e.g. by the SBI HSM extension)
+    pub stopped: bool,
 }
 
 impl Default for Cpu {
@@ -29,32 +144,100 @@ impl Default for Cpu {
         Self {
             x_regs: [Register::default(); 32],
             pc: ProgramCounter::default(),
+            csrs: Csrs::default(),
+            mode: PrivilegeMode::Machine,
             state: CpuState::Reset,
             step: 0,
+            cycle: 0,
+            instret: 0,
+            timing: Timing::default(),
+            reservation: None,
+            host_ecall: None,
+            ecall_pending: false,
+            semihosting: false,
+            semihosting_pending: false,
+            stopped: false,
         }
     }
 }
 
 impl Cpu {
+    pub fn new_with_hart_id(hart_id: u32) -> Self {
+        let mut cpu = Self::default();
+        cpu.csrs.set_hart_id(hart_id);
+        cpu
+    }
+
+    pub fn hart_id(&self) -> u32 {
+        self.csrs.load(csr::MHARTID)
+    }
+
     pub fn reset(&mut self) {
         self.x_regs = [Register::default(); 32];
         self.pc = ProgramCounter::default();
+        self.csrs.reset();
+        self.mode = PrivilegeMode::Machine;
         self.state = CpuState::Reset;
         self.step = 0;
+        self.cycle = 0;
+        self.instret = 0;
+        self.reservation = None;
+        self.host_ecall = None;
+        self.ecall_pending = false;
+        self.semihosting = false;
+        self.semihosting_pending = false;
+        self.stopped = false;
+    }
+
+    // address translation is active for instruction fetches
+    pub fn is_translating(&self) -> bool {
+        mmu::is_enabled(&self.csrs, self.mode)
     }
 
     pub fn fetch_decode_execute(
         &mut self,
-        ram: &mut Ram,
+        ram: &mut impl Memory,
         mmio_devices: &mut Vec<Box<dyn MmioDeviceInterface>>,
         print_instruction_log: bool,
     ) -> anyhow::Result<step_log::CpuStep> {
-        let fetched_instruction = self.fetch(ram)?;
-        let decoded_instruction = self.decode(fetched_instruction)?;
-        let ram_writes = self.execute(decoded_instruction, ram, mmio_devices)?;
+        self.csrs.set_counters(self.cycle, self.instret);
+        self.handle_interrupt();
+
+        let mut ram_writes = Vec::new();
+        let (fetched_instruction, decoded_instruction) =
+            match self.fetch_decode(ram, &mut ram_writes) {
+                Ok(instruction) => instruction,
+                // the trap handler is fetched in the same step
+                Err(e) => {
+                    let exception = e.downcast::<Exception>()?;
+                    // an illegal instruction is only known after the fetch
+                    self.state = CpuState::Execute;
+                    self.trap(exception.cause(), exception.tval());
+                    self.fetch_decode(ram, &mut ram_writes)?
+                }
+            };
+        let pc = self.pc.load();
+        match self.execute(
+            fetched_instruction,
+            decoded_instruction,
+            ram,
+            mmio_devices,
+            &mut ram_writes,
+        ) {
+            Ok(()) => self.instret = self.instret.wrapping_add(1),
+            Err(e) => {
+                let exception = e.downcast::<Exception>()?;
+                self.trap(exception.cause(), exception.tval());
+            }
+        }
+        let taken = self.pc.load() != pc.wrapping_add(4);
+        let latency = self.timing.latency(&decoded_instruction, taken);
+        self.cycle = self.cycle.wrapping_add(latency);
 
         let cpu_step = step_log::CpuStep {
             step: self.step,
+            cycle: self.cycle,
+            hart: self.hart_id(),
             fetched_instruction,
             decoded_instruction,
             cpu_state: step_log::CpuStateLog::new(&self),
@@ -70,21 +253,80 @@ impl Cpu {
         Ok(cpu_step)
     }
 
-    fn fetch(&mut self, ram: &Ram) -> anyhow::Result<u32> {
+    fn fetch_decode(
+        &mut self,
+        ram: &mut impl Memory,
+        ram_write_logs: &mut Vec<step_log::RamWrite>,
+    ) -> anyhow::Result<(u32, Instruction)> {
+        let instruction = self.fetch(ram, ram_write_logs)?;
+        Ok((instruction, self.decode(instruction)?))
+    }
+
+    fn fetch(
+        &mut self,
+        ram: &mut impl Memory,
+        ram_write_logs: &mut Vec<step_log::RamWrite>,
+    ) -> anyhow::Result<u32> {
         match self.state {
             CpuState::Reset | CpuState::Execute => (),
             _ => return Err(anyhow::anyhow!("Invalid state for fetch")),
         }
 
+        let pc = self.pc.load();
+        let addr = if self.is_translating() {
+            let addr = mmu::translate(
+                &self.csrs,
+                self.mode,
+                pc,
+                AccessType::Fetch,
+                ram,
+                ram_write_logs,
+            )?;
+            if !ram.is_available_addr(addr) {
+                return Err(AccessType::Fetch.access_fault(pc).into());
+            }
+            addr
+        } else {
+            if !ram.is_available_addr(pc) {
+                return Err(anyhow::anyhow!("PC is out of bounds memory"));
+            }
+            pc
+        };
+
         self.state = CpuState::Fetch;
 
+        let instruction = ram.load32(addr);
+        Ok(instruction)
+    }
+
+    // the ebreak is between `slli x0, x0, 0x1f` and `srai x0, x0, 7` on the same page
+    fn is_semihosting_call(
+        &self,
+        ram: &mut impl Memory,
+        ram_write_logs: &mut Vec<step_log::RamWrite>,
+    ) -> bool {
         let pc = self.pc.load();
-        if pc as usize >= ram.size() {
-            return Err(anyhow::anyhow!("PC is out of bounds memory"));
+        let offset = pc as u64 % mmu::PAGE_SIZE;
+        if offset < 4 || offset + 8 > mmu::PAGE_SIZE {
+            return false;
         }
 
-        let instruction = ram.load32(pc);
-        Ok(instruction)
+        let addr = match mmu::translate(
+            &self.csrs,
+            self.mode,
+            pc,
+            AccessType::Fetch,
+            ram,
+            ram_write_logs,
+        ) {
+            Ok(addr) => addr,
+            Err(_) => return false,
+        };
+
+        ram.is_available_addr(addr - 4)
+            && ram.is_available_addr(addr + 7)
+            && ram.load32(addr - 4) == semihosting::SLLI_X0_X0_0X1F
+            && ram.load32(addr + 4) == semihosting::SRAI_X0_X0_7
     }
 
     fn decode(&mut self, instruction: u32) -> anyhow::Result<Instruction> {
@@ -99,36 +341,38 @@ impl Cpu {
         Ok(parsed_instruction)
     }
 
+    // the raw instruction is the trap value of an illegal instruction
     fn execute(
         &mut self,
+        raw_instruction: u32,
         instruction: Instruction,
-        ram: &mut Ram,
+        ram: &mut impl Memory,
         mmio_devices: &mut Vec<Box<dyn MmioDeviceInterface>>,
-    ) -> anyhow::Result<Vec<step_log::RamWrite>> {
+        ram_write_logs: &mut Vec<step_log::RamWrite>,
+    ) -> anyhow::Result<()> {
         match self.state {
             CpuState::Decode => (),
             _ => return Err(anyhow::anyhow!("Invalid state for execute")),
         }
 
         self.state = CpuState::Execute;
-        let mut ram_write_logs = Vec::new();
 
         match instruction {
             Instruction::Add { rd, rs1, rs2 } => {
                 let x_rs1 = self.load_x_regs(rs1)?;
                 let x_rs2 = self.load_x_regs(rs2)?;
-                self.store_x_regs(rd, x_rs1 + x_rs2)?;
+                self.store_x_regs(rd, x_rs1.wrapping_add(x_rs2))?;
                 self.pc.increment();
             }
             Instruction::Addi { rd, rs1, imm } => {
                 let x_rs1 = self.load_x_regs(rs1)? as i32;
-                self.store_x_regs(rd, (x_rs1 + imm as i32) as u32)?;
+                self.store_x_regs(rd, x_rs1.wrapping_add(imm as i32) as u32)?;
                 self.pc.increment();
             }
             Instruction::Sub { rd, rs1, rs2 } => {
                 let x_rs1 = self.load_x_regs(rs1)? as i32;
                 let x_rs2 = self.load_x_regs(rs2)? as i32;
-                self.store_x_regs(rd, (x_rs1 - x_rs2) as u32)?;
+                self.store_x_regs(rd, x_rs1.wrapping_sub(x_rs2) as u32)?;
                 self.pc.increment();
             }
             Instruction::And { rd, rs1, rs2 } => {
@@ -226,7 +470,7 @@ impl Cpu {
                 } else {
                     addr - (-offset) as u32
                 };
-                let mut value = ram.load8_with_mmio(addr, mmio_devices) as u32;
+                let mut value = self.load_memory(addr, 1, ram, mmio_devices, ram_write_logs)?;
                 if value & 0x80 != 0 {
                     value |= 0xffffff00;
                 }
@@ -240,7 +484,7 @@ impl Cpu {
                 } else {
                     addr - (-offset) as u32
                 };
-                let value = ram.load8_with_mmio(addr, mmio_devices) as u32;
+                let value = self.load_memory(addr, 1, ram, mmio_devices, ram_write_logs)?;
                 self.store_x_regs(rd, value)?;
                 self.pc.increment();
             }
@@ -252,10 +496,8 @@ impl Cpu {
                     addr - (-offset) as u32
                 };
                 let value = self.load_x_regs(rs2)? as u8;
-                ram.store8_with_mmio(addr, value, mmio_devices);
+                self.store_memory(addr, 1, value as u32, ram, mmio_devices, ram_write_logs)?;
                 self.pc.increment();
-
-                ram_write_logs.push(step_log::RamWrite::new(addr, value))
             }
             Instruction::Lh { rd, rs1, offset } => {
                 let mut addr = self.load_x_regs(rs1)?;
@@ -264,7 +506,7 @@ impl Cpu {
                 } else {
                     addr - (-offset) as u32
                 };
-                let mut value = ram.load16_with_mmio(addr, mmio_devices) as u32;
+                let mut value = self.load_memory(addr, 2, ram, mmio_devices, ram_write_logs)?;
                 if value & 0x8000 != 0 {
                     value |= 0xffff0000;
                 }
@@ -278,7 +520,7 @@ impl Cpu {
                 } else {
                     addr - (-offset) as u32
                 };
-                let value = ram.load16_with_mmio(addr, mmio_devices) as u32;
+                let value = self.load_memory(addr, 2, ram, mmio_devices, ram_write_logs)?;
                 self.store_x_regs(rd, value)?;
                 self.pc.increment();
             }
@@ -290,12 +532,8 @@ impl Cpu {
                     addr - (-offset) as u32
                 };
                 let value = self.load_x_regs(rs2)? as u16;
-                ram.store16_with_mmio(addr, value, mmio_devices);
+                self.store_memory(addr, 2, value as u32, ram, mmio_devices, ram_write_logs)?;
                 self.pc.increment();
-
-                let values = value.to_le_bytes();
-                ram_write_logs.push(step_log::RamWrite::new(addr, values[0]));
-                ram_write_logs.push(step_log::RamWrite::new(addr + 1, values[1]));
             }
             Instruction::Lw { rd, rs1, offset } => {
                 let mut addr = self.load_x_regs(rs1)?;
@@ -304,7 +542,7 @@ impl Cpu {
                 } else {
                     addr - (-offset) as u32
                 };
-                let value = ram.load32_with_mmio(addr, mmio_devices);
+                let value = self.load_memory(addr, 4, ram, mmio_devices, ram_write_logs)?;
                 self.store_x_regs(rd, value)?;
                 self.pc.increment();
             }
@@ -316,14 +554,8 @@ impl Cpu {
                     addr - (-offset) as u32
                 };
                 let value = self.load_x_regs(rs2)?;
-                ram.store32_with_mmio(addr, value, mmio_devices);
+                self.store_memory(addr, 4, value, ram, mmio_devices, ram_write_logs)?;
                 self.pc.increment();
-
-                let values = value.to_le_bytes();
-                ram_write_logs.push(step_log::RamWrite::new(addr, values[0]));
-                ram_write_logs.push(step_log::RamWrite::new(addr + 1, values[1]));
-                ram_write_logs.push(step_log::RamWrite::new(addr + 2, values[2]));
-                ram_write_logs.push(step_log::RamWrite::new(addr + 3, values[3]));
             }
             Instruction::Jal { rd, offset } => {
                 let mut pc = self.pc.load();
@@ -442,26 +674,585 @@ impl Cpu {
                 self.pc.increment();
             }
             Instruction::Auipc { rd, imm } => {
-                let pc = self.pc.load() + imm;
+                let pc = self.pc.load().wrapping_add(imm);
                 self.store_x_regs(rd, pc)?;
                 self.pc.increment();
             }
-            Instruction::Fence { pred, succ } => {
-                return Err(anyhow::anyhow!(
-                    "Fence (pred: 0x{:x}, succ: 0x{:x})",
-                    pred,
-                    succ
-                ));
+            Instruction::Mul { rd, rs1, rs2 } => {
+                let x_rs1 = self.load_x_regs(rs1)?;
+                let x_rs2 = self.load_x_regs(rs2)?;
+                self.store_x_regs(rd, x_rs1.wrapping_mul(x_rs2))?;
+                self.pc.increment();
+            }
+            Instruction::Mulh { rd, rs1, rs2 } => {
+                let x_rs1 = self.load_x_regs(rs1)? as i32 as i64;
+                let x_rs2 = self.load_x_regs(rs2)? as i32 as i64;
+                self.store_x_regs(rd, ((x_rs1 * x_rs2) >> 32) as u32)?;
+                self.pc.increment();
+            }
+            Instruction::Mulhsu { rd, rs1, rs2 } => {
+                let x_rs1 = self.load_x_regs(rs1)? as i32 as i64;
+                let x_rs2 = self.load_x_regs(rs2)? as i64;
+                self.store_x_regs(rd, ((x_rs1 * x_rs2) >> 32) as u32)?;
+                self.pc.increment();
+            }
+            Instruction::Mulhu { rd, rs1, rs2 } => {
+                let x_rs1 = self.load_x_regs(rs1)? as u64;
+                let x_rs2 = self.load_x_regs(rs2)? as u64;
+                self.store_x_regs(rd, ((x_rs1 * x_rs2) >> 32) as u32)?;
+                self.pc.increment();
+            }
+            // division by zero gives all ones, the overflow case gives the dividend
+            Instruction::Div { rd, rs1, rs2 } => {
+                let x_rs1 = self.load_x_regs(rs1)? as i32;
+                let x_rs2 = self.load_x_regs(rs2)? as i32;
+                let value = match x_rs2 {
+                    0 => -1,
+                    _ => x_rs1.wrapping_div(x_rs2),
+                };
+                self.store_x_regs(rd, value as u32)?;
+                self.pc.increment();
+            }
+            Instruction::Divu { rd, rs1, rs2 } => {
+                let x_rs1 = self.load_x_regs(rs1)?;
+                let x_rs2 = self.load_x_regs(rs2)?;
+                let value = x_rs1.checked_div(x_rs2).unwrap_or(u32::MAX);
+                self.store_x_regs(rd, value)?;
+                self.pc.increment();
+            }
+            // the remainder of a division by zero is the dividend, the overflow case gives 0
+            Instruction::Rem { rd, rs1, rs2 } => {
+                let x_rs1 = self.load_x_regs(rs1)? as i32;
+                let x_rs2 = self.load_x_regs(rs2)? as i32;
+                let value = match x_rs2 {
+                    0 => x_rs1,
+                    _ => x_rs1.wrapping_rem(x_rs2),
+                };
+                self.store_x_regs(rd, value as u32)?;
+                self.pc.increment();
+            }
+            Instruction::Remu { rd, rs1, rs2 } => {
+                let x_rs1 = self.load_x_regs(rs1)?;
+                let x_rs2 = self.load_x_regs(rs2)?;
+                let value = x_rs1.checked_rem(x_rs2).unwrap_or(x_rs1);
+                self.store_x_regs(rd, value)?;
+                self.pc.increment();
+            }
+            Instruction::LrW { rd, rs1 } => {
+                let addr = self.load_x_regs(rs1)?;
+                if addr & 0b11 != 0 {
+                    return Err(AccessType::Load.access_fault(addr).into());
+                }
+                let paddr =
+                    self.translate(addr, 4, AccessType::Load, ram, mmio_devices, ram_write_logs)?;
+                let tag = ram.reservation_tag(paddr);
+                let value = ram.load32_with_mmio(paddr, mmio_devices);
+                self.reservation = Some((paddr, tag));
+                self.store_x_regs(rd, value)?;
+                self.pc.increment();
+            }
+            // fails (rd = 1) unless the address is still reserved by lr.w
+            Instruction::ScW { rd, rs1, rs2 } => {
+                let addr = self.load_x_regs(rs1)?;
+                if addr & 0b11 != 0 {
+                    return Err(AccessType::Store.access_fault(addr).into());
+                }
+                let paddr = self.translate(
+                    addr,
+                    4,
+                    AccessType::Store,
+                    ram,
+                    mmio_devices,
+                    ram_write_logs,
+                )?;
+                let value = self.load_x_regs(rs2)?;
+                let success = match self.reservation.take() {
+                    // the harts of the parallel mode don't see each other's reservations, the
+                    // tag tells if one of them stored to the reservation set since lr.w
+                    Some((reserved, tag)) if reserved == paddr && ram.is_available_addr(paddr) => {
+                        ram.store_conditional32(paddr, tag, value)
+                    }
+                    Some((reserved, _)) if reserved == paddr => {
+                        ram.store32_with_mmio(paddr, value, mmio_devices);
+                        true
+                    }
+                    _ => false,
+                };
+                if success {
+                    log_ram_writes(paddr, 4, value, ram_write_logs);
+                }
+                self.store_x_regs(rd, !success as u32)?;
+                self.pc.increment();
+            }
+            Instruction::AmoswapW { rd, rs1, rs2 } => {
+                self.amo(rd, rs1, rs2, ram, mmio_devices, ram_write_logs, |_, b| b)?;
+            }
+            Instruction::AmoaddW { rd, rs1, rs2 } => {
+                self.amo(rd, rs1, rs2, ram, mmio_devices, ram_write_logs, |a, b| {
+                    a.wrapping_add(b)
+                })?;
+            }
+            Instruction::AmoxorW { rd, rs1, rs2 } => {
+                self.amo(rd, rs1, rs2, ram, mmio_devices, ram_write_logs, |a, b| {
+                    a ^ b
+                })?;
+            }
+            Instruction::AmoandW { rd, rs1, rs2 } => {
+                self.amo(rd, rs1, rs2, ram, mmio_devices, ram_write_logs, |a, b| {
+                    a & b
+                })?;
+            }
+            Instruction::AmoorW { rd, rs1, rs2 } => {
+                self.amo(rd, rs1, rs2, ram, mmio_devices, ram_write_logs, |a, b| {
+                    a | b
+                })?;
+            }
+            Instruction::AmominW { rd, rs1, rs2 } => {
+                self.amo(rd, rs1, rs2, ram, mmio_devices, ram_write_logs, |a, b| {
+                    (a as i32).min(b as i32) as u32
+                })?;
+            }
+            Instruction::AmomaxW { rd, rs1, rs2 } => {
+                self.amo(rd, rs1, rs2, ram, mmio_devices, ram_write_logs, |a, b| {
+                    (a as i32).max(b as i32) as u32
+                })?;
+            }
+            Instruction::AmominuW { rd, rs1, rs2 } => {
+                self.amo(rd, rs1, rs2, ram, mmio_devices, ram_write_logs, |a, b| {
+                    a.min(b)
+                })?;
+            }
+            Instruction::AmomaxuW { rd, rs1, rs2 } => {
+                self.amo(rd, rs1, rs2, ram, mmio_devices, ram_write_logs, |a, b| {
+                    a.max(b)
+                })?;
+            }
+            // the hart threads of the parallel mode load and store with relaxed atomics
+            Instruction::Fence { pred: _, succ: _ } => {
+                fence(Ordering::SeqCst);
+                self.pc.increment();
+            }
+            Instruction::FenceI | Instruction::Wfi => {
+                self.pc.increment();
+            }
+            // there is no TLB, every access walks the page table
+            Instruction::SfenceVma { rs1: _, rs2: _ } => {
+                self.pc.increment();
             }
             Instruction::Ecall => {
-                return Err(anyhow::anyhow!("Ecall"));
+                if self.host_ecall == Some(self.mode) {
+                    self.ecall_pending = true;
+                    self.pc.increment();
+                } else {
+                    return Err(Exception::EnvironmentCall(self.mode).into());
+                }
             }
+            // stops the run unless a supervisor handles breakpoints (e.g. BUG() of Linux)
             Instruction::Ebreak => {
-                return Err(anyhow::anyhow!("Ebreak"));
+                if self.semihosting && self.is_semihosting_call(ram, ram_write_logs) {
+                    self.semihosting_pending = true;
+                    self.pc.increment();
+                    return Ok(());
+                }
+
+                let delegated = self.csrs.load(csr::MEDELEG) >> csr::CAUSE_BREAKPOINT & 1 != 0;
+                if self.mode == PrivilegeMode::Machine || !delegated {
+                    return Err(anyhow::anyhow!("Ebreak"));
+                }
+                return Err(Exception::Breakpoint(self.pc.load()).into());
+            }
+            Instruction::Sret => {
+                let mstatus = self.csrs.load(csr::MSTATUS);
+                // mstatus.TSR traps sret in S-mode (e.g. for a hypervisor)
+                if self.mode == PrivilegeMode::User
+                    || (self.mode == PrivilegeMode::Supervisor && mstatus & csr::MSTATUS_TSR != 0)
+                {
+                    return Err(Exception::IllegalInstruction(raw_instruction).into());
+                }
+                self.mode = match mstatus & csr::MSTATUS_SPP {
+                    0 => PrivilegeMode::User,
+                    _ => PrivilegeMode::Supervisor,
+                };
+
+                let mut mstatus =
+                    mstatus & !(csr::MSTATUS_SIE | csr::MSTATUS_SPP | csr::MSTATUS_MPRV);
+                if mstatus & csr::MSTATUS_SPIE != 0 {
+                    mstatus |= csr::MSTATUS_SIE;
+                }
+                mstatus |= csr::MSTATUS_SPIE;
+                self.csrs.store(csr::MSTATUS, mstatus);
+                self.pc.store(self.csrs.load(csr::SEPC));
+            }
+            Instruction::Mret => {
+                if self.mode != PrivilegeMode::Machine {
+                    return Err(Exception::IllegalInstruction(raw_instruction).into());
+                }
+                let mstatus = self.csrs.load(csr::MSTATUS);
+                self.mode = PrivilegeMode::from_bits(mstatus >> csr::MSTATUS_MPP_SHIFT);
+
+                let mut mstatus = mstatus & !(csr::MSTATUS_MIE | csr::MSTATUS_MPP);
+                if mstatus & csr::MSTATUS_MPIE != 0 {
+                    mstatus |= csr::MSTATUS_MIE;
+                }
+                mstatus |= csr::MSTATUS_MPIE;
+                if self.mode != PrivilegeMode::Machine {
+                    mstatus &= !csr::MSTATUS_MPRV;
+                }
+                self.csrs.store(csr::MSTATUS, mstatus);
+                self.pc.store(self.csrs.load(csr::MEPC));
+            }
+            Instruction::Csrrw { rd, rs1, csr } => {
+                self.check_csr_access(csr, true, raw_instruction)?;
+                let x_rs1 = self.load_x_regs(rs1)?;
+                let t = self.csrs.load(csr);
+                self.store_csr(csr, x_rs1);
+                self.store_x_regs(rd, t)?;
+                self.pc.increment();
+            }
+            Instruction::Csrrs { rd, rs1, csr } => {
+                self.check_csr_access(csr, rs1 != 0, raw_instruction)?;
+                let x_rs1 = self.load_x_regs(rs1)?;
+                let t = self.csrs.load(csr);
+                if rs1 != 0 {
+                    self.store_csr(csr, t | x_rs1);
+                }
+                self.store_x_regs(rd, t)?;
+                self.pc.increment();
+            }
+            Instruction::Csrrc { rd, rs1, csr } => {
+                self.check_csr_access(csr, rs1 != 0, raw_instruction)?;
+                let x_rs1 = self.load_x_regs(rs1)?;
+                let t = self.csrs.load(csr);
+                if rs1 != 0 {
+                    self.store_csr(csr, t & !x_rs1);
+                }
+                self.store_x_regs(rd, t)?;
+                self.pc.increment();
+            }
+            Instruction::Csrrwi { rd, uimm, csr } => {
+                self.check_csr_access(csr, true, raw_instruction)?;
+                let t = self.csrs.load(csr);
+                self.store_csr(csr, uimm as u32);
+                self.store_x_regs(rd, t)?;
+                self.pc.increment();
+            }
+            Instruction::Csrrsi { rd, uimm, csr } => {
+                self.check_csr_access(csr, uimm != 0, raw_instruction)?;
+                let t = self.csrs.load(csr);
+                if uimm != 0 {
+                    self.store_csr(csr, t | uimm as u32);
+                }
+                self.store_x_regs(rd, t)?;
+                self.pc.increment();
+            }
+            Instruction::Csrrci { rd, uimm, csr } => {
+                self.check_csr_access(csr, uimm != 0, raw_instruction)?;
+                let t = self.csrs.load(csr);
+                if uimm != 0 {
+                    self.store_csr(csr, t & !(uimm as u32));
+                }
+                self.store_x_regs(rd, t)?;
+                self.pc.increment();
             }
         }
 
-        Ok(ram_write_logs)
+        Ok(())
+    }
+
+    // csr[9:8] is the lowest privilege level allowed to access the csr and csr[11:10] == 0b11
+    // makes it read-only, the counters of the lower levels are enabled by mcounteren and scounteren
+    fn check_csr_access(
+        &self,
+        csr: u16,
+        write: bool,
+        raw_instruction: u32,
+    ) -> Result<(), Exception> {
+        let mut allowed =
+            (csr >> 8 & 0b11) as u32 <= self.mode as u32 && !(write && csr >> 10 == 0b11);
+
+        if let csr::CYCLE..=csr::HPMCOUNTER31 | csr::CYCLEH..=csr::HPMCOUNTER31H = csr {
+            let enabled = |counteren| self.csrs.load(counteren) >> (csr & 0x1f) & 1 != 0;
+            allowed &= match self.mode {
+                PrivilegeMode::Machine => true,
+                PrivilegeMode::Supervisor => enabled(csr::MCOUNTEREN),
+                PrivilegeMode::User => enabled(csr::MCOUNTEREN) && enabled(csr::SCOUNTEREN),
+            };
+        }
+
+        match allowed {
+            true => Ok(()),
+            false => Err(Exception::IllegalInstruction(raw_instruction)),
+        }
+    }
+
+    // mcycle and minstret are counted by the hart, the write takes precedence over the counting
+    // of the csr instruction itself (taken back here as it is added after the execution)
+    fn store_csr(&mut self, csr: u16, value: u32) {
+        let set_half = |counter: u64| match csr & 0x80 {
+            0 => counter & !0xffff_ffff | value as u64,
+            _ => counter & 0xffff_ffff | (value as u64) << 32,
+        };
+
+        match csr {
+            csr::MCYCLE | csr::MCYCLEH => {
+                self.cycle = set_half(self.cycle).wrapping_sub(self.timing.csr);
+            }
+            csr::MINSTRET | csr::MINSTRETH => {
+                self.instret = set_half(self.instret).wrapping_sub(1);
+            }
+            _ => self.csrs.store(csr, value),
+        }
+    }
+
+    // virtual to physical address, nothing mapped at the physical address is an access fault
+    fn translate(
+        &mut self,
+        addr: u32,
+        len: u32,
+        access: AccessType,
+        ram: &mut impl Memory,
+        mmio_devices: &[Box<dyn MmioDeviceInterface>],
+        ram_write_logs: &mut Vec<step_log::RamWrite>,
+    ) -> Result<u32, Exception> {
+        let paddr = mmu::translate(&self.csrs, self.mode, addr, access, ram, ram_write_logs)?;
+
+        let last = paddr.wrapping_add(len - 1);
+        let mapped = (ram.is_available_addr(paddr) && ram.is_available_addr(last))
+            || mmio_devices
+                .iter()
+                .any(|mmio_device| mmio_device.is_available_addr(paddr));
+        if !mapped {
+            return Err(access.access_fault(addr));
+        }
+
+        Ok(paddr)
+    }
+
+    // 1, 2 or 4 bytes, accesses crossing a page boundary are split into bytes
+    fn load_memory(
+        &mut self,
+        addr: u32,
+        len: u32,
+        ram: &mut impl Memory,
+        mmio_devices: &mut Vec<Box<dyn MmioDeviceInterface>>,
+        ram_write_logs: &mut Vec<step_log::RamWrite>,
+    ) -> anyhow::Result<u32> {
+        if (addr & 0xfff) + len > mmu::PAGE_SIZE as u32 {
+            let mut value = 0;
+            for i in 0..len {
+                let byte =
+                    self.load_memory(addr.wrapping_add(i), 1, ram, mmio_devices, ram_write_logs)?;
+                value |= byte << (8 * i);
+            }
+            return Ok(value);
+        }
+
+        let paddr = self.translate(
+            addr,
+            len,
+            AccessType::Load,
+            ram,
+            mmio_devices,
+            ram_write_logs,
+        )?;
+        let value = match len {
+            1 => ram.load8_with_mmio(paddr, mmio_devices) as u32,
+            2 => ram.load16_with_mmio(paddr, mmio_devices) as u32,
+            _ => ram.load32_with_mmio(paddr, mmio_devices),
+        };
+
+        Ok(value)
+    }
+
+    fn store_memory(
+        &mut self,
+        addr: u32,
+        len: u32,
+        value: u32,
+        ram: &mut impl Memory,
+        mmio_devices: &mut Vec<Box<dyn MmioDeviceInterface>>,
+        ram_write_logs: &mut Vec<step_log::RamWrite>,
+    ) -> anyhow::Result<()> {
+        if (addr & 0xfff) + len > mmu::PAGE_SIZE as u32 {
+            for i in 0..len {
+                let byte = value >> (8 * i) & 0xff;
+                self.store_memory(
+                    addr.wrapping_add(i),
+                    1,
+                    byte,
+                    ram,
+                    mmio_devices,
+                    ram_write_logs,
+                )?;
+            }
+            return Ok(());
+        }
+
+        let paddr = self.translate(
+            addr,
+            len,
+            AccessType::Store,
+            ram,
+            mmio_devices,
+            ram_write_logs,
+        )?;
+        self.store_physical(paddr, len, value, ram, mmio_devices, ram_write_logs);
+
+        Ok(())
+    }
+
+    // ram writes are logged with physical addresses
+    fn store_physical(
+        &mut self,
+        paddr: u32,
+        len: u32,
+        value: u32,
+        ram: &mut impl Memory,
+        mmio_devices: &mut Vec<Box<dyn MmioDeviceInterface>>,
+        ram_write_logs: &mut Vec<step_log::RamWrite>,
+    ) {
+        match len {
+            1 => ram.store8_with_mmio(paddr, value as u8, mmio_devices),
+            2 => ram.store16_with_mmio(paddr, value as u16, mmio_devices),
+            _ => ram.store32_with_mmio(paddr, value, mmio_devices),
+        }
+
+        log_ram_writes(paddr, len, value, ram_write_logs);
+    }
+
+    // read-modify-write of an aligned word, rd gets the old value
+    #[allow(clippy::too_many_arguments)]
+    fn amo(
+        &mut self,
+        rd: usize,
+        rs1: usize,
+        rs2: usize,
+        ram: &mut impl Memory,
+        mmio_devices: &mut Vec<Box<dyn MmioDeviceInterface>>,
+        ram_write_logs: &mut Vec<step_log::RamWrite>,
+        op: impl Fn(u32, u32) -> u32,
+    ) -> anyhow::Result<()> {
+        let addr = self.load_x_regs(rs1)?;
+        if addr & 0b11 != 0 {
+            return Err(AccessType::Store.access_fault(addr).into());
+        }
+
+        let paddr = self.translate(
+            addr,
+            4,
+            AccessType::Store,
+            ram,
+            mmio_devices,
+            ram_write_logs,
+        )?;
+        let x_rs2 = self.load_x_regs(rs2)?;
+        // a single access to ram, other harts may run at the same time
+        let t = if ram.is_available_addr(paddr) {
+            let t = ram.fetch_update32(paddr, |t| op(t, x_rs2));
+            log_ram_writes(paddr, 4, op(t, x_rs2), ram_write_logs);
+            t
+        } else {
+            let t = ram.load32_with_mmio(paddr, mmio_devices);
+            self.store_physical(paddr, 4, op(t, x_rs2), ram, mmio_devices, ram_write_logs);
+            t
+        };
+        self.store_x_regs(rd, t)?;
+        self.pc.increment();
+
+        Ok(())
+    }
+
+    // take the highest priority pending and enabled interrupt, machine level interrupts
+    // are taken before the ones delegated to supervisor mode
+    fn handle_interrupt(&mut self) {
+        let pending = self.csrs.load(csr::MIP) & self.csrs.load(csr::MIE);
+        if pending == 0 {
+            return;
+        }
+
+        let mstatus = self.csrs.load(csr::MSTATUS);
+        let mideleg = self.csrs.load(csr::MIDELEG);
+        let m_enabled = self.mode != PrivilegeMode::Machine || mstatus & csr::MSTATUS_MIE != 0;
+        let s_enabled = self.mode == PrivilegeMode::User
+            || (self.mode == PrivilegeMode::Supervisor && mstatus & csr::MSTATUS_SIE != 0);
+
+        let mut enabled = 0;
+        if m_enabled {
+            enabled |= pending & !mideleg;
+        }
+        if s_enabled && enabled == 0 {
+            enabled |= pending & mideleg;
+        }
+
+        for irq in [
+            csr::IRQ_M_EXT,
+            csr::IRQ_M_SOFT,
+            csr::IRQ_M_TIMER,
+            csr::IRQ_S_EXT,
+            csr::IRQ_S_SOFT,
+            csr::IRQ_S_TIMER,
+        ] {
+            if enabled & (1 << irq) != 0 {
+                self.trap(csr::CAUSE_INTERRUPT | irq, 0);
+                return;
+            }
+        }
+    }
+
+    // enter the machine mode trap handler, or the supervisor mode one if the trap is delegated
+    // and not taken in machine mode (interrupts use the vectored mode if xtvec.MODE == 1)
+    fn trap(&mut self, cause: u32, tval: u32) {
+        self.reservation = None;
+
+        let interrupt = cause & csr::CAUSE_INTERRUPT != 0;
+        let code = cause & !csr::CAUSE_INTERRUPT;
+        let deleg = self.csrs.load(if interrupt {
+            csr::MIDELEG
+        } else {
+            csr::MEDELEG
+        });
+
+        let tvec = if self.mode != PrivilegeMode::Machine && deleg >> code & 1 != 0 {
+            self.csrs.store(csr::SEPC, self.pc.load());
+            self.csrs.store(csr::SCAUSE, cause);
+            self.csrs.store(csr::STVAL, tval);
+
+            let mut mstatus = self.csrs.load(csr::MSTATUS);
+            mstatus &= !(csr::MSTATUS_SPIE | csr::MSTATUS_SPP);
+            if mstatus & csr::MSTATUS_SIE != 0 {
+                mstatus |= csr::MSTATUS_SPIE;
+            }
+            mstatus &= !csr::MSTATUS_SIE;
+            if self.mode == PrivilegeMode::Supervisor {
+                mstatus |= csr::MSTATUS_SPP;
+            }
+            self.csrs.store(csr::MSTATUS, mstatus);
+
+            self.mode = PrivilegeMode::Supervisor;
+            self.csrs.load(csr::STVEC)
+        } else {
+            self.csrs.store(csr::MEPC, self.pc.load());
+            self.csrs.store(csr::MCAUSE, cause);
+            self.csrs.store(csr::MTVAL, tval);
+
+            let mut mstatus = self.csrs.load(csr::MSTATUS);
+            mstatus &= !(csr::MSTATUS_MPIE | csr::MSTATUS_MPP);
+            if mstatus & csr::MSTATUS_MIE != 0 {
+                mstatus |= csr::MSTATUS_MPIE;
+            }
+            mstatus &= !csr::MSTATUS_MIE;
+            mstatus |= (self.mode as u32) << csr::MSTATUS_MPP_SHIFT;
+            self.csrs.store(csr::MSTATUS, mstatus);
+
+            self.mode = PrivilegeMode::Machine;
+            self.csrs.load(csr::MTVEC)
+        };
+
+        let mut pc = tvec & !0b11;
+        if tvec & 0b11 == 1 && interrupt {
+            pc += 4 * code;
+        }
+        self.pc.store(pc);
     }
 
     fn load_x_regs(&mut self, index: usize) -> anyhow::Result<u32> {
@@ -484,3 +1275,9 @@ impl Cpu {
         Ok(())
     }
 }
+
+fn log_ram_writes(paddr: u32, len: u32, value: u32, ram_write_logs: &mut Vec<step_log::RamWrite>) {
+    for (i, byte) in value.to_le_bytes()[..len as usize].iter().enumerate() {
+        ram_write_logs.push(step_log::RamWrite::new(paddr + i as u32, *byte));
+    }
+}
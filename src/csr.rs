@@ -0,0 +1,248 @@
+use std::fmt::Debug;
+
+// supervisor trap setup
+pub const SSTATUS: u16 = 0x100;
+pub const SIE: u16 = 0x104;
+pub const STVEC: u16 = 0x105;
+pub const SCOUNTEREN: u16 = 0x106;
+
+// supervisor trap handling
+pub const SSCRATCH: u16 = 0x140;
+pub const SEPC: u16 = 0x141;
+pub const SCAUSE: u16 = 0x142;
+pub const STVAL: u16 = 0x143;
+pub const SIP: u16 = 0x144;
+pub const STIMECMP: u16 = 0x14d;
+pub const STIMECMPH: u16 = 0x15d;
+
+// supervisor protection and translation
+pub const SATP: u16 = 0x180;
+
+// machine information registers
+pub const MVENDORID: u16 = 0xf11;
+pub const MARCHID: u16 = 0xf12;
+pub const MIMPID: u16 = 0xf13;
+pub const MHARTID: u16 = 0xf14;
+
+// machine trap setup
+pub const MSTATUS: u16 = 0x300;
+pub const MISA: u16 = 0x301;
+pub const MEDELEG: u16 = 0x302;
+pub const MIDELEG: u16 = 0x303;
+pub const MIE: u16 = 0x304;
+pub const MTVEC: u16 = 0x305;
+pub const MCOUNTEREN: u16 = 0x306;
+pub const MENVCFG: u16 = 0x30a;
+pub const MENVCFGH: u16 = 0x31a;
+
+// machine trap handling
+pub const MSCRATCH: u16 = 0x340;
+pub const MEPC: u16 = 0x341;
+pub const MCAUSE: u16 = 0x342;
+pub const MTVAL: u16 = 0x343;
+pub const MIP: u16 = 0x344;
+
+// machine counters
+pub const MCYCLE: u16 = 0xb00;
+pub const MINSTRET: u16 = 0xb02;
+pub const MCYCLEH: u16 = 0xb80;
+pub const MINSTRETH: u16 = 0xb82;
+
+// unprivileged counters
+pub const CYCLE: u16 = 0xc00;
+pub const TIME: u16 = 0xc01;
+pub const INSTRET: u16 = 0xc02;
+pub const CYCLEH: u16 = 0xc80;
+pub const TIMEH: u16 = 0xc81;
+pub const INSTRETH: u16 = 0xc82;
+pub const HPMCOUNTER31: u16 = 0xc1f;
+pub const HPMCOUNTER31H: u16 = 0xc9f;
+
+pub const MSTATUS_SIE: u32 = 1 << 1;
+pub const MSTATUS_MIE: u32 = 1 << 3;
+pub const MSTATUS_SPIE: u32 = 1 << 5;
+pub const MSTATUS_MPIE: u32 = 1 << 7;
+pub const MSTATUS_SPP: u32 = 1 << 8;
+pub const MSTATUS_MPP_SHIFT: u32 = 11;
+pub const MSTATUS_MPP: u32 = 0b11 << MSTATUS_MPP_SHIFT;
+pub const MSTATUS_MPRV: u32 = 1 << 17;
+pub const MSTATUS_SUM: u32 = 1 << 18;
+pub const MSTATUS_MXR: u32 = 1 << 19;
+pub const MSTATUS_TSR: u32 = 1 << 22;
+// the mstatus bits visible through sstatus
+pub const SSTATUS_MASK: u32 = MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP | MSTATUS_SUM | MSTATUS_MXR;
+
+// stimecmp is enabled (Sstc)
+pub const MENVCFGH_STCE: u32 = 1 << 31;
+
+pub const SATP_MODE_SV32: u32 = 1 << 31;
+pub const SATP_PPN_MASK: u32 = 0x003f_ffff;
+
+pub const MIP_SSIP: u32 = 1 << IRQ_S_SOFT;
+pub const MIP_STIP: u32 = 1 << IRQ_S_TIMER;
+
+// bits driven by interrupt controllers (read-only from software)
+pub const MIP_MSIP: u32 = 1 << IRQ_M_SOFT;
+pub const MIP_MTIP: u32 = 1 << IRQ_M_TIMER;
+pub const MIP_SEIP: u32 = 1 << IRQ_S_EXT;
+pub const MIP_MEIP: u32 = 1 << IRQ_M_EXT;
+pub const MIP_HW_MASK: u32 = MIP_MSIP | MIP_MTIP | MIP_SEIP | MIP_MEIP;
+
+pub const CAUSE_INTERRUPT: u32 = 1 << 31;
+pub const IRQ_S_SOFT: u32 = 1;
+pub const IRQ_M_SOFT: u32 = 3;
+pub const IRQ_S_TIMER: u32 = 5;
+pub const IRQ_M_TIMER: u32 = 7;
+pub const IRQ_S_EXT: u32 = 9;
+pub const IRQ_M_EXT: u32 = 11;
+
+pub const CAUSE_INSTRUCTION_ACCESS_FAULT: u32 = 1;
+pub const CAUSE_ILLEGAL_INSTRUCTION: u32 = 2;
+pub const CAUSE_BREAKPOINT: u32 = 3;
+pub const CAUSE_LOAD_ACCESS_FAULT: u32 = 5;
+pub const CAUSE_STORE_ACCESS_FAULT: u32 = 7;
+pub const CAUSE_ECALL_FROM_U: u32 = 8;
+pub const CAUSE_ECALL_FROM_S: u32 = 9;
+pub const CAUSE_ECALL_FROM_M: u32 = 11;
+pub const CAUSE_INSTRUCTION_PAGE_FAULT: u32 = 12;
+pub const CAUSE_LOAD_PAGE_FAULT: u32 = 13;
+pub const CAUSE_STORE_PAGE_FAULT: u32 = 15;
+
+// RV32 + I + M + A + S + U
+pub const MISA_VALUE: u32 = 1 << 30
+    | 1 << (b'I' - b'A')
+    | 1 << (b'M' - b'A')
+    | 1 << 0 // A
+    | 1 << (b'S' - b'A')
+    | 1 << (b'U' - b'A');
+
+pub const CSRS_LEN: usize = 4096;
+
+// "riscv,isa" of the device tree (e.g. "rv32i")
+pub fn isa_string(misa: u32) -> String {
+    let xlen = match misa >> 30 {
+        1 => 32,
+        2 => 64,
+        _ => 128,
+    };
+    let extensions: String = "imafdqc"
+        .chars()
+        .filter(|c| misa >> (*c as u8 - b'a') & 1 != 0)
+        .collect();
+
+    format!("rv{}{}", xlen, extensions)
+}
+
+#[derive(Clone)]
+pub struct Csrs(Box<[u32; CSRS_LEN]>);
+
+impl Debug for Csrs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // non-zero registers only
+        f.debug_map()
+            .entries(
+                self.0
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, value)| **value != 0)
+                    .map(|(addr, value)| (format!("0x{:03x}", addr), format!("0x{:08x}", value))),
+            )
+            .finish()
+    }
+}
+
+impl Default for Csrs {
+    fn default() -> Self {
+        let mut csrs = Self(Box::new([0; CSRS_LEN]));
+        csrs.reset();
+        csrs
+    }
+}
+
+impl Csrs {
+    pub fn reset(&mut self) {
+        let hart_id = self.load(MHARTID);
+        self.0.fill(0);
+        self.0[MISA as usize] = MISA_VALUE;
+        self.0[MHARTID as usize] = hart_id;
+    }
+
+    // mhartid is read-only for the harts and kept across resets
+    pub fn set_hart_id(&mut self, hart_id: u32) {
+        self.0[MHARTID as usize] = hart_id;
+    }
+
+    pub fn load(&self, addr: u16) -> u32 {
+        match addr {
+            SSTATUS => self.load(MSTATUS) & SSTATUS_MASK,
+            SIE => self.load(MIE) & self.load(MIDELEG),
+            SIP => self.load(MIP) & self.load(MIDELEG),
+            _ => self.0[addr as usize & (CSRS_LEN - 1)],
+        }
+    }
+
+    // update the mip bits driven by interrupt controllers
+    pub fn set_interrupt_pending(&mut self, bits: u32) {
+        let mip = self.load(MIP);
+        self.0[MIP as usize] = (mip & !MIP_HW_MASK) | (bits & MIP_HW_MASK);
+    }
+
+    // mirror the machine timer into time/timeh, stimecmp drives STIP when enabled
+    pub fn set_time(&mut self, time: u64) {
+        self.0[TIME as usize] = time as u32;
+        self.0[TIMEH as usize] = (time >> 32) as u32;
+
+        if self.load(MENVCFGH) & MENVCFGH_STCE != 0 {
+            let stimecmp = (self.load(STIMECMPH) as u64) << 32 | self.load(STIMECMP) as u64;
+            let mip = self.load(MIP) & !MIP_STIP;
+            self.0[MIP as usize] = if time >= stimecmp {
+                mip | MIP_STIP
+            } else {
+                mip
+            };
+        }
+    }
+
+    // mirror the cycles of the timing model and the retired instructions of the hart
+    // into the counters (cycle and instret are the read-only shadows)
+    pub fn set_counters(&mut self, cycle: u64, instret: u64) {
+        for (addr, addrh, value) in [
+            (MCYCLE, MCYCLEH, cycle),
+            (CYCLE, CYCLEH, cycle),
+            (MINSTRET, MINSTRETH, instret),
+            (INSTRET, INSTRETH, instret),
+        ] {
+            self.0[addr as usize] = value as u32;
+            self.0[addrh as usize] = (value >> 32) as u32;
+        }
+    }
+
+    pub fn store(&mut self, addr: u16, value: u32) {
+        match addr {
+            // read-only (the counters are driven by the hart like time, mcycle and minstret
+            // are written through the hart)
+            MVENDORID | MARCHID | MIMPID | MHARTID | MISA | TIME | TIMEH => (),
+            MCYCLE | MCYCLEH | MINSTRET | MINSTRETH | CYCLE | CYCLEH | INSTRET | INSTRETH => (),
+            SSTATUS => {
+                let mstatus = self.load(MSTATUS) & !SSTATUS_MASK;
+                self.store(MSTATUS, mstatus | (value & SSTATUS_MASK));
+            }
+            SIE => {
+                let mask = self.load(MIDELEG);
+                let mie = self.load(MIE) & !mask;
+                self.store(MIE, mie | (value & mask));
+            }
+            // only SSIP is writable from S-mode
+            SIP => {
+                let mask = self.load(MIDELEG) & MIP_SSIP;
+                let mip = self.load(MIP) & !mask;
+                self.store(MIP, mip | (value & mask));
+            }
+            MIP => {
+                let mip = self.load(MIP);
+                self.0[MIP as usize] = (mip & MIP_HW_MASK) | (value & !MIP_HW_MASK);
+            }
+            _ => self.0[addr as usize & (CSRS_LEN - 1)] = value,
+        }
+    }
+}
@@ -1,33 +1,91 @@
 use crate::{
-    cpu::Cpu,
-    mmio_device::{MmioDeviceInterface, RequestFromDevice},
+    cpu::{Cpu, PrivilegeMode},
+    csr,
+    fdt::{self, FdtNode, FdtValue, PLIC_LABEL},
+    mmio_device::{clint::Mtime, MmioDeviceInterface, RequestFromDevice, SystemBus},
+    parallel::{self, HartMessage, MmioProxy, Quantum, Reservations, SharedRam},
     ram::Ram,
+    sbi::Sbi,
+    semihosting::Semihosting,
     step_log,
+    syscall::Syscalls,
 };
-use std::fmt::Debug;
+use std::{fmt::Debug, sync::mpsc};
+
+// mtime counts one tick per step, same value as QEMU's virt machine
+const TIMEBASE_FREQUENCY: u32 = 10_000_000;
+// the device tree blob is placed at the end of ram with this alignment
+const FDT_ALIGN: u32 = 8;
+// every exception except the ecalls from S-mode and M-mode goes to the supervisor
+const SUPERVISOR_MEDELEG: u32 = 0xb1ff;
 
-#[derive(Default)]
 pub struct Emulator {
-    pub cpu: Cpu,
+    // hart i has mhartid i, they share the ram and the devices
+    pub harts: Vec<Cpu>,
+    // steps a hart runs before the next one takes its turn (1 = round-robin per instruction)
+    pub quantum: usize,
     pub ram: Ram,
     pub mmio_devices: Vec<Box<dyn MmioDeviceInterface>>,
+    pub max_steps: Option<usize>,
+    // /chosen/bootargs of the device tree
+    pub bootargs: Option<String>,
+    // start and end address of the initrd (/chosen)
+    pub initrd: Option<(u32, u32)>,
+    // the machine timer of the CLINT, mirrored into the time csr
+    pub mtime: Option<Mtime>,
+    // handles the ecalls of a kernel started by boot_supervisor
+    pub sbi: Option<Sbi>,
+    // handles the ecalls of a program started by boot_user
+    pub syscalls: Option<Syscalls>,
+    // handles the semihosting calls after enable_semihosting
+    pub semihosting: Option<Semihosting>,
+    // don't keep the cpu steps in the log (e.g. for long runs like a Linux boot)
+    pub discard_steps: bool,
+    // run each hart on its own host thread instead of taking turns (see run_parallel)
+    pub parallel: bool,
 }
 
 impl Debug for Emulator {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Emulator")
-            .field("cpu", &self.cpu)
+            .field("harts", &self.harts)
             .field("ram", &self.ram)
             .finish()
     }
 }
 
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
 impl Emulator {
     pub fn new(ram_data: Vec<u8>) -> Self {
+        Self::new_with_ram_base_addr(0, ram_data)
+    }
+
+    pub fn new_with_ram_base_addr(ram_base_addr: u32, ram_data: Vec<u8>) -> Self {
+        Self::new_with_harts(ram_base_addr, ram_data, 1)
+    }
+
+    pub fn new_with_harts(ram_base_addr: u32, ram_data: Vec<u8>, num_harts: usize) -> Self {
         Self {
-            cpu: Cpu::default(),
-            ram: Ram::new_with_data(ram_data),
+            harts: (0..num_harts)
+                .map(|hart| Cpu::new_with_hart_id(hart as u32))
+                .collect(),
+            quantum: 1,
+            ram: Ram::new_with_base_addr(ram_base_addr, ram_data),
             mmio_devices: Vec::new(),
+            max_steps: None,
+            bootargs: None,
+            initrd: None,
+            mtime: None,
+            sbi: None,
+            syscalls: None,
+            semihosting: None,
+            discard_steps: false,
+            parallel: false,
         }
     }
 
@@ -35,50 +93,611 @@ impl Emulator {
         self.mmio_devices.push(device);
     }
 
-    pub fn run(&mut self, print_instruction_log: bool) -> anyhow::Result<(u8, step_log::Log)> {
+    pub fn run(&mut self, print_instruction_log: bool) -> anyhow::Result<(u32, step_log::Log)> {
         let mut log = step_log::Log {
-            init_cpu_state: step_log::CpuStateLog::new(&self.cpu),
-            init_ram: self.ram.0.clone(),
+            init_cpu_state: step_log::CpuStateLog::new(&self.harts[0]),
+            init_ram_base_addr: self.ram.base_addr,
+            init_ram: self.ram.data.clone(),
             steps: Vec::new(),
             dev_reqs: Vec::new(),
+            dev_writes: Vec::new(),
+        };
+
+        let boot_harts = self.harts.clone();
+        let exit_code = if self.parallel {
+            self.run_parallel(&mut log, &boot_harts, print_instruction_log)?
+        } else {
+            self.run_scheduled(&mut log, &boot_harts, print_instruction_log)?
         };
 
-        let mut exit_code = 0;
+        Ok((exit_code, log))
+    }
+
+    // the harts take turns on this thread, the same run always gives the same log
+    fn run_scheduled(
+        &mut self,
+        log: &mut step_log::Log,
+        boot_harts: &[Cpu],
+        print_instruction_log: bool,
+    ) -> anyhow::Result<u32> {
+        // the hart that runs the next step and the steps it has run in its quantum
+        let mut hart = 0;
+        let mut quantum_steps = 0;
+
+        loop {
+            match self.tick_devices(log, boot_harts) {
+                Some(RequestFromDevice::Exit(exit_code)) => return Ok(exit_code),
+                Some(_) => {
+                    hart = 0;
+                    quantum_steps = 0;
+                }
+                None => (),
+            }
+
+            self.update_interrupts();
+
+            if quantum_steps >= self.quantum || self.harts[hart].stopped {
+                hart = self
+                    .next_hart(hart)
+                    .ok_or_else(|| anyhow::anyhow!("All harts are stopped"))?;
+                quantum_steps = 0;
+            }
+            quantum_steps += 1;
+
+            let step_log = self.harts[hart].fetch_decode_execute(
+                &mut self.ram,
+                &mut self.mmio_devices,
+                print_instruction_log,
+            )?;
+            self.invalidate_reservations(hart, &step_log.ram_writes);
+            if !self.discard_steps {
+                log.steps.push(step_log);
+            }
+
+            match self.host_call(hart, log, boot_harts)? {
+                Some(RequestFromDevice::Exit(exit_code)) => return Ok(exit_code),
+                Some(_) => {
+                    hart = 0;
+                    quantum_steps = 0;
+                }
+                None => (),
+            }
+
+            self.check_user_mode(hart)?;
+            self.check_max_steps()?;
+
+            if self.left_ram(hart) {
+                return Ok(0);
+            }
+        }
+    }
 
-        'a: loop {
-            for mmio_device in &mut self.mmio_devices {
-                let req = match mmio_device.poll_request() {
-                    Some(request) => request,
-                    None => continue,
-                };
+    // every hart runs its quantum on its own thread at the same time as the others, the
+    // devices and the host calls are handled by this thread between the quanta, the order
+    // of the memory accesses of different harts depends on the host (not deterministic)
+    fn run_parallel(
+        &mut self,
+        log: &mut step_log::Log,
+        boot_harts: &[Cpu],
+        print_instruction_log: bool,
+    ) -> anyhow::Result<u32> {
+        let quantum = self.quantum.max(1);
+        let keep_step_logs = !self.discard_steps;
 
+        std::thread::scope(|scope| {
+            let (requests, messages) = mpsc::channel();
+            let mut quanta = Vec::new();
+            let mut replies = Vec::new();
+            for hart in 0..self.harts.len() {
+                let (quantum_sender, quantum_receiver) = mpsc::channel();
+                let (reply_sender, reply_receiver) = mpsc::channel();
+                let proxy = MmioProxy::new(
+                    hart,
+                    &self.ram,
+                    &self.mmio_devices,
+                    requests.clone(),
+                    reply_receiver,
+                );
+                let results = requests.clone();
+                scope.spawn(move || {
+                    parallel::run_hart(
+                        hart,
+                        proxy,
+                        quantum_receiver,
+                        results,
+                        quantum,
+                        keep_step_logs,
+                        print_instruction_log,
+                    )
+                });
+                quanta.push(quantum_sender);
+                replies.push(reply_sender);
+            }
+            // the receiver fails if every hart thread is gone
+            drop(requests);
+            // kept across the quanta, a reservation may outlive the quantum of its lr.w
+            let reservations = Reservations::new(&self.ram);
+
+            loop {
+                let running: Vec<usize> = (0..self.harts.len())
+                    .filter(|&hart| !self.harts[hart].stopped)
+                    .collect();
+                if running.is_empty() {
+                    return Err(anyhow::anyhow!("All harts are stopped"));
+                }
+
+                let ram = SharedRam::new(&mut self.ram, &reservations)
+                    .ok_or_else(|| anyhow::anyhow!("RAM must be word aligned in parallel mode"))?;
+                for &hart in &running {
+                    let cpu = std::mem::take(&mut self.harts[hart]);
+                    quanta[hart]
+                        .send(Quantum { cpu, ram })
+                        .map_err(|_| anyhow::anyhow!("The thread of hart {} has stopped", hart))?;
+                }
+
+                let mut results = Vec::new();
+                while results.len() < running.len() {
+                    match messages.recv()? {
+                        HartMessage::Load { hart, addr, len } => {
+                            let value = self.mmio_load(addr, len);
+                            let _ = replies[hart].send(value);
+                        }
+                        HartMessage::Store { addr, len, value } => {
+                            self.mmio_store(addr, len, value)
+                        }
+                        HartMessage::Done(result) => results.push(result),
+                    }
+                }
+
+                // all harts are back, the ram belongs to this thread again
+                results.sort_by_key(|result| result.hart);
+                let mut elapsed = 0;
+                for result in results {
+                    elapsed = elapsed.max(result.steps);
+                    self.harts[result.hart] = result.cpu;
+                    log.steps.extend(result.step_logs);
+                    if let Some(e) = result.error {
+                        return Err(e);
+                    }
+                }
+
+                let mut left_ram = false;
+                for &hart in &running {
+                    match self.host_call(hart, log, boot_harts)? {
+                        Some(RequestFromDevice::Exit(exit_code)) => return Ok(exit_code),
+                        Some(_) => break,
+                        None => (),
+                    }
+
+                    self.check_user_mode(hart)?;
+                    left_ram |= self.left_ram(hart);
+                }
+                if left_ram {
+                    return Ok(0);
+                }
+
+                // the devices catch up with the harts
+                for _ in 0..elapsed {
+                    if let Some(RequestFromDevice::Exit(exit_code)) =
+                        self.tick_devices(log, boot_harts)
+                    {
+                        return Ok(exit_code);
+                    }
+                }
+
+                self.update_interrupts();
+                self.check_max_steps()?;
+            }
+        })
+    }
+
+    // one tick of the devices before a step, returns the exit or reset request (the machine
+    // is already rebooted then)
+    fn tick_devices(
+        &mut self,
+        log: &mut step_log::Log,
+        boot_harts: &[Cpu],
+    ) -> Option<RequestFromDevice> {
+        let mut reset_requested = false;
+
+        let step = self.steps();
+        for mmio_device in &mut self.mmio_devices {
+            mmio_device.tick(&mut self.ram);
+
+            while let Some(req) = mmio_device.poll_request() {
                 log.dev_reqs.push(step_log::DeviceRequest {
-                    step: self.cpu.step,
+                    step,
                     req: req.clone(),
                 });
 
                 match req {
-                    RequestFromDevice::Exit(exit_code_) => {
-                        exit_code = exit_code_;
-                        break 'a;
-                    }
+                    RequestFromDevice::Exit(_) => return Some(req),
+                    RequestFromDevice::Reset => reset_requested = true,
+                    RequestFromDevice::GpioOutput { .. }
+                    | RequestFromDevice::I2c(_)
+                    | RequestFromDevice::WatchdogExpired => (),
                 }
             }
+        }
 
-            let step_log = self
-                .cpu
-                .fetch_decode_execute(&mut self.ram, &mut self.mmio_devices, print_instruction_log)?;
-            log.steps.push(step_log);
+        self.bus_access(log);
 
-            if self.cpu.pc.load() as usize >= self.ram.size() {
-                break;
+        if reset_requested {
+            self.reboot(boot_harts, &log.init_ram);
+            return Some(RequestFromDevice::Reset);
+        }
+
+        None
+    }
+
+    // the ecall or semihosting call the hart has just made, handled like a device request
+    fn host_call(
+        &mut self,
+        hart: usize,
+        log: &mut step_log::Log,
+        boot_harts: &[Cpu],
+    ) -> anyhow::Result<Option<RequestFromDevice>> {
+        let cpu = &mut self.harts[hart];
+        let host_req = if cpu.ecall_pending {
+            cpu.ecall_pending = false;
+            if let Some(sbi) = &mut self.sbi {
+                sbi.handle_call(&mut self.harts, hart, &mut self.ram)
+            } else if let Some(syscalls) = &mut self.syscalls {
+                syscalls.handle_call(cpu, &mut self.ram)
+            } else {
+                return Err(anyhow::anyhow!("No host handler for the ecall"));
+            }
+        } else if cpu.semihosting_pending {
+            cpu.semihosting_pending = false;
+            match &mut self.semihosting {
+                Some(semihosting) => semihosting.handle_call(cpu, &mut self.ram),
+                None => return Err(anyhow::anyhow!("No host handler for semihosting")),
+            }
+        } else {
+            None
+        };
+
+        let Some(req) = host_req else {
+            return Ok(None);
+        };
+        log.dev_reqs.push(step_log::DeviceRequest {
+            step: self.steps(),
+            req: req.clone(),
+        });
+
+        match req {
+            RequestFromDevice::Exit(_) => Ok(Some(req)),
+            RequestFromDevice::Reset => {
+                self.reboot(boot_harts, &log.init_ram);
+                Ok(Some(req))
             }
+            _ => Ok(None),
         }
+    }
 
-        Ok((exit_code, log))
+    // there is no trap handler behind a user mode program (like a fatal signal)
+    fn check_user_mode(&self, hart: usize) -> anyhow::Result<()> {
+        let cpu = &self.harts[hart];
+        if self.syscalls.is_some() && cpu.mode != PrivilegeMode::User {
+            return Err(anyhow::anyhow!(
+                "Unhandled trap in user mode (mcause 0x{:x}, mepc 0x{:08x}, mtval 0x{:08x})",
+                cpu.csrs.load(csr::MCAUSE),
+                cpu.csrs.load(csr::MEPC),
+                cpu.csrs.load(csr::MTVAL)
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn check_max_steps(&self) -> anyhow::Result<()> {
+        if let Some(max_steps) = self.max_steps {
+            if self.steps() >= max_steps {
+                return Err(anyhow::anyhow!("Step limit exceeded ({} steps)", max_steps));
+            }
+        }
+
+        Ok(())
+    }
+
+    // virtual addresses are usually outside of ram
+    fn left_ram(&self, hart: usize) -> bool {
+        let cpu = &self.harts[hart];
+        !cpu.is_translating() && !self.ram.is_available_addr(cpu.pc.load())
+    }
+
+    // device accesses of the hart threads, the ram isn't touched
+    fn mmio_load(&mut self, addr: u32, len: u32) -> u32 {
+        let Some(mmio_device) = self
+            .mmio_devices
+            .iter_mut()
+            .find(|mmio_device| mmio_device.is_available_addr(addr))
+        else {
+            return 0;
+        };
+
+        let bytes_offset = (addr - mmio_device.base_addr()) as usize;
+        match len {
+            1 => mmio_device.load8(bytes_offset) as u32,
+            2 => mmio_device.load16(bytes_offset) as u32,
+            _ => mmio_device.load32(bytes_offset),
+        }
+    }
+
+    fn mmio_store(&mut self, addr: u32, len: u32, value: u32) {
+        let Some(mmio_device) = self
+            .mmio_devices
+            .iter_mut()
+            .find(|mmio_device| mmio_device.is_available_addr(addr))
+        else {
+            return;
+        };
+
+        let bytes_offset = (addr - mmio_device.base_addr()) as usize;
+        match len {
+            1 => mmio_device.store8(bytes_offset, value as u8),
+            2 => mmio_device.store16(bytes_offset, value as u16),
+            _ => mmio_device.store32(bytes_offset, value),
+        }
+    }
+
+    // steps of all harts
+    pub fn steps(&self) -> usize {
+        self.harts.iter().map(|cpu| cpu.step).sum()
     }
 
     pub fn reset(&mut self) {
-        self.cpu.reset();
+        for cpu in &mut self.harts {
+            cpu.reset();
+        }
+    }
+
+    // start a kernel in S-mode without firmware, the SBI calls are handled by the host,
+    // traps and interrupts are delegated like OpenSBI does and stimecmp drives the timer,
+    // the other harts wait for the HSM hart_start call
+    pub fn boot_supervisor(&mut self, entry: u32, sbi: Sbi) {
+        self.sbi = Some(sbi);
+
+        for cpu in &mut self.harts {
+            cpu.mode = PrivilegeMode::Supervisor;
+            cpu.host_ecall = Some(PrivilegeMode::Supervisor);
+            cpu.stopped = cpu.hart_id() != 0;
+
+            let csrs = &mut cpu.csrs;
+            csrs.store(csr::MEDELEG, SUPERVISOR_MEDELEG);
+            csrs.store(csr::MIDELEG, csr::MIP_SSIP | csr::MIP_STIP | csr::MIP_SEIP);
+            csrs.store(csr::MCOUNTEREN, u32::MAX);
+            csrs.store(csr::MENVCFGH, csr::MENVCFGH_STCE);
+            csrs.store(csr::STIMECMP, u32::MAX);
+            csrs.store(csr::STIMECMPH, u32::MAX);
+        }
+        self.harts[0].pc.store(entry);
+    }
+
+    // run a static Linux executable in U-mode without a kernel, the ecalls are handled as
+    // syscalls by the host and the initial stack holds argv, envp and the auxiliary vector
+    pub fn boot_user(
+        &mut self,
+        entry: u32,
+        stack_top: u32,
+        mut syscalls: Syscalls,
+        argv: &[String],
+        envp: &[String],
+        auxv: &[(u32, u32)],
+    ) -> anyhow::Result<()> {
+        // a device there would hide the memory the program gets from the syscalls
+        let memory_start = syscalls.memory_start() as u64;
+        let memory_end = self.ram.base_addr as u64 + self.ram.size() as u64;
+        for device in &self.mmio_devices {
+            let start = device.base_addr() as u64;
+            let end = start + device.used_mem_bytes_len() as u64;
+            if start < memory_end && end > memory_start {
+                return Err(anyhow::anyhow!(
+                    "{} at 0x{:x} overlaps the memory of the user program",
+                    device.device_name(),
+                    start
+                ));
+            }
+        }
+
+        let sp = syscalls
+            .init_stack(&mut self.ram, stack_top, argv, envp, auxv)
+            .ok_or_else(|| anyhow::anyhow!("RAM is too small for the initial stack"))?;
+
+        self.syscalls = Some(syscalls);
+        // the program is single-threaded, it runs on hart 0
+        for cpu in &mut self.harts[1..] {
+            cpu.stopped = true;
+        }
+        let cpu = &mut self.harts[0];
+        cpu.mode = PrivilegeMode::User;
+        cpu.host_ecall = Some(PrivilegeMode::User);
+        // rdcycle, rdtime and rdinstret work like under Linux
+        cpu.csrs.store(csr::MCOUNTEREN, u32::MAX);
+        cpu.csrs.store(csr::SCOUNTEREN, u32::MAX);
+        cpu.pc.store(entry);
+        cpu.x_regs[2].store(sp); // sp
+
+        Ok(())
+    }
+
+    // handle the semihosting sequences (slli/ebreak/srai) in the host instead of stopping
+    pub fn enable_semihosting(&mut self, semihosting: Semihosting) {
+        self.semihosting = Some(semihosting);
+        for cpu in &mut self.harts {
+            cpu.semihosting = true;
+        }
+    }
+
+    // the machine as seen by the guest (devices under /soc)
+    pub fn fdt(&self) -> FdtNode {
+        let mut cpus = FdtNode::new("cpus")
+            .prop_u32("#address-cells", 1)
+            .prop_u32("#size-cells", 0)
+            .prop_u32("timebase-frequency", TIMEBASE_FREQUENCY);
+        for hart in 0..self.harts.len() {
+            let cpu = FdtNode::new_with_addr("cpu", hart as u32)
+                .prop_str("device_type", "cpu")
+                .prop_u32("reg", hart as u32)
+                .prop_str("status", "okay")
+                .prop_str("compatible", "riscv")
+                .prop_str("riscv,isa", &csr::isa_string(csr::MISA_VALUE))
+                .prop_str("mmu-type", "riscv,sv32")
+                .child(
+                    FdtNode::new("interrupt-controller")
+                        .label(&fdt::cpu_intc_label(hart))
+                        .prop_str("compatible", "riscv,cpu-intc")
+                        .prop_empty("interrupt-controller")
+                        .prop_u32("#interrupt-cells", 1),
+                );
+            cpus = cpus.child(cpu);
+        }
+
+        let memory = FdtNode::new_with_addr("memory", self.ram.base_addr)
+            .prop_str("device_type", "memory")
+            .prop_reg(self.ram.base_addr, self.ram.size());
+
+        let mut soc = FdtNode::new("soc")
+            .prop_str("compatible", "simple-bus")
+            .prop_u32("#address-cells", 1)
+            .prop_u32("#size-cells", 1)
+            .prop_empty("ranges");
+        soc.children = self
+            .mmio_devices
+            .iter()
+            .flat_map(|mmio_device| mmio_device.fdt_nodes())
+            .collect();
+        if soc
+            .children
+            .iter()
+            .any(|node| node.label.as_deref() == Some(PLIC_LABEL))
+        {
+            soc = soc.prop_ref("interrupt-parent", PLIC_LABEL);
+        }
+
+        let mut chosen = FdtNode::new("chosen");
+        if let Some(bootargs) = &self.bootargs {
+            chosen = chosen.prop_str("bootargs", bootargs);
+        }
+        if let Some((start, end)) = self.initrd {
+            chosen = chosen
+                .prop_u32("linux,initrd-start", start)
+                .prop_u32("linux,initrd-end", end);
+        }
+        let serial = soc.children.iter().find(|node| {
+            node.property("compatible") == Some(&FdtValue::Strings(vec![String::from("ns16550a")]))
+        });
+        if let Some(serial) = serial {
+            chosen = chosen.prop_str("stdout-path", &format!("/soc/{}", serial.name));
+        }
+
+        FdtNode::new("")
+            .prop_u32("#address-cells", 1)
+            .prop_u32("#size-cells", 1)
+            .prop_str("compatible", "frisc")
+            .prop_str("model", "frisc")
+            .child(chosen)
+            .child(cpus)
+            .child(memory)
+            .child(soc)
+    }
+
+    // copy the device tree blob to the end of ram and pass it like a boot loader
+    // (a0 = hart id, a1 = address on every hart), returns the address
+    pub fn load_fdt(&mut self) -> anyhow::Result<u32> {
+        let dtb = self.fdt().to_dtb()?;
+        let ram_end = self.ram.base_addr.wrapping_add(self.ram.size() as u32);
+        let addr = ram_end.wrapping_sub(dtb.len() as u32) & !(FDT_ALIGN - 1);
+
+        match self.ram.slice_mut(addr, dtb.len()) {
+            Some(slice) => slice.copy_from_slice(&dtb),
+            _ => return Err(anyhow::anyhow!("RAM is too small for the device tree")),
+        }
+        for cpu in &mut self.harts {
+            cpu.x_regs[10].store(cpu.hart_id()); // a0
+            cpu.x_regs[11].store(addr); // a1
+        }
+
+        Ok(addr)
+    }
+
+    // each device is taken out of the list while it accesses the others
+    fn bus_access(&mut self, log: &mut step_log::Log) {
+        let step = self.steps();
+        for i in 0..self.mmio_devices.len() {
+            let mut mmio_device = self.mmio_devices.swap_remove(i);
+            let mut bus = SystemBus::new(&mut self.ram, &mut self.mmio_devices);
+            mmio_device.bus_access(&mut bus);
+
+            for ram_writes in bus.into_bursts() {
+                log.dev_writes.push(step_log::DeviceWrite {
+                    step,
+                    device_name: mmio_device.device_name().to_string(),
+                    ram_writes,
+                });
+            }
+
+            self.mmio_devices.push(mmio_device);
+            let last = self.mmio_devices.len() - 1;
+            self.mmio_devices.swap(i, last);
+        }
+    }
+
+    // route the device irq lines through the interrupt controllers into mip
+    fn update_interrupts(&mut self) {
+        let irq_lines = self
+            .mmio_devices
+            .iter()
+            .filter_map(|mmio_device| mmio_device.irq())
+            .filter(|irq| *irq < 64)
+            .fold(0u64, |lines, irq| lines | 1 << irq);
+
+        for (hart, cpu) in self.harts.iter_mut().enumerate() {
+            let mip = self.mmio_devices.iter_mut().fold(0, |mip, mmio_device| {
+                mip | mmio_device.pending_interrupts(irq_lines, hart)
+            });
+
+            cpu.csrs.set_interrupt_pending(mip);
+
+            if let Some(mtime) = &self.mtime {
+                cpu.csrs.set_time(mtime.get());
+            }
+        }
+    }
+
+    // the next hart after this one that isn't stopped (this one again if it is the only one)
+    fn next_hart(&self, hart: usize) -> Option<usize> {
+        (1..=self.harts.len())
+            .map(|i| (hart + i) % self.harts.len())
+            .find(|&i| !self.harts[i].stopped)
+    }
+
+    // a store to the word reserved by lr.w of another hart makes its sc.w fail
+    fn invalidate_reservations(&mut self, hart: usize, ram_writes: &[step_log::RamWrite]) {
+        for (i, cpu) in self.harts.iter_mut().enumerate() {
+            if i != hart
+                && cpu
+                    .reservation
+                    .is_some_and(|(addr, _)| ram_writes.iter().any(|w| w.addr & !0b11 == addr))
+            {
+                cpu.reservation = None;
+            }
+        }
+    }
+
+    // restore the state at the beginning of run() (the step and cycle counters keep counting)
+    fn reboot(&mut self, boot_harts: &[Cpu], boot_ram: &[u8]) {
+        for (cpu, boot_cpu) in self.harts.iter_mut().zip(boot_harts) {
+            let (step, cycle, instret) = (cpu.step, cpu.cycle, cpu.instret);
+            *cpu = boot_cpu.clone();
+            cpu.step = step;
+            cpu.cycle = cycle;
+            cpu.instret = instret;
+        }
+        self.ram.data.copy_from_slice(boot_ram);
+
+        for mmio_device in &mut self.mmio_devices {
+            mmio_device.reset();
+        }
     }
 }
@@ -0,0 +1,314 @@
+use std::{collections::HashMap, fmt::Write as _};
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_VERSION: u32 = 17;
+const FDT_LAST_COMP_VERSION: u32 = 16;
+const FDT_HEADER_LEN: usize = 40;
+// one empty reservation entry
+const FDT_MEM_RSVMAP_LEN: usize = 16;
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_END: u32 = 9;
+
+// labels the interrupt wiring refers to
+pub const PLIC_LABEL: &str = "plic";
+
+// the local interrupt controller of a hart
+pub fn cpu_intc_label(hart: usize) -> String {
+    format!("cpu{}_intc", hart)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FdtCell {
+    Value(u32),
+    // phandle of the node with the label
+    Ref(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FdtValue {
+    Empty,
+    Cells(Vec<FdtCell>),
+    Strings(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FdtNode {
+    pub name: String,
+    // other nodes refer to this one by the label (a phandle is assigned when needed)
+    pub label: Option<String>,
+    pub properties: Vec<(String, FdtValue)>,
+    pub children: Vec<FdtNode>,
+}
+
+impl FdtNode {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            label: None,
+            properties: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    // "<name>@<addr>"
+    pub fn new_with_addr(name: &str, addr: u32) -> Self {
+        Self::new(&format!("{}@{:x}", name, addr))
+    }
+
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    pub fn prop_empty(mut self, name: &str) -> Self {
+        self.properties.push((name.to_string(), FdtValue::Empty));
+        self
+    }
+
+    pub fn prop_u32(self, name: &str, value: u32) -> Self {
+        self.prop_cells(name, &[value])
+    }
+
+    pub fn prop_cells(mut self, name: &str, values: &[u32]) -> Self {
+        let cells = values.iter().map(|value| FdtCell::Value(*value)).collect();
+        self.properties
+            .push((name.to_string(), FdtValue::Cells(cells)));
+        self
+    }
+
+    pub fn prop_ref_cells(mut self, name: &str, cells: Vec<FdtCell>) -> Self {
+        self.properties
+            .push((name.to_string(), FdtValue::Cells(cells)));
+        self
+    }
+
+    pub fn prop_ref(self, name: &str, label: &str) -> Self {
+        self.prop_ref_cells(name, vec![FdtCell::Ref(label.to_string())])
+    }
+
+    pub fn prop_str(self, name: &str, value: &str) -> Self {
+        self.prop_strs(name, &[value])
+    }
+
+    pub fn prop_strs(mut self, name: &str, values: &[&str]) -> Self {
+        let values = values.iter().map(|value| value.to_string()).collect();
+        self.properties
+            .push((name.to_string(), FdtValue::Strings(values)));
+        self
+    }
+
+    // "reg" with one address cell and one size cell
+    pub fn prop_reg(self, addr: u32, size: usize) -> Self {
+        self.prop_cells("reg", &[addr, size as u32])
+    }
+
+    pub fn child(mut self, child: FdtNode) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    pub fn property(&self, name: &str) -> Option<&FdtValue> {
+        self.properties
+            .iter()
+            .find(|(prop_name, _)| prop_name == name)
+            .map(|(_, value)| value)
+    }
+
+    fn collect_labels<'a>(&'a self, labels: &mut Vec<&'a str>) {
+        if let Some(label) = &self.label {
+            labels.push(label);
+        }
+
+        for child in &self.children {
+            child.collect_labels(labels);
+        }
+    }
+
+    fn collect_refs<'a>(&'a self, refs: &mut Vec<&'a str>) {
+        for (_, value) in &self.properties {
+            if let FdtValue::Cells(cells) = value {
+                for cell in cells {
+                    if let FdtCell::Ref(label) = cell {
+                        refs.push(label);
+                    }
+                }
+            }
+        }
+
+        for child in &self.children {
+            child.collect_refs(refs);
+        }
+    }
+
+    // phandles are numbered from 1 in tree order, only referenced labels get one
+    fn phandles(&self) -> anyhow::Result<HashMap<String, u32>> {
+        let mut labels = Vec::new();
+        self.collect_labels(&mut labels);
+        let mut refs = Vec::new();
+        self.collect_refs(&mut refs);
+
+        if let Some(label) = refs.iter().find(|label| !labels.contains(label)) {
+            return Err(anyhow::anyhow!("Undefined label: {}", label));
+        }
+
+        Ok(labels
+            .into_iter()
+            .filter(|label| refs.contains(label))
+            .enumerate()
+            .map(|(i, label)| (label.to_string(), i as u32 + 1))
+            .collect())
+    }
+
+    // flattened device tree blob (version 17)
+    pub fn to_dtb(&self) -> anyhow::Result<Vec<u8>> {
+        let phandles = self.phandles()?;
+        let mut dt_struct = Vec::new();
+        let mut dt_strings = Vec::new();
+        let mut string_offsets = HashMap::new();
+
+        self.write_struct(
+            &phandles,
+            &mut dt_struct,
+            &mut dt_strings,
+            &mut string_offsets,
+        );
+        push_u32(&mut dt_struct, FDT_END);
+
+        let off_mem_rsvmap = FDT_HEADER_LEN;
+        let off_dt_struct = off_mem_rsvmap + FDT_MEM_RSVMAP_LEN;
+        let off_dt_strings = off_dt_struct + dt_struct.len();
+        let total_size = off_dt_strings + dt_strings.len();
+
+        let mut dtb = Vec::with_capacity(total_size);
+        for value in [
+            FDT_MAGIC,
+            total_size as u32,
+            off_dt_struct as u32,
+            off_dt_strings as u32,
+            off_mem_rsvmap as u32,
+            FDT_VERSION,
+            FDT_LAST_COMP_VERSION,
+            0, // boot_cpuid_phys
+            dt_strings.len() as u32,
+            dt_struct.len() as u32,
+        ] {
+            push_u32(&mut dtb, value);
+        }
+        dtb.extend([0; FDT_MEM_RSVMAP_LEN]);
+        dtb.extend(dt_struct);
+        dtb.extend(dt_strings);
+
+        Ok(dtb)
+    }
+
+    fn write_struct(
+        &self,
+        phandles: &HashMap<String, u32>,
+        dt_struct: &mut Vec<u8>,
+        dt_strings: &mut Vec<u8>,
+        string_offsets: &mut HashMap<String, u32>,
+    ) {
+        push_u32(dt_struct, FDT_BEGIN_NODE);
+        dt_struct.extend(self.name.as_bytes());
+        dt_struct.push(0);
+        align4(dt_struct);
+
+        let phandle = self.label.as_ref().and_then(|label| phandles.get(label));
+        let phandle_prop = phandle.map(|phandle| {
+            (
+                String::from("phandle"),
+                FdtValue::Cells(vec![FdtCell::Value(*phandle)]),
+            )
+        });
+
+        for (name, value) in self.properties.iter().chain(phandle_prop.iter()) {
+            let bytes = match value {
+                FdtValue::Empty => Vec::new(),
+                FdtValue::Cells(cells) => cells
+                    .iter()
+                    .flat_map(|cell| match cell {
+                        FdtCell::Value(value) => value.to_be_bytes(),
+                        FdtCell::Ref(label) => phandles[label].to_be_bytes(),
+                    })
+                    .collect(),
+                FdtValue::Strings(strings) => {
+                    strings.iter().flat_map(|s| s.bytes().chain([0])).collect()
+                }
+            };
+
+            let name_offset = *string_offsets.entry(name.clone()).or_insert_with(|| {
+                let offset = dt_strings.len() as u32;
+                dt_strings.extend(name.as_bytes());
+                dt_strings.push(0);
+                offset
+            });
+
+            push_u32(dt_struct, FDT_PROP);
+            push_u32(dt_struct, bytes.len() as u32);
+            push_u32(dt_struct, name_offset);
+            dt_struct.extend(bytes);
+            align4(dt_struct);
+        }
+
+        for child in &self.children {
+            child.write_struct(phandles, dt_struct, dt_strings, string_offsets);
+        }
+
+        push_u32(dt_struct, FDT_END_NODE);
+    }
+
+    // device tree source (labels are kept, phandle properties are left to dtc)
+    pub fn to_dts(&self) -> String {
+        let mut s = String::from("/dts-v1/;\n\n");
+        self.write_dts(&mut s, 0);
+        s
+    }
+
+    fn write_dts(&self, s: &mut String, depth: usize) {
+        let indent = "\t".repeat(depth);
+        let name = if depth == 0 { "/" } else { &self.name };
+        match &self.label {
+            Some(label) => writeln!(s, "{}{}: {} {{", indent, label, name).unwrap(),
+            None => writeln!(s, "{}{} {{", indent, name).unwrap(),
+        }
+
+        for (name, value) in &self.properties {
+            let value = match value {
+                FdtValue::Empty => String::new(),
+                FdtValue::Cells(cells) => {
+                    let cells: Vec<String> = cells
+                        .iter()
+                        .map(|cell| match cell {
+                            FdtCell::Value(value) => format!("0x{:x}", value),
+                            FdtCell::Ref(label) => format!("&{}", label),
+                        })
+                        .collect();
+                    format!(" = <{}>", cells.join(" "))
+                }
+                FdtValue::Strings(strings) => {
+                    let strings: Vec<String> = strings.iter().map(|s| format!("{:?}", s)).collect();
+                    format!(" = {}", strings.join(", "))
+                }
+            };
+            writeln!(s, "{}\t{}{};", indent, name, value).unwrap();
+        }
+
+        for child in &self.children {
+            s.push('\n');
+            child.write_dts(s, depth + 1);
+        }
+
+        writeln!(s, "{}}};", indent).unwrap();
+    }
+}
+
+fn push_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend(value.to_be_bytes());
+}
+
+fn align4(buf: &mut Vec<u8>) {
+    buf.resize(buf.len().next_multiple_of(4), 0);
+}
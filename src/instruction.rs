@@ -1,6 +1,8 @@
+use crate::cpu::Exception;
 use serde::Serialize;
 use std::fmt::{self, Debug};
 
+#[derive(Clone, Copy)]
 pub enum InstructionFormat {
     R {
         opcode: u8,
@@ -150,7 +152,8 @@ impl InstructionFormat {
         let rs2 = ((instruction >> 20) & 0x1f) as u8;
 
         let format = match opcode {
-            0b0110011 => {
+            // OP (including the M extension) and AMO
+            0b0110011 | 0b0101111 => {
                 let funct7 = ((instruction >> 25) & 0x7f) as u8;
 
                 Self::R {
@@ -162,7 +165,10 @@ impl InstructionFormat {
                     funct7,
                 }
             }
-            0b0010011 | 0b0000011 | 0b1100111 => {
+            // SYSTEM instructions except CSR instructions are decoded from the raw value
+            0b0010011 | 0b0000011 | 0b1100111 | 0b1110011
+                if opcode != 0b1110011 || funct3 != 0 =>
+            {
                 let imm0_11 = ((instruction >> 20) & 0xfff) as u16;
 
                 Self::I {
@@ -228,7 +234,7 @@ impl InstructionFormat {
                 }
             }
             0b0001111 | 0b1110011 => Self::None(instruction),
-            _ => return Err(anyhow::anyhow!("Invalid instruction")),
+            _ => return Err(Exception::IllegalInstruction(instruction).into()),
         };
         Ok(format)
     }
@@ -273,9 +279,39 @@ pub enum Instruction {
     Bgeu { rs1: usize, rs2: usize, offset: i16 },
     Lui { rd: usize, imm: u32 },
     Auipc { rd: usize, imm: u32 },
+    Mul { rd: usize, rs1: usize, rs2: usize },
+    Mulh { rd: usize, rs1: usize, rs2: usize },
+    Mulhsu { rd: usize, rs1: usize, rs2: usize },
+    Mulhu { rd: usize, rs1: usize, rs2: usize },
+    Div { rd: usize, rs1: usize, rs2: usize },
+    Divu { rd: usize, rs1: usize, rs2: usize },
+    Rem { rd: usize, rs1: usize, rs2: usize },
+    Remu { rd: usize, rs1: usize, rs2: usize },
+    LrW { rd: usize, rs1: usize },
+    ScW { rd: usize, rs1: usize, rs2: usize },
+    AmoswapW { rd: usize, rs1: usize, rs2: usize },
+    AmoaddW { rd: usize, rs1: usize, rs2: usize },
+    AmoxorW { rd: usize, rs1: usize, rs2: usize },
+    AmoandW { rd: usize, rs1: usize, rs2: usize },
+    AmoorW { rd: usize, rs1: usize, rs2: usize },
+    AmominW { rd: usize, rs1: usize, rs2: usize },
+    AmomaxW { rd: usize, rs1: usize, rs2: usize },
+    AmominuW { rd: usize, rs1: usize, rs2: usize },
+    AmomaxuW { rd: usize, rs1: usize, rs2: usize },
     Fence { pred: u8, succ: u8 },
+    FenceI,
     Ecall,
     Ebreak,
+    Sret,
+    Mret,
+    Wfi,
+    SfenceVma { rs1: usize, rs2: usize },
+    Csrrw { rd: usize, rs1: usize, csr: u16 },
+    Csrrs { rd: usize, rs1: usize, csr: u16 },
+    Csrrc { rd: usize, rs1: usize, csr: u16 },
+    Csrrwi { rd: usize, uimm: u8, csr: u16 },
+    Csrrsi { rd: usize, uimm: u8, csr: u16 },
+    Csrrci { rd: usize, uimm: u8, csr: u16 },
 }
 
 impl Debug for Instruction {
@@ -318,16 +354,76 @@ impl Debug for Instruction {
             Self::Bgeu { rs1, rs2, offset } => write!(f, "bgeu x{rs1}, x{rs2}, {offset}"),
             Self::Lui { rd, imm } => write!(f, "lui x{rd}, {imm}"),
             Self::Auipc { rd, imm } => write!(f, "auipc x{rd}, {imm}"),
+            Self::Mul { rd, rs1, rs2 } => write!(f, "mul x{rd}, x{rs1}, x{rs2}"),
+            Self::Mulh { rd, rs1, rs2 } => write!(f, "mulh x{rd}, x{rs1}, x{rs2}"),
+            Self::Mulhsu { rd, rs1, rs2 } => write!(f, "mulhsu x{rd}, x{rs1}, x{rs2}"),
+            Self::Mulhu { rd, rs1, rs2 } => write!(f, "mulhu x{rd}, x{rs1}, x{rs2}"),
+            Self::Div { rd, rs1, rs2 } => write!(f, "div x{rd}, x{rs1}, x{rs2}"),
+            Self::Divu { rd, rs1, rs2 } => write!(f, "divu x{rd}, x{rs1}, x{rs2}"),
+            Self::Rem { rd, rs1, rs2 } => write!(f, "rem x{rd}, x{rs1}, x{rs2}"),
+            Self::Remu { rd, rs1, rs2 } => write!(f, "remu x{rd}, x{rs1}, x{rs2}"),
+            Self::LrW { rd, rs1 } => write!(f, "lr.w x{rd}, (x{rs1})"),
+            Self::ScW { rd, rs1, rs2 } => write!(f, "sc.w x{rd}, x{rs2}, (x{rs1})"),
+            Self::AmoswapW { rd, rs1, rs2 } => write!(f, "amoswap.w x{rd}, x{rs2}, (x{rs1})"),
+            Self::AmoaddW { rd, rs1, rs2 } => write!(f, "amoadd.w x{rd}, x{rs2}, (x{rs1})"),
+            Self::AmoxorW { rd, rs1, rs2 } => write!(f, "amoxor.w x{rd}, x{rs2}, (x{rs1})"),
+            Self::AmoandW { rd, rs1, rs2 } => write!(f, "amoand.w x{rd}, x{rs2}, (x{rs1})"),
+            Self::AmoorW { rd, rs1, rs2 } => write!(f, "amoor.w x{rd}, x{rs2}, (x{rs1})"),
+            Self::AmominW { rd, rs1, rs2 } => write!(f, "amomin.w x{rd}, x{rs2}, (x{rs1})"),
+            Self::AmomaxW { rd, rs1, rs2 } => write!(f, "amomax.w x{rd}, x{rs2}, (x{rs1})"),
+            Self::AmominuW { rd, rs1, rs2 } => write!(f, "amominu.w x{rd}, x{rs2}, (x{rs1})"),
+            Self::AmomaxuW { rd, rs1, rs2 } => write!(f, "amomaxu.w x{rd}, x{rs2}, (x{rs1})"),
             Self::Fence { pred, succ } => write!(f, "fence {pred}, {succ}"),
+            Self::FenceI => write!(f, "fence.i"),
             Self::Ecall => write!(f, "ecall"),
             Self::Ebreak => write!(f, "ebreak"),
+            Self::Sret => write!(f, "sret"),
+            Self::Mret => write!(f, "mret"),
+            Self::Wfi => write!(f, "wfi"),
+            Self::SfenceVma { rs1, rs2 } => write!(f, "sfence.vma x{rs1}, x{rs2}"),
+            Self::Csrrw { rd, rs1, csr } => write!(f, "csrrw x{rd}, 0x{csr:03x}, x{rs1}"),
+            Self::Csrrs { rd, rs1, csr } => write!(f, "csrrs x{rd}, 0x{csr:03x}, x{rs1}"),
+            Self::Csrrc { rd, rs1, csr } => write!(f, "csrrc x{rd}, 0x{csr:03x}, x{rs1}"),
+            Self::Csrrwi { rd, uimm, csr } => write!(f, "csrrwi x{rd}, 0x{csr:03x}, {uimm}"),
+            Self::Csrrsi { rd, uimm, csr } => write!(f, "csrrsi x{rd}, 0x{csr:03x}, {uimm}"),
+            Self::Csrrci { rd, uimm, csr } => write!(f, "csrrci x{rd}, 0x{csr:03x}, {uimm}"),
         }
     }
 }
 
 impl Instruction {
+    // encodings that aren't implemented are illegal instructions (trapped by the hart)
     pub fn parse(instruction_format: InstructionFormat) -> anyhow::Result<Self> {
+        let illegal = || Exception::IllegalInstruction(instruction_format.into());
         let ins = match instruction_format {
+            InstructionFormat::R {
+                opcode: 0b0101111,
+                rd,
+                funct3,
+                rs1,
+                rs2,
+                funct7,
+            } => {
+                let rd = rd as usize;
+                let rs1 = rs1 as usize;
+                let rs2 = rs2 as usize;
+
+                // funct5 (the aq and rl bits are ignored)
+                match (funct3, funct7 >> 2) {
+                    (0b010, 0b00010) => Self::LrW { rd, rs1 },
+                    (0b010, 0b00011) => Self::ScW { rd, rs1, rs2 },
+                    (0b010, 0b00001) => Self::AmoswapW { rd, rs1, rs2 },
+                    (0b010, 0b00000) => Self::AmoaddW { rd, rs1, rs2 },
+                    (0b010, 0b00100) => Self::AmoxorW { rd, rs1, rs2 },
+                    (0b010, 0b01100) => Self::AmoandW { rd, rs1, rs2 },
+                    (0b010, 0b01000) => Self::AmoorW { rd, rs1, rs2 },
+                    (0b010, 0b10000) => Self::AmominW { rd, rs1, rs2 },
+                    (0b010, 0b10100) => Self::AmomaxW { rd, rs1, rs2 },
+                    (0b010, 0b11000) => Self::AmominuW { rd, rs1, rs2 },
+                    (0b010, 0b11100) => Self::AmomaxuW { rd, rs1, rs2 },
+                    _ => return Err(illegal().into()),
+                }
+            }
             InstructionFormat::R {
                 opcode: _,
                 rd,
@@ -351,7 +447,15 @@ impl Instruction {
                     (0b101, 0b0100000) => Self::Sra { rd, rs1, rs2 },
                     (0b010, 0b0000000) => Self::Slt { rd, rs1, rs2 },
                     (0b011, 0b0000000) => Self::Sltu { rd, rs1, rs2 },
-                    _ => unimplemented!(),
+                    (0b000, 0b0000001) => Self::Mul { rd, rs1, rs2 },
+                    (0b001, 0b0000001) => Self::Mulh { rd, rs1, rs2 },
+                    (0b010, 0b0000001) => Self::Mulhsu { rd, rs1, rs2 },
+                    (0b011, 0b0000001) => Self::Mulhu { rd, rs1, rs2 },
+                    (0b100, 0b0000001) => Self::Div { rd, rs1, rs2 },
+                    (0b101, 0b0000001) => Self::Divu { rd, rs1, rs2 },
+                    (0b110, 0b0000001) => Self::Rem { rd, rs1, rs2 },
+                    (0b111, 0b0000001) => Self::Remu { rd, rs1, rs2 },
+                    _ => return Err(illegal().into()),
                 }
             }
             InstructionFormat::I {
@@ -369,15 +473,17 @@ impl Instruction {
                 }
                 let offset = imm;
                 let shamt = (imm & 0x1f) as u8;
+                let csr = imm0_11;
+                let uimm = rs1 as u8;
 
                 match (opcode, funct3, imm0_11 >> 5) {
                     (0b0010011, 0b000, _) => Self::Addi { rd, rs1, imm },
                     (0b0010011, 0b111, _) => Self::Andi { rd, rs1, imm },
                     (0b0010011, 0b110, _) => Self::Ori { rd, rs1, imm },
                     (0b0010011, 0b100, _) => Self::Xori { rd, rs1, imm },
-                    (0b0010011, 0b001, _) => Self::Slli { rd, rs1, shamt },
+                    (0b0010011, 0b001, 0b0000000) => Self::Slli { rd, rs1, shamt },
                     (0b0010011, 0b101, 0b0100000) => Self::Srai { rd, rs1, shamt },
-                    (0b0010011, 0b101, _) => Self::Srli { rd, rs1, shamt },
+                    (0b0010011, 0b101, 0b0000000) => Self::Srli { rd, rs1, shamt },
                     (0b0010011, 0b010, _) => Self::Slti { rd, rs1, imm },
                     (0b0010011, 0b011, _) => Self::Sltiu {
                         rd,
@@ -390,7 +496,13 @@ impl Instruction {
                     (0b0000011, 0b101, _) => Self::Lhu { rd, rs1, offset },
                     (0b0000011, 0b010, _) => Self::Lw { rd, rs1, offset },
                     (0b1100111, 0b000, _) => Self::Jalr { rd, rs1, offset },
-                    _ => unimplemented!(),
+                    (0b1110011, 0b001, _) => Self::Csrrw { rd, rs1, csr },
+                    (0b1110011, 0b010, _) => Self::Csrrs { rd, rs1, csr },
+                    (0b1110011, 0b011, _) => Self::Csrrc { rd, rs1, csr },
+                    (0b1110011, 0b101, _) => Self::Csrrwi { rd, uimm, csr },
+                    (0b1110011, 0b110, _) => Self::Csrrsi { rd, uimm, csr },
+                    (0b1110011, 0b111, _) => Self::Csrrci { rd, uimm, csr },
+                    _ => return Err(illegal().into()),
                 }
             }
             InstructionFormat::S {
@@ -410,7 +522,7 @@ impl Instruction {
                     0b000 => Self::Sb { rs1, rs2, offset },
                     0b001 => Self::Sh { rs1, rs2, offset },
                     0b010 => Self::Sw { rs1, rs2, offset },
-                    _ => unimplemented!(),
+                    _ => return Err(illegal().into()),
                 }
             }
             InstructionFormat::B {
@@ -440,7 +552,7 @@ impl Instruction {
                     0b101 => Self::Bge { rs1, rs2, offset },
                     0b110 => Self::Bltu { rs1, rs2, offset },
                     0b111 => Self::Bgeu { rs1, rs2, offset },
-                    _ => unimplemented!(),
+                    _ => return Err(illegal().into()),
                 }
             }
             InstructionFormat::U {
@@ -454,7 +566,7 @@ impl Instruction {
                 match opcode {
                     0b0110111 => Self::Lui { rd, imm },
                     0b0010111 => Self::Auipc { rd, imm },
-                    _ => unimplemented!(),
+                    _ => return Err(illegal().into()),
                 }
             }
             InstructionFormat::J {
@@ -483,13 +595,25 @@ impl Instruction {
             }
             InstructionFormat::None(i) => {
                 let opcode = i & 0x7f;
+                let funct3 = (i >> 12) & 0x7;
+                let funct12 = i >> 20;
                 let pred = ((i >> 27) & 0x7) as u8;
                 let succ = ((i >> 20) & 0x7) as u8;
-                match (opcode, succ) {
-                    (0b0001111, _) => Self::Fence { pred, succ },
-                    (0b1110011, 0) => Self::Ecall,
-                    (0b1110011, 1) => Self::Ebreak,
-                    _ => unimplemented!(),
+                let rs1 = ((i >> 15) & 0x1f) as usize;
+                let rs2 = ((i >> 20) & 0x1f) as usize;
+                match (opcode, funct3, funct12) {
+                    (0b0001111, 0b000, _) => Self::Fence { pred, succ },
+                    (0b0001111, 0b001, _) => Self::FenceI,
+                    (0b1110011, 0b000, 0x000) => Self::Ecall,
+                    (0b1110011, 0b000, 0x001) => Self::Ebreak,
+                    (0b1110011, 0b000, 0x102) => Self::Sret,
+                    (0b1110011, 0b000, 0x302) => Self::Mret,
+                    (0b1110011, 0b000, 0x105) => Self::Wfi,
+                    // funct7 = 0b0001001
+                    (0b1110011, 0b000, _) if funct12 >> 5 == 0b0001001 => {
+                        Self::SfenceVma { rs1, rs2 }
+                    }
+                    _ => return Err(illegal().into()),
                 }
             }
         };
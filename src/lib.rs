@@ -1,10 +1,20 @@
 pub mod cpu;
+pub mod csr;
 pub mod emulator;
+pub mod fdt;
 pub mod instruction;
 pub mod mmio_device;
+pub mod mmu;
+pub mod net;
+pub mod parallel;
 pub mod ram;
 pub mod register;
+pub mod sbi;
+pub mod semihosting;
+pub mod serial;
 pub mod step_log;
+pub mod syscall;
+pub mod timing;
 
 #[test]
 fn test_add_addi() -> anyhow::Result<()> {
@@ -20,10 +30,10 @@ fn test_add_addi() -> anyhow::Result<()> {
     emulator.reset();
     emulator.run(false)?;
 
-    assert_eq!(emulator.cpu.x_regs[1].load(), 1);
-    assert_eq!(emulator.cpu.x_regs[2].load(), 2);
-    assert_eq!(emulator.cpu.x_regs[3].load(), 3);
-    assert_eq!(emulator.cpu.x_regs[4].load() as i32, -1);
+    assert_eq!(emulator.harts[0].x_regs[1].load(), 1);
+    assert_eq!(emulator.harts[0].x_regs[2].load(), 2);
+    assert_eq!(emulator.harts[0].x_regs[3].load(), 3);
+    assert_eq!(emulator.harts[0].x_regs[4].load() as i32, -1);
 
     Ok(())
 }
@@ -43,8 +53,8 @@ fn test_sub() -> anyhow::Result<()> {
     emulator.reset();
     emulator.run(false)?;
 
-    assert_eq!(emulator.cpu.x_regs[3].load(), 1);
-    assert_eq!(emulator.cpu.x_regs[4].load() as i32, -2);
+    assert_eq!(emulator.harts[0].x_regs[3].load(), 1);
+    assert_eq!(emulator.harts[0].x_regs[4].load() as i32, -2);
 
     Ok(())
 }
@@ -64,8 +74,8 @@ fn test_and_andi() -> anyhow::Result<()> {
     emulator.reset();
     emulator.run(false)?;
 
-    assert_eq!(emulator.cpu.x_regs[10].load(), 130);
-    assert_eq!(emulator.cpu.x_regs[12].load(), 128);
+    assert_eq!(emulator.harts[0].x_regs[10].load(), 130);
+    assert_eq!(emulator.harts[0].x_regs[12].load(), 128);
 
     Ok(())
 }
@@ -85,8 +95,8 @@ fn test_or_ori() -> anyhow::Result<()> {
     emulator.reset();
     emulator.run(false)?;
 
-    assert_eq!(emulator.cpu.x_regs[8].load(), 839);
-    assert_eq!(emulator.cpu.x_regs[9].load() as i32, -1);
+    assert_eq!(emulator.harts[0].x_regs[8].load(), 839);
+    assert_eq!(emulator.harts[0].x_regs[9].load() as i32, -1);
 
     Ok(())
 }
@@ -106,8 +116,8 @@ fn test_xor_xori() -> anyhow::Result<()> {
     emulator.reset();
     emulator.run(false)?;
 
-    assert_eq!(emulator.cpu.x_regs[9].load(), 1641);
-    assert_eq!(emulator.cpu.x_regs[10].load() as i32, -2028);
+    assert_eq!(emulator.harts[0].x_regs[9].load(), 1641);
+    assert_eq!(emulator.harts[0].x_regs[10].load() as i32, -2028);
 
     Ok(())
 }
@@ -129,10 +139,10 @@ fn test_sll_slli_srl_srli() -> anyhow::Result<()> {
     emulator.reset();
     emulator.run(false)?;
 
-    assert_eq!(emulator.cpu.x_regs[4].load(), 736);
-    assert_eq!(emulator.cpu.x_regs[5].load(), 40);
-    assert_eq!(emulator.cpu.x_regs[6].load(), 2);
-    assert_eq!(emulator.cpu.x_regs[7].load(), 2);
+    assert_eq!(emulator.harts[0].x_regs[4].load(), 736);
+    assert_eq!(emulator.harts[0].x_regs[5].load(), 40);
+    assert_eq!(emulator.harts[0].x_regs[6].load(), 2);
+    assert_eq!(emulator.harts[0].x_regs[7].load(), 2);
 
     Ok(())
 }
@@ -152,8 +162,8 @@ fn test_sra_srai() -> anyhow::Result<()> {
     emulator.reset();
     emulator.run(false)?;
 
-    assert_eq!(emulator.cpu.x_regs[3].load() as i32, -1);
-    assert_eq!(emulator.cpu.x_regs[4].load() as i32, -1);
+    assert_eq!(emulator.harts[0].x_regs[3].load() as i32, -1);
+    assert_eq!(emulator.harts[0].x_regs[4].load() as i32, -1);
 
     Ok(())
 }
@@ -175,10 +185,10 @@ fn test_slt_slti_sltu_sltiu() -> anyhow::Result<()> {
     emulator.reset();
     emulator.run(false)?;
 
-    assert_eq!(emulator.cpu.x_regs[3].load(), 0);
-    assert_eq!(emulator.cpu.x_regs[4].load(), 0);
-    assert_eq!(emulator.cpu.x_regs[5].load(), 1);
-    assert_eq!(emulator.cpu.x_regs[6].load(), 0);
+    assert_eq!(emulator.harts[0].x_regs[3].load(), 0);
+    assert_eq!(emulator.harts[0].x_regs[4].load(), 0);
+    assert_eq!(emulator.harts[0].x_regs[5].load(), 1);
+    assert_eq!(emulator.harts[0].x_regs[6].load(), 0);
 
     Ok(())
 }
@@ -198,8 +208,8 @@ fn test_lb_lbu_sb() -> anyhow::Result<()> {
     emulator.reset();
     emulator.run(false)?;
 
-    assert_eq!(emulator.cpu.x_regs[2].load() as i32, -25);
-    assert_eq!(emulator.cpu.x_regs[3].load(), 231);
+    assert_eq!(emulator.harts[0].x_regs[2].load() as i32, -25);
+    assert_eq!(emulator.harts[0].x_regs[3].load(), 231);
     assert_eq!(emulator.ram.load8(0), 231);
 
     Ok(())
@@ -219,7 +229,7 @@ fn test_lw_sw() -> anyhow::Result<()> {
     emulator.reset();
     emulator.run(false)?;
 
-    assert_eq!(emulator.cpu.x_regs[2].load() as i32, -25);
+    assert_eq!(emulator.harts[0].x_regs[2].load() as i32, -25);
     assert_eq!(emulator.ram.load32(0) as i32, -25);
 
     Ok(())
@@ -237,8 +247,8 @@ fn test_jal() -> anyhow::Result<()> {
     emulator.reset();
     emulator.run(false)?;
 
-    assert_eq!(emulator.cpu.x_regs[1].load(), 4);
-    assert_eq!(emulator.cpu.pc.load(), 128);
+    assert_eq!(emulator.harts[0].x_regs[1].load(), 4);
+    assert_eq!(emulator.harts[0].pc.load(), 128);
 
     Ok(())
 }
@@ -277,7 +287,7 @@ fn test_hello() -> anyhow::Result<()> {
 
     let mut emulator = Emulator::new(ram);
     emulator.reset();
-    emulator.cpu.x_regs[2].store(0x1000); // sp
+    emulator.harts[0].x_regs[2].store(0x1000); // sp
     let _ = emulator.run(false)?;
 
     assert_eq!(emulator.ram.load8(0x123), 65); // A
@@ -306,3 +316,1790 @@ fn test_debug_exit() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_simple_uart_rx() -> anyhow::Result<()> {
+    use emulator::Emulator;
+    use mmio_device::simple_uart::SimpleUart;
+    use serial::SerialBackend;
+    use std::collections::VecDeque;
+
+    #[derive(Debug)]
+    struct TestBackend(VecDeque<u8>);
+
+    impl SerialBackend for TestBackend {
+        fn write(&mut self, _value: u8) {}
+
+        fn read(&mut self) -> Option<u8> {
+            self.0.pop_front()
+        }
+    }
+
+    let ram_data = vec![
+        0x93, 0x00, 0x80, 0x3f, // ADDI x1, x0, 0x3f8
+        0x03, 0xc1, 0x50, 0x00, // LBU x2, 5(x1)
+        0x83, 0xc1, 0x00, 0x00, // LBU x3, 0(x1)
+        0x03, 0xc2, 0x00, 0x00, // LBU x4, 0(x1)
+    ];
+
+    let backend = TestBackend(VecDeque::from(b"hi".to_vec()));
+    let mut emulator = Emulator::new(ram_data);
+    emulator.register_mmio_device(Box::new(SimpleUart::new_with_backend(Box::new(backend))));
+    emulator.reset();
+    emulator.run(false)?;
+
+    assert_eq!(emulator.harts[0].x_regs[2].load() & 0x1, 1); // data ready
+    assert_eq!(emulator.harts[0].x_regs[3].load(), b'h' as u32);
+    assert_eq!(emulator.harts[0].x_regs[4].load(), b'i' as u32);
+
+    Ok(())
+}
+
+#[test]
+fn test_sifive_test_fail() -> anyhow::Result<()> {
+    use emulator::Emulator;
+    use mmio_device::sifive_test::SifiveTest;
+
+    let ram_data = vec![
+        0xb7, 0x00, 0x10, 0x00, // LUI x1, 0x100
+        0x37, 0x31, 0x12, 0x00, // LUI x2, 0x123
+        0x13, 0x01, 0x31, 0x33, // ADDI x2, x2, 0x333
+        0x23, 0xa0, 0x20, 0x00, // SW x2, 0(x1)
+        0x00, 0x00, 0x00, 0x00, // unreachable
+    ];
+
+    let mut emulator = Emulator::new(ram_data);
+    emulator.register_mmio_device(Box::new(SifiveTest::default()));
+    emulator.reset();
+    let (exit_code, _) = emulator.run(false)?;
+
+    assert_eq!(exit_code, 0x12);
+
+    Ok(())
+}
+
+#[test]
+fn test_sifive_test_reset() -> anyhow::Result<()> {
+    use emulator::Emulator;
+    use mmio_device::{sifive_test::SifiveTest, simple_uart::SimpleUart, RequestFromDevice};
+    use serial::SerialBackend;
+
+    // sends 'r' once, then 'p' forever (not cleared by the reset)
+    #[derive(Debug, Default)]
+    struct TestBackend(usize);
+
+    impl SerialBackend for TestBackend {
+        fn write(&mut self, _value: u8) {}
+
+        fn read(&mut self) -> Option<u8> {
+            self.0 += 1;
+            Some(if self.0 == 1 { b'r' } else { b'p' })
+        }
+    }
+
+    let ram_data = vec![
+        0xb7, 0x00, 0x10, 0x00, // LUI x1, 0x100
+        0x13, 0x02, 0x80, 0x3f, // ADDI x4, x0, 0x3f8
+        0x03, 0x41, 0x52, 0x00, // LBU x2, 5(x4)
+        0x13, 0x71, 0x11, 0x00, // ANDI x2, x2, 1
+        0xe3, 0x0c, 0x01, 0xfe, // BEQ x2, x0, -8
+        0x83, 0x41, 0x02, 0x00, // LBU x3, 0(x4)
+        0x93, 0x02, 0x20, 0x07, // ADDI x5, x0, 'r'
+        0x63, 0x98, 0x51, 0x00, // BNE x3, x5, 16
+        0x37, 0x71, 0x00, 0x00, // LUI x2, 0x7
+        0x13, 0x01, 0x71, 0x77, // ADDI x2, x2, 0x777
+        0x23, 0xa0, 0x20, 0x00, // SW x2, 0(x1) (reset)
+        0x37, 0x51, 0x00, 0x00, // LUI x2, 0x5
+        0x13, 0x01, 0x51, 0x55, // ADDI x2, x2, 0x555
+        0x23, 0xa0, 0x20, 0x00, // SW x2, 0(x1) (pass)
+    ];
+
+    let mut emulator = Emulator::new(ram_data);
+    emulator.register_mmio_device(Box::new(SifiveTest::default()));
+    emulator.register_mmio_device(Box::new(SimpleUart::new_with_backend(Box::new(
+        TestBackend::default(),
+    ))));
+    emulator.reset();
+    let (exit_code, log) = emulator.run(false)?;
+
+    assert_eq!(exit_code, 0);
+    assert!(matches!(log.dev_reqs[0].req, RequestFromDevice::Reset));
+    assert_eq!(emulator.harts[0].x_regs[3].load(), b'p' as u32);
+
+    Ok(())
+}
+
+#[test]
+fn test_ecall_mret() -> anyhow::Result<()> {
+    use emulator::Emulator;
+
+    let ram_data = vec![
+        0x93, 0x02, 0x40, 0x01, // ADDI x5, x0, 0x14
+        0x73, 0x90, 0x52, 0x30, // CSRRW x0, mtvec, x5
+        0x73, 0x00, 0x00, 0x00, // ECALL
+        0x93, 0x03, 0x10, 0x00, // ADDI x7, x0, 1
+        0x6f, 0x00, 0x80, 0x01, // JAL x0, 24
+        0x73, 0x23, 0x20, 0x34, // CSRRS x6, mcause, x0 (trap handler)
+        0x73, 0x25, 0x10, 0x34, // CSRRS x10, mepc, x0
+        0x13, 0x05, 0x45, 0x00, // ADDI x10, x10, 4
+        0x73, 0x10, 0x15, 0x34, // CSRRW x0, mepc, x10
+        0x73, 0x00, 0x20, 0x30, // MRET
+    ];
+
+    let mut emulator = Emulator::new(ram_data);
+    emulator.reset();
+    emulator.run(false)?;
+
+    assert_eq!(emulator.harts[0].x_regs[6].load(), 11); // environment call from M-mode
+    assert_eq!(emulator.harts[0].x_regs[10].load(), 0xc);
+    assert_eq!(emulator.harts[0].x_regs[7].load(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_illegal_instruction() -> anyhow::Result<()> {
+    use cpu::{
+        Cpu,
+        PrivilegeMode::{Machine, Supervisor, User},
+    };
+    use csr::MSTATUS_TSR;
+    use ram::Ram;
+
+    const MRET: u32 = 0x3020_0073;
+    const SRET: u32 = 0x1020_0073;
+    const SLLI_32: u32 = 0x0200_1013; // SLLI x0, x0, 32
+    const READ_MSTATUS: u32 = 0x3000_22f3; // CSRRS x5, mstatus, x0
+    const READ_SSTATUS: u32 = 0x1000_22f3; // CSRRS x5, sstatus, x0
+    const READ_MHARTID: u32 = 0xf140_22f3; // CSRRS x5, mhartid, x0
+    const WRITE_MHARTID: u32 = 0xf142_9073; // CSRRW x0, mhartid, x5
+    const READ_CYCLE: u32 = 0xc000_22f3; // CSRRS x5, cycle, x0
+    const WRITE_CYCLE: u32 = 0xc002_9073; // CSRRW x0, cycle, x5
+
+    // (mode, mstatus, mcounteren, scounteren, instruction, illegal)
+    let cases = [
+        (Machine, 0, 0, 0, 0xffff_ffff, true),
+        (Machine, 0, 0, 0, SLLI_32, true),
+        (Supervisor, 0, 0, 0, MRET, true),
+        (User, 0, 0, 0, SRET, true),
+        (Supervisor, MSTATUS_TSR, 0, 0, SRET, true),
+        (Supervisor, 0, 0, 0, SRET, false),
+        (Supervisor, 0, 0, 0, READ_MSTATUS, true),
+        (Supervisor, 0, 0, 0, READ_SSTATUS, false),
+        (Machine, 0, 0, 0, WRITE_MHARTID, true),
+        (Machine, 0, 0, 0, READ_MHARTID, false),
+        (Machine, 0, 0, 0, WRITE_CYCLE, true),
+        (Supervisor, 0, 0, 0, READ_CYCLE, true),
+        (Supervisor, 0, 1, 0, READ_CYCLE, false),
+        (User, 0, 1, 0, READ_CYCLE, true),
+        (User, 0, 1, 1, READ_CYCLE, false),
+    ];
+
+    for (mode, mstatus, mcounteren, scounteren, instruction, illegal) in cases {
+        let mut cpu = Cpu::default();
+        let mut ram = Ram::new(0x100);
+        ram.store32(0, instruction);
+        ram.store32(0x80, 0x13); // NOP (trap handler)
+        cpu.csrs.store(csr::MTVEC, 0x80);
+        cpu.csrs.store(csr::MSTATUS, mstatus);
+        cpu.csrs.store(csr::MCOUNTEREN, mcounteren);
+        cpu.csrs.store(csr::SCOUNTEREN, scounteren);
+        cpu.mode = mode;
+        cpu.fetch_decode_execute(&mut ram, &mut Vec::new(), false)?;
+
+        let expected_cause = if illegal {
+            csr::CAUSE_ILLEGAL_INSTRUCTION
+        } else {
+            0
+        };
+        let cause = cpu.csrs.load(csr::MCAUSE);
+        assert_eq!(cause, expected_cause, "0x{:08x}", instruction);
+        if illegal {
+            assert_eq!(cpu.csrs.load(csr::MTVAL), instruction);
+            assert_eq!(cpu.csrs.load(csr::MEPC), 0);
+            assert_eq!(cpu.mode, Machine);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_htif_exit() -> anyhow::Result<()> {
+    use emulator::Emulator;
+    use mmio_device::htif::Htif;
+    use serial::StdoutBackend;
+
+    let ram_data = vec![
+        0x93, 0x00, 0x00, 0x10, // ADDI x1, x0, 0x100 (tohost)
+        0x13, 0x01, 0x50, 0x00, // ADDI x2, x0, 5
+        0x23, 0xa0, 0x20, 0x00, // SW x2, 0(x1)
+        0x23, 0xa2, 0x00, 0x00, // SW x0, 4(x1)
+        0x00, 0x00, 0x00, 0x00, // unreachable
+    ];
+
+    let mut emulator = Emulator::new(ram_data);
+    emulator.register_mmio_device(Box::new(Htif::new(0x100, 0x140, Box::new(StdoutBackend))));
+    emulator.reset();
+    let (exit_code, _) = emulator.run(false)?;
+
+    assert_eq!(exit_code, 2); // test #2 failed
+
+    Ok(())
+}
+
+#[test]
+fn test_htif_bad_syscall() {
+    use mmio_device::{htif::Htif, MmioDeviceInterface};
+    use ram::Ram;
+    use serial::StdoutBackend;
+
+    let mut ram = Ram::new(0x100);
+    let mut htif = Htif::new(0x100, 0x140, Box::new(StdoutBackend));
+
+    // write(1, 0xfffffff0, 0x20) at magic_mem 0x40, the buffer wraps around
+    let magic_mem = [64u32, 0, 1, 0, 0xffff_fff0, 0, 0x20, 0];
+    for (i, value) in magic_mem.into_iter().enumerate() {
+        ram.store32(0x40 + i as u32 * 4, value);
+    }
+    htif.store32(0, 0x40);
+    htif.store32(4, 0);
+    htif.tick(&mut ram);
+    assert_eq!(ram.load32(0x40) as i32, -14); // EFAULT
+    assert_eq!(htif.load32(0x40), 1); // fromhost
+
+    // magic_mem outside of ram is ignored
+    htif.store32(0x40, 0);
+    htif.store32(0, 0xfff0);
+    htif.store32(4, 0);
+    htif.tick(&mut ram);
+    assert_eq!(htif.load32(0x40), 0);
+    assert!(htif.poll_request().is_none());
+}
+
+#[test]
+fn test_virtio_blk_read() -> anyhow::Result<()> {
+    use csr::{MIP, MIP_MEIP};
+    use emulator::Emulator;
+    use mmio_device::{
+        plic::Plic,
+        virtio::{
+            blk::{DiskImage, DiskMode, VirtioBlk},
+            VirtioMmio,
+        },
+    };
+
+    let mut ram_data = vec![
+        0xb7, 0x10, 0x00, 0x00, // LUI x1, 0x1 (virtio-mmio)
+        0x13, 0x01, 0x40, 0x00, // ADDI x2, x0, 4
+        0x23, 0xac, 0x20, 0x02, // SW x2, 0x38(x1) (QueueNum)
+        0x13, 0x01, 0x00, 0x40, // ADDI x2, x0, 0x400
+        0x23, 0xa0, 0x20, 0x08, // SW x2, 0x80(x1) (QueueDescLow)
+        0x13, 0x01, 0x00, 0x48, // ADDI x2, x0, 0x480
+        0x23, 0xa8, 0x20, 0x08, // SW x2, 0x90(x1) (QueueDriverLow)
+        0x13, 0x01, 0x00, 0x4c, // ADDI x2, x0, 0x4c0
+        0x23, 0xa0, 0x20, 0x0a, // SW x2, 0xa0(x1) (QueueDeviceLow)
+        0x13, 0x01, 0x10, 0x00, // ADDI x2, x0, 1
+        0x23, 0xa2, 0x20, 0x04, // SW x2, 0x44(x1) (QueueReady)
+        0x13, 0x01, 0xf0, 0x00, // ADDI x2, x0, 0xf
+        0x23, 0xa8, 0x20, 0x06, // SW x2, 0x70(x1) (Status)
+        0x37, 0x04, 0x00, 0x0c, // LUI x8, 0xc000 (plic)
+        0x13, 0x01, 0x10, 0x00, // ADDI x2, x0, 1
+        0x23, 0x22, 0x24, 0x00, // SW x2, 4(x8) (priority of irq 1)
+        0x13, 0x01, 0x20, 0x00, // ADDI x2, x0, 2
+        0xb7, 0x24, 0x00, 0x0c, // LUI x9, 0xc002
+        0x23, 0xa0, 0x24, 0x00, // SW x2, 0(x9) (enable irq 1 for context 0)
+        0x23, 0xa8, 0x00, 0x04, // SW x0, 0x50(x1) (QueueNotify)
+        0x83, 0xa1, 0x00, 0x06, // LW x3, 0x60(x1) (InterruptStatus)
+        0x03, 0x42, 0x00, 0x58, // LBU x4, 0x580(x0) (data)
+        0x83, 0x42, 0x00, 0x78, // LBU x5, 0x780(x0) (status)
+        0x03, 0x53, 0x20, 0x4c, // LHU x6, 0x4c2(x0) (used idx)
+        0xb7, 0x23, 0x00, 0x00, // LUI x7, 0x2
+        0x67, 0x80, 0x03, 0x00, // JALR x0, 0(x7)
+    ];
+    ram_data.resize(0x800, 0);
+
+    // descriptor table: header -> data (512 bytes) -> status
+    let descs: [(u32, u32, u16, u16); 3] =
+        [(0x500, 16, 1, 1), (0x580, 512, 3, 2), (0x780, 1, 2, 0)];
+    for (i, (addr, len, flags, next)) in descs.iter().enumerate() {
+        let offset = 0x400 + i * 16;
+        ram_data[offset..offset + 4].copy_from_slice(&addr.to_le_bytes());
+        ram_data[offset + 8..offset + 12].copy_from_slice(&len.to_le_bytes());
+        ram_data[offset + 12..offset + 14].copy_from_slice(&flags.to_le_bytes());
+        ram_data[offset + 14..offset + 16].copy_from_slice(&next.to_le_bytes());
+    }
+    ram_data[0x482] = 1; // avail idx
+    ram_data[0x508] = 1; // VIRTIO_BLK_T_IN, sector 1
+    ram_data[0x780] = 0xff;
+
+    let disk_path = std::env::temp_dir().join("frisc_test_virtio_blk.img");
+    let mut disk_data = vec![0; 1024];
+    disk_data[512..].fill(0xab);
+    std::fs::write(&disk_path, disk_data)?;
+
+    let mut emulator = Emulator::new(ram_data);
+    emulator.register_mmio_device(Box::new(Plic::default()));
+    emulator.register_mmio_device(Box::new(VirtioMmio::new(
+        0x1000,
+        1,
+        Box::new(VirtioBlk::new(DiskImage::open(
+            &disk_path,
+            DiskMode::ReadOnly,
+        )?)),
+    )));
+    emulator.reset();
+    emulator.run(false)?;
+
+    assert_eq!(emulator.harts[0].x_regs[3].load(), 1); // used buffer notification
+    assert_eq!(emulator.harts[0].x_regs[4].load(), 0xab);
+    assert_eq!(emulator.harts[0].x_regs[5].load(), 0); // VIRTIO_BLK_S_OK
+    assert_eq!(emulator.harts[0].x_regs[6].load(), 1);
+    assert_ne!(emulator.harts[0].csrs.load(MIP) & MIP_MEIP, 0);
+
+    // the end of the request doesn't fit in a u64
+    let mut disk = DiskImage::open(&disk_path, DiskMode::ReadOnly)?;
+    assert!(disk.read(u64::MAX, &mut [0; 1024]).is_err());
+
+    std::fs::remove_file(disk_path)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_virtio_blk_copy_on_write() -> anyhow::Result<()> {
+    use mmio_device::virtio::blk::{DiskImage, DiskMode};
+
+    let disk_path = std::env::temp_dir().join("frisc_test_virtio_blk_cow.img");
+    std::fs::write(&disk_path, vec![0xab; 1024])?;
+
+    let mut disk = DiskImage::open(&disk_path, DiskMode::CopyOnWrite)?;
+    disk.write(1, &[0xcd; 512])?;
+    disk.flush()?;
+    let mut buf = [0; 1024];
+    disk.read(0, &mut buf)?;
+    assert!(buf[..512].iter().all(|b| *b == 0xab));
+    assert!(buf[512..].iter().all(|b| *b == 0xcd));
+
+    // the write only lives in the overlay
+    assert_eq!(std::fs::read(&disk_path)?, vec![0xab; 1024]);
+    let mut disk = DiskImage::open(&disk_path, DiskMode::ReadOnly)?;
+    assert!(disk.write(1, &[0xcd; 512]).is_err());
+
+    std::fs::remove_file(disk_path)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_virtio_queue_high_addr() {
+    use mmio_device::{
+        virtio::{rng::VirtioRng, VirtioMmio},
+        MmioDeviceInterface,
+    };
+    use ram::Ram;
+
+    let mut ram = Ram::new(0x1000);
+    let mut virtio = VirtioMmio::new(0x1000, 1, Box::new(VirtioRng::new_with_seed(0)));
+    virtio.store32(0x38, 1); // QueueNum
+    virtio.store32(0x80, 0x100); // QueueDescLow
+    virtio.store32(0x84, 1); // QueueDescHigh (above 4 GiB)
+    virtio.store32(0x90, 0x200); // QueueDriverLow
+    virtio.store32(0xa0, 0x300); // QueueDeviceLow
+    virtio.store32(0x44, 1); // QueueReady
+    virtio.store32(0x70, 0xf); // Status (DRIVER_OK)
+    ram.store16(0x202, 1); // avail idx at the truncated address
+    virtio.tick(&mut ram);
+
+    // DEVICE_NEEDS_RESET with a configuration change, the low memory is untouched
+    assert_eq!(virtio.load32(0x70), 0x4f);
+    assert_eq!(virtio.load32(0x60), 2);
+    assert_eq!(ram.load16(0x302), 0);
+
+    // the driver resets the device
+    virtio.store32(0x70, 0);
+    assert_eq!(virtio.load32(0x70), 0);
+}
+
+#[test]
+fn test_virtio_console() {
+    use mmio_device::virtio::{console::VirtioConsole, queue::Virtqueue, VirtioDevice};
+    use ram::Ram;
+    use serial::{SerialBackend, SharedBackend};
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+    #[derive(Debug, Default)]
+    struct TestBackend {
+        tx: Rc<RefCell<Vec<u8>>>,
+        rx: VecDeque<u8>,
+    }
+
+    impl SerialBackend for TestBackend {
+        fn write(&mut self, value: u8) {
+            self.tx.borrow_mut().push(value);
+        }
+
+        fn read(&mut self) -> Option<u8> {
+            self.rx.pop_front()
+        }
+    }
+
+    let mut ram = Ram::new(0x1000);
+    let mut queues = vec![Virtqueue::default(), Virtqueue::default()];
+    for (i, queue) in queues.iter_mut().enumerate() {
+        let base = 0x100 * i as u64;
+        queue.num = 1;
+        queue.ready = true;
+        queue.notified = true;
+        queue.desc_addr = base;
+        queue.driver_addr = base + 0x40;
+        queue.device_addr = base + 0x80;
+
+        ram.store32(base as u32, 0x800 + 0x10 * i as u32); // buffer addr
+        ram.store32(base as u32 + 8, 2); // len
+        ram.store16(base as u32 + 12, if i == 0 { 2 } else { 0 }); // receiveq is device-writable
+        ram.store16(base as u32 + 0x42, 1); // avail idx
+    }
+    ram.store8(0x810, b'h');
+    ram.store8(0x811, b'i');
+
+    let tx = Rc::new(RefCell::new(Vec::new()));
+    let backend = SharedBackend::new(Box::new(TestBackend {
+        tx: tx.clone(),
+        rx: VecDeque::from(b"ok".to_vec()),
+    }));
+    // the UART only writes, the input goes to the console
+    let mut uart = backend.output_only();
+    assert_eq!(uart.read(), None);
+    let mut console = VirtioConsole::new(Box::new(backend));
+
+    assert!(console.process_queues(&mut queues, &mut ram));
+    assert_eq!(ram.load8(0x800), b'o');
+    assert_eq!(ram.load8(0x801), b'k');
+    assert_eq!(ram.load16(0x82), 1); // receiveq used idx
+    assert_eq!(ram.load32(0x88), 2); // received len
+    assert_eq!(ram.load16(0x182), 1); // transmitq used idx
+    uart.write(b'!');
+    assert_eq!(*tx.borrow(), b"hi!");
+}
+
+#[test]
+fn test_virtio_rng_seed() {
+    use mmio_device::virtio::{queue::Virtqueue, rng::VirtioRng, VirtioDevice};
+    use ram::Ram;
+
+    let mut outputs = Vec::new();
+    for _ in 0..2 {
+        let mut ram = Ram::new(0x1000);
+        let mut queues = vec![Virtqueue::default()];
+        queues[0].num = 1;
+        queues[0].ready = true;
+        queues[0].notified = true;
+        queues[0].desc_addr = 0;
+        queues[0].driver_addr = 0x40;
+        queues[0].device_addr = 0x80;
+
+        ram.store32(0, 0x800); // buffer addr
+        ram.store32(8, 32); // len
+        ram.store16(12, 2); // device-writable
+        ram.store16(0x42, 1); // avail idx
+
+        let mut rng = VirtioRng::new_with_seed(42);
+        assert!(rng.process_queues(&mut queues, &mut ram));
+        assert_eq!(ram.load32(0x88), 32);
+        outputs.push(ram.data[0x800..0x820].to_vec());
+    }
+
+    assert_eq!(outputs[0], outputs[1]);
+    assert_ne!(outputs[0], vec![0; 32]);
+}
+
+#[test]
+fn test_virtio_net_pair() -> anyhow::Result<()> {
+    use mmio_device::virtio::{net::VirtioNet, queue::Virtqueue, VirtioDevice};
+    use net::{PairBackend, PcapBackend};
+    use ram::Ram;
+
+    // receiveq at 0x000 (writable buffer 0x800), transmitq at 0x100 (readable buffer 0x900)
+    let new_ram_and_queues = || {
+        let mut ram = Ram::new(0x1000);
+        let mut queues = vec![Virtqueue::default(), Virtqueue::default()];
+        for (i, queue) in queues.iter_mut().enumerate() {
+            let base = 0x100 * i as u32;
+            queue.num = 1;
+            queue.ready = true;
+            queue.desc_addr = base as u64;
+            queue.driver_addr = base as u64 + 0x40;
+            queue.device_addr = base as u64 + 0x80;
+
+            ram.store32(base, 0x800 + base);
+            ram.store32(base + 8, 0x100);
+            ram.store16(base + 12, if i == 0 { 2 } else { 0 });
+        }
+        (ram, queues)
+    };
+
+    let pcap_path = std::env::temp_dir().join("frisc_test_virtio_net.pcap");
+    let (backend_a, backend_b) = PairBackend::new_pair();
+    let mut net_a = VirtioNet::new([2, 0, 0, 0, 0, 1], Box::new(backend_a));
+    let mut net_b = VirtioNet::new(
+        [2, 0, 0, 0, 0, 2],
+        Box::new(PcapBackend::new(&pcap_path, Box::new(backend_b))?),
+    );
+    let (mut ram_a, mut queues_a) = new_ram_and_queues();
+    let (mut ram_b, mut queues_b) = new_ram_and_queues();
+
+    // a: transmit a 64 bytes frame (after the 12 bytes virtio_net_hdr)
+    ram_a.store32(0x108, 12 + 64);
+    ram_a.data[0x90c..0x94c].fill(0x5a);
+    ram_a.store16(0x142, 1);
+    queues_a[1].notified = true;
+    assert!(net_a.process_queues(&mut queues_a, &mut ram_a));
+
+    // b: receive it
+    ram_b.store16(0x42, 1);
+    assert!(net_b.process_queues(&mut queues_b, &mut ram_b));
+    assert_eq!(ram_b.load16(0x82), 1); // used idx
+    assert_eq!(ram_b.load32(0x88), 12 + 64); // used len
+    assert_eq!(ram_b.load16(0x80a), 1); // num_buffers
+    assert_eq!(ram_b.data[0x80c..0x84c], [0x5a; 64]);
+
+    // pcap header + record header + frame
+    assert_eq!(std::fs::metadata(&pcap_path)?.len(), 24 + 16 + 64);
+    std::fs::remove_file(pcap_path)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_virtio_9p_read() -> anyhow::Result<()> {
+    use mmio_device::virtio::{p9::Virtio9p, queue::Virtqueue, VirtioDevice};
+    use ram::Ram;
+
+    let share_dir = std::env::temp_dir().join("frisc_test_virtio_9p");
+    std::fs::create_dir_all(&share_dir)?;
+    std::fs::write(share_dir.join("input.txt"), b"hello 9p")?;
+
+    let mut p9 = Virtio9p::new(&share_dir, "frisc", true)?;
+    let mut ram = Ram::new(0x2000);
+    let mut queues = vec![Virtqueue::default()];
+    queues[0].num = 2;
+    queues[0].ready = true;
+    queues[0].desc_addr = 0;
+    queues[0].driver_addr = 0x40;
+    queues[0].device_addr = 0x80;
+
+    // T-message at 0x1000 -> R-message at 0x1800
+    ram.store32(0, 0x1000);
+    ram.store16(12, 1); // VIRTQ_DESC_F_NEXT
+    ram.store16(14, 1);
+    ram.store32(16, 0x1800);
+    ram.store32(24, 0x800);
+    ram.store16(28, 2); // VIRTQ_DESC_F_WRITE
+
+    let mut transact = |msg_type: u8, body: &[u8]| -> (u8, Vec<u8>) {
+        let mut msg = ((7 + body.len()) as u32).to_le_bytes().to_vec();
+        msg.push(msg_type);
+        msg.extend_from_slice(&1u16.to_le_bytes()); // tag
+        msg.extend_from_slice(body);
+
+        ram.data[0x1000..0x1000 + msg.len()].copy_from_slice(&msg);
+        ram.store32(8, msg.len() as u32);
+        ram.store16(0x42, ram.load16(0x42) + 1); // avail idx
+        queues[0].notified = true;
+        assert!(p9.process_queues(&mut queues, &mut ram));
+
+        let size = ram.load32(0x1800) as usize;
+        (ram.load8(0x1804), ram.data[0x1807..0x1800 + size].to_vec())
+    };
+
+    // an msize without room for the Rread header is rejected
+    let mut tversion = 4u32.to_le_bytes().to_vec();
+    tversion.extend_from_slice(&[8, 0]);
+    tversion.extend_from_slice(b"9P2000.L");
+    let (reply_type, body) = transact(100, &tversion);
+    assert_eq!(reply_type, 7); // Rlerror
+    assert_eq!(body, 22u32.to_le_bytes()); // EINVAL
+
+    tversion[..4].copy_from_slice(&0x2000u32.to_le_bytes());
+    assert_eq!(transact(100, &tversion).0, 101); // Rversion
+
+    let mut tattach = vec![0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]; // fid, afid (NOFID)
+    tattach.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0]); // uname, aname, n_uname
+    assert_eq!(transact(104, &tattach).0, 105); // Rattach
+
+    let mut twalk = vec![0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 9, 0]; // fid 0 -> newfid 1
+    twalk.extend_from_slice(b"input.txt");
+    assert_eq!(transact(110, &twalk).0, 111); // Rwalk
+
+    assert_eq!(transact(12, &[1, 0, 0, 0, 0, 0, 0, 0]).0, 13); // Rlopen (O_RDONLY)
+
+    let tread = [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x40, 0, 0, 0];
+    let (reply_type, body) = transact(116, &tread);
+    assert_eq!(reply_type, 117); // Rread
+    assert_eq!(&body[4..], b"hello 9p");
+
+    // read-only by default
+    let mut tmkdir = vec![0, 0, 0, 0, 3, 0];
+    tmkdir.extend_from_slice(b"out");
+    tmkdir.extend_from_slice(&[0xed, 0x01, 0, 0, 0, 0, 0, 0]); // mode, gid
+    let (reply_type, body) = transact(72, &tmkdir);
+    assert_eq!(reply_type, 7); // Rlerror
+    assert_eq!(body, 30u32.to_le_bytes()); // EROFS
+
+    std::fs::remove_dir_all(share_dir)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_framebuffer() -> anyhow::Result<()> {
+    use emulator::Emulator;
+    use mmio_device::framebuffer::{Framebuffer, PixelFormat};
+
+    let ram_data = vec![
+        0xb7, 0x00, 0x00, 0x28, // LUI x1, 0x28000
+        0x37, 0x81, 0xff, 0x00, // LUI x2, 0xff8
+        0x23, 0xa2, 0x20, 0x00, // SW x2, 4(x1)
+        0x23, 0x90, 0x20, 0x00, // SH x2, 0(x1)
+    ];
+
+    let framebuffer = Framebuffer::new_with_mode(2, 1, PixelFormat::X8r8g8b8);
+    let screen = framebuffer.screen();
+    let mut emulator = Emulator::new(ram_data);
+    emulator.register_mmio_device(Box::new(framebuffer));
+    emulator.reset();
+    emulator.run(false)?;
+
+    assert_eq!(screen.stride(), 8);
+    assert_eq!(screen.to_rgb8(), [0x00, 0x80, 0x00, 0xff, 0x80, 0x00]);
+
+    let png_path = std::env::temp_dir().join(format!("frisc-fb-{}.png", std::process::id()));
+    screen.save_png(&png_path)?;
+    let png = std::fs::read(&png_path)?;
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    std::fs::remove_file(png_path)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_goldfish_rtc_virtual_clock() {
+    use mmio_device::{
+        goldfish_rtc::{GoldfishRtc, RtcClock, DEFAULT_IRQ},
+        MmioDeviceInterface,
+    };
+    use ram::Ram;
+
+    let mut ram = Ram::new(0);
+    let mut rtc = GoldfishRtc::new_with_clock(RtcClock::Virtual {
+        start_ns: 0x1_0000_0000,
+        ns_per_step: 10,
+    });
+
+    rtc.tick(&mut ram);
+    assert_eq!(rtc.load32(0x00), 10); // TIME_LOW
+    assert_eq!(rtc.load32(0x04), 1); // TIME_HIGH
+
+    // alarm 30 ns later
+    rtc.store32(0x10, 1); // IRQ_ENABLED
+    rtc.store32(0x0c, 1); // ALARM_HIGH
+    rtc.store32(0x08, 40); // ALARM_LOW
+    assert_eq!(rtc.load32(0x18), 1); // ALARM_STATUS
+
+    rtc.tick(&mut ram);
+    rtc.tick(&mut ram);
+    assert_eq!(rtc.irq(), None);
+    rtc.tick(&mut ram);
+    assert_eq!(rtc.irq(), Some(DEFAULT_IRQ));
+    assert_eq!(rtc.load32(0x18), 0);
+
+    rtc.store32(0x1c, 1); // CLEAR_INTERRUPT
+    assert_eq!(rtc.irq(), None);
+
+    // set the time
+    rtc.store32(0x04, 0);
+    rtc.store32(0x00, 1000);
+    rtc.tick(&mut ram);
+    assert_eq!(rtc.load32(0x00), 1010);
+}
+
+#[test]
+fn test_gpio_output_events() -> anyhow::Result<()> {
+    use emulator::Emulator;
+    use mmio_device::{gpio::Gpio, RequestFromDevice};
+
+    let ram_data = vec![
+        0xb7, 0x00, 0x06, 0x10, // LUI x1, 0x10060
+        0x13, 0x01, 0x10, 0x00, // ADDI x2, x0, 1
+        0x23, 0xa4, 0x20, 0x00, // SW x2, 8(x1) (OUTPUT_EN)
+        0x23, 0xa6, 0x20, 0x00, // SW x2, 12(x1) (OUTPUT_VAL)
+        0x23, 0xa6, 0x00, 0x00, // SW x0, 12(x1)
+        0x13, 0x00, 0x00, 0x00, // NOP
+    ];
+
+    let mut emulator = Emulator::new(ram_data);
+    emulator.register_mmio_device(Box::new(Gpio::default()));
+    emulator.reset();
+    let (_, log) = emulator.run(false)?;
+
+    let events: Vec<(usize, u32, bool)> = log
+        .dev_reqs
+        .iter()
+        .filter_map(|dev_req| match dev_req.req {
+            RequestFromDevice::GpioOutput { pin, level } => Some((dev_req.step, pin, level)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(events, [(4, 0, true), (5, 0, false)]);
+
+    Ok(())
+}
+
+#[test]
+fn test_gpio_input_schedule() -> anyhow::Result<()> {
+    use mmio_device::{
+        gpio::{Gpio, GpioSchedule, DEFAULT_IRQ},
+        MmioDeviceInterface,
+    };
+    use ram::Ram;
+
+    let mut ram = Ram::new(0);
+    let mut gpio = Gpio::new_with_schedule(GpioSchedule::parse("# step pin level\n2 3 1\n")?);
+    let inputs = gpio.inputs();
+    gpio.store32(0x04, 1 << 3 | 1 << 4); // INPUT_EN
+    gpio.store32(0x18, 1 << 3); // RISE_IE
+
+    gpio.tick(&mut ram);
+    gpio.tick(&mut ram);
+    assert_eq!(gpio.load32(0x00), 0); // INPUT_VAL
+    assert_eq!(gpio.irq(), None);
+
+    gpio.tick(&mut ram);
+    assert_eq!(gpio.load32(0x00), 1 << 3);
+    assert_eq!(gpio.irq(), Some(DEFAULT_IRQ));
+
+    gpio.store32(0x1c, 1 << 3); // RISE_IP (write-1-to-clear)
+    assert_eq!(gpio.irq(), None);
+
+    // driven from the host
+    inputs.set(4, true);
+    gpio.tick(&mut ram);
+    assert_eq!(gpio.load32(0x00), 1 << 3 | 1 << 4);
+
+    assert!(GpioSchedule::parse("1 32 1").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_spi_sd_card() -> anyhow::Result<()> {
+    use mmio_device::{
+        spi::{sd_card::SdCard, Spi},
+        virtio::blk::{DiskImage, DiskMode},
+        MmioDeviceInterface,
+    };
+
+    let image_path = std::env::temp_dir().join(format!("frisc-sd-{}.img", std::process::id()));
+    let mut image = vec![0u8; 4 * 512];
+    image[512..1024].fill(0xa5);
+    std::fs::write(&image_path, &image)?;
+
+    let mut spi = Spi::default();
+    spi.attach(0, Box::new(SdCard::new(DiskImage::open(&image_path, DiskMode::ReadWrite)?)));
+    spi.store32(0x18, 2); // CSMODE = HOLD
+
+    // sends the bytes and returns what was received
+    let transfer = |spi: &mut Spi, tx: &[u8]| -> Vec<u8> {
+        tx.iter()
+            .map(|&value| {
+                spi.store32(0x48, value as u32); // TXDATA
+                spi.load32(0x4c) as u8 // RXDATA
+            })
+            .collect()
+    };
+    let command = |spi: &mut Spi, cmd: u8, arg: u32, response_len: usize| -> Vec<u8> {
+        let mut frame = vec![0x40 | cmd];
+        frame.extend(arg.to_be_bytes());
+        frame.push(0x01);
+        transfer(spi, &frame);
+        transfer(spi, &vec![0xff; response_len + 1])[1..].to_vec()
+    };
+
+    assert_eq!(command(&mut spi, 0, 0, 1), [0x01]); // GO_IDLE_STATE
+    assert_eq!(command(&mut spi, 8, 0x1aa, 5), [0x01, 0, 0, 0x01, 0xaa]); // SEND_IF_COND
+    assert_eq!(command(&mut spi, 17, 1, 1), [0x05]); // illegal while idle
+    assert_eq!(command(&mut spi, 55, 0, 1), [0x01]); // APP_CMD
+    assert_eq!(command(&mut spi, 41, 1 << 30, 1), [0x00]); // SD_SEND_OP_COND
+    assert_eq!(command(&mut spi, 58, 0, 5), [0x00, 0xc0, 0xff, 0x80, 0x00]); // READ_OCR
+
+    // READ_SINGLE_BLOCK
+    assert_eq!(command(&mut spi, 17, 1, 1), [0x00]);
+    let data = transfer(&mut spi, &[0xff; 2 + 512 + 2]);
+    assert_eq!(data[1], 0xfe);
+    assert_eq!(data[2..514], [0xa5; 512]);
+
+    // WRITE_BLOCK
+    assert_eq!(command(&mut spi, 24, 2, 1), [0x00]);
+    transfer(&mut spi, &[0xff, 0xfe]);
+    transfer(&mut spi, &[0x3c; 512]);
+    transfer(&mut spi, &[0xff, 0xff]);
+    assert_eq!(transfer(&mut spi, &[0xff])[0] & 0x1f, 0x05);
+
+    assert_eq!(command(&mut spi, 17, 4, 1), [0x20]); // out of range
+
+    spi.store32(0x18, 0); // CSMODE = AUTO
+    let image = std::fs::read(&image_path)?;
+    assert_eq!(image[1024..1536], [0x3c; 512]);
+    std::fs::remove_file(image_path)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_i2c_eeprom_lm75() {
+    use mmio_device::{
+        i2c::{eeprom::Eeprom, lm75::Lm75, I2c, I2cEvent},
+        MmioDeviceInterface, RequestFromDevice,
+    };
+
+    let mut i2c = I2c::default();
+    i2c.attach(Box::new(Eeprom::new(0x50, 256)));
+    let lm75 = Lm75::new(0x48, 25_500);
+    let thermometer = lm75.thermometer();
+    i2c.attach(Box::new(lm75));
+    i2c.store8(0x08, 0x80); // CTR = EN
+
+    // returns RxACK (true = acknowledged)
+    let write = |i2c: &mut I2c, value: u8, cr: u8| -> bool {
+        i2c.store8(0x0c, value); // TXR
+        i2c.store8(0x10, cr); // CR
+        i2c.load8(0x10) & 0x80 == 0 // SR
+    };
+    let read = |i2c: &mut I2c, cr: u8| -> u8 {
+        i2c.store8(0x10, cr);
+        i2c.load8(0x0c) // RXR
+    };
+
+    // EEPROM: write 2 bytes at 0x10, then read them back with a repeated start
+    assert!(write(&mut i2c, 0x50 << 1, 0x90)); // STA | WR
+    assert!(write(&mut i2c, 0x10, 0x10));
+    assert!(write(&mut i2c, 0xca, 0x10));
+    assert!(write(&mut i2c, 0xfe, 0x50)); // STO | WR
+    assert!(write(&mut i2c, 0x50 << 1, 0x90));
+    assert!(write(&mut i2c, 0x10, 0x10));
+    assert!(write(&mut i2c, 0x50 << 1 | 1, 0x90));
+    assert_eq!(read(&mut i2c, 0x20), 0xca); // RD
+    assert_eq!(read(&mut i2c, 0x68), 0xfe); // STO | RD | NACK
+
+    // LM75: temperature register
+    thermometer.set(-1_000);
+    assert!(write(&mut i2c, 0x48 << 1 | 1, 0x90));
+    assert_eq!(read(&mut i2c, 0x20), 0xff);
+    assert_eq!(read(&mut i2c, 0x68), 0x00);
+
+    // nobody at 0x20
+    assert!(!write(&mut i2c, 0x20 << 1, 0xd0)); // STA | STO | WR
+
+    let mut events = Vec::new();
+    while let Some(RequestFromDevice::I2c(event)) = i2c.poll_request() {
+        events.push(event);
+    }
+    assert_eq!(events.len(), 17);
+    assert!(matches!(
+        events[..3],
+        [
+            I2cEvent::Start { address: 0x50, read: false, ack: true },
+            I2cEvent::Write { value: 0x10, ack: true },
+            I2cEvent::Write { value: 0xca, ack: true },
+        ]
+    ));
+    assert!(matches!(
+        events[14..],
+        [
+            I2cEvent::Stop,
+            I2cEvent::Start { address: 0x20, read: false, ack: false },
+            I2cEvent::Stop,
+        ]
+    ));
+}
+
+#[test]
+fn test_dma() -> anyhow::Result<()> {
+    use emulator::Emulator;
+    use mmio_device::{dma::Dma, MmioDeviceInterface};
+
+    // JAL over the data at 0x04 - 0x40, then 4 NOPs
+    let mut ram_data = vec![0u8; 0x50];
+    ram_data[..4].copy_from_slice(&[0x6f, 0x00, 0x00, 0x04]); // JAL x0, 0x40
+    for i in 0..4 {
+        ram_data[0x40 + i * 4..0x44 + i * 4].copy_from_slice(&[0x13, 0x00, 0x00, 0x00]);
+    }
+    for (i, byte) in ram_data[0x04..0x14].iter_mut().enumerate() {
+        *byte = i as u8 + 1;
+    }
+
+    let mut dma = Dma::default();
+    // channel 0: 16 bytes from 0x04 to 0x20 in 8 bytes transactions
+    dma.store32(0x000, 1); // CLAIM
+    dma.store32(0x004, 3 << 24 | 3 << 28); // NEXT_CONFIG (wsize, rsize)
+    dma.store32(0x008, 16); // NEXT_BYTES
+    dma.store32(0x010, 0x20); // NEXT_DEST
+    dma.store32(0x018, 0x04); // NEXT_SOURCE
+    dma.store32(0x000, 1 << 14 | 3); // DONE_IE | RUN | CLAIM
+    // channel 1: unmapped destination
+    dma.store32(0x1000, 1);
+    dma.store32(0x1008, 4);
+    dma.store32(0x1010, 0xdead_0000);
+    dma.store32(0x1018, 0x04);
+    dma.store32(0x1000, 3);
+    // channel 2: source above 4 GiB (not truncated to 0x04)
+    dma.store32(0x2000, 1);
+    dma.store32(0x2008, 4);
+    dma.store32(0x2010, 0x30);
+    dma.store32(0x2018, 0x04);
+    dma.store32(0x201c, 1);
+    dma.store32(0x2000, 3);
+
+    let mut emulator = Emulator::new(ram_data);
+    emulator.register_mmio_device(Box::new(dma));
+    emulator.reset();
+    let (_, log) = emulator.run(false)?;
+
+    assert_eq!(emulator.ram.slice(0x20, 16), emulator.ram.slice(0x04, 16));
+    assert_eq!(log.dev_writes.len(), 2);
+    assert_eq!(log.dev_writes[0].step, 0);
+    assert_eq!(log.dev_writes[1].step, 1);
+    assert_eq!(log.dev_writes[1].ram_writes.len(), 8);
+    assert_eq!(log.dev_writes[1].ram_writes[0].addr, 0x28);
+
+    let dma = &mut emulator.mmio_devices[0];
+    assert_eq!(dma.load32(0x000), 1 << 30 | 1 << 14 | 1); // DONE
+    assert_eq!(dma.load32(0x108), 0); // EXEC_BYTES
+    assert_eq!(dma.load32(0x1000), 1 << 31 | 1); // ERROR
+    assert_eq!(dma.load32(0x2000), 1 << 31 | 1);
+    assert_eq!(dma.load32(0x2108), 4);
+    assert!(dma.irq().is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_watchdog_exit() -> anyhow::Result<()> {
+    use emulator::Emulator;
+    use mmio_device::{
+        watchdog::{Watchdog, WatchdogAction},
+        RequestFromDevice,
+    };
+
+    // JAL x0, 0 (hang)
+    let ram_data = vec![0x6f, 0x00, 0x00, 0x00];
+
+    let mut watchdog = Watchdog::new_with_action(WatchdogAction::Exit(0xdead));
+    watchdog.arm(100);
+    let mut emulator = Emulator::new(ram_data);
+    emulator.max_steps = Some(1000);
+    emulator.register_mmio_device(Box::new(watchdog));
+    emulator.reset();
+    let (exit_code, log) = emulator.run(false)?;
+
+    assert_eq!(exit_code, 0xdead);
+    assert_eq!(emulator.harts[0].step, 99);
+    assert!(matches!(
+        log.dev_reqs[..],
+        [
+            step_log::DeviceRequest {
+                step: 99,
+                req: RequestFromDevice::WatchdogExpired,
+            },
+            step_log::DeviceRequest {
+                req: RequestFromDevice::Exit(0xdead),
+                ..
+            },
+        ]
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_watchdog_feed() {
+    use mmio_device::{watchdog::Watchdog, MmioDeviceInterface};
+    use ram::Ram;
+
+    let mut ram = Ram::new(0);
+    let mut watchdog = Watchdog::default();
+
+    // locked
+    watchdog.store32(0x00, 1 << 12);
+    assert_eq!(watchdog.load32(0x00), 0);
+
+    watchdog.store32(0x1c, 0x51f15e); // WDOGKEY
+    watchdog.store32(0x20, 2); // WDOGCMP0
+    watchdog.store32(0x1c, 0x51f15e);
+    watchdog.store32(0x00, 1 << 12 | 1); // WDOGCFG = ENALWAYS, scale 1
+
+    for _ in 0..3 {
+        watchdog.tick(&mut ram);
+    }
+    assert_eq!(watchdog.load32(0x10), 1); // WDOGS
+
+    watchdog.store32(0x1c, 0x51f15e);
+    watchdog.store32(0x18, 0xd09f00d); // WDOGFEED
+    assert_eq!(watchdog.load32(0x08), 0);
+
+    for _ in 0..4 {
+        watchdog.tick(&mut ram);
+    }
+    assert!(watchdog.irq().is_some()); // IP0 without RSTEN
+    assert!(watchdog.poll_request().is_none());
+}
+
+#[test]
+fn test_fdt() -> anyhow::Result<()> {
+    use emulator::Emulator;
+    use mmio_device::{
+        i2c::{eeprom::Eeprom, I2c},
+        plic::Plic,
+        sifive_test::SifiveTest,
+        simple_uart::SimpleUart,
+    };
+    use serial::StdoutBackend;
+
+    let mut i2c = I2c::default();
+    i2c.attach(Box::new(Eeprom::new(0x50, 256)));
+    let mut emulator = Emulator::new_with_ram_base_addr(0x8000_0000, vec![0; 0x2000]);
+    emulator.bootargs = Some(String::from("console=ttyS0"));
+    emulator.register_mmio_device(Box::new(Plic::default()));
+    emulator.register_mmio_device(Box::new(SimpleUart::new_with_backend(Box::new(
+        StdoutBackend,
+    ))));
+    emulator.register_mmio_device(Box::new(SifiveTest::default()));
+    emulator.register_mmio_device(Box::new(i2c));
+    emulator.reset();
+
+    let addr = emulator.load_fdt()?;
+    let dtb = emulator.fdt().to_dtb()?;
+    assert_eq!(addr % 8, 0);
+    assert!(addr as usize + dtb.len() <= 0x8000_2000);
+    assert_eq!(emulator.harts[0].x_regs[10].load(), 0);
+    assert_eq!(emulator.harts[0].x_regs[11].load(), addr);
+    assert_eq!(emulator.ram.load32(addr).swap_bytes(), 0xd00dfeed); // magic
+    assert_eq!(emulator.ram.load32(addr + 4).swap_bytes(), dtb.len() as u32); // totalsize
+    assert_eq!(emulator.ram.slice(addr, dtb.len()), Some(&dtb[..]));
+
+    let dts = emulator.fdt().to_dts();
+    for s in [
+        "\tchosen {\n\t\tbootargs = \"console=ttyS0\";\n\t\tstdout-path = \"/soc/serial@3f8\";",
+        "\t\triscv,isa = \"rv32ima\";",
+        "\t\t\tcpu0_intc: interrupt-controller {",
+        "\tmemory@80000000 {\n\t\tdevice_type = \"memory\";\n\t\treg = <0x80000000 0x2000>;",
+        "\t\tinterrupt-parent = <&plic>;",
+        "\t\tinterrupts-extended = <&cpu0_intc 0xb &cpu0_intc 0x9>;",
+        "\t\tregmap = <&test>;",
+        "\t\t\teeprom@50 {\n\t\t\t\tcompatible = \"atmel,24c02\";",
+    ] {
+        assert!(dts.contains(s), "{}", s);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_m_extension() -> anyhow::Result<()> {
+    use emulator::Emulator;
+
+    let ram_data = vec![
+        0x93, 0x00, 0x90, 0xff, // ADDI x1, x0, -7
+        0x13, 0x01, 0x30, 0x00, // ADDI x2, x0, 3
+        0xb3, 0x81, 0x20, 0x02, // MUL x3, x1, x2
+        0x33, 0x92, 0x20, 0x02, // MULH x4, x1, x2
+        0xb3, 0xb2, 0x20, 0x02, // MULHU x5, x1, x2
+        0x33, 0xc3, 0x20, 0x02, // DIV x6, x1, x2
+        0xb3, 0xe3, 0x20, 0x02, // REM x7, x1, x2
+        0x33, 0xd4, 0x00, 0x02, // DIVU x8, x1, x0
+        0xb3, 0xe4, 0x00, 0x02, // REM x9, x1, x0
+        0x37, 0x05, 0x00, 0x80, // LUI x10, 0x80000
+        0x93, 0x05, 0xf0, 0xff, // ADDI x11, x0, -1
+        0x33, 0x46, 0xb5, 0x02, // DIV x12, x10, x11
+        0xb3, 0x66, 0xb5, 0x02, // REM x13, x10, x11
+    ];
+
+    let mut emulator = Emulator::new(ram_data);
+    emulator.reset();
+    emulator.run(false)?;
+
+    let x = |i: usize| emulator.harts[0].x_regs[i].load();
+    assert_eq!(x(3) as i32, -21);
+    assert_eq!(x(4) as i32, -1);
+    assert_eq!(x(5), 2);
+    assert_eq!(x(6) as i32, -2);
+    assert_eq!(x(7) as i32, -1);
+    assert_eq!(x(8), u32::MAX); // division by zero
+    assert_eq!(x(9) as i32, -7);
+    assert_eq!(x(12), 0x8000_0000); // overflow
+    assert_eq!(x(13), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_lr_sc_amo() -> anyhow::Result<()> {
+    use emulator::Emulator;
+
+    let ram_data = vec![
+        0x93, 0x00, 0x50, 0x00, // ADDI x1, x0, 5
+        0x23, 0x20, 0x10, 0x00, // SW x1, 0(x0)
+        0x2f, 0x21, 0x00, 0x10, // LR.W x2, (x0)
+        0x93, 0x01, 0x90, 0x00, // ADDI x3, x0, 9
+        0x2f, 0x22, 0x30, 0x18, // SC.W x4, x3, (x0)
+        0xaf, 0x22, 0x30, 0x18, // SC.W x5, x3, (x0)
+        0x2f, 0x23, 0x10, 0x00, // AMOADD.W x6, x1, (x0)
+        0x93, 0x03, 0xf0, 0xff, // ADDI x7, x0, -1
+        0x2f, 0x24, 0x70, 0x80, // AMOMIN.W x8, x7, (x0)
+        0xaf, 0x24, 0x10, 0xe0, // AMOMAXU.W x9, x1, (x0)
+        0x2f, 0x25, 0x00, 0x08, // AMOSWAP.W x10, x0, (x0)
+        0x83, 0x25, 0x00, 0x00, // LW x11, 0(x0)
+    ];
+
+    let mut emulator = Emulator::new(ram_data);
+    emulator.reset();
+    emulator.run(false)?;
+
+    let x = |i: usize| emulator.harts[0].x_regs[i].load();
+    assert_eq!(x(2), 5);
+    assert_eq!(x(4), 0); // reserved
+    assert_eq!(x(5), 1); // the reservation was used
+    assert_eq!(x(6), 9);
+    assert_eq!(x(8), 14);
+    assert_eq!(x(9), u32::MAX);
+    assert_eq!(x(10), u32::MAX);
+    assert_eq!(x(11), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_sv32_page_fault_sbi_shutdown() -> anyhow::Result<()> {
+    use emulator::Emulator;
+    use mmio_device::RequestFromDevice;
+    use sbi::Sbi;
+    use serial::StdoutBackend;
+
+    let mut ram_data = vec![
+        0xb7, 0x02, 0x00, 0x80, // LUI x5, 0x80000
+        0x93, 0x82, 0x12, 0x00, // ADDI x5, x5, 1
+        0x73, 0x90, 0x02, 0x18, // CSRRW x0, satp, x5 (root table at 0x1000)
+        0x13, 0x03, 0x00, 0x02, // ADDI x6, x0, 0x20
+        0x73, 0x10, 0x53, 0x10, // CSRRW x0, stvec, x6
+        0xb7, 0x03, 0x00, 0x80, // LUI x7, 0x80000
+        0x03, 0xa4, 0x03, 0x00, // LW x8, 0(x7)
+        0x23, 0xa0, 0x83, 0x00, // SW x8, 0(x7) (read-only page)
+        0x73, 0x29, 0x20, 0x14, // CSRRS x18, scause, x0 (trap handler)
+        0xf3, 0x29, 0x30, 0x14, // CSRRS x19, stval, x0
+        0xb7, 0x58, 0x52, 0x53, // LUI x17, 0x53525
+        0x93, 0x88, 0x48, 0x35, // ADDI x17, x17, 0x354 (SRST)
+        0x13, 0x08, 0x00, 0x00, // ADDI x16, x0, 0
+        0x13, 0x05, 0x00, 0x00, // ADDI x10, x0, 0
+        0x93, 0x05, 0x00, 0x00, // ADDI x11, x0, 0
+        0x73, 0x00, 0x00, 0x00, // ECALL (system shutdown)
+    ];
+    ram_data.resize(0x3000, 0);
+    // identity mapped megapage (V R W X A D)
+    ram_data[0x1000..0x1004].copy_from_slice(&0xcfu32.to_le_bytes());
+    // 0x80000000 -> next level table at 0x2000 -> read-only page at 0 (V R)
+    ram_data[0x1800..0x1804].copy_from_slice(&0x801u32.to_le_bytes());
+    ram_data[0x2000..0x2004].copy_from_slice(&0x3u32.to_le_bytes());
+
+    let mut emulator = Emulator::new(ram_data);
+    emulator.max_steps = Some(100);
+    emulator.reset();
+    emulator.boot_supervisor(0, Sbi::new(Box::new(StdoutBackend)));
+    let (exit_code, log) = emulator.run(false)?;
+
+    assert_eq!(exit_code, 0);
+    assert!(matches!(
+        log.dev_reqs[..],
+        [step_log::DeviceRequest {
+            req: RequestFromDevice::Exit(0),
+            ..
+        }]
+    ));
+    assert_eq!(emulator.harts[0].x_regs[8].load(), 0x8000_02b7);
+    assert_eq!(
+        emulator.harts[0].x_regs[18].load(),
+        csr::CAUSE_STORE_PAGE_FAULT
+    );
+    assert_eq!(emulator.harts[0].x_regs[19].load(), 0x8000_0000);
+    assert_eq!(emulator.ram.load32(0x2000), 0x43); // accessed, not dirty
+
+    Ok(())
+}
+
+#[test]
+fn test_clint_timer_interrupt() -> anyhow::Result<()> {
+    use emulator::Emulator;
+    use mmio_device::clint::Clint;
+
+    let ram_data = vec![
+        0x93, 0x02, 0x80, 0x02, // ADDI x5, x0, 0x28
+        0x73, 0x90, 0x52, 0x30, // CSRRW x0, mtvec, x5
+        0x37, 0x43, 0x00, 0x02, // LUI x6, 0x2004
+        0x93, 0x03, 0xa0, 0x00, // ADDI x7, x0, 10
+        0x23, 0x20, 0x73, 0x00, // SW x7, 0(x6) (mtimecmp)
+        0x23, 0x22, 0x03, 0x00, // SW x0, 4(x6)
+        0x93, 0x03, 0x00, 0x08, // ADDI x7, x0, 0x80
+        0x73, 0x90, 0x43, 0x30, // CSRRW x0, mie, x7
+        0x73, 0x60, 0x04, 0x30, // CSRRSI x0, mstatus, 8
+        0x6f, 0x00, 0x00, 0x00, // JAL x0, 0
+        0x73, 0x24, 0x20, 0x34, // CSRRS x8, mcause, x0 (trap handler)
+    ];
+
+    let clint = Clint::default();
+    let mtime = clint.mtime();
+    let mut emulator = Emulator::new(ram_data);
+    emulator.max_steps = Some(100);
+    emulator.register_mmio_device(Box::new(clint));
+    emulator.mtime = Some(mtime.clone());
+    emulator.reset();
+    emulator.run(false)?;
+
+    assert_eq!(
+        emulator.harts[0].x_regs[8].load(),
+        1 << 31 | csr::IRQ_M_TIMER
+    );
+    assert_eq!(emulator.harts[0].csrs.load(csr::TIME), mtime.get() as u32);
+    assert!(mtime.get() >= 10);
+
+    Ok(())
+}
+
+#[test]
+fn test_user_mode() -> anyhow::Result<()> {
+    use emulator::Emulator;
+    use syscall::Syscalls;
+
+    let mut ram_data = vec![
+        0x93, 0x08, 0x60, 0x0d, // ADDI x17, x0, 214 (brk)
+        0x13, 0x05, 0x00, 0x00, // ADDI x10, x0, 0
+        0x73, 0x00, 0x00, 0x00, // ECALL
+        0x13, 0x09, 0x05, 0x00, // ADDI x18, x10, 0
+        0x83, 0x29, 0x01, 0x00, // LW x19, 0(x2) (argc)
+        0x93, 0x08, 0x00, 0x04, // ADDI x17, x0, 64 (write)
+        0x13, 0x05, 0x10, 0x00, // ADDI x10, x0, 1
+        0x93, 0x05, 0x00, 0x04, // ADDI x11, x0, 0x40
+        0x13, 0x06, 0x30, 0x00, // ADDI x12, x0, 3
+        0x73, 0x00, 0x00, 0x00, // ECALL
+        0x13, 0x0a, 0x05, 0x00, // ADDI x20, x10, 0
+        0x93, 0x08, 0xe0, 0x05, // ADDI x17, x0, 94 (exit_group)
+        0x13, 0x05, 0xa0, 0x02, // ADDI x10, x0, 42
+        0x73, 0x00, 0x00, 0x00, // ECALL
+    ];
+    ram_data.resize(0x40, 0);
+    ram_data.extend_from_slice(b"ok\n");
+    ram_data.resize(0x4000, 0);
+
+    let argv = [String::from("prog"), String::from("arg")];
+    let envp = [String::from("HOME=/")];
+    let mut emulator = Emulator::new(ram_data);
+    emulator.max_steps = Some(100);
+    emulator.reset();
+    emulator.boot_user(0, 0x4000, Syscalls::new(0x43, 0x3000), &argv, &envp, &[])?;
+    let sp = emulator.harts[0].x_regs[2].load();
+    let (exit_code, _) = emulator.run(false)?;
+
+    assert_eq!(exit_code, 42);
+    assert_eq!(emulator.harts[0].x_regs[18].load(), 0x1000); // page aligned end of the program
+    assert_eq!(emulator.harts[0].x_regs[19].load(), 2);
+    assert_eq!(emulator.harts[0].x_regs[20].load(), 3);
+
+    // argv[1], envp[0] and AT_PAGESZ
+    assert_eq!(sp % 16, 0);
+    let arg = emulator.ram.load32(sp + 8);
+    assert_eq!(emulator.ram.slice(arg, 4), Some(&b"arg\0"[..]));
+    let env = emulator.ram.load32(sp + 16);
+    assert_eq!(emulator.ram.slice(env, 7), Some(&b"HOME=/\0"[..]));
+    assert_eq!(emulator.ram.load32(sp + 24), syscall::AT_PAGESZ);
+    assert_eq!(emulator.ram.load32(sp + 28), 4096);
+
+    Ok(())
+}
+
+#[test]
+fn test_user_mode_device_overlap() {
+    use emulator::Emulator;
+    use mmio_device::sifive_test::SifiveTest;
+    use syscall::Syscalls;
+
+    // the heap of a program linked at 0 covers the test finisher
+    let mut emulator = Emulator::new(vec![0; 0x200000]);
+    emulator.register_mmio_device(Box::new(SifiveTest::default()));
+    emulator.reset();
+    let result = emulator.boot_user(0, 0x200000, Syscalls::new(0x1000, 0x1f0000), &[], &[], &[]);
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_user_mode_files() -> anyhow::Result<()> {
+    use cpu::Cpu;
+    use ram::Ram;
+    use syscall::Syscalls;
+
+    let path = std::env::temp_dir().join(format!("frisc-syscall-{}.txt", std::process::id()));
+    let mut cpu = Cpu::default();
+    let mut ram = Ram::new(0x1000);
+    let mut syscalls = Syscalls::new(0x800, 0x1000);
+    ram.slice_mut(0x100, path.to_str().unwrap().len())
+        .unwrap()
+        .copy_from_slice(path.to_str().unwrap().as_bytes());
+    ram.slice_mut(0x400, 5).unwrap().copy_from_slice(b"hello");
+
+    // returns a0
+    let mut call = |nr: u32, args: &[u32], ram: &mut Ram| {
+        cpu.x_regs[17].store(nr);
+        for (i, arg) in args.iter().enumerate() {
+            cpu.x_regs[10 + i].store(*arg);
+        }
+        assert!(syscalls.handle_call(&mut cpu, ram).is_none());
+        cpu.x_regs[10].load() as i32
+    };
+
+    // openat(AT_FDCWD, path, O_RDWR | O_CREAT | O_TRUNC, 0644)
+    let fd = call(56, &[-100i32 as u32, 0x100, 0o1102, 0o644], &mut ram);
+    assert_eq!(fd, 3);
+    assert_eq!(call(64, &[3, 0x400, 5], &mut ram), 5); // write
+    assert_eq!(call(62, &[3, 0, 1, 0x500, 0], &mut ram), 0); // llseek(SEEK_SET)
+    assert_eq!(ram.load32(0x500), 1);
+    assert_eq!(call(63, &[3, 0x600, 16], &mut ram), 4); // read
+    assert_eq!(ram.slice(0x600, 4), Some(&b"ello"[..]));
+    // statx(fd, "", AT_EMPTY_PATH, STATX_BASIC_STATS, buf)
+    assert_eq!(call(291, &[3, 0x0ff, 0x1000, 0x7ff, 0x700], &mut ram), 0);
+    assert_eq!(ram.load32(0x700 + 40), 5);
+    assert_eq!(call(57, &[3], &mut ram), 0); // close
+    assert_eq!(call(57, &[3], &mut ram), -9); // EBADF
+    assert_eq!(call(56, &[-100i32 as u32, 0x100, 0o300, 0], &mut ram), -17); // O_CREAT | O_EXCL
+    assert_eq!(call(0xffff, &[], &mut ram), -38); // ENOSYS
+
+    std::fs::remove_file(&path)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_semihosting() -> anyhow::Result<()> {
+    use cpu::Cpu;
+    use emulator::Emulator;
+    use semihosting::{HeapInfo, Semihosting};
+    use serial::SerialBackend;
+    use std::{cell::RefCell, rc::Rc};
+
+    #[derive(Debug, Default)]
+    struct TestBackend {
+        tx: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl SerialBackend for TestBackend {
+        fn write(&mut self, value: u8) {
+            self.tx.borrow_mut().push(value);
+        }
+
+        fn read(&mut self) -> Option<u8> {
+            None
+        }
+    }
+
+    let mut ram_data = vec![
+        0x13, 0x05, 0x40, 0x00, // ADDI x10, x0, 4 (SYS_WRITE0)
+        0x93, 0x05, 0x00, 0x20, // ADDI x11, x0, 0x200
+        0x13, 0x10, 0xf0, 0x01, // SLLI x0, x0, 0x1f
+        0x73, 0x00, 0x10, 0x00, // EBREAK
+        0x13, 0x50, 0x70, 0x40, // SRAI x0, x0, 7
+        0x13, 0x05, 0x10, 0x00, // ADDI x10, x0, 1 (SYS_OPEN)
+        0x93, 0x05, 0x00, 0x22, // ADDI x11, x0, 0x220
+        0x13, 0x10, 0xf0, 0x01, // SLLI x0, x0, 0x1f
+        0x73, 0x00, 0x10, 0x00, // EBREAK
+        0x13, 0x50, 0x70, 0x40, // SRAI x0, x0, 7
+        0x23, 0x28, 0xa0, 0x22, // SW x10, 0x230(x0)
+        0x23, 0x20, 0xa0, 0x24, // SW x10, 0x240(x0)
+        0x13, 0x05, 0x50, 0x00, // ADDI x10, x0, 5 (SYS_WRITE)
+        0x93, 0x05, 0x00, 0x23, // ADDI x11, x0, 0x230
+        0x13, 0x10, 0xf0, 0x01, // SLLI x0, x0, 0x1f
+        0x73, 0x00, 0x10, 0x00, // EBREAK
+        0x13, 0x50, 0x70, 0x40, // SRAI x0, x0, 7
+        0x93, 0x02, 0x05, 0x00, // ADDI x5, x10, 0
+        0x13, 0x05, 0x20, 0x00, // ADDI x10, x0, 2 (SYS_CLOSE)
+        0x93, 0x05, 0x00, 0x24, // ADDI x11, x0, 0x240
+        0x13, 0x10, 0xf0, 0x01, // SLLI x0, x0, 0x1f
+        0x73, 0x00, 0x10, 0x00, // EBREAK
+        0x13, 0x50, 0x70, 0x40, // SRAI x0, x0, 7
+        0x13, 0x05, 0x00, 0x02, // ADDI x10, x0, 32 (SYS_EXIT_EXTENDED)
+        0x93, 0x05, 0x00, 0x25, // ADDI x11, x0, 0x250
+        0x13, 0x10, 0xf0, 0x01, // SLLI x0, x0, 0x1f
+        0x73, 0x00, 0x10, 0x00, // EBREAK
+        0x13, 0x50, 0x70, 0x40, // SRAI x0, x0, 7
+    ];
+    ram_data.resize(0x1000, 0);
+    ram_data[0x200..0x204].copy_from_slice(b"hi\n\0");
+    ram_data[0x210..0x217].copy_from_slice(b"out.txt");
+    // open block [name, mode ("w"), length], write block [handle, data, length]
+    let blocks = [(0x220, [0x210, 4, 7]), (0x234, [0x200, 3, 0])];
+    for (addr, words) in blocks {
+        for (i, word) in words.into_iter().enumerate() {
+            let addr = addr + i * 4;
+            ram_data[addr..addr + 4].copy_from_slice(&(word as u32).to_le_bytes());
+        }
+    }
+    // exit block [ADP_Stopped_ApplicationExit, subcode]
+    ram_data[0x250..0x254].copy_from_slice(&0x20026u32.to_le_bytes());
+    ram_data[0x254..0x258].copy_from_slice(&3u32.to_le_bytes());
+
+    let sandbox = std::env::temp_dir().join(format!("frisc-semihosting-{}", std::process::id()));
+    std::fs::create_dir_all(&sandbox)?;
+    let tx = Rc::new(RefCell::new(Vec::new()));
+    let backend = TestBackend { tx: tx.clone() };
+
+    let mut emulator = Emulator::new(ram_data);
+    emulator.max_steps = Some(100);
+    emulator.reset();
+    emulator.enable_semihosting(Semihosting::new(
+        Box::new(backend),
+        "prog",
+        HeapInfo::default(),
+        Some(sandbox.clone()),
+    ));
+    let (exit_code, _) = emulator.run(false)?;
+
+    assert_eq!(exit_code, 3);
+    assert_eq!(emulator.harts[0].x_regs[5].load(), 0); // no bytes left
+    assert_eq!(*tx.borrow(), b"hi\n");
+    assert_eq!(std::fs::read(sandbox.join("out.txt"))?, b"hi\n");
+
+    // files outside of the sandbox can't be opened
+    let mut cpu = Cpu::default();
+    let mut semihosting = Semihosting::new(
+        Box::new(TestBackend::default()),
+        "",
+        HeapInfo::default(),
+        Some(sandbox.clone()),
+    );
+    let ram = &mut emulator.ram;
+    ram.store32(0x224, 0); // "r"
+    ram.slice_mut(0x210, 6).unwrap().copy_from_slice(b"../etc");
+    ram.store32(0x228, 6);
+    cpu.x_regs[10].store(1); // SYS_OPEN
+    cpu.x_regs[11].store(0x220);
+    assert!(semihosting.handle_call(&mut cpu, ram).is_none());
+    assert_eq!(cpu.x_regs[10].load(), u32::MAX);
+    cpu.x_regs[10].store(0x13); // SYS_ERRNO
+    assert!(semihosting.handle_call(&mut cpu, ram).is_none());
+    assert_eq!(cpu.x_regs[10].load(), 13); // EACCES
+
+    // nor through a symlink inside of it
+    #[cfg(unix)]
+    {
+        let outside = format!("frisc-semihosting-{}-outside", std::process::id());
+        std::os::unix::fs::symlink(std::env::temp_dir(), sandbox.join("tmp"))?;
+        let dangling = std::env::temp_dir().join(&outside);
+        std::os::unix::fs::symlink(dangling, sandbox.join("dangling"))?;
+        for name in [format!("tmp/{}", outside).as_bytes(), b"dangling"] {
+            ram.store32(0x220, 0x300);
+            ram.store32(0x224, 4); // "w"
+            ram.slice_mut(0x300, name.len())
+                .unwrap()
+                .copy_from_slice(name);
+            ram.store32(0x228, name.len() as u32);
+            cpu.x_regs[10].store(1); // SYS_OPEN
+            cpu.x_regs[11].store(0x220);
+            assert!(semihosting.handle_call(&mut cpu, ram).is_none());
+            assert_eq!(cpu.x_regs[10].load(), u32::MAX);
+            cpu.x_regs[10].store(0x13); // SYS_ERRNO
+            assert!(semihosting.handle_call(&mut cpu, ram).is_none());
+            assert_eq!(cpu.x_regs[10].load(), 13); // EACCES
+        }
+        assert!(!std::env::temp_dir().join(&outside).exists());
+    }
+
+    std::fs::remove_dir_all(&sandbox)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_multi_hart() -> anyhow::Result<()> {
+    use emulator::Emulator;
+    use mmio_device::{clint::Clint, MmioDeviceInterface};
+    use sbi::Sbi;
+    use serial::StdoutBackend;
+
+    let ram_data = vec![
+        0xf3, 0x22, 0x40, 0xf1, // CSRRS x5, mhartid, x0
+        0x93, 0x03, 0x80, 0x01, // ADDI x7, x0, 0x18
+        0xaf, 0xa4, 0x03, 0x10, // LR.W x9, (x7)
+        0x93, 0x84, 0x14, 0x00, // ADDI x9, x9, 1
+        0x2f, 0xa5, 0x93, 0x18, // SC.W x10, x9, (x7)
+        0x67, 0x00, 0x00, 0x10, // JALR x0, 0x100(x0)
+        0x00, 0x00, 0x00, 0x00, // counter
+    ];
+
+    // one step each, the sc.w of hart 0 breaks the reservation of hart 1
+    let mut emulator = Emulator::new_with_harts(0, ram_data.clone(), 2);
+    emulator.reset();
+    let (_, log) = emulator.run(false)?;
+
+    let harts: Vec<u32> = log.steps.iter().map(|step| step.hart).collect();
+    assert_eq!(harts, [0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0]);
+    for (hart, cpu) in emulator.harts.iter().enumerate() {
+        assert_eq!(cpu.x_regs[5].load(), hart as u32);
+        assert_eq!(cpu.x_regs[10].load(), hart as u32); // sc.w fails on hart 1
+    }
+    assert_eq!(emulator.ram.load32(0x18), 1);
+
+    // a quantum of 5 steps runs the whole sequence on each hart in turn
+    let mut emulator = Emulator::new_with_harts(0, ram_data, 2);
+    emulator.quantum = 5;
+    emulator.reset();
+    let (_, log) = emulator.run(false)?;
+
+    let harts: Vec<u32> = log.steps.iter().map(|step| step.hart).collect();
+    assert_eq!(harts, [0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 0]);
+    assert_eq!(emulator.harts[1].x_regs[10].load(), 0);
+    assert_eq!(emulator.ram.load32(0x18), 2);
+    let dts = emulator.fdt().to_dts();
+    assert!(dts.contains("cpu1_intc: interrupt-controller {"));
+
+    // msip of hart 1
+    let mut clint = Clint::new_with_harts(2);
+    clint.store32(4, 1);
+    assert_eq!(clint.pending_interrupts(0, 0), 0);
+    assert_eq!(clint.pending_interrupts(0, 1), csr::MIP_MSIP);
+
+    // the secondary harts of an S-mode kernel wait for the SBI hart_start call
+    let mut emulator = Emulator::new_with_harts(0, vec![0; 0x100], 2);
+    emulator.reset();
+    emulator.boot_supervisor(0, Sbi::new(Box::new(StdoutBackend)));
+    assert!(emulator.harts[1].stopped);
+
+    let mut sbi = emulator.sbi.take().unwrap();
+    // HSM hart_start(1, 0x40, 0x1234)
+    let args = [1, 0x40, 0x1234, 0, 0, 0, 0, 0x48534d];
+    for (i, value) in args.into_iter().enumerate() {
+        emulator.harts[0].x_regs[10 + i].store(value);
+    }
+    sbi.handle_call(&mut emulator.harts, 0, &mut emulator.ram);
+    assert_eq!(emulator.harts[0].x_regs[10].load(), 0);
+    let cpu = &emulator.harts[1];
+    assert!(!cpu.stopped);
+    assert_eq!(cpu.mode, cpu::PrivilegeMode::Supervisor);
+    assert_eq!(cpu.pc.load(), 0x40);
+    assert_eq!(cpu.x_regs[10].load(), 1);
+    assert_eq!(cpu.x_regs[11].load(), 0x1234);
+
+    Ok(())
+}
+
+#[test]
+fn test_parallel() -> anyhow::Result<()> {
+    use emulator::Emulator;
+
+    // every hart adds 500 to the counters at 0x100 (amoadd.w) and 0x104 (lr.w/sc.w),
+    // hart 0 leaves the ram once the other harts are done
+    let mut ram_data = vec![
+        0x73, 0x24, 0x40, 0xf1, // CSRRS x8, mhartid, x0
+        0x93, 0x02, 0x40, 0x1f, // ADDI x5, x0, 500
+        0x13, 0x03, 0x00, 0x10, // ADDI x6, x0, 0x100
+        0x93, 0x05, 0x40, 0x10, // ADDI x11, x0, 0x104
+        0x93, 0x03, 0x10, 0x00, // ADDI x7, x0, 1
+        0x2f, 0x20, 0x73, 0x00, // AMOADD.W x0, x7, (x6)
+        0x2f, 0xa5, 0x05, 0x10, // LR.W x10, (x11)
+        0x13, 0x05, 0x15, 0x00, // ADDI x10, x10, 1
+        0x2f, 0xa6, 0xa5, 0x18, // SC.W x12, x10, (x11)
+        0xe3, 0x1a, 0x06, 0xfe, // BNE x12, x0, -12
+        0x93, 0x82, 0xf2, 0xff, // ADDI x5, x5, -1
+        0xe3, 0x94, 0x02, 0xfe, // BNE x5, x0, -24
+        0x63, 0x10, 0x04, 0x00, // BNE x8, x0, 0
+        0x93, 0x04, 0x00, 0x7d, // ADDI x9, x0, 2000
+        0x03, 0xa5, 0x05, 0x00, // LW x10, 0(x11)
+        0xe3, 0x1e, 0x95, 0xfe, // BNE x10, x9, -4
+        0x67, 0x00, 0x00, 0x20, // JALR x0, 0x200(x0)
+    ];
+    ram_data.resize(0x108, 0);
+
+    for parallel in [false, true] {
+        let mut emulator = Emulator::new_with_harts(0, ram_data.clone(), 4);
+        emulator.parallel = parallel;
+        emulator.quantum = 100;
+        emulator.discard_steps = true;
+        emulator.reset();
+        emulator.run(false)?;
+
+        assert_eq!(emulator.ram.load32(0x100), 2000);
+        assert_eq!(emulator.ram.load32(0x104), 2000);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_parallel_sc_after_store() -> anyhow::Result<()> {
+    use cpu::Cpu;
+    use parallel::{Reservations, SharedRam};
+    use ram::{Memory, Ram};
+
+    // hart 0 runs lr.w/sc.w at 0, hart 1 stores 1 and then 0 again at 0x40 in between
+    let mut ram = Ram::new(0x100);
+    for (addr, instruction) in [
+        (0x00, 0x1005_a52f), // LR.W x10, (x11)
+        (0x04, 0x18a5_a62f), // SC.W x12, x10, (x11)
+        (0x20, 0x0055_a023), // SW x5, 0(x11)
+        (0x24, 0x0005_a023), // SW x0, 0(x11)
+    ] {
+        ram.store32(addr, instruction);
+    }
+    let reservations = Reservations::new(&ram);
+    let mut shared = SharedRam::new(&mut ram, &reservations).unwrap();
+
+    let mut harts = [Cpu::new_with_hart_id(0), Cpu::new_with_hart_id(1)];
+    harts[1].pc.store(0x20);
+    for cpu in &mut harts {
+        cpu.x_regs[5].store(1);
+        cpu.x_regs[11].store(0x40);
+    }
+    for hart in [0, 1, 1, 0] {
+        harts[hart].fetch_decode_execute(&mut shared, &mut Vec::new(), false)?;
+    }
+
+    // the word has its loaded value again, sc.w fails anyway
+    assert_eq!(harts[0].x_regs[12].load(), 1);
+    assert_eq!(shared.load32(0x40), 0);
+
+    let tag = shared.reservation_tag(0x40);
+    shared.store8(0x80, 1); // another reservation granule
+    assert!(shared.store_conditional32(0x40, tag, 2));
+    assert!(!shared.store_conditional32(0x40, tag, 3));
+    assert_eq!(shared.load32(0x40), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_parallel_device_in_ram() -> anyhow::Result<()> {
+    use emulator::Emulator;
+    use mmio_device::debug_exit::DebugExit;
+
+    let mut ram_data = vec![
+        0x93, 0x00, 0xe0, 0x0a, // ADDI x1, x0, 0xae
+        0x13, 0x01, 0x40, 0x0f, // ADDI x2, x0, 0xf4
+        0x23, 0x00, 0x11, 0x00, // SB x1, 0(x2)
+        0x00, 0x00, 0x00, 0x00, // unreachable
+    ];
+    ram_data.resize(0x100, 0);
+
+    // the debug exit port hides the ram behind it, also for the harts of the parallel mode
+    for parallel in [false, true] {
+        let mut emulator = Emulator::new(ram_data.clone());
+        emulator.parallel = parallel;
+        emulator.quantum = 100;
+        emulator.register_mmio_device(Box::new(DebugExit::default()));
+        emulator.reset();
+        let (exit_code, _) = emulator.run(false)?;
+
+        assert_eq!(exit_code, 0xae);
+        assert_eq!(emulator.ram.load8(0xf4), 0);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_parallel_hart_panic() {
+    use emulator::Emulator;
+
+    // hart 0 spins, hart 1 fetches the last halfword of the ram and its thread panics
+    let mut ram_data = vec![
+        0x73, 0x24, 0x40, 0xf1, // CSRRS x8, mhartid, x0
+        0x63, 0x14, 0x04, 0x00, // BNE x8, x0, 8
+        0x6f, 0x00, 0x00, 0x00, // JAL x0, 0
+        0x67, 0x00, 0xe0, 0x0f, // JALR x0, 0xfe(x0)
+    ];
+    ram_data.resize(0x100, 0);
+
+    let mut emulator = Emulator::new_with_harts(0, ram_data, 2);
+    emulator.parallel = true;
+    emulator.quantum = 100;
+    emulator.reset();
+    let error = emulator.run(false).unwrap_err();
+
+    assert!(error.to_string().starts_with("Hart 1 panicked"));
+}
+
+#[test]
+fn test_timing() -> anyhow::Result<()> {
+    use emulator::Emulator;
+    use timing::Timing;
+
+    let mut ram_data = vec![
+        0x93, 0x00, 0x60, 0x00, // ADDI x1, x0, 6
+        0x13, 0x01, 0x30, 0x00, // ADDI x2, x0, 3
+        0xb3, 0x81, 0x20, 0x02, // MUL x3, x1, x2
+        0x33, 0xc2, 0x20, 0x02, // DIV x4, x1, x2
+        0x83, 0x22, 0x00, 0x10, // LW x5, 0x100(x0)
+        0x63, 0x04, 0x00, 0x00, // BEQ x0, x0, 8
+        0x13, 0x00, 0x00, 0x00, // ADDI x0, x0, 0
+        0x63, 0x14, 0x00, 0x00, // BNE x0, x0, 8
+        0x73, 0x23, 0x00, 0xb0, // CSRRS x6, mcycle, x0
+        0xf3, 0x23, 0x20, 0xb0, // CSRRS x7, minstret, x0
+        0x67, 0x00, 0x00, 0x20, // JALR x0, 0x200(x0)
+    ];
+    ram_data.resize(0x104, 0);
+
+    // alu 1 + 1, mul 3, div 34, load 2, taken branch 3, branch 1, csr 3 + 3, jump 2
+    let mut emulator = Emulator::new(ram_data.clone());
+    emulator.reset();
+    let (_, log) = emulator.run(false)?;
+
+    let cpu = &emulator.harts[0];
+    assert_eq!(cpu.x_regs[6].load(), 45); // mcycle before the csrrs
+    assert_eq!(cpu.x_regs[7].load(), 8); // minstret
+    assert_eq!((cpu.step, cpu.cycle), (10, 53));
+    assert_eq!(log.steps.last().map(|step| step.cycle), Some(53));
+    assert_eq!(
+        timing::report(&emulator.harts),
+        "hart 0: 10 instructions, 53 cycles, CPI 5.300\n"
+    );
+
+    // the timing is configuration and survives the reset
+    let mut emulator = Emulator::new(ram_data);
+    emulator.harts[0].timing = Timing {
+        div: 20,
+        ..Timing::default()
+    };
+    emulator.reset();
+    emulator.run(false)?;
+    assert_eq!(emulator.harts[0].x_regs[6].load(), 31);
+    assert_eq!(emulator.harts[0].cycle, 39);
+
+    // mcycle and minstret are writable, the trapped instruction doesn't retire
+    let mut ram_data = vec![
+        0x93, 0x02, 0x40, 0x06, // ADDI x5, x0, 100
+        0x73, 0x90, 0x02, 0xb0, // CSRRW x0, mcycle, x5
+        0x73, 0x90, 0x22, 0xb0, // CSRRW x0, minstret, x5
+        0x73, 0x23, 0x00, 0xb0, // CSRRS x6, mcycle, x0
+        0x13, 0x04, 0xc0, 0x01, // ADDI x8, x0, 0x1c
+        0x73, 0x10, 0x54, 0x30, // CSRRW x0, mtvec, x8
+        0xff, 0xff, 0xff, 0xff, // illegal instruction
+        0xf3, 0x23, 0x20, 0xb0, // CSRRS x7, minstret, x0
+        0x67, 0x00, 0x00, 0x20, // JALR x0, 0x200(x0)
+    ];
+    ram_data.resize(0x104, 0);
+
+    let mut emulator = Emulator::new(ram_data);
+    emulator.reset();
+    emulator.run(false)?;
+    let cpu = &emulator.harts[0];
+    assert_eq!(cpu.x_regs[6].load(), 103);
+    assert_eq!(cpu.x_regs[7].load(), 103);
+    assert_eq!(cpu.instret, 105);
+
+    Ok(())
+}
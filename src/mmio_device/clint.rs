@@ -0,0 +1,199 @@
+use super::{MmioDeviceBase, MmioDeviceInterface, RequestFromDevice};
+use crate::{
+    csr,
+    fdt::{self, FdtCell, FdtNode},
+    ram::Ram,
+};
+use std::{cell::Cell, rc::Rc};
+
+// same address as QEMU's virt machine
+const DEFAULT_BASE_ADDR: u32 = 0x0200_0000;
+const DEFAULT_MEM_BYTES_LEN: usize = 0x1_0000;
+
+// one register per hart
+const REG_MSIP: usize = 0x0000;
+const REG_MTIMECMP: usize = 0x4000;
+const REG_MTIME: usize = 0xbff8;
+const REG_MTIMEH: usize = 0xbffc;
+
+// the machine timer, shared with the host (the time csr of the cpu follows it)
+#[derive(Debug, Clone, Default)]
+pub struct Mtime {
+    ticks: Rc<Cell<u64>>,
+}
+
+impl Mtime {
+    pub fn get(&self) -> u64 {
+        self.ticks.get()
+    }
+
+    fn set(&self, ticks: u64) {
+        self.ticks.set(ticks);
+    }
+}
+
+// Core-Local Interruptor, mtime counts one tick per step and is shared by the harts
+#[derive(Debug)]
+pub struct Clint {
+    device_base: MmioDeviceBase,
+    mtime: Mtime,
+    mtimecmp: Vec<u64>,
+    msip: Vec<bool>,
+}
+
+impl Clint {
+    fn new(
+        device_name: String,
+        base_addr: u32,
+        used_mem_bytes_len: usize,
+        num_harts: usize,
+    ) -> Self {
+        Self {
+            device_base: MmioDeviceBase {
+                device_name,
+                base_addr,
+                used_mem_bytes_len,
+            },
+            mtime: Mtime::default(),
+            mtimecmp: vec![u64::MAX; num_harts],
+            msip: vec![false; num_harts],
+        }
+    }
+
+    pub fn new_with_harts(num_harts: usize) -> Self {
+        Self::new(
+            String::from("clint"),
+            DEFAULT_BASE_ADDR,
+            DEFAULT_MEM_BYTES_LEN,
+            num_harts,
+        )
+    }
+
+    pub fn mtime(&self) -> Mtime {
+        self.mtime.clone()
+    }
+}
+
+fn set_low(value: u64, low: u32) -> u64 {
+    (value & !0xffff_ffff) | low as u64
+}
+
+fn set_high(value: u64, high: u32) -> u64 {
+    (value & 0xffff_ffff) | (high as u64) << 32
+}
+
+impl MmioDeviceInterface for Clint {
+    fn tick(&mut self, _ram: &mut Ram) {
+        self.mtime.set(self.mtime.get().wrapping_add(1));
+    }
+
+    fn poll_request(&mut self) -> Option<RequestFromDevice> {
+        None
+    }
+
+    fn pending_interrupts(&mut self, _irq_lines: u64, hart: usize) -> u32 {
+        if hart >= self.msip.len() {
+            return 0;
+        }
+
+        let mut mip = 0;
+        if self.msip[hart] {
+            mip |= csr::MIP_MSIP;
+        }
+        if self.mtime.get() >= self.mtimecmp[hart] {
+            mip |= csr::MIP_MTIP;
+        }
+
+        mip
+    }
+
+    fn load8(&mut self, _bytes_offset: usize) -> u8 {
+        0
+    }
+
+    fn store8(&mut self, _bytes_offset: usize, _value: u8) {}
+
+    fn load16(&mut self, _bytes_offset: usize) -> u16 {
+        0
+    }
+
+    fn store16(&mut self, _bytes_offset: usize, _value: u16) {}
+
+    fn load32(&mut self, bytes_offset: usize) -> u32 {
+        match bytes_offset {
+            REG_MTIME => self.mtime.get() as u32,
+            REG_MTIMEH => (self.mtime.get() >> 32) as u32,
+            o if o >= REG_MTIMECMP => match self.mtimecmp.get((o - REG_MTIMECMP) / 8) {
+                Some(mtimecmp) if o % 8 == 0 => *mtimecmp as u32,
+                Some(mtimecmp) => (*mtimecmp >> 32) as u32,
+                None => 0,
+            },
+            o => self
+                .msip
+                .get((o - REG_MSIP) / 4)
+                .map_or(0, |msip| *msip as u32),
+        }
+    }
+
+    fn store32(&mut self, bytes_offset: usize, value: u32) {
+        match bytes_offset {
+            REG_MTIME => self.mtime.set(set_low(self.mtime.get(), value)),
+            REG_MTIMEH => self.mtime.set(set_high(self.mtime.get(), value)),
+            o if o >= REG_MTIMECMP => {
+                if let Some(mtimecmp) = self.mtimecmp.get_mut((o - REG_MTIMECMP) / 8) {
+                    *mtimecmp = if o % 8 == 0 {
+                        set_low(*mtimecmp, value)
+                    } else {
+                        set_high(*mtimecmp, value)
+                    };
+                }
+            }
+            o => {
+                if let Some(msip) = self.msip.get_mut((o - REG_MSIP) / 4) {
+                    *msip = value & 1 != 0;
+                }
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.mtime.set(0);
+        self.mtimecmp.fill(u64::MAX);
+        self.msip.fill(false);
+    }
+
+    fn is_available_addr(&self, addr: u32) -> bool {
+        addr >= self.base_addr() && addr < self.base_addr() + self.used_mem_bytes_len() as u32
+    }
+
+    fn device_name(&self) -> &str {
+        &self.device_base.device_name
+    }
+
+    fn base_addr(&self) -> u32 {
+        self.device_base.base_addr
+    }
+
+    fn used_mem_bytes_len(&self) -> usize {
+        self.device_base.used_mem_bytes_len
+    }
+
+    // wired to the machine software and timer interrupts of each hart
+    fn fdt_nodes(&self) -> Vec<FdtNode> {
+        let cells = (0..self.msip.len())
+            .flat_map(|hart| [csr::IRQ_M_SOFT, csr::IRQ_M_TIMER].map(|irq| (hart, irq)))
+            .flat_map(|(hart, irq)| [FdtCell::Ref(fdt::cpu_intc_label(hart)), FdtCell::Value(irq)])
+            .collect();
+
+        vec![FdtNode::new_with_addr("clint", self.base_addr())
+            .prop_strs("compatible", &["sifive,clint0", "riscv,clint0"])
+            .prop_reg(self.base_addr(), self.used_mem_bytes_len())
+            .prop_ref_cells("interrupts-extended", cells)]
+    }
+}
+
+impl Default for Clint {
+    fn default() -> Self {
+        Self::new_with_harts(1)
+    }
+}
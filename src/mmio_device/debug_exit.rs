@@ -1,4 +1,5 @@
 use super::{MmioDeviceBase, MmioDeviceInterface, RequestFromDevice};
+use crate::{fdt::FdtNode, ram::Ram};
 
 const DEFAULT_BASE_ADDR: u32 = 0xf4;
 const DEFAULT_MEM_BYTES_LEN: usize = 1;
@@ -23,15 +24,17 @@ impl DebugExit {
 }
 
 impl MmioDeviceInterface for DebugExit {
+    fn tick(&mut self, _ram: &mut Ram) {}
+
     fn poll_request(&mut self) -> Option<RequestFromDevice> {
         if let Some(exit_code) = self.exit_code {
-            return Some(RequestFromDevice::Exit(exit_code));
+            return Some(RequestFromDevice::Exit(exit_code as u32));
         }
 
         None
     }
 
-    fn load8(&self, _bytes_offset: usize) -> u8 {
+    fn load8(&mut self, _bytes_offset: usize) -> u8 {
         0
     }
 
@@ -41,17 +44,21 @@ impl MmioDeviceInterface for DebugExit {
         }
     }
 
-    fn load16(&self, _bytes_offset: usize) -> u16 {
+    fn load16(&mut self, _bytes_offset: usize) -> u16 {
         0
     }
 
     fn store16(&mut self, _bytes_offset: usize, _value: u16) {}
 
-    fn load32(&self, _bytes_offset: usize) -> u32 {
+    fn load32(&mut self, _bytes_offset: usize) -> u32 {
         0
     }
 
-    fn store32(&self, _bytes_offset: usize, _value: u32) {}
+    fn store32(&mut self, _bytes_offset: usize, _value: u32) {}
+
+    fn reset(&mut self) {
+        self.exit_code = None;
+    }
 
     fn is_available_addr(&self, addr: u32) -> bool {
         addr >= self.base_addr() && addr < self.base_addr() + self.used_mem_bytes_len() as u32
@@ -68,6 +75,11 @@ impl MmioDeviceInterface for DebugExit {
     fn used_mem_bytes_len(&self) -> usize {
         self.device_base.used_mem_bytes_len
     }
+
+    // x86 isa-debug-exit has no device tree binding
+    fn fdt_nodes(&self) -> Vec<FdtNode> {
+        Vec::new()
+    }
 }
 
 impl Default for DebugExit {
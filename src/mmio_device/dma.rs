@@ -0,0 +1,310 @@
+use super::{MmioDeviceBase, MmioDeviceInterface, RequestFromDevice, SystemBus};
+use crate::{fdt::FdtNode, ram::Ram};
+
+// same address as the PDMA of the SiFive FU540
+const DEFAULT_BASE_ADDR: u32 = 0x300_0000;
+pub const NUM_CHANNELS: usize = 4;
+const CHANNEL_STRIDE: usize = 0x1000;
+// done and error interrupts of all channels share one irq line
+pub const DEFAULT_IRQ: u32 = 15;
+
+const REG_CONTROL: usize = 0x000;
+const REG_NEXT_CONFIG: usize = 0x004;
+const REG_NEXT_BYTES_LOW: usize = 0x008;
+const REG_NEXT_BYTES_HIGH: usize = 0x00c;
+const REG_NEXT_DEST_LOW: usize = 0x010;
+const REG_NEXT_DEST_HIGH: usize = 0x014;
+const REG_NEXT_SOURCE_LOW: usize = 0x018;
+const REG_NEXT_SOURCE_HIGH: usize = 0x01c;
+const REG_EXEC_CONFIG: usize = 0x104;
+const REG_EXEC_BYTES_LOW: usize = 0x108;
+const REG_EXEC_BYTES_HIGH: usize = 0x10c;
+const REG_EXEC_DEST_LOW: usize = 0x110;
+const REG_EXEC_DEST_HIGH: usize = 0x114;
+const REG_EXEC_SOURCE_LOW: usize = 0x118;
+const REG_EXEC_SOURCE_HIGH: usize = 0x11c;
+
+const CONTROL_CLAIM: u32 = 1 << 0;
+const CONTROL_RUN: u32 = 1 << 1;
+const CONTROL_DONE_IE: u32 = 1 << 14;
+const CONTROL_ERROR_IE: u32 = 1 << 15;
+const CONTROL_DONE: u32 = 1 << 30;
+const CONTROL_ERROR: u32 = 1 << 31;
+const CONTROL_MASK: u32 =
+    CONTROL_CLAIM | CONTROL_RUN | CONTROL_DONE_IE | CONTROL_ERROR_IE | CONTROL_DONE | CONTROL_ERROR;
+
+const CONFIG_REPEAT: u32 = 1 << 2;
+const CONFIG_ORDER: u32 = 1 << 3;
+const CONFIG_WSIZE_SHIFT: u32 = 24;
+const CONFIG_RSIZE_SHIFT: u32 = 28;
+// log2 of the transaction size (64 bytes at most)
+const MAX_SIZE: u32 = 6;
+const CONFIG_DEFAULT: u32 = MAX_SIZE << CONFIG_WSIZE_SHIFT | MAX_SIZE << CONFIG_RSIZE_SHIFT;
+const CONFIG_MASK: u32 =
+    CONFIG_REPEAT | CONFIG_ORDER | 0xf << CONFIG_WSIZE_SHIFT | 0xf << CONFIG_RSIZE_SHIFT;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Transfer {
+    config: u32,
+    bytes: u64,
+    dest: u64,
+    source: u64,
+}
+
+impl Transfer {
+    fn transaction_size(&self) -> u64 {
+        let wsize = self.config >> CONFIG_WSIZE_SHIFT & 0xf;
+        let rsize = self.config >> CONFIG_RSIZE_SHIFT & 0xf;
+        1 << wsize.min(rsize).min(MAX_SIZE)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Channel {
+    control: u32,
+    next: Transfer,
+    exec: Transfer,
+}
+
+impl Channel {
+    fn write_control(&mut self, value: u32) {
+        let mut value = value & CONTROL_MASK;
+        let claimed = self.control & CONTROL_CLAIM != 0;
+        let running = self.control & CONTROL_RUN != 0;
+
+        if !claimed && value & CONTROL_CLAIM != 0 {
+            self.next = Transfer {
+                config: CONFIG_DEFAULT,
+                ..Transfer::default()
+            };
+        }
+
+        // the claim can't be released and a transfer can't be stopped while running
+        if running {
+            value |= CONTROL_CLAIM | CONTROL_RUN;
+        }
+
+        if !running && value & CONTROL_RUN != 0 {
+            if value & CONTROL_CLAIM == 0 {
+                value &= !CONTROL_RUN;
+            } else {
+                value &= !(CONTROL_DONE | CONTROL_ERROR);
+                self.exec = self.next;
+            }
+        }
+
+        self.control = value;
+    }
+
+    fn is_running(&self) -> bool {
+        self.control & CONTROL_RUN != 0
+    }
+
+    fn finish(&mut self, status: u32) {
+        self.control |= status;
+
+        if status == CONTROL_DONE && self.exec.config & CONFIG_REPEAT != 0 {
+            self.exec = self.next;
+        } else {
+            self.control &= !CONTROL_RUN;
+        }
+    }
+
+    // one transaction per tick
+    fn step(&mut self, bus: &mut SystemBus) {
+        if self.exec.bytes == 0 {
+            self.finish(CONTROL_DONE);
+            return;
+        }
+
+        let len = self.exec.transaction_size().min(self.exec.bytes);
+        bus.begin_burst();
+
+        let mut offset = 0;
+        while offset < len {
+            // widest access that fits
+            let width = [4, 2, 1]
+                .into_iter()
+                .find(|width| offset + width <= len)
+                .unwrap();
+            // the bus is 32-bit, an address above it fails like an unmapped one
+            let source = u32::try_from(self.exec.source + offset).ok();
+            let dest = u32::try_from(self.exec.dest + offset).ok();
+
+            let transferred = source.zip(dest).and_then(|(source, dest)| {
+                let value = bus.load(source, width as u32)?;
+                bus.store(dest, width as u32, value)
+            });
+            if transferred.is_none() {
+                self.finish(CONTROL_ERROR);
+                return;
+            }
+            offset += width;
+        }
+
+        self.exec.bytes -= len;
+        self.exec.source += len;
+        self.exec.dest += len;
+
+        if self.exec.bytes == 0 {
+            self.finish(CONTROL_DONE);
+        }
+    }
+}
+
+fn set_low(value: u64, low: u32) -> u64 {
+    value & !0xffff_ffff | low as u64
+}
+
+fn set_high(value: u64, high: u32) -> u64 {
+    value & 0xffff_ffff | (high as u64) << 32
+}
+
+// compatible with the SiFive Platform DMA engine (sifive,fu540-c000-pdma)
+#[derive(Debug)]
+pub struct Dma {
+    device_base: MmioDeviceBase,
+    irq: u32,
+    channels: [Channel; NUM_CHANNELS],
+}
+
+impl Dma {
+    fn new(device_name: String, base_addr: u32, irq: u32) -> Self {
+        Self {
+            device_base: MmioDeviceBase {
+                device_name,
+                base_addr,
+                used_mem_bytes_len: CHANNEL_STRIDE * NUM_CHANNELS,
+            },
+            irq,
+            channels: Default::default(),
+        }
+    }
+}
+
+impl MmioDeviceInterface for Dma {
+    fn tick(&mut self, _ram: &mut Ram) {}
+
+    fn poll_request(&mut self) -> Option<RequestFromDevice> {
+        None
+    }
+
+    fn bus_access(&mut self, bus: &mut SystemBus) {
+        for channel in &mut self.channels {
+            if channel.is_running() {
+                channel.step(bus);
+            }
+        }
+    }
+
+    fn irq(&self) -> Option<u32> {
+        let pending = self.channels.iter().any(|channel| {
+            let control = channel.control;
+            (control & CONTROL_DONE != 0 && control & CONTROL_DONE_IE != 0)
+                || (control & CONTROL_ERROR != 0 && control & CONTROL_ERROR_IE != 0)
+        });
+
+        if pending {
+            Some(self.irq)
+        } else {
+            None
+        }
+    }
+
+    fn load8(&mut self, _bytes_offset: usize) -> u8 {
+        0
+    }
+
+    fn store8(&mut self, _bytes_offset: usize, _value: u8) {}
+
+    fn load16(&mut self, _bytes_offset: usize) -> u16 {
+        0
+    }
+
+    fn store16(&mut self, _bytes_offset: usize, _value: u16) {}
+
+    fn load32(&mut self, bytes_offset: usize) -> u32 {
+        let channel = &self.channels[bytes_offset / CHANNEL_STRIDE];
+
+        match bytes_offset % CHANNEL_STRIDE {
+            REG_CONTROL => channel.control,
+            REG_NEXT_CONFIG => channel.next.config,
+            REG_NEXT_BYTES_LOW => channel.next.bytes as u32,
+            REG_NEXT_BYTES_HIGH => (channel.next.bytes >> 32) as u32,
+            REG_NEXT_DEST_LOW => channel.next.dest as u32,
+            REG_NEXT_DEST_HIGH => (channel.next.dest >> 32) as u32,
+            REG_NEXT_SOURCE_LOW => channel.next.source as u32,
+            REG_NEXT_SOURCE_HIGH => (channel.next.source >> 32) as u32,
+            REG_EXEC_CONFIG => channel.exec.config,
+            REG_EXEC_BYTES_LOW => channel.exec.bytes as u32,
+            REG_EXEC_BYTES_HIGH => (channel.exec.bytes >> 32) as u32,
+            REG_EXEC_DEST_LOW => channel.exec.dest as u32,
+            REG_EXEC_DEST_HIGH => (channel.exec.dest >> 32) as u32,
+            REG_EXEC_SOURCE_LOW => channel.exec.source as u32,
+            REG_EXEC_SOURCE_HIGH => (channel.exec.source >> 32) as u32,
+            _ => 0,
+        }
+    }
+
+    // the next registers can only be written while the channel is claimed
+    fn store32(&mut self, bytes_offset: usize, value: u32) {
+        let channel = &mut self.channels[bytes_offset / CHANNEL_STRIDE];
+        let offset = bytes_offset % CHANNEL_STRIDE;
+
+        if offset == REG_CONTROL {
+            channel.write_control(value);
+            return;
+        }
+
+        if channel.control & CONTROL_CLAIM == 0 {
+            return;
+        }
+
+        let next = &mut channel.next;
+        match offset {
+            REG_NEXT_CONFIG => next.config = value & CONFIG_MASK,
+            REG_NEXT_BYTES_LOW => next.bytes = set_low(next.bytes, value),
+            REG_NEXT_BYTES_HIGH => next.bytes = set_high(next.bytes, value),
+            REG_NEXT_DEST_LOW => next.dest = set_low(next.dest, value),
+            REG_NEXT_DEST_HIGH => next.dest = set_high(next.dest, value),
+            REG_NEXT_SOURCE_LOW => next.source = set_low(next.source, value),
+            REG_NEXT_SOURCE_HIGH => next.source = set_high(next.source, value),
+            _ => (),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.channels = Default::default();
+    }
+
+    fn is_available_addr(&self, addr: u32) -> bool {
+        addr >= self.base_addr() && addr < self.base_addr() + self.used_mem_bytes_len() as u32
+    }
+
+    fn device_name(&self) -> &str {
+        &self.device_base.device_name
+    }
+
+    fn base_addr(&self) -> u32 {
+        self.device_base.base_addr
+    }
+
+    fn used_mem_bytes_len(&self) -> usize {
+        self.device_base.used_mem_bytes_len
+    }
+
+    fn fdt_nodes(&self) -> Vec<FdtNode> {
+        vec![FdtNode::new_with_addr("dma-controller", self.base_addr())
+            .prop_strs("compatible", &["sifive,fu540-c000-pdma", "sifive,pdma0"])
+            .prop_reg(self.base_addr(), self.used_mem_bytes_len())
+            .prop_u32("interrupts", self.irq)
+            .prop_u32("dma-channels", NUM_CHANNELS as u32)
+            .prop_u32("#dma-cells", 1)]
+    }
+}
+
+impl Default for Dma {
+    fn default() -> Self {
+        Self::new(String::from("pdma"), DEFAULT_BASE_ADDR, DEFAULT_IRQ)
+    }
+}
@@ -0,0 +1,285 @@
+use super::{MmioDeviceBase, MmioDeviceInterface, RequestFromDevice};
+use crate::{fdt::FdtNode, ram::Ram};
+use std::{
+    cell::RefCell,
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+// free region in QEMU's virt machine memory map
+const DEFAULT_BASE_ADDR: u32 = 0x2800_0000;
+const DEFAULT_WIDTH: u32 = 640;
+const DEFAULT_HEIGHT: u32 = 480;
+
+const PAGE_SIZE: usize = 0x1000;
+
+// pixel formats of the simple-framebuffer binding (little-endian words)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    R5g6b5,
+    X8r8g8b8,
+    A8r8g8b8,
+    A8b8g8r8,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            Self::R5g6b5 => 2,
+            Self::X8r8g8b8 | Self::A8r8g8b8 | Self::A8b8g8r8 => 4,
+        }
+    }
+
+    // "format" property of the device tree node
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::R5g6b5 => "r5g6b5",
+            Self::X8r8g8b8 => "x8r8g8b8",
+            Self::A8r8g8b8 => "a8r8g8b8",
+            Self::A8b8g8r8 => "a8b8g8r8",
+        }
+    }
+
+    // alpha is ignored
+    fn to_rgb(self, pixel: &[u8]) -> [u8; 3] {
+        match self {
+            Self::R5g6b5 => {
+                let value = u16::from_le_bytes([pixel[0], pixel[1]]);
+                let r = (value >> 11) as u8 & 0x1f;
+                let g = (value >> 5) as u8 & 0x3f;
+                let b = value as u8 & 0x1f;
+                [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2]
+            }
+            Self::X8r8g8b8 | Self::A8r8g8b8 => [pixel[2], pixel[1], pixel[0]],
+            Self::A8b8g8r8 => [pixel[0], pixel[1], pixel[2]],
+        }
+    }
+}
+
+// the pixel memory of a framebuffer, shared with the host (e.g. for a screenshot at exit)
+#[derive(Debug, Clone)]
+pub struct Screen {
+    width: u32,
+    height: u32,
+    format: PixelFormat,
+    data: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Screen {
+    fn new(width: u32, height: u32, format: PixelFormat) -> Self {
+        let len = width as usize * height as usize * format.bytes_per_pixel();
+
+        Self {
+            width,
+            height,
+            format,
+            data: Rc::new(RefCell::new(vec![0; len])),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    // bytes per line
+    pub fn stride(&self) -> u32 {
+        self.width * self.format.bytes_per_pixel() as u32
+    }
+
+    // 8-bit RGB, row by row from the top left pixel
+    pub fn to_rgb8(&self) -> Vec<u8> {
+        self.data
+            .borrow()
+            .chunks_exact(self.format.bytes_per_pixel())
+            .flat_map(|pixel| self.format.to_rgb(pixel))
+            .collect()
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.to_rgb8())?;
+        writer.finish()?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct PngDump {
+    path: PathBuf,
+    interval: usize,
+}
+
+// linear framebuffer without any control registers (simple-framebuffer)
+#[derive(Debug)]
+pub struct Framebuffer {
+    device_base: MmioDeviceBase,
+    screen: Screen,
+    png_dump: Option<PngDump>,
+    ticks: usize,
+}
+
+impl Framebuffer {
+    fn new(
+        device_name: String,
+        base_addr: u32,
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+    ) -> Self {
+        let screen = Screen::new(width, height, format);
+        let used_mem_bytes_len = screen.data.borrow().len().div_ceil(PAGE_SIZE) * PAGE_SIZE;
+
+        Self {
+            device_base: MmioDeviceBase {
+                device_name,
+                base_addr,
+                used_mem_bytes_len,
+            },
+            screen,
+            png_dump: None,
+            ticks: 0,
+        }
+    }
+
+    pub fn new_with_mode(width: u32, height: u32, format: PixelFormat) -> Self {
+        Self::new(
+            String::from("framebuffer"),
+            DEFAULT_BASE_ADDR,
+            width,
+            height,
+            format,
+        )
+    }
+
+    pub fn screen(&self) -> Screen {
+        self.screen.clone()
+    }
+
+    // write "<path stem>-<step>.png" every interval steps
+    pub fn set_png_dump<P: AsRef<Path>>(&mut self, path: P, interval: usize) {
+        self.png_dump = Some(PngDump {
+            path: path.as_ref().to_path_buf(),
+            interval,
+        });
+    }
+
+    fn dump_png(&self, path: &Path) {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let path = path.with_file_name(format!("{}-{:010}.png", stem, self.ticks));
+
+        if let Err(err) = self.screen.save_png(&path) {
+            eprintln!("Failed to write {}: {}", path.display(), err);
+        }
+    }
+
+    fn load(&self, bytes_offset: usize, len: usize) -> u32 {
+        let data = self.screen.data.borrow();
+        match data.get(bytes_offset..bytes_offset + len) {
+            Some(bytes) => bytes
+                .iter()
+                .rev()
+                .fold(0, |value, byte| value << 8 | *byte as u32),
+            None => 0,
+        }
+    }
+
+    fn store(&mut self, bytes_offset: usize, len: usize, value: u32) {
+        let mut data = self.screen.data.borrow_mut();
+        if let Some(bytes) = data.get_mut(bytes_offset..bytes_offset + len) {
+            bytes.copy_from_slice(&value.to_le_bytes()[..len]);
+        }
+    }
+}
+
+impl MmioDeviceInterface for Framebuffer {
+    fn tick(&mut self, _ram: &mut Ram) {
+        self.ticks += 1;
+
+        if let Some(png_dump) = &self.png_dump {
+            if self.ticks.is_multiple_of(png_dump.interval) {
+                self.dump_png(&png_dump.path);
+            }
+        }
+    }
+
+    fn poll_request(&mut self) -> Option<RequestFromDevice> {
+        None
+    }
+
+    fn load8(&mut self, bytes_offset: usize) -> u8 {
+        self.load(bytes_offset, 1) as u8
+    }
+
+    fn store8(&mut self, bytes_offset: usize, value: u8) {
+        self.store(bytes_offset, 1, value as u32);
+    }
+
+    fn load16(&mut self, bytes_offset: usize) -> u16 {
+        self.load(bytes_offset, 2) as u16
+    }
+
+    fn store16(&mut self, bytes_offset: usize, value: u16) {
+        self.store(bytes_offset, 2, value as u32);
+    }
+
+    fn load32(&mut self, bytes_offset: usize) -> u32 {
+        self.load(bytes_offset, 4)
+    }
+
+    fn store32(&mut self, bytes_offset: usize, value: u32) {
+        self.store(bytes_offset, 4, value);
+    }
+
+    // the step counter used for the png file names keeps counting
+    fn reset(&mut self) {
+        self.screen.data.borrow_mut().fill(0);
+    }
+
+    fn is_available_addr(&self, addr: u32) -> bool {
+        addr >= self.base_addr() && addr < self.base_addr() + self.used_mem_bytes_len() as u32
+    }
+
+    fn device_name(&self) -> &str {
+        &self.device_base.device_name
+    }
+
+    fn base_addr(&self) -> u32 {
+        self.device_base.base_addr
+    }
+
+    fn used_mem_bytes_len(&self) -> usize {
+        self.device_base.used_mem_bytes_len
+    }
+
+    fn fdt_nodes(&self) -> Vec<FdtNode> {
+        vec![FdtNode::new_with_addr("framebuffer", self.base_addr())
+            .prop_str("compatible", "simple-framebuffer")
+            .prop_reg(self.base_addr(), self.used_mem_bytes_len())
+            .prop_u32("width", self.screen.width())
+            .prop_u32("height", self.screen.height())
+            .prop_u32("stride", self.screen.stride())
+            .prop_str("format", self.screen.format().name())]
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new_with_mode(DEFAULT_WIDTH, DEFAULT_HEIGHT, PixelFormat::X8r8g8b8)
+    }
+}
@@ -0,0 +1,213 @@
+use super::{MmioDeviceBase, MmioDeviceInterface, RequestFromDevice};
+use crate::{fdt::FdtNode, ram::Ram};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// same address and irq as QEMU's virt machine
+const DEFAULT_BASE_ADDR: u32 = 0x101000;
+const DEFAULT_MEM_BYTES_LEN: usize = 0x1000;
+pub const DEFAULT_IRQ: u32 = 11;
+
+const REG_TIME_LOW: usize = 0x00;
+const REG_TIME_HIGH: usize = 0x04;
+const REG_ALARM_LOW: usize = 0x08;
+const REG_ALARM_HIGH: usize = 0x0c;
+const REG_IRQ_ENABLED: usize = 0x10;
+const REG_CLEAR_ALARM: usize = 0x14;
+const REG_ALARM_STATUS: usize = 0x18;
+const REG_CLEAR_INTERRUPT: usize = 0x1c;
+
+// nanoseconds since the unix epoch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtcClock {
+    Host,
+    // start_ns + steps * ns_per_step (ns_per_step = 0 gives a fixed time)
+    Virtual { start_ns: u64, ns_per_step: u64 },
+}
+
+// compatible with the Goldfish RTC (google,goldfish-rtc)
+#[derive(Debug)]
+pub struct GoldfishRtc {
+    device_base: MmioDeviceBase,
+    irq: u32,
+    clock: RtcClock,
+    ticks: u64,
+    // difference between the guest time and the clock (set by writing the time registers)
+    offset: i64,
+    time_high: u32,
+    alarm: u64,
+    alarm_high: u32,
+    alarm_armed: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+impl GoldfishRtc {
+    fn new(
+        device_name: String,
+        base_addr: u32,
+        used_mem_bytes_len: usize,
+        irq: u32,
+        clock: RtcClock,
+    ) -> Self {
+        Self {
+            device_base: MmioDeviceBase {
+                device_name,
+                base_addr,
+                used_mem_bytes_len,
+            },
+            irq,
+            clock,
+            ticks: 0,
+            offset: 0,
+            time_high: 0,
+            alarm: 0,
+            alarm_high: 0,
+            alarm_armed: false,
+            irq_enabled: false,
+            irq_pending: false,
+        }
+    }
+
+    pub fn new_with_clock(clock: RtcClock) -> Self {
+        Self::new(
+            String::from("goldfish-rtc"),
+            DEFAULT_BASE_ADDR,
+            DEFAULT_MEM_BYTES_LEN,
+            DEFAULT_IRQ,
+            clock,
+        )
+    }
+
+    fn clock_ns(&self) -> u64 {
+        match self.clock {
+            RtcClock::Host => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64,
+            RtcClock::Virtual {
+                start_ns,
+                ns_per_step,
+            } => start_ns.wrapping_add(self.ticks.wrapping_mul(ns_per_step)),
+        }
+    }
+
+    pub fn time_ns(&self) -> u64 {
+        self.clock_ns().wrapping_add_signed(self.offset)
+    }
+
+    fn set_time_ns(&mut self, ns: u64) {
+        self.offset = ns.wrapping_sub(self.clock_ns()) as i64;
+    }
+
+    fn check_alarm(&mut self) {
+        if self.alarm_armed && self.time_ns() >= self.alarm {
+            self.alarm_armed = false;
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl MmioDeviceInterface for GoldfishRtc {
+    fn tick(&mut self, _ram: &mut Ram) {
+        self.ticks += 1;
+        self.check_alarm();
+    }
+
+    fn poll_request(&mut self) -> Option<RequestFromDevice> {
+        None
+    }
+
+    fn irq(&self) -> Option<u32> {
+        if self.irq_enabled && self.irq_pending {
+            Some(self.irq)
+        } else {
+            None
+        }
+    }
+
+    fn load8(&mut self, _bytes_offset: usize) -> u8 {
+        0
+    }
+
+    fn store8(&mut self, _bytes_offset: usize, _value: u8) {}
+
+    fn load16(&mut self, _bytes_offset: usize) -> u16 {
+        0
+    }
+
+    fn store16(&mut self, _bytes_offset: usize, _value: u16) {}
+
+    fn load32(&mut self, bytes_offset: usize) -> u32 {
+        match bytes_offset {
+            // reading the low word latches the high word
+            REG_TIME_LOW => {
+                let time = self.time_ns();
+                self.time_high = (time >> 32) as u32;
+                time as u32
+            }
+            REG_TIME_HIGH => self.time_high,
+            REG_ALARM_LOW => self.alarm as u32,
+            REG_ALARM_HIGH => (self.alarm >> 32) as u32,
+            REG_IRQ_ENABLED => self.irq_enabled as u32,
+            REG_ALARM_STATUS => self.alarm_armed as u32,
+            _ => 0,
+        }
+    }
+
+    fn store32(&mut self, bytes_offset: usize, value: u32) {
+        match bytes_offset {
+            // the high word has to be written first
+            REG_TIME_LOW => self.set_time_ns((self.time_high as u64) << 32 | value as u64),
+            REG_TIME_HIGH => self.time_high = value,
+            REG_ALARM_LOW => {
+                self.alarm = (self.alarm_high as u64) << 32 | value as u64;
+                self.alarm_armed = true;
+                self.check_alarm();
+            }
+            REG_ALARM_HIGH => self.alarm_high = value,
+            REG_IRQ_ENABLED => self.irq_enabled = value & 1 != 0,
+            REG_CLEAR_ALARM => self.alarm_armed = false,
+            REG_CLEAR_INTERRUPT => self.irq_pending = false,
+            _ => (),
+        }
+    }
+
+    // the time keeps running across a reset
+    fn reset(&mut self) {
+        self.time_high = 0;
+        self.alarm = 0;
+        self.alarm_high = 0;
+        self.alarm_armed = false;
+        self.irq_enabled = false;
+        self.irq_pending = false;
+    }
+
+    fn is_available_addr(&self, addr: u32) -> bool {
+        addr >= self.base_addr() && addr < self.base_addr() + self.used_mem_bytes_len() as u32
+    }
+
+    fn device_name(&self) -> &str {
+        &self.device_base.device_name
+    }
+
+    fn base_addr(&self) -> u32 {
+        self.device_base.base_addr
+    }
+
+    fn used_mem_bytes_len(&self) -> usize {
+        self.device_base.used_mem_bytes_len
+    }
+
+    fn fdt_nodes(&self) -> Vec<FdtNode> {
+        vec![FdtNode::new_with_addr("rtc", self.base_addr())
+            .prop_str("compatible", "google,goldfish-rtc")
+            .prop_reg(self.base_addr(), self.used_mem_bytes_len())
+            .prop_u32("interrupts", self.irq)]
+    }
+}
+
+impl Default for GoldfishRtc {
+    fn default() -> Self {
+        Self::new_with_clock(RtcClock::Host)
+    }
+}
@@ -0,0 +1,373 @@
+use super::{MmioDeviceBase, MmioDeviceInterface, RequestFromDevice};
+use crate::{fdt::FdtNode, ram::Ram};
+use std::{cell::Cell, collections::VecDeque, fs, path::Path, rc::Rc};
+
+// same address as the GPIO of QEMU's sifive_u machine (free in the virt machine)
+const DEFAULT_BASE_ADDR: u32 = 0x1006_0000;
+const DEFAULT_MEM_BYTES_LEN: usize = 0x1000;
+pub const DEFAULT_IRQ: u32 = 12;
+
+const REG_INPUT_VAL: usize = 0x00;
+const REG_INPUT_EN: usize = 0x04;
+const REG_OUTPUT_EN: usize = 0x08;
+const REG_OUTPUT_VAL: usize = 0x0c;
+const REG_PUE: usize = 0x10;
+const REG_DS: usize = 0x14;
+const REG_RISE_IE: usize = 0x18;
+const REG_RISE_IP: usize = 0x1c;
+const REG_FALL_IE: usize = 0x20;
+const REG_FALL_IP: usize = 0x24;
+const REG_HIGH_IE: usize = 0x28;
+const REG_HIGH_IP: usize = 0x2c;
+const REG_LOW_IE: usize = 0x30;
+const REG_LOW_IP: usize = 0x34;
+const REG_IOF_EN: usize = 0x38;
+const REG_IOF_SEL: usize = 0x3c;
+const REG_OUT_XOR: usize = 0x40;
+
+pub const NUM_PINS: u32 = 32;
+
+// levels the host drives on the input pins (bit n = pin n), shared with the host
+#[derive(Debug, Clone, Default)]
+pub struct GpioInputs {
+    levels: Rc<Cell<u32>>,
+}
+
+impl GpioInputs {
+    pub fn set(&self, pin: u32, level: bool) {
+        if pin < NUM_PINS {
+            let levels = self.levels.get() & !(1 << pin);
+            self.levels.set(levels | (level as u32) << pin);
+        }
+    }
+
+    pub fn get(&self, pin: u32) -> bool {
+        pin < NUM_PINS && self.levels.get() >> pin & 1 != 0
+    }
+
+    pub fn levels(&self) -> u32 {
+        self.levels.get()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GpioScheduleEntry {
+    pub step: usize,
+    pub pin: u32,
+    pub level: bool,
+}
+
+// input pin changes applied before the given steps
+#[derive(Debug, Clone, Default)]
+pub struct GpioSchedule {
+    entries: VecDeque<GpioScheduleEntry>,
+}
+
+impl GpioSchedule {
+    pub fn new(mut entries: Vec<GpioScheduleEntry>) -> Self {
+        entries.sort_by_key(|entry| entry.step);
+
+        Self {
+            entries: entries.into(),
+        }
+    }
+
+    // one "<step> <pin> <0|1>" per line, '#' starts a comment
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let mut entries = Vec::new();
+
+        for (i, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let entry = match fields[..] {
+                [step, pin, level] => GpioScheduleEntry {
+                    step: step.parse()?,
+                    pin: pin.parse()?,
+                    level: match level {
+                        "0" => false,
+                        "1" => true,
+                        _ => return Err(anyhow::anyhow!("Invalid level at line {}", i + 1)),
+                    },
+                },
+                _ => return Err(anyhow::anyhow!("Invalid GPIO schedule at line {}", i + 1)),
+            };
+
+            if entry.pin >= NUM_PINS {
+                return Err(anyhow::anyhow!("Invalid pin number at line {}", i + 1));
+            }
+            entries.push(entry);
+        }
+
+        Ok(Self::new(entries))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+}
+
+// compatible with the SiFive GPIO controller (sifive,gpio0)
+#[derive(Debug)]
+pub struct Gpio {
+    device_base: MmioDeviceBase,
+    irq: u32,
+    inputs: GpioInputs,
+    schedule: GpioSchedule,
+    ticks: usize,
+    input_val: u32,
+    input_en: u32,
+    output_en: u32,
+    output_val: u32,
+    pue: u32,
+    ds: u32,
+    rise_ie: u32,
+    rise_ip: u32,
+    fall_ie: u32,
+    fall_ip: u32,
+    high_ie: u32,
+    high_ip: u32,
+    low_ie: u32,
+    low_ip: u32,
+    iof_en: u32,
+    iof_sel: u32,
+    out_xor: u32,
+    // driven output levels the last change was reported for
+    out_levels: u32,
+    events: VecDeque<RequestFromDevice>,
+}
+
+impl Gpio {
+    fn new(device_name: String, base_addr: u32, used_mem_bytes_len: usize, irq: u32) -> Self {
+        Self {
+            device_base: MmioDeviceBase {
+                device_name,
+                base_addr,
+                used_mem_bytes_len,
+            },
+            irq,
+            inputs: GpioInputs::default(),
+            schedule: GpioSchedule::default(),
+            ticks: 0,
+            input_val: 0,
+            input_en: 0,
+            output_en: 0,
+            output_val: 0,
+            pue: 0,
+            ds: 0,
+            rise_ie: 0,
+            rise_ip: 0,
+            fall_ie: 0,
+            fall_ip: 0,
+            high_ie: 0,
+            high_ip: 0,
+            low_ie: 0,
+            low_ip: 0,
+            iof_en: 0,
+            iof_sel: 0,
+            out_xor: 0,
+            out_levels: 0,
+            events: VecDeque::new(),
+        }
+    }
+
+    pub fn new_with_schedule(schedule: GpioSchedule) -> Self {
+        Self {
+            schedule,
+            ..Self::default()
+        }
+    }
+
+    pub fn inputs(&self) -> GpioInputs {
+        self.inputs.clone()
+    }
+
+    // undriven pins read as low
+    fn out_levels(&self) -> u32 {
+        (self.output_val ^ self.out_xor) & self.output_en
+    }
+
+    // output enabled pins read back their own level
+    fn pin_levels(&self) -> u32 {
+        self.out_levels() | self.inputs.levels() & !self.output_en
+    }
+
+    fn update_outputs(&mut self) {
+        let out_levels = self.out_levels();
+        let changed = out_levels ^ self.out_levels;
+        self.out_levels = out_levels;
+
+        for pin in (0..NUM_PINS).filter(|pin| changed >> pin & 1 != 0) {
+            self.events.push_back(RequestFromDevice::GpioOutput {
+                pin,
+                level: out_levels >> pin & 1 != 0,
+            });
+        }
+    }
+
+    fn update_inputs(&mut self) {
+        let input_val = self.pin_levels() & self.input_en;
+        self.rise_ip |= input_val & !self.input_val;
+        self.fall_ip |= !input_val & self.input_val & self.input_en;
+        self.high_ip |= input_val;
+        self.low_ip |= !input_val & self.input_en;
+        self.input_val = input_val;
+    }
+}
+
+impl MmioDeviceInterface for Gpio {
+    fn tick(&mut self, _ram: &mut Ram) {
+        while let Some(entry) = self.schedule.entries.front() {
+            if entry.step > self.ticks {
+                break;
+            }
+            self.inputs.set(entry.pin, entry.level);
+            self.schedule.entries.pop_front();
+        }
+
+        self.ticks += 1;
+        self.update_inputs();
+    }
+
+    fn poll_request(&mut self) -> Option<RequestFromDevice> {
+        self.events.pop_front()
+    }
+
+    fn irq(&self) -> Option<u32> {
+        let pending = self.rise_ip & self.rise_ie
+            | self.fall_ip & self.fall_ie
+            | self.high_ip & self.high_ie
+            | self.low_ip & self.low_ie;
+
+        if pending != 0 {
+            Some(self.irq)
+        } else {
+            None
+        }
+    }
+
+    fn load8(&mut self, _bytes_offset: usize) -> u8 {
+        0
+    }
+
+    fn store8(&mut self, _bytes_offset: usize, _value: u8) {}
+
+    fn load16(&mut self, _bytes_offset: usize) -> u16 {
+        0
+    }
+
+    fn store16(&mut self, _bytes_offset: usize, _value: u16) {}
+
+    fn load32(&mut self, bytes_offset: usize) -> u32 {
+        match bytes_offset {
+            REG_INPUT_VAL => self.input_val,
+            REG_INPUT_EN => self.input_en,
+            REG_OUTPUT_EN => self.output_en,
+            REG_OUTPUT_VAL => self.output_val,
+            REG_PUE => self.pue,
+            REG_DS => self.ds,
+            REG_RISE_IE => self.rise_ie,
+            REG_RISE_IP => self.rise_ip,
+            REG_FALL_IE => self.fall_ie,
+            REG_FALL_IP => self.fall_ip,
+            REG_HIGH_IE => self.high_ie,
+            REG_HIGH_IP => self.high_ip,
+            REG_LOW_IE => self.low_ie,
+            REG_LOW_IP => self.low_ip,
+            REG_IOF_EN => self.iof_en,
+            REG_IOF_SEL => self.iof_sel,
+            REG_OUT_XOR => self.out_xor,
+            _ => 0,
+        }
+    }
+
+    fn store32(&mut self, bytes_offset: usize, value: u32) {
+        match bytes_offset {
+            REG_INPUT_EN => {
+                self.input_en = value;
+                self.input_val &= value;
+            }
+            REG_OUTPUT_EN => self.output_en = value,
+            REG_OUTPUT_VAL => self.output_val = value,
+            REG_PUE => self.pue = value,
+            REG_DS => self.ds = value,
+            REG_RISE_IE => self.rise_ie = value,
+            REG_FALL_IE => self.fall_ie = value,
+            REG_HIGH_IE => self.high_ie = value,
+            REG_LOW_IE => self.low_ie = value,
+            // interrupt pending bits are write-1-to-clear
+            REG_RISE_IP => self.rise_ip &= !value,
+            REG_FALL_IP => self.fall_ip &= !value,
+            REG_HIGH_IP => self.high_ip &= !value,
+            REG_LOW_IP => self.low_ip &= !value,
+            REG_IOF_EN => self.iof_en = value,
+            REG_IOF_SEL => self.iof_sel = value,
+            REG_OUT_XOR => self.out_xor = value,
+            _ => return,
+        }
+
+        self.update_outputs();
+    }
+
+    // the host side input levels and the schedule position are kept
+    fn reset(&mut self) {
+        self.input_val = 0;
+        self.input_en = 0;
+        self.output_en = 0;
+        self.output_val = 0;
+        self.pue = 0;
+        self.ds = 0;
+        self.rise_ie = 0;
+        self.rise_ip = 0;
+        self.fall_ie = 0;
+        self.fall_ip = 0;
+        self.high_ie = 0;
+        self.high_ip = 0;
+        self.low_ie = 0;
+        self.low_ip = 0;
+        self.iof_en = 0;
+        self.iof_sel = 0;
+        self.out_xor = 0;
+        self.update_outputs();
+    }
+
+    fn is_available_addr(&self, addr: u32) -> bool {
+        addr >= self.base_addr() && addr < self.base_addr() + self.used_mem_bytes_len() as u32
+    }
+
+    fn device_name(&self) -> &str {
+        &self.device_base.device_name
+    }
+
+    fn base_addr(&self) -> u32 {
+        self.device_base.base_addr
+    }
+
+    fn used_mem_bytes_len(&self) -> usize {
+        self.device_base.used_mem_bytes_len
+    }
+
+    fn fdt_nodes(&self) -> Vec<FdtNode> {
+        vec![FdtNode::new_with_addr("gpio", self.base_addr())
+            .prop_str("compatible", "sifive,gpio0")
+            .prop_reg(self.base_addr(), self.used_mem_bytes_len())
+            .prop_u32("interrupts", self.irq)
+            .prop_empty("gpio-controller")
+            .prop_u32("#gpio-cells", 2)
+            .prop_u32("ngpios", NUM_PINS)]
+    }
+}
+
+impl Default for Gpio {
+    fn default() -> Self {
+        Self::new(
+            String::from("gpio"),
+            DEFAULT_BASE_ADDR,
+            DEFAULT_MEM_BYTES_LEN,
+            DEFAULT_IRQ,
+        )
+    }
+}
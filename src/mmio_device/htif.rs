@@ -0,0 +1,235 @@
+use super::{MmioDeviceBase, MmioDeviceInterface, RequestFromDevice};
+use crate::{fdt::FdtNode, ram::Ram, serial::SerialBackend};
+
+const REG_LEN: u32 = 8; // tohost/fromhost are 64-bit
+
+const DEVICE_SYSCALL: u64 = 0;
+const DEVICE_BCD: u64 = 1;
+
+const BCD_CMD_PUTCHAR: u64 = 1;
+
+const SYS_WRITE: u64 = 64;
+const SYS_EXIT: u64 = 93;
+
+const EFAULT: i64 = 14;
+const ENOSYS: i64 = 38;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reg {
+    ToHost,
+    FromHost,
+}
+
+// Host-Target Interface (riscv-tests, spike)
+// the command written to tohost is handled once its upper half is written
+#[derive(Debug)]
+pub struct Htif {
+    device_base: MmioDeviceBase,
+    tohost_addr: u32,
+    fromhost_addr: u32,
+    tohost: u64,
+    fromhost: u64,
+    pending: bool,
+    backend: Box<dyn SerialBackend>,
+    exit_code: Option<u32>,
+}
+
+impl Htif {
+    pub fn new(tohost_addr: u32, fromhost_addr: u32, backend: Box<dyn SerialBackend>) -> Self {
+        let base_addr = tohost_addr.min(fromhost_addr);
+        let used_mem_bytes_len = (tohost_addr.max(fromhost_addr) + REG_LEN - base_addr) as usize;
+
+        Self {
+            device_base: MmioDeviceBase {
+                device_name: String::from("htif"),
+                base_addr,
+                used_mem_bytes_len,
+            },
+            tohost_addr,
+            fromhost_addr,
+            tohost: 0,
+            fromhost: 0,
+            pending: false,
+            backend,
+            exit_code: None,
+        }
+    }
+
+    // magic_mem of the syscall (which, arg0, arg1, arg2), None if it isn't in ram
+    fn load_magic_mem(ram: &Ram, addr: u32) -> Option<[u64; 4]> {
+        let bytes = ram.slice(addr, 32)?;
+        let mut words = [0; 4];
+        for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(8)) {
+            *word = u64::from_le_bytes(chunk.try_into().unwrap());
+        }
+        Some(words)
+    }
+
+    fn handle_command(&mut self, ram: &mut Ram) {
+        let device = self.tohost >> 56;
+        let cmd = (self.tohost >> 48) & 0xff;
+        let payload = self.tohost & 0xffff_ffff_ffff;
+
+        match (device, cmd) {
+            // exit (riscv-tests write (test number << 1) | 1)
+            (DEVICE_SYSCALL, _) if payload & 1 != 0 => {
+                self.exit_code = Some((payload >> 1) as u32);
+            }
+            // payload is the address of magic_mem (which, arg0, arg1, arg2, ...),
+            // a syscall with a magic_mem outside of ram is ignored
+            (DEVICE_SYSCALL, _) => {
+                let Some(magic_mem) = u32::try_from(payload).ok() else {
+                    self.tohost = 0;
+                    return;
+                };
+                let Some([which, arg0, arg1, arg2]) = Self::load_magic_mem(ram, magic_mem) else {
+                    self.tohost = 0;
+                    return;
+                };
+
+                let ret = match which {
+                    SYS_WRITE if arg0 == 1 || arg0 == 2 => {
+                        let buf = u32::try_from(arg1).ok().zip(usize::try_from(arg2).ok());
+                        match buf.and_then(|(addr, len)| ram.slice(addr, len)) {
+                            Some(buf) => {
+                                for value in buf {
+                                    self.backend.write(*value);
+                                }
+                                arg2 as i64
+                            }
+                            None => -EFAULT,
+                        }
+                    }
+                    SYS_EXIT => {
+                        self.exit_code = Some(arg0 as u32);
+                        0
+                    }
+                    _ => -ENOSYS,
+                };
+
+                if let Some(slice) = ram.slice_mut(magic_mem, 8) {
+                    slice.copy_from_slice(&(ret as u64).to_le_bytes());
+                }
+                self.fromhost = 1;
+            }
+            (DEVICE_BCD, BCD_CMD_PUTCHAR) => {
+                self.backend.write(payload as u8);
+                self.fromhost = (DEVICE_BCD << 56) | (BCD_CMD_PUTCHAR << 48) | 0x100 | (payload & 0xff);
+            }
+            _ => (),
+        }
+
+        self.tohost = 0;
+    }
+
+    fn locate(&self, bytes_offset: usize) -> Option<(Reg, u32)> {
+        let addr = self.base_addr() + bytes_offset as u32;
+
+        if addr >= self.tohost_addr && addr < self.tohost_addr + REG_LEN {
+            Some((Reg::ToHost, addr - self.tohost_addr))
+        } else if addr >= self.fromhost_addr && addr < self.fromhost_addr + REG_LEN {
+            Some((Reg::FromHost, addr - self.fromhost_addr))
+        } else {
+            None
+        }
+    }
+
+    fn reg_mut(&mut self, reg: Reg) -> &mut u64 {
+        match reg {
+            Reg::ToHost => &mut self.tohost,
+            Reg::FromHost => &mut self.fromhost,
+        }
+    }
+
+    fn load(&mut self, bytes_offset: usize, size: u32) -> u64 {
+        let (reg, offset) = match self.locate(bytes_offset) {
+            Some(loc) => loc,
+            None => return 0,
+        };
+        let mask = u64::MAX >> (64 - size * 8);
+
+        (*self.reg_mut(reg) >> (offset * 8)) & mask
+    }
+
+    fn store(&mut self, bytes_offset: usize, size: u32, value: u64) {
+        let (reg, offset) = match self.locate(bytes_offset) {
+            Some(loc) => loc,
+            None => return,
+        };
+        let mask = u64::MAX >> (64 - size * 8);
+        let shift = offset * 8;
+
+        let reg_value = self.reg_mut(reg);
+        *reg_value = (*reg_value & !(mask << shift)) | ((value & mask) << shift);
+
+        if reg == Reg::ToHost && offset + size == REG_LEN && self.tohost != 0 {
+            self.pending = true;
+        }
+    }
+}
+
+impl MmioDeviceInterface for Htif {
+    fn tick(&mut self, ram: &mut Ram) {
+        if self.pending {
+            self.pending = false;
+            self.handle_command(ram);
+        }
+    }
+
+    fn poll_request(&mut self) -> Option<RequestFromDevice> {
+        self.exit_code.map(RequestFromDevice::Exit)
+    }
+
+    fn load8(&mut self, bytes_offset: usize) -> u8 {
+        self.load(bytes_offset, 1) as u8
+    }
+
+    fn store8(&mut self, bytes_offset: usize, value: u8) {
+        self.store(bytes_offset, 1, value as u64);
+    }
+
+    fn load16(&mut self, bytes_offset: usize) -> u16 {
+        self.load(bytes_offset, 2) as u16
+    }
+
+    fn store16(&mut self, bytes_offset: usize, value: u16) {
+        self.store(bytes_offset, 2, value as u64);
+    }
+
+    fn load32(&mut self, bytes_offset: usize) -> u32 {
+        self.load(bytes_offset, 4) as u32
+    }
+
+    fn store32(&mut self, bytes_offset: usize, value: u32) {
+        self.store(bytes_offset, 4, value as u64);
+    }
+
+    fn reset(&mut self) {
+        self.tohost = 0;
+        self.fromhost = 0;
+        self.pending = false;
+        self.exit_code = None;
+    }
+
+    fn is_available_addr(&self, addr: u32) -> bool {
+        (addr >= self.tohost_addr && addr < self.tohost_addr + REG_LEN)
+            || (addr >= self.fromhost_addr && addr < self.fromhost_addr + REG_LEN)
+    }
+
+    fn device_name(&self) -> &str {
+        &self.device_base.device_name
+    }
+
+    fn base_addr(&self) -> u32 {
+        self.device_base.base_addr
+    }
+
+    fn used_mem_bytes_len(&self) -> usize {
+        self.device_base.used_mem_bytes_len
+    }
+
+    // riscv-pk finds tohost/fromhost by symbol, the node only tells that htif is there
+    fn fdt_nodes(&self) -> Vec<FdtNode> {
+        vec![FdtNode::new("htif").prop_str("compatible", "ucb,htif0")]
+    }
+}
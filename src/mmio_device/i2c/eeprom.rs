@@ -0,0 +1,116 @@
+use super::I2cDevice;
+use crate::fdt::FdtNode;
+
+pub const DEFAULT_ADDRESS: u8 = 0x50;
+
+// 24Cxx serial EEPROM (24C01 - 24C512), writes complete immediately
+#[derive(Debug)]
+pub struct Eeprom {
+    address: u8,
+    data: Vec<u8>,
+    page_size: usize,
+    // 24C32 and larger take a 2 bytes word address
+    address_bytes: usize,
+    pointer: usize,
+    // word address bytes received in the current write
+    received_address_bytes: usize,
+}
+
+impl Eeprom {
+    // size in bytes (128 - 65536, a power of 2)
+    pub fn new(address: u8, size: usize) -> Self {
+        Self::new_with_data(address, vec![0xff; size])
+    }
+
+    pub fn new_with_data(address: u8, data: Vec<u8>) -> Self {
+        assert!(data.len().is_power_of_two() && (128..=0x10000).contains(&data.len()));
+
+        let (page_size, address_bytes) = match data.len() {
+            0..=256 => (8, 1),
+            257..=2048 => (16, 1),
+            2049..=8192 => (32, 2),
+            8193..=32768 => (64, 2),
+            _ => (128, 2),
+        };
+
+        Self {
+            address,
+            data,
+            page_size,
+            address_bytes,
+            pointer: 0,
+            received_address_bytes: 0,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl I2cDevice for Eeprom {
+    // 24C04 - 24C16 use the low address bits as the upper word address bits
+    fn start(&mut self, address: u8, read: bool) -> bool {
+        let blocks = if self.address_bytes == 1 {
+            (self.data.len() / 256).max(1) as u8
+        } else {
+            1
+        };
+        if address < self.address || address >= self.address + blocks {
+            return false;
+        }
+
+        if self.address_bytes == 1 && blocks > 1 {
+            self.pointer = self.pointer & 0xff | ((address - self.address) as usize) << 8;
+        }
+        if !read {
+            self.received_address_bytes = 0;
+        }
+        true
+    }
+
+    fn write(&mut self, value: u8) -> bool {
+        if self.received_address_bytes < self.address_bytes {
+            let pointer = if self.address_bytes == 2 && self.received_address_bytes == 0 {
+                (value as usize) << 8
+            } else {
+                self.pointer & !0xff | value as usize
+            };
+            self.pointer = pointer & (self.data.len() - 1);
+            self.received_address_bytes += 1;
+            return true;
+        }
+
+        self.data[self.pointer] = value;
+        // wraps around within the page
+        let page = self.pointer & !(self.page_size - 1);
+        self.pointer = page | (self.pointer + 1) & (self.page_size - 1);
+        true
+    }
+
+    fn read(&mut self) -> u8 {
+        let value = self.data[self.pointer];
+        self.pointer = (self.pointer + 1) & (self.data.len() - 1);
+        value
+    }
+
+    fn stop(&mut self) {}
+
+    // the contents are non-volatile
+    fn reset(&mut self) {
+        self.pointer = 0;
+        self.received_address_bytes = 0;
+    }
+
+    // e.g. "atmel,24c02" for 256 bytes
+    fn fdt_node(&self) -> Option<FdtNode> {
+        let kbits = self.data.len() * 8 / 1024;
+
+        Some(
+            FdtNode::new_with_addr("eeprom", self.address as u32)
+                .prop_str("compatible", &format!("atmel,24c{:02}", kbits))
+                .prop_u32("reg", self.address as u32)
+                .prop_u32("pagesize", self.page_size as u32),
+        )
+    }
+}
@@ -0,0 +1,141 @@
+use super::I2cDevice;
+use crate::fdt::FdtNode;
+use std::{cell::Cell, rc::Rc};
+
+pub const DEFAULT_ADDRESS: u8 = 0x48;
+
+const REG_TEMP: u8 = 0;
+const REG_CONF: u8 = 1;
+const REG_THYST: u8 = 2;
+const REG_TOS: u8 = 3;
+
+// temperature seen by the sensor in millidegrees Celsius, shared with the host
+#[derive(Debug, Clone, Default)]
+pub struct Thermometer {
+    millicelsius: Rc<Cell<i32>>,
+}
+
+impl Thermometer {
+    pub fn set(&self, millicelsius: i32) {
+        self.millicelsius.set(millicelsius);
+    }
+
+    pub fn get(&self) -> i32 {
+        self.millicelsius.get()
+    }
+}
+
+// LM75 temperature sensor (9-bit, 0.5 degrees resolution)
+#[derive(Debug)]
+pub struct Lm75 {
+    address: u8,
+    thermometer: Thermometer,
+    pointer: u8,
+    conf: u8,
+    thyst: u16,
+    tos: u16,
+    // bytes transferred since the start condition (not counting the pointer byte)
+    index: usize,
+    pointer_written: bool,
+}
+
+impl Lm75 {
+    pub fn new(address: u8, millicelsius: i32) -> Self {
+        let thermometer = Thermometer::default();
+        thermometer.set(millicelsius);
+
+        Self {
+            address,
+            thermometer,
+            pointer: REG_TEMP,
+            conf: 0,
+            thyst: to_register(75_000),
+            tos: to_register(80_000),
+            index: 0,
+            pointer_written: false,
+        }
+    }
+
+    pub fn thermometer(&self) -> Thermometer {
+        self.thermometer.clone()
+    }
+
+    fn register(&self) -> u16 {
+        match self.pointer {
+            REG_TEMP => to_register(self.thermometer.get()),
+            REG_CONF => (self.conf as u16) << 8,
+            REG_THYST => self.thyst,
+            REG_TOS => self.tos,
+            _ => 0,
+        }
+    }
+}
+
+// left-justified two's complement in 0.5 degrees
+fn to_register(millicelsius: i32) -> u16 {
+    (((millicelsius / 500) as i16) << 7) as u16
+}
+
+impl I2cDevice for Lm75 {
+    fn start(&mut self, address: u8, read: bool) -> bool {
+        if address != self.address {
+            return false;
+        }
+
+        self.index = 0;
+        self.pointer_written = read;
+        true
+    }
+
+    fn write(&mut self, value: u8) -> bool {
+        if !self.pointer_written {
+            self.pointer = value & 0x3;
+            self.pointer_written = true;
+            return true;
+        }
+
+        // MSB first, the unused low bits read as 0
+        let shift = if self.index == 0 { 8 } else { 0 };
+        let update =
+            |register: u16| (register & !(0xff << shift) | (value as u16) << shift) & 0xff80;
+        match self.pointer {
+            REG_CONF => self.conf = value,
+            REG_THYST => self.thyst = update(self.thyst),
+            REG_TOS => self.tos = update(self.tos),
+            _ => return false,
+        }
+        self.index += 1;
+        true
+    }
+
+    // MSB first, then LSB (repeated)
+    fn read(&mut self) -> u8 {
+        let register = self.register();
+        let value = if self.index & 1 == 0 || self.pointer == REG_CONF {
+            (register >> 8) as u8
+        } else {
+            register as u8
+        };
+        self.index += 1;
+        value
+    }
+
+    fn stop(&mut self) {}
+
+    fn reset(&mut self) {
+        self.pointer = REG_TEMP;
+        self.conf = 0;
+        self.thyst = to_register(75_000);
+        self.tos = to_register(80_000);
+        self.index = 0;
+        self.pointer_written = false;
+    }
+
+    fn fdt_node(&self) -> Option<FdtNode> {
+        Some(
+            FdtNode::new_with_addr("sensor", self.address as u32)
+                .prop_str("compatible", "national,lm75")
+                .prop_u32("reg", self.address as u32),
+        )
+    }
+}
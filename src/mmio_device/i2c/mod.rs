@@ -0,0 +1,280 @@
+use super::{MmioDeviceBase, MmioDeviceInterface, RequestFromDevice};
+use crate::{fdt::FdtNode, ram::Ram};
+use serde::Serialize;
+use std::{collections::VecDeque, fmt::Debug};
+
+pub mod eeprom;
+pub mod lm75;
+
+// same address as the I2C controller of the SiFive FU540
+const DEFAULT_BASE_ADDR: u32 = 0x1003_0000;
+const DEFAULT_MEM_BYTES_LEN: usize = 0x1000;
+pub const DEFAULT_IRQ: u32 = 14;
+// only reported in the device tree (prescale = ip clock / (5 * bus clock) - 1)
+const IP_CLOCK_FREQUENCY: u32 = 20_000_000;
+const BUS_CLOCK_FREQUENCY: u32 = 100_000;
+
+// registers are 4 bytes apart (reg-shift = 2)
+const REG_PRERLO: usize = 0x00;
+const REG_PRERHI: usize = 0x04;
+const REG_CTR: usize = 0x08;
+// TXR on write, RXR on read
+const REG_TXR_RXR: usize = 0x0c;
+// CR on write, SR on read
+const REG_CR_SR: usize = 0x10;
+
+const CTR_EN: u8 = 1 << 7;
+const CTR_IEN: u8 = 1 << 6;
+
+const CR_STA: u8 = 1 << 7;
+const CR_STO: u8 = 1 << 6;
+const CR_RD: u8 = 1 << 5;
+const CR_WR: u8 = 1 << 4;
+// set = NACK
+const CR_ACK: u8 = 1 << 3;
+const CR_IACK: u8 = 1 << 0;
+
+// set = no acknowledge from the slave
+const SR_RXACK: u8 = 1 << 7;
+const SR_BUSY: u8 = 1 << 6;
+const SR_IF: u8 = 1 << 0;
+
+// bus activity recorded in the step log
+#[derive(Debug, Clone, Serialize)]
+pub enum I2cEvent {
+    Start { address: u8, read: bool, ack: bool },
+    Write { value: u8, ack: bool },
+    // ack is sent by the master
+    Read { value: u8, ack: bool },
+    Stop,
+}
+
+// slave on the bus, addresses are 7-bit
+pub trait I2cDevice: Debug {
+    // (repeated) start condition, returns whether the device acknowledges the address
+    fn start(&mut self, address: u8, read: bool) -> bool;
+    fn write(&mut self, value: u8) -> bool;
+    fn read(&mut self) -> u8;
+    fn stop(&mut self);
+    fn reset(&mut self);
+    // child node of the controller
+    fn fdt_node(&self) -> Option<FdtNode>;
+}
+
+// compatible with the OpenCores I2C master (opencores,i2c-ocores), commands complete immediately
+#[derive(Debug)]
+pub struct I2c {
+    device_base: MmioDeviceBase,
+    irq: u32,
+    devices: Vec<Box<dyn I2cDevice>>,
+    // index of the addressed device
+    target: Option<usize>,
+    prer: u16,
+    ctr: u8,
+    txr: u8,
+    rxr: u8,
+    sr: u8,
+    events: VecDeque<RequestFromDevice>,
+}
+
+impl I2c {
+    fn new(device_name: String, base_addr: u32, used_mem_bytes_len: usize, irq: u32) -> Self {
+        Self {
+            device_base: MmioDeviceBase {
+                device_name,
+                base_addr,
+                used_mem_bytes_len,
+            },
+            irq,
+            devices: Vec::new(),
+            target: None,
+            prer: 0xffff,
+            ctr: 0,
+            txr: 0,
+            rxr: 0,
+            sr: 0,
+            events: VecDeque::new(),
+        }
+    }
+
+    pub fn attach(&mut self, device: Box<dyn I2cDevice>) {
+        self.devices.push(device);
+    }
+
+    fn log(&mut self, event: I2cEvent) {
+        self.events.push_back(RequestFromDevice::I2c(event));
+    }
+
+    fn set_rxack(&mut self, ack: bool) {
+        if ack {
+            self.sr &= !SR_RXACK;
+        } else {
+            self.sr |= SR_RXACK;
+        }
+    }
+
+    fn command(&mut self, cr: u8) {
+        if cr & CR_IACK != 0 {
+            self.sr &= !SR_IF;
+        }
+
+        if self.ctr & CTR_EN == 0 || cr & (CR_STA | CR_STO | CR_RD | CR_WR) == 0 {
+            return;
+        }
+
+        if cr & CR_STA != 0 && cr & CR_WR != 0 {
+            // the address byte
+            let address = self.txr >> 1;
+            let read = self.txr & 1 != 0;
+            self.target = self
+                .devices
+                .iter_mut()
+                .position(|device| device.start(address, read));
+            let ack = self.target.is_some();
+            self.sr |= SR_BUSY;
+            self.set_rxack(ack);
+            self.log(I2cEvent::Start { address, read, ack });
+        } else if cr & CR_WR != 0 {
+            let value = self.txr;
+            let ack = match self.target {
+                Some(target) => self.devices[target].write(value),
+                None => false,
+            };
+            self.set_rxack(ack);
+            self.log(I2cEvent::Write { value, ack });
+        } else if cr & CR_RD != 0 {
+            let value = match self.target {
+                Some(target) => self.devices[target].read(),
+                None => 0xff,
+            };
+            self.rxr = value;
+            self.log(I2cEvent::Read {
+                value,
+                ack: cr & CR_ACK == 0,
+            });
+        }
+
+        if cr & CR_STO != 0 {
+            if let Some(target) = self.target.take() {
+                self.devices[target].stop();
+            }
+            self.sr &= !SR_BUSY;
+            self.log(I2cEvent::Stop);
+        }
+
+        self.sr |= SR_IF;
+    }
+}
+
+impl MmioDeviceInterface for I2c {
+    fn tick(&mut self, _ram: &mut Ram) {}
+
+    fn poll_request(&mut self) -> Option<RequestFromDevice> {
+        self.events.pop_front()
+    }
+
+    fn irq(&self) -> Option<u32> {
+        if self.ctr & CTR_IEN != 0 && self.sr & SR_IF != 0 {
+            Some(self.irq)
+        } else {
+            None
+        }
+    }
+
+    fn load8(&mut self, bytes_offset: usize) -> u8 {
+        match bytes_offset {
+            REG_PRERLO => self.prer as u8,
+            REG_PRERHI => (self.prer >> 8) as u8,
+            REG_CTR => self.ctr,
+            REG_TXR_RXR => self.rxr,
+            REG_CR_SR => self.sr,
+            _ => 0,
+        }
+    }
+
+    fn store8(&mut self, bytes_offset: usize, value: u8) {
+        match bytes_offset {
+            REG_PRERLO => self.prer = self.prer & 0xff00 | value as u16,
+            REG_PRERHI => self.prer = self.prer & 0x00ff | (value as u16) << 8,
+            REG_CTR => self.ctr = value & (CTR_EN | CTR_IEN),
+            REG_TXR_RXR => self.txr = value,
+            REG_CR_SR => self.command(value),
+            _ => (),
+        }
+    }
+
+    fn load16(&mut self, bytes_offset: usize) -> u16 {
+        self.load8(bytes_offset) as u16
+    }
+
+    fn store16(&mut self, bytes_offset: usize, value: u16) {
+        self.store8(bytes_offset, value as u8);
+    }
+
+    fn load32(&mut self, bytes_offset: usize) -> u32 {
+        self.load8(bytes_offset) as u32
+    }
+
+    fn store32(&mut self, bytes_offset: usize, value: u32) {
+        self.store8(bytes_offset, value as u8);
+    }
+
+    fn reset(&mut self) {
+        self.target = None;
+        self.prer = 0xffff;
+        self.ctr = 0;
+        self.txr = 0;
+        self.rxr = 0;
+        self.sr = 0;
+
+        for device in &mut self.devices {
+            device.reset();
+        }
+    }
+
+    fn is_available_addr(&self, addr: u32) -> bool {
+        addr >= self.base_addr() && addr < self.base_addr() + self.used_mem_bytes_len() as u32
+    }
+
+    fn device_name(&self) -> &str {
+        &self.device_base.device_name
+    }
+
+    fn base_addr(&self) -> u32 {
+        self.device_base.base_addr
+    }
+
+    fn used_mem_bytes_len(&self) -> usize {
+        self.device_base.used_mem_bytes_len
+    }
+
+    fn fdt_nodes(&self) -> Vec<FdtNode> {
+        let node = FdtNode::new_with_addr("i2c", self.base_addr())
+            .prop_str("compatible", "opencores,i2c-ocores")
+            .prop_reg(self.base_addr(), self.used_mem_bytes_len())
+            .prop_u32("interrupts", self.irq)
+            .prop_u32("reg-shift", 2)
+            .prop_u32("reg-io-width", 1)
+            .prop_u32("opencores,ip-clock-frequency", IP_CLOCK_FREQUENCY)
+            .prop_u32("clock-frequency", BUS_CLOCK_FREQUENCY)
+            .prop_u32("#address-cells", 1)
+            .prop_u32("#size-cells", 0);
+
+        vec![self
+            .devices
+            .iter()
+            .filter_map(|device| device.fdt_node())
+            .fold(node, FdtNode::child)]
+    }
+}
+
+impl Default for I2c {
+    fn default() -> Self {
+        Self::new(
+            String::from("i2c"),
+            DEFAULT_BASE_ADDR,
+            DEFAULT_MEM_BYTES_LEN,
+            DEFAULT_IRQ,
+        )
+    }
+}
@@ -1,7 +1,25 @@
+use crate::{
+    fdt::FdtNode,
+    ram::{Memory, Ram},
+    step_log::RamWrite,
+};
+use i2c::I2cEvent;
 use serde::Serialize;
 
+pub mod clint;
 pub mod debug_exit;
+pub mod dma;
+pub mod framebuffer;
+pub mod goldfish_rtc;
+pub mod gpio;
+pub mod htif;
+pub mod i2c;
+pub mod plic;
+pub mod sifive_test;
 pub mod simple_uart;
+pub mod spi;
+pub mod virtio;
+pub mod watchdog;
 
 #[derive(Debug)]
 pub struct MmioDeviceBase {
@@ -12,19 +30,129 @@ pub struct MmioDeviceBase {
 
 #[derive(Debug, Clone, Serialize)]
 pub enum RequestFromDevice {
-    Exit(u8),
+    Exit(u32),
+    Reset,
+    // not requests, only recorded in the step log
+    GpioOutput { pin: u32, level: bool },
+    I2c(I2cEvent),
+    // followed by the configured Reset or Exit request
+    WatchdogExpired,
 }
 
 pub trait MmioDeviceInterface {
+    // called before every cpu step
+    fn tick(&mut self, ram: &mut Ram);
     fn poll_request(&mut self) -> Option<RequestFromDevice>;
-    fn load8(&self, bytes_offset: usize) -> u8;
+    // bus masters access the ram and the other devices here (called after tick)
+    fn bus_access(&mut self, _bus: &mut SystemBus) {}
+    // irq number while the device asserts its interrupt line
+    fn irq(&self) -> Option<u32> {
+        None
+    }
+    // interrupt controllers return the mip bits of the hart driven by the irq lines (bit n = irq n)
+    fn pending_interrupts(&mut self, _irq_lines: u64, _hart: usize) -> u32 {
+        0
+    }
+    fn load8(&mut self, bytes_offset: usize) -> u8;
     fn store8(&mut self, bytes_offset: usize, value: u8);
-    fn load16(&self, bytes_offset: usize) -> u16;
+    fn load16(&mut self, bytes_offset: usize) -> u16;
     fn store16(&mut self, bytes_offset: usize, value: u16);
-    fn load32(&self, bytes_offset: usize) -> u32;
-    fn store32(&self, bytes_offset: usize, value: u32);
+    fn load32(&mut self, bytes_offset: usize) -> u32;
+    fn store32(&mut self, bytes_offset: usize, value: u32);
+    fn reset(&mut self);
     fn is_available_addr(&self, addr: u32) -> bool;
     fn device_name(&self) -> &str;
     fn base_addr(&self) -> u32;
     fn used_mem_bytes_len(&self) -> usize;
+    // nodes under /soc of the device tree (empty if the device has no binding)
+    fn fdt_nodes(&self) -> Vec<FdtNode> {
+        Vec::new()
+    }
+}
+
+// ram and the mmio devices other than the bus master, accesses to unmapped addresses fail
+pub struct SystemBus<'a> {
+    ram: &'a mut Ram,
+    mmio_devices: &'a mut Vec<Box<dyn MmioDeviceInterface>>,
+    bursts: Vec<Vec<RamWrite>>,
+}
+
+impl<'a> SystemBus<'a> {
+    pub fn new(ram: &'a mut Ram, mmio_devices: &'a mut Vec<Box<dyn MmioDeviceInterface>>) -> Self {
+        Self {
+            ram,
+            mmio_devices,
+            bursts: Vec::new(),
+        }
+    }
+
+    fn is_available_addr(&self, addr: u32, len: u32) -> bool {
+        let last = match addr.checked_add(len - 1) {
+            Some(last) => last,
+            None => return false,
+        };
+
+        (self.ram.is_available_addr(addr) && self.ram.is_available_addr(last))
+            || self
+                .mmio_devices
+                .iter()
+                .any(|mmio_device| mmio_device.is_available_addr(addr))
+    }
+
+    // 1, 2 or 4 bytes
+    pub fn load(&mut self, addr: u32, len: u32) -> Option<u32> {
+        if !self.is_available_addr(addr, len) {
+            return None;
+        }
+
+        let value = match len {
+            1 => self.ram.load8_with_mmio(addr, self.mmio_devices) as u32,
+            2 => self.ram.load16_with_mmio(addr, self.mmio_devices) as u32,
+            4 => self.ram.load32_with_mmio(addr, self.mmio_devices),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    pub fn store(&mut self, addr: u32, len: u32, value: u32) -> Option<()> {
+        if !self.is_available_addr(addr, len) {
+            return None;
+        }
+
+        match len {
+            1 => self
+                .ram
+                .store8_with_mmio(addr, value as u8, self.mmio_devices),
+            2 => self
+                .ram
+                .store16_with_mmio(addr, value as u16, self.mmio_devices),
+            4 => self.ram.store32_with_mmio(addr, value, self.mmio_devices),
+            _ => return None,
+        }
+
+        if self.bursts.is_empty() {
+            self.begin_burst();
+        }
+        let burst = self.bursts.last_mut().unwrap();
+        for (i, byte) in value.to_le_bytes()[..len as usize].iter().enumerate() {
+            burst.push(RamWrite::new(addr + i as u32, *byte));
+        }
+
+        Some(())
+    }
+
+    // the following writes are logged as a separate burst
+    pub fn begin_burst(&mut self) {
+        if self.bursts.last().is_none_or(|burst| !burst.is_empty()) {
+            self.bursts.push(Vec::new());
+        }
+    }
+
+    pub fn into_bursts(self) -> Vec<Vec<RamWrite>> {
+        self.bursts
+            .into_iter()
+            .filter(|burst| !burst.is_empty())
+            .collect()
+    }
 }
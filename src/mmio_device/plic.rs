@@ -0,0 +1,269 @@
+use super::{MmioDeviceBase, MmioDeviceInterface, RequestFromDevice};
+use crate::{
+    csr,
+    fdt::{self, FdtCell, FdtNode, PLIC_LABEL},
+    ram::Ram,
+};
+
+// same address as QEMU's virt machine
+const DEFAULT_BASE_ADDR: u32 = 0x0c00_0000;
+const DEFAULT_MEM_BYTES_LEN: usize = 0x0400_0000;
+
+// irq 0 is reserved
+pub const NUM_SOURCES: usize = 64;
+// M-mode and S-mode of each hart
+const CONTEXTS_PER_HART: usize = 2;
+
+const PRIORITY_BASE: usize = 0;
+const PENDING_BASE: usize = 0x1000;
+const ENABLE_BASE: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT_BASE: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+
+// Platform-Level Interrupt Controller (level-triggered sources)
+#[derive(Debug)]
+pub struct Plic {
+    device_base: MmioDeviceBase,
+    priority: [u32; NUM_SOURCES],
+    pending: u64,
+    claimed: u64,
+    enable: Vec<u64>,
+    threshold: Vec<u32>,
+}
+
+impl Plic {
+    fn new(
+        device_name: String,
+        base_addr: u32,
+        used_mem_bytes_len: usize,
+        num_harts: usize,
+    ) -> Self {
+        Self {
+            device_base: MmioDeviceBase {
+                device_name,
+                base_addr,
+                used_mem_bytes_len,
+            },
+            priority: [0; NUM_SOURCES],
+            pending: 0,
+            claimed: 0,
+            enable: vec![0; num_harts * CONTEXTS_PER_HART],
+            threshold: vec![0; num_harts * CONTEXTS_PER_HART],
+        }
+    }
+
+    pub fn new_with_harts(num_harts: usize) -> Self {
+        Self::new(
+            String::from("plic"),
+            DEFAULT_BASE_ADDR,
+            DEFAULT_MEM_BYTES_LEN,
+            num_harts,
+        )
+    }
+
+    fn num_contexts(&self) -> usize {
+        self.enable.len()
+    }
+
+    // the pending and enabled irq with the highest priority (the lowest id wins ties)
+    fn best_irq(&self, context: usize) -> Option<usize> {
+        let mut best: Option<usize> = None;
+
+        for irq in 1..NUM_SOURCES {
+            let bit = 1 << irq;
+            if self.pending & self.enable[context] & bit == 0
+                || self.priority[irq] <= self.threshold[context]
+            {
+                continue;
+            }
+
+            if best.is_none_or(|b| self.priority[irq] > self.priority[b]) {
+                best = Some(irq);
+            }
+        }
+
+        best
+    }
+
+    fn claim(&mut self, context: usize) -> u32 {
+        match self.best_irq(context) {
+            Some(irq) => {
+                self.pending &= !(1 << irq);
+                self.claimed |= 1 << irq;
+                irq as u32
+            }
+            None => 0,
+        }
+    }
+
+    fn complete(&mut self, irq: u32) {
+        if (irq as usize) < NUM_SOURCES {
+            self.claimed &= !(1 << irq);
+        }
+    }
+}
+
+impl MmioDeviceInterface for Plic {
+    fn tick(&mut self, _ram: &mut Ram) {}
+
+    fn poll_request(&mut self) -> Option<RequestFromDevice> {
+        None
+    }
+
+    fn pending_interrupts(&mut self, irq_lines: u64, hart: usize) -> u32 {
+        // claimed irqs stay masked until completed
+        self.pending = irq_lines & !self.claimed & !1;
+
+        let context = hart * CONTEXTS_PER_HART;
+        if context >= self.num_contexts() {
+            return 0;
+        }
+
+        let mut mip = 0;
+        if self.best_irq(context).is_some() {
+            mip |= csr::MIP_MEIP;
+        }
+        if self.best_irq(context + 1).is_some() {
+            mip |= csr::MIP_SEIP;
+        }
+
+        mip
+    }
+
+    fn load8(&mut self, _bytes_offset: usize) -> u8 {
+        0
+    }
+
+    fn store8(&mut self, _bytes_offset: usize, _value: u8) {}
+
+    fn load16(&mut self, _bytes_offset: usize) -> u16 {
+        0
+    }
+
+    fn store16(&mut self, _bytes_offset: usize, _value: u16) {}
+
+    fn load32(&mut self, bytes_offset: usize) -> u32 {
+        match bytes_offset {
+            o if o < PENDING_BASE => self
+                .priority
+                .get((o - PRIORITY_BASE) / 4)
+                .copied()
+                .unwrap_or(0),
+            o if o < ENABLE_BASE => {
+                let word = (o - PENDING_BASE) / 4;
+                if word < NUM_SOURCES / 32 {
+                    (self.pending >> (word * 32)) as u32
+                } else {
+                    0
+                }
+            }
+            o if o < CONTEXT_BASE => {
+                let context = (o - ENABLE_BASE) / ENABLE_STRIDE;
+                let word = (o - ENABLE_BASE) % ENABLE_STRIDE / 4;
+                if context < self.num_contexts() && word < NUM_SOURCES / 32 {
+                    (self.enable[context] >> (word * 32)) as u32
+                } else {
+                    0
+                }
+            }
+            o => {
+                let context = (o - CONTEXT_BASE) / CONTEXT_STRIDE;
+                if context >= self.num_contexts() {
+                    return 0;
+                }
+
+                match (o - CONTEXT_BASE) % CONTEXT_STRIDE {
+                    0 => self.threshold[context],
+                    4 => self.claim(context),
+                    _ => 0,
+                }
+            }
+        }
+    }
+
+    fn store32(&mut self, bytes_offset: usize, value: u32) {
+        match bytes_offset {
+            o if o < PENDING_BASE => {
+                let irq = (o - PRIORITY_BASE) / 4;
+                if irq != 0 && irq < NUM_SOURCES {
+                    self.priority[irq] = value;
+                }
+            }
+            // pending bits are read-only
+            o if o < ENABLE_BASE => (),
+            o if o < CONTEXT_BASE => {
+                let context = (o - ENABLE_BASE) / ENABLE_STRIDE;
+                let word = (o - ENABLE_BASE) % ENABLE_STRIDE / 4;
+                if context < self.num_contexts() && word < NUM_SOURCES / 32 {
+                    let shift = word * 32;
+                    let enable = &mut self.enable[context];
+                    *enable = (*enable & !(0xffff_ffff << shift)) | ((value as u64) << shift);
+                    *enable &= !1;
+                }
+            }
+            o => {
+                let context = (o - CONTEXT_BASE) / CONTEXT_STRIDE;
+                if context >= self.num_contexts() {
+                    return;
+                }
+
+                match (o - CONTEXT_BASE) % CONTEXT_STRIDE {
+                    0 => self.threshold[context] = value,
+                    4 => self.complete(value),
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.priority = [0; NUM_SOURCES];
+        self.pending = 0;
+        self.claimed = 0;
+        self.enable.fill(0);
+        self.threshold.fill(0);
+    }
+
+    fn is_available_addr(&self, addr: u32) -> bool {
+        addr >= self.base_addr() && addr < self.base_addr() + self.used_mem_bytes_len() as u32
+    }
+
+    fn device_name(&self) -> &str {
+        &self.device_base.device_name
+    }
+
+    fn base_addr(&self) -> u32 {
+        self.device_base.base_addr
+    }
+
+    fn used_mem_bytes_len(&self) -> usize {
+        self.device_base.used_mem_bytes_len
+    }
+
+    // contexts are wired to the machine and supervisor external interrupts of each hart
+    fn fdt_nodes(&self) -> Vec<FdtNode> {
+        let cells = (0..self.num_contexts() / CONTEXTS_PER_HART)
+            .flat_map(|hart| [csr::IRQ_M_EXT, csr::IRQ_S_EXT].map(|irq| (hart, irq)))
+            .flat_map(|(hart, irq)| [FdtCell::Ref(fdt::cpu_intc_label(hart)), FdtCell::Value(irq)])
+            .collect();
+
+        vec![
+            FdtNode::new_with_addr("interrupt-controller", self.base_addr())
+                .label(PLIC_LABEL)
+                .prop_strs("compatible", &["sifive,plic-1.0.0", "riscv,plic0"])
+                .prop_reg(self.base_addr(), self.used_mem_bytes_len())
+                .prop_empty("interrupt-controller")
+                .prop_u32("#interrupt-cells", 1)
+                .prop_u32("#address-cells", 0)
+                .prop_u32("riscv,ndev", NUM_SOURCES as u32 - 1)
+                .prop_ref_cells("interrupts-extended", cells),
+        ]
+    }
+}
+
+impl Default for Plic {
+    fn default() -> Self {
+        Self::new_with_harts(1)
+    }
+}
@@ -0,0 +1,125 @@
+use super::{MmioDeviceBase, MmioDeviceInterface, RequestFromDevice};
+use crate::{fdt::FdtNode, ram::Ram};
+
+// same address as QEMU's virt machine
+const DEFAULT_BASE_ADDR: u32 = 0x100000;
+const DEFAULT_MEM_BYTES_LEN: usize = 0x1000;
+
+const FINISHER_FAIL: u32 = 0x3333;
+const FINISHER_PASS: u32 = 0x5555;
+const FINISHER_RESET: u32 = 0x7777;
+
+// compatible with QEMU's sifive_test (syscon poweroff/reboot)
+#[derive(Debug)]
+pub struct SifiveTest {
+    device_base: MmioDeviceBase,
+    request: Option<RequestFromDevice>,
+}
+
+impl SifiveTest {
+    fn new(device_name: String, base_addr: u32, used_mem_bytes_len: usize) -> Self {
+        Self {
+            device_base: MmioDeviceBase {
+                device_name,
+                base_addr,
+                used_mem_bytes_len,
+            },
+            request: None,
+        }
+    }
+
+    fn finish(&mut self, value: u32) {
+        let status = value & 0xffff;
+        let code = value >> 16;
+
+        self.request = match status {
+            FINISHER_FAIL => Some(RequestFromDevice::Exit(code)),
+            FINISHER_PASS => Some(RequestFromDevice::Exit(0)),
+            FINISHER_RESET => Some(RequestFromDevice::Reset),
+            _ => return,
+        };
+    }
+}
+
+impl MmioDeviceInterface for SifiveTest {
+    fn tick(&mut self, _ram: &mut Ram) {}
+
+    fn poll_request(&mut self) -> Option<RequestFromDevice> {
+        self.request.take()
+    }
+
+    fn load8(&mut self, _bytes_offset: usize) -> u8 {
+        0
+    }
+
+    fn store8(&mut self, _bytes_offset: usize, _value: u8) {}
+
+    fn load16(&mut self, _bytes_offset: usize) -> u16 {
+        0
+    }
+
+    fn store16(&mut self, bytes_offset: usize, value: u16) {
+        if bytes_offset == 0 {
+            self.finish(value as u32);
+        }
+    }
+
+    fn load32(&mut self, _bytes_offset: usize) -> u32 {
+        0
+    }
+
+    fn store32(&mut self, bytes_offset: usize, value: u32) {
+        if bytes_offset == 0 {
+            self.finish(value);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.request = None;
+    }
+
+    fn is_available_addr(&self, addr: u32) -> bool {
+        addr >= self.base_addr() && addr < self.base_addr() + self.used_mem_bytes_len() as u32
+    }
+
+    fn device_name(&self) -> &str {
+        &self.device_base.device_name
+    }
+
+    fn base_addr(&self) -> u32 {
+        self.device_base.base_addr
+    }
+
+    fn used_mem_bytes_len(&self) -> usize {
+        self.device_base.used_mem_bytes_len
+    }
+
+    fn fdt_nodes(&self) -> Vec<FdtNode> {
+        vec![
+            FdtNode::new_with_addr("test", self.base_addr())
+                .label("test")
+                .prop_strs("compatible", &["sifive,test1", "sifive,test0", "syscon"])
+                .prop_reg(self.base_addr(), self.used_mem_bytes_len()),
+            FdtNode::new("poweroff")
+                .prop_str("compatible", "syscon-poweroff")
+                .prop_ref("regmap", "test")
+                .prop_u32("offset", 0)
+                .prop_u32("value", FINISHER_PASS),
+            FdtNode::new("reboot")
+                .prop_str("compatible", "syscon-reboot")
+                .prop_ref("regmap", "test")
+                .prop_u32("offset", 0)
+                .prop_u32("value", FINISHER_RESET),
+        ]
+    }
+}
+
+impl Default for SifiveTest {
+    fn default() -> Self {
+        Self::new(
+            String::from("sifive-test"),
+            DEFAULT_BASE_ADDR,
+            DEFAULT_MEM_BYTES_LEN,
+        )
+    }
+}
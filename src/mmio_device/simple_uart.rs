@@ -1,51 +1,233 @@
-use super::{MmioDeviceBase, MmioDeviceInterface};
+use super::{MmioDeviceBase, MmioDeviceInterface, RequestFromDevice};
+use crate::{
+    fdt::FdtNode,
+    ram::Ram,
+    serial::{SerialBackend, StdoutBackend},
+};
+use std::collections::VecDeque;
 
 const DEFAULT_BASE_ADDR: u32 = 0x3f8; // COM1
-const DEFAULT_MEM_BYTES_LEN: usize = 5;
+const DEFAULT_MEM_BYTES_LEN: usize = 8;
+pub const DEFAULT_IRQ: u32 = 10;
+// standard 1.8432 MHz crystal (the divisor latch is ignored anyway)
+const CLOCK_FREQUENCY: u32 = 1_843_200;
+
+// 16550 compatible register offsets (DLL and DLM replace RBR/THR and IER while LCR.DLAB is set)
+const REG_RBR_THR: usize = 0;
+const REG_IER: usize = 1;
+const REG_IIR_FCR: usize = 2;
+const REG_LCR: usize = 3;
+const REG_MCR: usize = 4;
+const REG_LSR: usize = 5;
+const REG_MSR: usize = 6;
+const REG_SCR: usize = 7;
+
+const IER_RDA: u8 = 0x01;
+const IER_THRE: u8 = 0x02;
+const IER_MASK: u8 = 0x0f;
+
+const IIR_NO_INTERRUPT: u8 = 0x01;
+const IIR_THRE: u8 = 0x02;
+const IIR_RDA: u8 = 0x04;
+const IIR_FIFO_ENABLED: u8 = 0xc0;
+
+const FCR_FIFO_ENABLE: u8 = 0x01;
+const FCR_CLEAR_RX: u8 = 0x02;
+
+const LCR_DLAB: u8 = 0x80;
+
+const LSR_DATA_READY: u8 = 0x01;
+const LSR_THR_EMPTY: u8 = 0x20;
+const LSR_TRANSMITTER_EMPTY: u8 = 0x40;
+
+// carrier detect, data set ready and clear to send are always asserted
+const MSR_VALUE: u8 = 0xb0;
+
+const RX_FIFO_LEN: usize = 16;
 
 #[derive(Debug)]
 pub struct SimpleUart {
     device_base: MmioDeviceBase,
+    irq: u32,
+    backend: Box<dyn SerialBackend>,
+    rx_fifo: VecDeque<u8>,
+    ier: u8,
+    lcr: u8,
+    mcr: u8,
+    scr: u8,
+    fifo_enabled: bool,
+    divisor: u16,
+    // the transmitter is always empty, the THRE interrupt is pending until IIR is read or THR is written
+    thre_pending: bool,
 }
 
 impl SimpleUart {
-    fn new(device_name: String, base_addr: u32, used_mem_bytes_len: usize) -> Self {
+    fn new(
+        device_name: String,
+        base_addr: u32,
+        used_mem_bytes_len: usize,
+        irq: u32,
+        backend: Box<dyn SerialBackend>,
+    ) -> Self {
         Self {
             device_base: MmioDeviceBase {
                 device_name,
                 base_addr,
                 used_mem_bytes_len,
             },
+            irq,
+            backend,
+            rx_fifo: VecDeque::with_capacity(RX_FIFO_LEN),
+            ier: 0,
+            lcr: 0,
+            mcr: 0,
+            scr: 0,
+            fifo_enabled: false,
+            divisor: 0,
+            thre_pending: false,
+        }
+    }
+
+    pub fn new_with_backend(backend: Box<dyn SerialBackend>) -> Self {
+        Self::new(
+            String::from("simple-uart"),
+            DEFAULT_BASE_ADDR,
+            DEFAULT_MEM_BYTES_LEN,
+            DEFAULT_IRQ,
+            backend,
+        )
+    }
+
+    fn dlab(&self) -> bool {
+        self.lcr & LCR_DLAB != 0
+    }
+
+    // received data has priority over the transmitter holding register
+    fn interrupt_id(&self) -> u8 {
+        if self.ier & IER_RDA != 0 && !self.rx_fifo.is_empty() {
+            IIR_RDA
+        } else if self.ier & IER_THRE != 0 && self.thre_pending {
+            IIR_THRE
+        } else {
+            IIR_NO_INTERRUPT
+        }
+    }
+
+    fn line_status(&self) -> u8 {
+        let mut lsr = LSR_THR_EMPTY | LSR_TRANSMITTER_EMPTY;
+        if !self.rx_fifo.is_empty() {
+            lsr |= LSR_DATA_READY;
         }
+
+        lsr
     }
 }
 
 impl MmioDeviceInterface for SimpleUart {
-    fn poll_request(&mut self) -> Option<super::RequestFromDevice> {
+    fn tick(&mut self, _ram: &mut Ram) {}
+
+    fn poll_request(&mut self) -> Option<RequestFromDevice> {
+        if self.backend.quit_requested() {
+            return Some(RequestFromDevice::Exit(0));
+        }
+
+        while self.rx_fifo.len() < RX_FIFO_LEN {
+            match self.backend.read() {
+                Some(value) => self.rx_fifo.push_back(value),
+                None => break,
+            }
+        }
+
         None
     }
 
-    fn load8(&self, _bytes_offset: usize) -> u8 {
-        0
+    fn irq(&self) -> Option<u32> {
+        if self.interrupt_id() != IIR_NO_INTERRUPT {
+            Some(self.irq)
+        } else {
+            None
+        }
+    }
+
+    fn load8(&mut self, bytes_offset: usize) -> u8 {
+        match bytes_offset {
+            REG_RBR_THR if self.dlab() => self.divisor as u8,
+            REG_RBR_THR => self.rx_fifo.pop_front().unwrap_or(0),
+            REG_IER if self.dlab() => (self.divisor >> 8) as u8,
+            REG_IER => self.ier,
+            REG_IIR_FCR => {
+                let id = self.interrupt_id();
+                if id == IIR_THRE {
+                    self.thre_pending = false;
+                }
+
+                if self.fifo_enabled {
+                    id | IIR_FIFO_ENABLED
+                } else {
+                    id
+                }
+            }
+            REG_LCR => self.lcr,
+            REG_MCR => self.mcr,
+            REG_LSR => self.line_status(),
+            REG_MSR => MSR_VALUE,
+            REG_SCR => self.scr,
+            _ => 0,
+        }
     }
 
     fn store8(&mut self, bytes_offset: usize, value: u8) {
-        if bytes_offset == 0 {
-            print!("{}", value as char);
+        match bytes_offset {
+            REG_RBR_THR if self.dlab() => self.divisor = (self.divisor & 0xff00) | value as u16,
+            REG_RBR_THR => {
+                self.backend.write(value);
+                self.thre_pending = true;
+            }
+            REG_IER if self.dlab() => {
+                self.divisor = (self.divisor & 0x00ff) | (value as u16) << 8;
+            }
+            REG_IER => {
+                // enabling the interrupt while the holding register is empty raises it
+                if value & IER_THRE != 0 && self.ier & IER_THRE == 0 {
+                    self.thre_pending = true;
+                }
+                self.ier = value & IER_MASK;
+            }
+            REG_IIR_FCR => {
+                self.fifo_enabled = value & FCR_FIFO_ENABLE != 0;
+                if value & FCR_CLEAR_RX != 0 {
+                    self.rx_fifo.clear();
+                }
+            }
+            REG_LCR => self.lcr = value,
+            REG_MCR => self.mcr = value & 0x1f,
+            REG_SCR => self.scr = value,
+            _ => (),
         }
     }
 
-    fn load16(&self, _bytes_offset: usize) -> u16 {
+    fn load16(&mut self, _bytes_offset: usize) -> u16 {
         0
     }
 
     fn store16(&mut self, _bytes_offset: usize, _value: u16) {}
 
-    fn load32(&self, _bytes_offset: usize) -> u32 {
+    fn load32(&mut self, _bytes_offset: usize) -> u32 {
         0
     }
 
-    fn store32(&self, _bytes_offset: usize, _value: u32) {}
+    fn store32(&mut self, _bytes_offset: usize, _value: u32) {}
+
+    fn reset(&mut self) {
+        self.rx_fifo.clear();
+        self.ier = 0;
+        self.lcr = 0;
+        self.mcr = 0;
+        self.scr = 0;
+        self.fifo_enabled = false;
+        self.divisor = 0;
+        self.thre_pending = false;
+    }
 
     fn is_available_addr(&self, addr: u32) -> bool {
         addr >= self.base_addr() && addr < self.base_addr() + self.used_mem_bytes_len() as u32
@@ -62,14 +244,18 @@ impl MmioDeviceInterface for SimpleUart {
     fn used_mem_bytes_len(&self) -> usize {
         self.device_base.used_mem_bytes_len
     }
+
+    fn fdt_nodes(&self) -> Vec<FdtNode> {
+        vec![FdtNode::new_with_addr("serial", self.base_addr())
+            .prop_str("compatible", "ns16550a")
+            .prop_reg(self.base_addr(), self.used_mem_bytes_len())
+            .prop_u32("clock-frequency", CLOCK_FREQUENCY)
+            .prop_u32("interrupts", self.irq)]
+    }
 }
 
 impl Default for SimpleUart {
     fn default() -> Self {
-        Self::new(
-            String::from("simple-uart"),
-            DEFAULT_BASE_ADDR,
-            DEFAULT_MEM_BYTES_LEN,
-        )
+        Self::new_with_backend(Box::new(StdoutBackend))
     }
 }
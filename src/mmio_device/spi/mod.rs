@@ -0,0 +1,312 @@
+use super::{MmioDeviceBase, MmioDeviceInterface, RequestFromDevice};
+use crate::{fdt::FdtNode, ram::Ram};
+use std::{collections::VecDeque, fmt::Debug};
+
+pub mod sd_card;
+
+// same address as the SPI controller with the SD card slot in QEMU's sifive_u machine
+const DEFAULT_BASE_ADDR: u32 = 0x1005_0000;
+const DEFAULT_MEM_BYTES_LEN: usize = 0x1000;
+pub const DEFAULT_IRQ: u32 = 13;
+
+const REG_SCKDIV: usize = 0x00;
+const REG_SCKMODE: usize = 0x04;
+const REG_CSID: usize = 0x10;
+const REG_CSDEF: usize = 0x14;
+const REG_CSMODE: usize = 0x18;
+const REG_DELAY0: usize = 0x28;
+const REG_DELAY1: usize = 0x2c;
+const REG_FMT: usize = 0x40;
+const REG_TXDATA: usize = 0x48;
+const REG_RXDATA: usize = 0x4c;
+const REG_TXMARK: usize = 0x50;
+const REG_RXMARK: usize = 0x54;
+const REG_FCTRL: usize = 0x60;
+const REG_FFMT: usize = 0x64;
+const REG_IE: usize = 0x70;
+const REG_IP: usize = 0x74;
+
+const FIFO_DEPTH: usize = 8;
+pub const NUM_CS: usize = 4;
+
+const CSMODE_AUTO: u32 = 0;
+const CSMODE_HOLD: u32 = 2;
+const CSMODE_OFF: u32 = 3;
+
+// rx fifo is not filled
+const FMT_DIR_TX: u32 = 1 << 3;
+
+const DATA_EMPTY: u32 = 1 << 31;
+
+const IP_TXWM: u32 = 1 << 0;
+const IP_RXWM: u32 = 1 << 1;
+
+// device on the other end of a chip select line
+pub trait SpiDevice: Debug {
+    fn select(&mut self, selected: bool);
+    // full-duplex exchange of one frame (MOSI in, MISO out)
+    fn transfer(&mut self, value: u8) -> u8;
+    fn reset(&mut self);
+    // child node of the controller (reg = chip select)
+    fn fdt_node(&self, cs: u32) -> Option<FdtNode>;
+}
+
+// compatible with the SiFive SPI controller (sifive,spi0), frames are transferred immediately
+#[derive(Debug)]
+pub struct Spi {
+    device_base: MmioDeviceBase,
+    irq: u32,
+    devices: [Option<Box<dyn SpiDevice>>; NUM_CS],
+    rx_fifo: VecDeque<u8>,
+    sckdiv: u32,
+    sckmode: u32,
+    csid: u32,
+    csdef: u32,
+    csmode: u32,
+    delay0: u32,
+    delay1: u32,
+    fmt: u32,
+    txmark: u32,
+    rxmark: u32,
+    fctrl: u32,
+    ffmt: u32,
+    ie: u32,
+    // chip select currently asserted in hold mode
+    selected: Option<usize>,
+}
+
+impl Spi {
+    fn new(device_name: String, base_addr: u32, used_mem_bytes_len: usize, irq: u32) -> Self {
+        let mut spi = Self {
+            device_base: MmioDeviceBase {
+                device_name,
+                base_addr,
+                used_mem_bytes_len,
+            },
+            irq,
+            devices: Default::default(),
+            rx_fifo: VecDeque::new(),
+            sckdiv: 0,
+            sckmode: 0,
+            csid: 0,
+            csdef: 0,
+            csmode: 0,
+            delay0: 0,
+            delay1: 0,
+            fmt: 0,
+            txmark: 0,
+            rxmark: 0,
+            fctrl: 0,
+            ffmt: 0,
+            ie: 0,
+            selected: None,
+        };
+        spi.reset_registers();
+        spi
+    }
+
+    pub fn attach(&mut self, cs: usize, device: Box<dyn SpiDevice>) {
+        self.devices[cs] = Some(device);
+    }
+
+    fn reset_registers(&mut self) {
+        self.rx_fifo.clear();
+        self.sckdiv = 3;
+        self.sckmode = 0;
+        self.csid = 0;
+        self.csdef = (1 << NUM_CS) - 1;
+        self.csmode = CSMODE_AUTO;
+        self.delay0 = 0x0001_0001;
+        self.delay1 = 0x0000_0001;
+        self.fmt = 0x0008_0000; // 8 bits per frame
+        self.txmark = 0;
+        self.rxmark = 0;
+        self.fctrl = 1;
+        self.ffmt = 0x0003_0007;
+        self.ie = 0;
+    }
+
+    fn set_selected(&mut self, selected: Option<usize>) {
+        if self.selected == selected {
+            return;
+        }
+
+        if let Some(device) = self.selected.and_then(|cs| self.devices[cs].as_mut()) {
+            device.select(false);
+        }
+        if let Some(device) = selected.and_then(|cs| self.devices[cs].as_mut()) {
+            device.select(true);
+        }
+        self.selected = selected;
+    }
+
+    // chip select of the next frame (the polarity in csdef doesn't matter to the devices)
+    fn active_cs(&self) -> Option<usize> {
+        let cs = self.csid as usize;
+        if cs < NUM_CS && self.csmode != CSMODE_OFF {
+            Some(cs)
+        } else {
+            None
+        }
+    }
+
+    fn transmit(&mut self, value: u8) {
+        self.set_selected(self.active_cs());
+
+        let rx = match self.selected.and_then(|cs| self.devices[cs].as_mut()) {
+            Some(device) => device.transfer(value),
+            None => 0xff,
+        };
+
+        if self.fmt & FMT_DIR_TX == 0 && self.rx_fifo.len() < FIFO_DEPTH {
+            self.rx_fifo.push_back(rx);
+        }
+
+        if self.csmode == CSMODE_AUTO {
+            self.set_selected(None);
+        }
+    }
+
+    fn ip(&self) -> u32 {
+        let mut ip = 0;
+        // the tx fifo is always empty
+        if self.txmark > 0 {
+            ip |= IP_TXWM;
+        }
+        if self.rx_fifo.len() as u32 > self.rxmark {
+            ip |= IP_RXWM;
+        }
+        ip
+    }
+}
+
+impl MmioDeviceInterface for Spi {
+    fn tick(&mut self, _ram: &mut Ram) {}
+
+    fn poll_request(&mut self) -> Option<RequestFromDevice> {
+        None
+    }
+
+    fn irq(&self) -> Option<u32> {
+        if self.ip() & self.ie != 0 {
+            Some(self.irq)
+        } else {
+            None
+        }
+    }
+
+    fn load8(&mut self, _bytes_offset: usize) -> u8 {
+        0
+    }
+
+    fn store8(&mut self, _bytes_offset: usize, _value: u8) {}
+
+    fn load16(&mut self, _bytes_offset: usize) -> u16 {
+        0
+    }
+
+    fn store16(&mut self, _bytes_offset: usize, _value: u16) {}
+
+    fn load32(&mut self, bytes_offset: usize) -> u32 {
+        match bytes_offset {
+            REG_SCKDIV => self.sckdiv,
+            REG_SCKMODE => self.sckmode,
+            REG_CSID => self.csid,
+            REG_CSDEF => self.csdef,
+            REG_CSMODE => self.csmode,
+            REG_DELAY0 => self.delay0,
+            REG_DELAY1 => self.delay1,
+            REG_FMT => self.fmt,
+            // never full
+            REG_TXDATA => 0,
+            REG_RXDATA => match self.rx_fifo.pop_front() {
+                Some(value) => value as u32,
+                None => DATA_EMPTY,
+            },
+            REG_TXMARK => self.txmark,
+            REG_RXMARK => self.rxmark,
+            REG_FCTRL => self.fctrl,
+            REG_FFMT => self.ffmt,
+            REG_IE => self.ie,
+            REG_IP => self.ip(),
+            _ => 0,
+        }
+    }
+
+    fn store32(&mut self, bytes_offset: usize, value: u32) {
+        match bytes_offset {
+            REG_SCKDIV => self.sckdiv = value & 0xfff,
+            REG_SCKMODE => self.sckmode = value & 0x3,
+            REG_CSID => self.csid = value,
+            REG_CSDEF => self.csdef = value & ((1 << NUM_CS) - 1),
+            REG_CSMODE => self.csmode = value & 0x3,
+            REG_DELAY0 => self.delay0 = value,
+            REG_DELAY1 => self.delay1 = value,
+            REG_FMT => self.fmt = value,
+            REG_TXDATA => self.transmit(value as u8),
+            REG_TXMARK => self.txmark = value & 0x7,
+            REG_RXMARK => self.rxmark = value & 0x7,
+            REG_FCTRL => self.fctrl = value & 1,
+            REG_FFMT => self.ffmt = value,
+            REG_IE => self.ie = value & (IP_TXWM | IP_RXWM),
+            _ => return,
+        }
+
+        // leaving hold mode (or selecting another device) releases the chip select
+        if self.csmode != CSMODE_HOLD || self.active_cs() != self.selected {
+            self.set_selected(None);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.set_selected(None);
+        self.reset_registers();
+
+        for device in self.devices.iter_mut().flatten() {
+            device.reset();
+        }
+    }
+
+    fn is_available_addr(&self, addr: u32) -> bool {
+        addr >= self.base_addr() && addr < self.base_addr() + self.used_mem_bytes_len() as u32
+    }
+
+    fn device_name(&self) -> &str {
+        &self.device_base.device_name
+    }
+
+    fn base_addr(&self) -> u32 {
+        self.device_base.base_addr
+    }
+
+    fn used_mem_bytes_len(&self) -> usize {
+        self.device_base.used_mem_bytes_len
+    }
+
+    fn fdt_nodes(&self) -> Vec<FdtNode> {
+        let node = FdtNode::new_with_addr("spi", self.base_addr())
+            .prop_strs("compatible", &["sifive,fu540-c000-spi", "sifive,spi0"])
+            .prop_reg(self.base_addr(), self.used_mem_bytes_len())
+            .prop_u32("interrupts", self.irq)
+            .prop_u32("#address-cells", 1)
+            .prop_u32("#size-cells", 0);
+
+        vec![self
+            .devices
+            .iter()
+            .enumerate()
+            .filter_map(|(cs, device)| device.as_ref()?.fdt_node(cs as u32))
+            .fold(node, FdtNode::child)]
+    }
+}
+
+impl Default for Spi {
+    fn default() -> Self {
+        Self::new(
+            String::from("spi"),
+            DEFAULT_BASE_ADDR,
+            DEFAULT_MEM_BYTES_LEN,
+            DEFAULT_IRQ,
+        )
+    }
+}
@@ -0,0 +1,375 @@
+use super::SpiDevice;
+use crate::{
+    fdt::FdtNode,
+    mmio_device::virtio::blk::{DiskImage, SECTOR_SIZE},
+};
+use std::collections::VecDeque;
+
+const CMD_GO_IDLE_STATE: u8 = 0;
+const CMD_SEND_IF_COND: u8 = 8;
+const CMD_SEND_CSD: u8 = 9;
+const CMD_SEND_CID: u8 = 10;
+const CMD_STOP_TRANSMISSION: u8 = 12;
+const CMD_SEND_STATUS: u8 = 13;
+const CMD_SET_BLOCKLEN: u8 = 16;
+const CMD_READ_SINGLE_BLOCK: u8 = 17;
+const CMD_READ_MULTIPLE_BLOCK: u8 = 18;
+const CMD_WRITE_BLOCK: u8 = 24;
+const CMD_WRITE_MULTIPLE_BLOCK: u8 = 25;
+const CMD_APP_CMD: u8 = 55;
+const CMD_READ_OCR: u8 = 58;
+const CMD_CRC_ON_OFF: u8 = 59;
+const ACMD_SET_WR_BLK_ERASE_COUNT: u8 = 23;
+const ACMD_SD_SEND_OP_COND: u8 = 41;
+
+const R1_IDLE: u8 = 1 << 0;
+const R1_ILLEGAL_COMMAND: u8 = 1 << 2;
+const R1_ADDRESS_ERROR: u8 = 1 << 5;
+const R1_PARAMETER_ERROR: u8 = 1 << 6;
+
+const TOKEN_START_BLOCK: u8 = 0xfe;
+const TOKEN_START_BLOCK_MULTIPLE: u8 = 0xfc;
+const TOKEN_STOP_TRAN: u8 = 0xfd;
+
+const DATA_ACCEPTED: u8 = 0x05;
+const DATA_WRITE_ERROR: u8 = 0x0d;
+
+// powered up, high capacity (block addressing)
+const OCR: u32 = 0xc0ff_8000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Command,
+    // streaming blocks until CMD12
+    ReadMultiple { block: u64 },
+    // waiting for a start block token
+    WriteWait { block: u64, multiple: bool },
+    // data block followed by a 2 bytes CRC
+    WriteData { block: u64, multiple: bool },
+}
+
+// SDHC card in SPI mode backed by a raw image (CRCs are generated but never checked)
+#[derive(Debug)]
+pub struct SdCard {
+    disk: DiskImage,
+    selected: bool,
+    idle: bool,
+    app_cmd: bool,
+    state: State,
+    cmd: Vec<u8>,
+    data: Vec<u8>,
+    response: VecDeque<u8>,
+}
+
+impl SdCard {
+    pub fn new(disk: DiskImage) -> Self {
+        Self {
+            disk,
+            selected: false,
+            idle: true,
+            app_cmd: false,
+            state: State::Command,
+            cmd: Vec::new(),
+            data: Vec::new(),
+            response: VecDeque::new(),
+        }
+    }
+
+    fn r1(&self, flags: u8) -> u8 {
+        if self.idle {
+            flags | R1_IDLE
+        } else {
+            flags
+        }
+    }
+
+    // Ncr (one byte) + response
+    fn respond(&mut self, bytes: &[u8]) {
+        self.response.push_back(0xff);
+        self.response.extend(bytes);
+    }
+
+    // start block token + data + CRC16
+    fn push_data_block(&mut self, data: &[u8]) {
+        self.response.push_back(0xff);
+        self.response.push_back(TOKEN_START_BLOCK);
+        self.response.extend(data);
+        self.response.extend(crc16(data).to_be_bytes());
+    }
+
+    fn read_block(&mut self, block: u64) -> bool {
+        let mut data = vec![0; SECTOR_SIZE];
+        if self.disk.read(block, &mut data).is_err() {
+            return false;
+        }
+
+        self.push_data_block(&data);
+        true
+    }
+
+    fn csd(&self) -> [u8; 16] {
+        // capacity = (C_SIZE + 1) * 512 KiB
+        let c_size = (self.disk.sectors() / 1024).saturating_sub(1) as u32;
+        let mut csd = [
+            0x40, // CSD_STRUCTURE = 1 (version 2.0)
+            0x0e, // TAAC
+            0x00, // NSAC
+            0x32, // TRAN_SPEED (25 MHz)
+            0x5b, // CCC
+            0x59, // CCC, READ_BL_LEN = 9
+            0x00,
+            (c_size >> 16) as u8 & 0x3f,
+            (c_size >> 8) as u8,
+            c_size as u8,
+            0x7f, // ERASE_BLK_EN, SECTOR_SIZE
+            0x80, // SECTOR_SIZE, WP_GRP_SIZE
+            0x0a, // R2W_FACTOR, WRITE_BL_LEN = 9
+            0x40, // WRITE_BL_LEN
+            0x00,
+            0x00,
+        ];
+        csd[15] = crc7(&csd[..15]) << 1 | 1;
+        csd
+    }
+
+    fn cid(&self) -> [u8; 16] {
+        let mut cid = [0; 16];
+        cid[1..3].copy_from_slice(b"FR"); // OID
+        cid[3..8].copy_from_slice(b"FRISC"); // PNM
+        cid[8] = 0x10; // PRV 1.0
+        cid[15] = crc7(&cid[..15]) << 1 | 1;
+        cid
+    }
+
+    fn handle_command(&mut self, cmd: u8, arg: u32) {
+        let app_cmd = self.app_cmd;
+        self.app_cmd = false;
+
+        if app_cmd {
+            match cmd {
+                ACMD_SD_SEND_OP_COND => {
+                    self.idle = false;
+                    self.respond(&[self.r1(0)]);
+                }
+                ACMD_SET_WR_BLK_ERASE_COUNT => self.respond(&[self.r1(0)]),
+                _ => self.respond(&[self.r1(R1_ILLEGAL_COMMAND)]),
+            }
+            return;
+        }
+
+        match cmd {
+            CMD_GO_IDLE_STATE => {
+                self.idle = true;
+                self.state = State::Command;
+                self.respond(&[R1_IDLE]);
+            }
+            // voltage accepted, echo back the check pattern
+            CMD_SEND_IF_COND => {
+                let r1 = self.r1(0);
+                self.respond(&[r1, 0x00, 0x00, (arg >> 8) as u8 & 0xf, arg as u8]);
+            }
+            CMD_APP_CMD => {
+                self.app_cmd = true;
+                self.respond(&[self.r1(0)]);
+            }
+            CMD_READ_OCR => {
+                let r1 = self.r1(0);
+                self.respond(&[r1]);
+                self.response.extend(OCR.to_be_bytes());
+            }
+            CMD_CRC_ON_OFF => self.respond(&[self.r1(0)]),
+            _ if self.idle => self.respond(&[self.r1(R1_ILLEGAL_COMMAND)]),
+            CMD_SEND_CSD => {
+                self.respond(&[0x00]);
+                self.push_data_block(&self.csd());
+            }
+            CMD_SEND_CID => {
+                self.respond(&[0x00]);
+                self.push_data_block(&self.cid());
+            }
+            CMD_SEND_STATUS => self.respond(&[0x00, 0x00]),
+            CMD_SET_BLOCKLEN => {
+                let r1 = if arg as usize == SECTOR_SIZE {
+                    0
+                } else {
+                    R1_PARAMETER_ERROR
+                };
+                self.respond(&[r1]);
+            }
+            CMD_READ_SINGLE_BLOCK | CMD_READ_MULTIPLE_BLOCK => {
+                let block = arg as u64;
+                if block >= self.disk.sectors() {
+                    self.respond(&[R1_ADDRESS_ERROR]);
+                    return;
+                }
+
+                self.respond(&[0x00]);
+                self.read_block(block);
+                if cmd == CMD_READ_MULTIPLE_BLOCK {
+                    self.state = State::ReadMultiple { block: block + 1 };
+                }
+            }
+            CMD_WRITE_BLOCK | CMD_WRITE_MULTIPLE_BLOCK => {
+                let block = arg as u64;
+                if block >= self.disk.sectors() {
+                    self.respond(&[R1_ADDRESS_ERROR]);
+                    return;
+                }
+
+                self.respond(&[0x00]);
+                self.state = State::WriteWait {
+                    block,
+                    multiple: cmd == CMD_WRITE_MULTIPLE_BLOCK,
+                };
+            }
+            // R1b (stuff byte + R1 + busy)
+            CMD_STOP_TRANSMISSION => {
+                self.response.clear();
+                self.state = State::Command;
+                self.respond(&[0xff, 0x00, 0x00]);
+            }
+            _ => self.respond(&[R1_ILLEGAL_COMMAND]),
+        }
+    }
+
+    fn receive_command_byte(&mut self, value: u8) {
+        // a command starts with 0b01
+        if self.cmd.is_empty() && value & 0xc0 != 0x40 {
+            return;
+        }
+
+        self.cmd.push(value);
+        if self.cmd.len() == 6 {
+            let cmd = self.cmd[0] & 0x3f;
+            let arg = u32::from_be_bytes([self.cmd[1], self.cmd[2], self.cmd[3], self.cmd[4]]);
+            self.cmd.clear();
+            self.handle_command(cmd, arg);
+        }
+    }
+
+    fn receive(&mut self, value: u8) {
+        match self.state {
+            State::Command => self.receive_command_byte(value),
+            State::ReadMultiple { block } => {
+                self.receive_command_byte(value);
+
+                // the next block is sent once the previous one has been clocked out
+                if self.state == (State::ReadMultiple { block }) && self.response.is_empty() {
+                    if self.read_block(block) {
+                        self.state = State::ReadMultiple { block: block + 1 };
+                    } else {
+                        self.state = State::Command;
+                    }
+                }
+            }
+            State::WriteWait { block, multiple } => match value {
+                TOKEN_START_BLOCK if !multiple => {
+                    self.state = State::WriteData { block, multiple }
+                }
+                TOKEN_START_BLOCK_MULTIPLE if multiple => {
+                    self.state = State::WriteData { block, multiple }
+                }
+                // busy for one byte
+                TOKEN_STOP_TRAN if multiple => {
+                    self.state = State::Command;
+                    self.response.extend([0xff, 0x00]);
+                }
+                _ => (),
+            },
+            State::WriteData { block, multiple } => {
+                self.data.push(value);
+                if self.data.len() < SECTOR_SIZE + 2 {
+                    return;
+                }
+
+                let data = std::mem::take(&mut self.data);
+                let status = match self.disk.write(block, &data[..SECTOR_SIZE]) {
+                    Ok(()) => DATA_ACCEPTED,
+                    Err(_) => DATA_WRITE_ERROR,
+                };
+                // data response + busy
+                self.response.extend([status, 0x00]);
+
+                self.state = if multiple && status == DATA_ACCEPTED {
+                    State::WriteWait {
+                        block: block + 1,
+                        multiple,
+                    }
+                } else {
+                    State::Command
+                };
+            }
+        }
+    }
+}
+
+impl SpiDevice for SdCard {
+    // a partially received command is discarded on deselect
+    fn select(&mut self, selected: bool) {
+        self.selected = selected;
+        if !selected {
+            self.cmd.clear();
+        }
+    }
+
+    fn transfer(&mut self, value: u8) -> u8 {
+        if !self.selected {
+            return 0xff;
+        }
+
+        let out = self.response.pop_front().unwrap_or(0xff);
+        self.receive(value);
+        out
+    }
+
+    fn reset(&mut self) {
+        self.selected = false;
+        self.idle = true;
+        self.app_cmd = false;
+        self.state = State::Command;
+        self.cmd.clear();
+        self.data.clear();
+        self.response.clear();
+    }
+
+    fn fdt_node(&self, cs: u32) -> Option<FdtNode> {
+        Some(
+            FdtNode::new_with_addr("mmc", cs)
+                .prop_str("compatible", "mmc-spi-slot")
+                .prop_u32("reg", cs)
+                .prop_u32("spi-max-frequency", 20_000_000)
+                .prop_cells("voltage-ranges", &[3300, 3300]),
+        )
+    }
+}
+
+// x^7 + x^3 + 1
+fn crc7(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        for i in (0..8).rev() {
+            let bit = (byte >> i & 1) ^ (crc >> 6 & 1);
+            crc = crc << 1 & 0x7f;
+            if bit != 0 {
+                crc ^= 0x09;
+            }
+        }
+    }
+    crc
+}
+
+// CRC-16-CCITT (XModem)
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
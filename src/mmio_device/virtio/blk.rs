@@ -0,0 +1,230 @@
+use super::{queue::Virtqueue, VirtioDevice};
+use crate::ram::Ram;
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+const DEVICE_ID: u32 = 2;
+
+pub const SECTOR_SIZE: usize = 512;
+
+const VIRTIO_BLK_F_RO: u64 = 1 << 5;
+const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;
+
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+const VIRTIO_BLK_T_GET_ID: u32 = 8;
+
+const VIRTIO_BLK_S_OK: u8 = 0;
+const VIRTIO_BLK_S_IOERR: u8 = 1;
+const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+
+const REQ_HEADER_LEN: usize = 16;
+const ID_LEN: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskMode {
+    ReadWrite,
+    ReadOnly,
+    // writes are kept in memory and the image file is never modified
+    CopyOnWrite,
+}
+
+// raw disk image file
+#[derive(Debug)]
+pub struct DiskImage {
+    file: File,
+    sectors: u64,
+    mode: DiskMode,
+    overlay: HashMap<u64, Box<[u8; SECTOR_SIZE]>>,
+}
+
+impl DiskImage {
+    pub fn open<P: AsRef<Path>>(path: P, mode: DiskMode) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(mode == DiskMode::ReadWrite)
+            .open(path)?;
+        let sectors = file.metadata()?.len() / SECTOR_SIZE as u64;
+
+        Ok(Self {
+            file,
+            sectors,
+            mode,
+            overlay: HashMap::new(),
+        })
+    }
+
+    pub fn sectors(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_sector(&mut self, sector: u64, buf: &mut [u8]) -> anyhow::Result<()> {
+        if let Some(data) = self.overlay.get(&sector) {
+            buf.copy_from_slice(&data[..]);
+            return Ok(());
+        }
+
+        self.file
+            .seek(SeekFrom::Start(sector * SECTOR_SIZE as u64))?;
+        self.file.read_exact(buf)?;
+        Ok(())
+    }
+
+    fn write_sector(&mut self, sector: u64, buf: &[u8]) -> anyhow::Result<()> {
+        match self.mode {
+            DiskMode::ReadWrite => {
+                self.file
+                    .seek(SeekFrom::Start(sector * SECTOR_SIZE as u64))?;
+                self.file.write_all(buf)?;
+            }
+            DiskMode::ReadOnly => return Err(anyhow::anyhow!("Disk is read-only")),
+            DiskMode::CopyOnWrite => {
+                let mut data = Box::new([0; SECTOR_SIZE]);
+                data.copy_from_slice(buf);
+                self.overlay.insert(sector, data);
+            }
+        }
+
+        Ok(())
+    }
+
+    // the sector comes from the guest, the end may not even fit in a u64
+    fn check_range(&self, sector: u64, len: usize) -> anyhow::Result<()> {
+        match sector.checked_add((len / SECTOR_SIZE) as u64) {
+            Some(end) if end <= self.sectors => Ok(()),
+            _ => Err(anyhow::anyhow!("Sector is out of range")),
+        }
+    }
+
+    pub fn read(&mut self, sector: u64, buf: &mut [u8]) -> anyhow::Result<()> {
+        self.check_range(sector, buf.len())?;
+
+        for (i, chunk) in buf.chunks_exact_mut(SECTOR_SIZE).enumerate() {
+            self.read_sector(sector + i as u64, chunk)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn write(&mut self, sector: u64, buf: &[u8]) -> anyhow::Result<()> {
+        self.check_range(sector, buf.len())?;
+
+        for (i, chunk) in buf.chunks_exact(SECTOR_SIZE).enumerate() {
+            self.write_sector(sector + i as u64, chunk)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        if self.mode == DiskMode::ReadWrite {
+            self.file.sync_data()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct VirtioBlk {
+    disk: DiskImage,
+}
+
+impl VirtioBlk {
+    pub fn new(disk: DiskImage) -> Self {
+        Self { disk }
+    }
+
+    // returns the status and the data for the device-writable buffers
+    fn handle_request(&mut self, header: &[u8], data: &[u8], data_len: usize) -> (u8, Vec<u8>) {
+        let req_type = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let sector = u64::from_le_bytes(header[8..16].try_into().unwrap());
+
+        match req_type {
+            VIRTIO_BLK_T_IN => {
+                let mut buf = vec![0; data_len / SECTOR_SIZE * SECTOR_SIZE];
+                match self.disk.read(sector, &mut buf) {
+                    Ok(()) => (VIRTIO_BLK_S_OK, buf),
+                    Err(_) => (VIRTIO_BLK_S_IOERR, Vec::new()),
+                }
+            }
+            VIRTIO_BLK_T_OUT => match self.disk.write(sector, data) {
+                Ok(()) => (VIRTIO_BLK_S_OK, Vec::new()),
+                Err(_) => (VIRTIO_BLK_S_IOERR, Vec::new()),
+            },
+            VIRTIO_BLK_T_FLUSH => match self.disk.flush() {
+                Ok(()) => (VIRTIO_BLK_S_OK, Vec::new()),
+                Err(_) => (VIRTIO_BLK_S_IOERR, Vec::new()),
+            },
+            VIRTIO_BLK_T_GET_ID => {
+                let mut id = b"frisc-virtio-blk".to_vec();
+                id.resize(ID_LEN.min(data_len), 0);
+                (VIRTIO_BLK_S_OK, id)
+            }
+            _ => (VIRTIO_BLK_S_UNSUPP, Vec::new()),
+        }
+    }
+}
+
+impl VirtioDevice for VirtioBlk {
+    fn device_id(&self) -> u32 {
+        DEVICE_ID
+    }
+
+    fn device_features(&self) -> u64 {
+        match self.disk.mode {
+            DiskMode::ReadOnly => VIRTIO_BLK_F_RO | VIRTIO_BLK_F_FLUSH,
+            _ => VIRTIO_BLK_F_FLUSH,
+        }
+    }
+
+    fn num_queues(&self) -> usize {
+        1
+    }
+
+    // capacity (in 512-byte sectors)
+    fn load_config(&self, offset: usize) -> u8 {
+        match offset {
+            0..=7 => self.disk.sectors().to_le_bytes()[offset],
+            _ => 0,
+        }
+    }
+
+    fn store_config(&mut self, _offset: usize, _value: u8) {}
+
+    fn process_queues(&mut self, queues: &mut [Virtqueue], ram: &mut Ram) -> bool {
+        let queue = &mut queues[0];
+        if !queue.notified {
+            return false;
+        }
+
+        let mut interrupt = false;
+        while let Some(chain) = queue.pop(ram) {
+            let readable = chain.read(ram);
+            // the last writable byte is the status
+            let data_len = chain.writable_len().saturating_sub(1);
+
+            let (status, mut response) = if readable.len() >= REQ_HEADER_LEN {
+                let (header, data) = readable.split_at(REQ_HEADER_LEN);
+                self.handle_request(header, data, data_len)
+            } else {
+                (VIRTIO_BLK_S_IOERR, Vec::new())
+            };
+
+            response.resize(data_len, 0);
+            response.push(status);
+            let written = chain.write(ram, &response);
+
+            interrupt |= queue.push(ram, chain.head, written);
+        }
+
+        interrupt
+    }
+
+    fn reset(&mut self) {}
+}
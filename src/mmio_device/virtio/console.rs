@@ -0,0 +1,99 @@
+use super::{queue::Virtqueue, VirtioDevice};
+use crate::{ram::Ram, serial::SerialBackend};
+use std::collections::VecDeque;
+
+const DEVICE_ID: u32 = 3;
+
+const RECEIVEQ: usize = 0;
+const TRANSMITQ: usize = 1;
+
+const RX_BUF_LEN: usize = 4096;
+
+// single port console (no VIRTIO_CONSOLE_F_MULTIPORT)
+#[derive(Debug)]
+pub struct VirtioConsole {
+    backend: Box<dyn SerialBackend>,
+    rx_buf: VecDeque<u8>,
+}
+
+impl VirtioConsole {
+    pub fn new(backend: Box<dyn SerialBackend>) -> Self {
+        Self {
+            backend,
+            rx_buf: VecDeque::with_capacity(RX_BUF_LEN),
+        }
+    }
+
+    fn transmit(&mut self, queue: &mut Virtqueue, ram: &mut Ram) -> bool {
+        let mut interrupt = false;
+        while let Some(chain) = queue.pop(ram) {
+            for value in chain.read(ram) {
+                self.backend.write(value);
+            }
+            interrupt |= queue.push(ram, chain.head, 0);
+        }
+
+        interrupt
+    }
+
+    fn receive(&mut self, queue: &mut Virtqueue, ram: &mut Ram) -> bool {
+        while self.rx_buf.len() < RX_BUF_LEN {
+            match self.backend.read() {
+                Some(value) => self.rx_buf.push_back(value),
+                None => break,
+            }
+        }
+
+        let mut interrupt = false;
+        while !self.rx_buf.is_empty() {
+            let chain = match queue.pop(ram) {
+                Some(chain) => chain,
+                None => break,
+            };
+
+            let len = chain.writable_len().min(self.rx_buf.len());
+            let data: Vec<u8> = self.rx_buf.drain(..len).collect();
+            let written = chain.write(ram, &data);
+            interrupt |= queue.push(ram, chain.head, written);
+        }
+
+        interrupt
+    }
+}
+
+impl VirtioDevice for VirtioConsole {
+    fn device_id(&self) -> u32 {
+        DEVICE_ID
+    }
+
+    fn device_features(&self) -> u64 {
+        0
+    }
+
+    fn num_queues(&self) -> usize {
+        2
+    }
+
+    // cols, rows, max_nr_ports and emerg_wr are not supported
+    fn load_config(&self, _offset: usize) -> u8 {
+        0
+    }
+
+    fn store_config(&mut self, _offset: usize, _value: u8) {}
+
+    fn process_queues(&mut self, queues: &mut [Virtqueue], ram: &mut Ram) -> bool {
+        let mut interrupt = false;
+
+        if queues[TRANSMITQ].notified {
+            interrupt |= self.transmit(&mut queues[TRANSMITQ], ram);
+        }
+        // host input can arrive at any time
+        interrupt |= self.receive(&mut queues[RECEIVEQ], ram);
+
+        interrupt
+    }
+
+    fn reset(&mut self) {
+        self.rx_buf.clear();
+    }
+}
@@ -0,0 +1,342 @@
+use super::{MmioDeviceBase, MmioDeviceInterface, RequestFromDevice};
+use crate::{fdt::FdtNode, ram::Ram};
+use queue::{Virtqueue, QUEUE_NUM_MAX};
+use std::fmt::Debug;
+
+pub mod blk;
+pub mod console;
+pub mod net;
+pub mod p9;
+pub mod queue;
+pub mod rng;
+
+// same layout as QEMU's virt machine (slot n is at BASE + STRIDE * n with irq IRQ_BASE + n)
+pub const VIRTIO_MMIO_BASE_ADDR: u32 = 0x1000_1000;
+pub const VIRTIO_MMIO_STRIDE: u32 = 0x1000;
+pub const VIRTIO_MMIO_IRQ_BASE: u32 = 1;
+const VIRTIO_MMIO_MEM_BYTES_LEN: usize = 0x200;
+
+const MAGIC_VALUE: u32 = 0x7472_6976; // "virt"
+const VERSION: u32 = 2;
+const VENDOR_ID: u32 = 0x6373_6966; // "fisc"
+
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+// virtio-mmio register offsets
+const REG_MAGIC_VALUE: usize = 0x000;
+const REG_VERSION: usize = 0x004;
+const REG_DEVICE_ID: usize = 0x008;
+const REG_VENDOR_ID: usize = 0x00c;
+const REG_DEVICE_FEATURES: usize = 0x010;
+const REG_DEVICE_FEATURES_SEL: usize = 0x014;
+const REG_DRIVER_FEATURES: usize = 0x020;
+const REG_DRIVER_FEATURES_SEL: usize = 0x024;
+const REG_QUEUE_SEL: usize = 0x030;
+const REG_QUEUE_NUM_MAX: usize = 0x034;
+const REG_QUEUE_NUM: usize = 0x038;
+const REG_QUEUE_READY: usize = 0x044;
+const REG_QUEUE_NOTIFY: usize = 0x050;
+const REG_INTERRUPT_STATUS: usize = 0x060;
+const REG_INTERRUPT_ACK: usize = 0x064;
+const REG_STATUS: usize = 0x070;
+const REG_QUEUE_DESC_LOW: usize = 0x080;
+const REG_QUEUE_DESC_HIGH: usize = 0x084;
+const REG_QUEUE_DRIVER_LOW: usize = 0x090;
+const REG_QUEUE_DRIVER_HIGH: usize = 0x094;
+const REG_QUEUE_DEVICE_LOW: usize = 0x0a0;
+const REG_QUEUE_DEVICE_HIGH: usize = 0x0a4;
+const REG_CONFIG_GENERATION: usize = 0x0fc;
+const REG_CONFIG: usize = 0x100;
+
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_DEVICE_NEEDS_RESET: u32 = 0x40;
+
+const INTERRUPT_USED_BUFFER: u32 = 1;
+const INTERRUPT_CONFIG_CHANGE: u32 = 2;
+
+// device side of a virtio device, the transport owns the virtqueues
+pub trait VirtioDevice: Debug {
+    fn device_id(&self) -> u32;
+    // VIRTIO_F_VERSION_1 is added by the transport
+    fn device_features(&self) -> u64;
+    fn num_queues(&self) -> usize;
+    fn load_config(&self, offset: usize) -> u8;
+    fn store_config(&mut self, offset: usize, value: u8);
+    // called every tick after DRIVER_OK, returns whether an interrupt should be raised
+    fn process_queues(&mut self, queues: &mut [Virtqueue], ram: &mut Ram) -> bool;
+    fn reset(&mut self);
+}
+
+// virtio-mmio transport (version 2)
+#[derive(Debug)]
+pub struct VirtioMmio {
+    device_base: MmioDeviceBase,
+    irq: u32,
+    device: Box<dyn VirtioDevice>,
+    queues: Vec<Virtqueue>,
+    device_features_sel: u32,
+    driver_features: u64,
+    driver_features_sel: u32,
+    queue_sel: u32,
+    interrupt_status: u32,
+    status: u32,
+}
+
+impl VirtioMmio {
+    pub fn new(base_addr: u32, irq: u32, device: Box<dyn VirtioDevice>) -> Self {
+        let queues = vec![Virtqueue::default(); device.num_queues()];
+
+        Self {
+            device_base: MmioDeviceBase {
+                device_name: String::from("virtio-mmio"),
+                base_addr,
+                used_mem_bytes_len: VIRTIO_MMIO_MEM_BYTES_LEN,
+            },
+            irq,
+            device,
+            queues,
+            device_features_sel: 0,
+            driver_features: 0,
+            driver_features_sel: 0,
+            queue_sel: 0,
+            interrupt_status: 0,
+            status: 0,
+        }
+    }
+
+    pub fn new_with_slot(slot: u32, device: Box<dyn VirtioDevice>) -> Self {
+        Self::new(
+            VIRTIO_MMIO_BASE_ADDR + VIRTIO_MMIO_STRIDE * slot,
+            VIRTIO_MMIO_IRQ_BASE + slot,
+            device,
+        )
+    }
+
+    fn device_features(&self) -> u64 {
+        self.device.device_features() | VIRTIO_F_VERSION_1
+    }
+
+    fn selected_queue(&mut self) -> Option<&mut Virtqueue> {
+        self.queues.get_mut(self.queue_sel as usize)
+    }
+
+    fn set_low(value: &mut u64, low: u32) {
+        *value = (*value & !0xffff_ffff) | low as u64;
+    }
+
+    fn set_high(value: &mut u64, high: u32) {
+        *value = (*value & 0xffff_ffff) | (high as u64) << 32;
+    }
+
+    fn reset_device(&mut self) {
+        self.device.reset();
+        for queue in &mut self.queues {
+            queue.reset();
+        }
+        self.device_features_sel = 0;
+        self.driver_features = 0;
+        self.driver_features_sel = 0;
+        self.queue_sel = 0;
+        self.interrupt_status = 0;
+        self.status = 0;
+    }
+}
+
+impl MmioDeviceInterface for VirtioMmio {
+    fn tick(&mut self, ram: &mut Ram) {
+        if self.status & STATUS_DRIVER_OK == 0 || self.status & STATUS_DEVICE_NEEDS_RESET != 0 {
+            return;
+        }
+
+        // the driver is told through a configuration change and has to reset the device
+        if self.queues.iter().any(|q| q.ready && !q.is_addressable()) {
+            self.status |= STATUS_DEVICE_NEEDS_RESET;
+            self.interrupt_status |= INTERRUPT_CONFIG_CHANGE;
+            return;
+        }
+
+        if self.device.process_queues(&mut self.queues, ram) {
+            self.interrupt_status |= INTERRUPT_USED_BUFFER;
+        }
+
+        for queue in &mut self.queues {
+            queue.notified = false;
+        }
+    }
+
+    fn poll_request(&mut self) -> Option<RequestFromDevice> {
+        None
+    }
+
+    fn irq(&self) -> Option<u32> {
+        if self.interrupt_status != 0 {
+            Some(self.irq)
+        } else {
+            None
+        }
+    }
+
+    fn load8(&mut self, bytes_offset: usize) -> u8 {
+        match bytes_offset {
+            o if o >= REG_CONFIG => self.device.load_config(o - REG_CONFIG),
+            _ => 0,
+        }
+    }
+
+    fn store8(&mut self, bytes_offset: usize, value: u8) {
+        if bytes_offset >= REG_CONFIG {
+            self.device.store_config(bytes_offset - REG_CONFIG, value);
+        }
+    }
+
+    fn load16(&mut self, bytes_offset: usize) -> u16 {
+        u16::from_le_bytes([self.load8(bytes_offset), self.load8(bytes_offset + 1)])
+    }
+
+    fn store16(&mut self, bytes_offset: usize, value: u16) {
+        for (i, b) in value.to_le_bytes().iter().enumerate() {
+            self.store8(bytes_offset + i, *b);
+        }
+    }
+
+    fn load32(&mut self, bytes_offset: usize) -> u32 {
+        match bytes_offset {
+            REG_MAGIC_VALUE => MAGIC_VALUE,
+            REG_VERSION => VERSION,
+            REG_DEVICE_ID => self.device.device_id(),
+            REG_VENDOR_ID => VENDOR_ID,
+            REG_DEVICE_FEATURES => match self.device_features_sel {
+                0 => self.device_features() as u32,
+                1 => (self.device_features() >> 32) as u32,
+                _ => 0,
+            },
+            REG_QUEUE_NUM_MAX => match self.selected_queue() {
+                Some(_) => QUEUE_NUM_MAX as u32,
+                None => 0,
+            },
+            REG_QUEUE_NUM => self.selected_queue().map_or(0, |q| q.num as u32),
+            REG_QUEUE_READY => self.selected_queue().map_or(0, |q| q.ready as u32),
+            REG_INTERRUPT_STATUS => self.interrupt_status,
+            REG_STATUS => self.status,
+            REG_QUEUE_DESC_LOW => self.selected_queue().map_or(0, |q| q.desc_addr as u32),
+            REG_QUEUE_DESC_HIGH => self
+                .selected_queue()
+                .map_or(0, |q| (q.desc_addr >> 32) as u32),
+            REG_QUEUE_DRIVER_LOW => self.selected_queue().map_or(0, |q| q.driver_addr as u32),
+            REG_QUEUE_DRIVER_HIGH => self
+                .selected_queue()
+                .map_or(0, |q| (q.driver_addr >> 32) as u32),
+            REG_QUEUE_DEVICE_LOW => self.selected_queue().map_or(0, |q| q.device_addr as u32),
+            REG_QUEUE_DEVICE_HIGH => self
+                .selected_queue()
+                .map_or(0, |q| (q.device_addr >> 32) as u32),
+            REG_CONFIG_GENERATION => 0,
+            o if o >= REG_CONFIG => u32::from_le_bytes([
+                self.load8(o),
+                self.load8(o + 1),
+                self.load8(o + 2),
+                self.load8(o + 3),
+            ]),
+            _ => 0,
+        }
+    }
+
+    fn store32(&mut self, bytes_offset: usize, value: u32) {
+        match bytes_offset {
+            REG_DEVICE_FEATURES_SEL => self.device_features_sel = value,
+            REG_DRIVER_FEATURES => match self.driver_features_sel {
+                0 => Self::set_low(&mut self.driver_features, value),
+                1 => Self::set_high(&mut self.driver_features, value),
+                _ => (),
+            },
+            REG_DRIVER_FEATURES_SEL => self.driver_features_sel = value,
+            REG_QUEUE_SEL => self.queue_sel = value,
+            REG_QUEUE_NUM => {
+                if let Some(q) = self.selected_queue() {
+                    q.num = (value as u16).min(QUEUE_NUM_MAX);
+                }
+            }
+            REG_QUEUE_READY => {
+                if let Some(q) = self.selected_queue() {
+                    q.ready = value & 1 != 0;
+                }
+            }
+            REG_QUEUE_NOTIFY => {
+                if let Some(q) = self.queues.get_mut(value as usize) {
+                    q.notified = true;
+                }
+            }
+            REG_INTERRUPT_ACK => self.interrupt_status &= !value,
+            REG_STATUS => {
+                if value == 0 {
+                    self.reset_device();
+                } else {
+                    self.status = value;
+                }
+            }
+            REG_QUEUE_DESC_LOW => {
+                if let Some(q) = self.selected_queue() {
+                    Self::set_low(&mut q.desc_addr, value);
+                }
+            }
+            REG_QUEUE_DESC_HIGH => {
+                if let Some(q) = self.selected_queue() {
+                    Self::set_high(&mut q.desc_addr, value);
+                }
+            }
+            REG_QUEUE_DRIVER_LOW => {
+                if let Some(q) = self.selected_queue() {
+                    Self::set_low(&mut q.driver_addr, value);
+                }
+            }
+            REG_QUEUE_DRIVER_HIGH => {
+                if let Some(q) = self.selected_queue() {
+                    Self::set_high(&mut q.driver_addr, value);
+                }
+            }
+            REG_QUEUE_DEVICE_LOW => {
+                if let Some(q) = self.selected_queue() {
+                    Self::set_low(&mut q.device_addr, value);
+                }
+            }
+            REG_QUEUE_DEVICE_HIGH => {
+                if let Some(q) = self.selected_queue() {
+                    Self::set_high(&mut q.device_addr, value);
+                }
+            }
+            o if o >= REG_CONFIG => {
+                for (i, b) in value.to_le_bytes().iter().enumerate() {
+                    self.store8(o + i, *b);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.reset_device();
+    }
+
+    fn is_available_addr(&self, addr: u32) -> bool {
+        addr >= self.base_addr() && addr < self.base_addr() + self.used_mem_bytes_len() as u32
+    }
+
+    fn device_name(&self) -> &str {
+        &self.device_base.device_name
+    }
+
+    fn base_addr(&self) -> u32 {
+        self.device_base.base_addr
+    }
+
+    fn used_mem_bytes_len(&self) -> usize {
+        self.device_base.used_mem_bytes_len
+    }
+
+    fn fdt_nodes(&self) -> Vec<FdtNode> {
+        vec![FdtNode::new_with_addr("virtio_mmio", self.base_addr())
+            .prop_str("compatible", "virtio,mmio")
+            .prop_reg(self.base_addr(), self.used_mem_bytes_len())
+            .prop_u32("interrupts", self.irq)]
+    }
+}
@@ -0,0 +1,119 @@
+use super::{queue::Virtqueue, VirtioDevice};
+use crate::{net::NetBackend, ram::Ram};
+use std::collections::VecDeque;
+
+const DEVICE_ID: u32 = 1;
+
+pub const DEFAULT_MAC: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
+const VIRTIO_NET_F_MAC: u64 = 1 << 5;
+const VIRTIO_NET_F_STATUS: u64 = 1 << 16;
+
+const VIRTIO_NET_S_LINK_UP: u16 = 1;
+
+const RECEIVEQ: usize = 0;
+const TRANSMITQ: usize = 1;
+
+// struct virtio_net_hdr (with num_buffers, always present for VIRTIO_F_VERSION_1)
+const NET_HDR_LEN: usize = 12;
+
+// frames from the backend waiting for receive buffers (older frames are dropped)
+const RX_FRAMES_LEN: usize = 256;
+
+#[derive(Debug)]
+pub struct VirtioNet {
+    mac: [u8; 6],
+    backend: Box<dyn NetBackend>,
+    rx_frames: VecDeque<Vec<u8>>,
+}
+
+impl VirtioNet {
+    pub fn new(mac: [u8; 6], backend: Box<dyn NetBackend>) -> Self {
+        Self {
+            mac,
+            backend,
+            rx_frames: VecDeque::with_capacity(RX_FRAMES_LEN),
+        }
+    }
+
+    fn transmit(&mut self, queue: &mut Virtqueue, ram: &mut Ram) -> bool {
+        let mut interrupt = false;
+        while let Some(chain) = queue.pop(ram) {
+            let data = chain.read(ram);
+            if data.len() > NET_HDR_LEN {
+                self.backend.send(&data[NET_HDR_LEN..]);
+            }
+            interrupt |= queue.push(ram, chain.head, 0);
+        }
+
+        interrupt
+    }
+
+    fn receive(&mut self, queue: &mut Virtqueue, ram: &mut Ram) -> bool {
+        while let Some(frame) = self.backend.recv() {
+            if self.rx_frames.len() == RX_FRAMES_LEN {
+                self.rx_frames.pop_front();
+            }
+            self.rx_frames.push_back(frame);
+        }
+
+        let mut interrupt = false;
+        while !self.rx_frames.is_empty() {
+            let chain = match queue.pop(ram) {
+                Some(chain) => chain,
+                None => break,
+            };
+            let frame = self.rx_frames.pop_front().unwrap();
+
+            // num_buffers = 1, the frame is truncated if the buffer is too small
+            let mut data = vec![0; NET_HDR_LEN];
+            data[10] = 1;
+            data.extend_from_slice(&frame);
+            let written = chain.write(ram, &data);
+
+            interrupt |= queue.push(ram, chain.head, written);
+        }
+
+        interrupt
+    }
+}
+
+impl VirtioDevice for VirtioNet {
+    fn device_id(&self) -> u32 {
+        DEVICE_ID
+    }
+
+    fn device_features(&self) -> u64 {
+        VIRTIO_NET_F_MAC | VIRTIO_NET_F_STATUS
+    }
+
+    fn num_queues(&self) -> usize {
+        2
+    }
+
+    // mac[6], status
+    fn load_config(&self, offset: usize) -> u8 {
+        match offset {
+            0..=5 => self.mac[offset],
+            6..=7 => VIRTIO_NET_S_LINK_UP.to_le_bytes()[offset - 6],
+            _ => 0,
+        }
+    }
+
+    fn store_config(&mut self, _offset: usize, _value: u8) {}
+
+    fn process_queues(&mut self, queues: &mut [Virtqueue], ram: &mut Ram) -> bool {
+        let mut interrupt = false;
+
+        if queues[TRANSMITQ].notified {
+            interrupt |= self.transmit(&mut queues[TRANSMITQ], ram);
+        }
+        interrupt |= self.receive(&mut queues[RECEIVEQ], ram);
+
+        interrupt
+    }
+
+    fn reset(&mut self) {
+        self.rx_frames.clear();
+    }
+}
@@ -0,0 +1,667 @@
+use super::{queue::Virtqueue, VirtioDevice};
+use crate::ram::Ram;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fs::{self, File, Metadata, OpenOptions},
+    hash::{Hash, Hasher},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const DEVICE_ID: u32 = 9;
+
+const VIRTIO_9P_MOUNT_TAG: u64 = 1 << 0;
+
+const VERSION_9P2000_L: &str = "9P2000.L";
+const MAX_MSIZE: u32 = 128 * 1024;
+// size[4] type[1] tag[2] count[4]
+const IO_HEADER_LEN: u32 = 11;
+
+// 9P2000.L message types (R-message = T-message + 1)
+const RLERROR: u8 = 7;
+const TSTATFS: u8 = 8;
+const TLOPEN: u8 = 12;
+const TLCREATE: u8 = 14;
+const TRENAME: u8 = 20;
+const TGETATTR: u8 = 24;
+const TSETATTR: u8 = 26;
+const TREADDIR: u8 = 40;
+const TFSYNC: u8 = 50;
+const TMKDIR: u8 = 72;
+const TRENAMEAT: u8 = 74;
+const TUNLINKAT: u8 = 76;
+const TVERSION: u8 = 100;
+const TATTACH: u8 = 104;
+const TFLUSH: u8 = 108;
+const TWALK: u8 = 110;
+const TREAD: u8 = 116;
+const TWRITE: u8 = 118;
+const TCLUNK: u8 = 120;
+const TREMOVE: u8 = 122;
+
+// Linux errno
+const EIO: u32 = 5;
+const EBADF: u32 = 9;
+const EACCES: u32 = 13;
+const EINVAL: u32 = 22;
+const EROFS: u32 = 30;
+const EOPNOTSUPP: u32 = 95;
+
+const QID_TYPE_DIR: u8 = 0x80;
+const QID_TYPE_SYMLINK: u8 = 0x02;
+const QID_TYPE_FILE: u8 = 0;
+
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+const DT_DIR: u8 = 4;
+const DT_REG: u8 = 8;
+const DT_LNK: u8 = 10;
+
+const P9_GETATTR_BASIC: u64 = 0x7ff;
+const P9_SETATTR_SIZE: u32 = 0x8;
+
+// Linux open flags
+const O_ACCMODE: u32 = 3;
+const O_WRONLY: u32 = 1;
+const O_RDWR: u32 = 2;
+const O_TRUNC: u32 = 0o1000;
+const O_APPEND: u32 = 0o2000;
+
+const AT_REMOVEDIR: u32 = 0x200;
+
+const BLOCK_SIZE: u64 = 4096;
+
+type P9Result<T> = Result<T, u32>;
+
+fn errno(e: io::Error) -> u32 {
+    // the host errno (same numbers as the guest on Linux hosts)
+    e.raw_os_error().map_or(EIO, |e| e as u32)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> P9Result<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos + len).ok_or(EINVAL)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> P9Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> P9Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> P9Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> P9Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> P9Result<String> {
+        let len = self.u16()? as usize;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+}
+
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, value: u8) -> &mut Self {
+        self.0.push(value);
+        self
+    }
+
+    fn u16(&mut self, value: u16) -> &mut Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn u32(&mut self, value: u32) -> &mut Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn u64(&mut self, value: u64) -> &mut Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn string(&mut self, value: &str) -> &mut Self {
+        self.u16(value.len() as u16);
+        self.0.extend_from_slice(value.as_bytes());
+        self
+    }
+
+    fn qid(&mut self, qid: &Qid) -> &mut Self {
+        self.u8(qid.qid_type).u32(0).u64(qid.path)
+    }
+}
+
+struct Qid {
+    qid_type: u8,
+    path: u64,
+}
+
+#[derive(Debug)]
+struct Fid {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+// 9P2000.L file server for a host directory
+#[derive(Debug)]
+pub struct Virtio9p {
+    root: PathBuf,
+    tag: String,
+    read_only: bool,
+    msize: u32,
+    fids: HashMap<u32, Fid>,
+}
+
+impl Virtio9p {
+    pub fn new<P: AsRef<Path>>(root: P, tag: &str, read_only: bool) -> anyhow::Result<Self> {
+        let root = fs::canonicalize(root)?;
+        if !root.is_dir() {
+            return Err(anyhow::anyhow!("{} is not a directory", root.display()));
+        }
+
+        Ok(Self {
+            root,
+            tag: tag.to_string(),
+            read_only,
+            msize: MAX_MSIZE,
+            fids: HashMap::new(),
+        })
+    }
+
+    fn fid(&mut self, fid: u32) -> P9Result<&mut Fid> {
+        self.fids.get_mut(&fid).ok_or(EBADF)
+    }
+
+    fn check_writable(&self) -> P9Result<()> {
+        if self.read_only {
+            Err(EROFS)
+        } else {
+            Ok(())
+        }
+    }
+
+    // paths never leave the shared directory (also through symlinks)
+    fn resolve(&self, dir: &Path, name: &str) -> P9Result<PathBuf> {
+        let path = match name {
+            "." => dir.to_path_buf(),
+            ".." if dir == self.root => dir.to_path_buf(),
+            ".." => dir.parent().ok_or(EINVAL)?.to_path_buf(),
+            _ if name.is_empty() || name.contains('/') => return Err(EINVAL),
+            _ => dir.join(name),
+        };
+
+        match fs::canonicalize(&path) {
+            Ok(canonical) if !canonical.starts_with(&self.root) => Err(EACCES),
+            _ => Ok(path),
+        }
+    }
+
+    fn qid(&self, path: &Path, metadata: &Metadata) -> Qid {
+        let qid_type = if metadata.is_dir() {
+            QID_TYPE_DIR
+        } else if metadata.is_symlink() {
+            QID_TYPE_SYMLINK
+        } else {
+            QID_TYPE_FILE
+        };
+
+        // stable for the same path
+        let mut hasher = DefaultHasher::new();
+        path.strip_prefix(&self.root)
+            .unwrap_or(path)
+            .hash(&mut hasher);
+
+        Qid {
+            qid_type,
+            path: hasher.finish(),
+        }
+    }
+
+    fn qid_of(&self, path: &Path) -> P9Result<Qid> {
+        let metadata = fs::symlink_metadata(path).map_err(errno)?;
+        Ok(self.qid(path, &metadata))
+    }
+
+    #[cfg(unix)]
+    fn permission_bits(metadata: &Metadata) -> u32 {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.permissions().mode() & 0o7777
+    }
+
+    #[cfg(not(unix))]
+    fn permission_bits(metadata: &Metadata) -> u32 {
+        match (metadata.is_dir(), metadata.permissions().readonly()) {
+            (true, _) => 0o755,
+            (false, true) => 0o444,
+            (false, false) => 0o644,
+        }
+    }
+
+    fn timestamp(time: io::Result<SystemTime>) -> (u64, u64) {
+        let duration = time
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        (duration.as_secs(), duration.subsec_nanos() as u64)
+    }
+
+    fn handle(&mut self, msg_type: u8, r: &mut Reader, w: &mut Writer) -> P9Result<()> {
+        match msg_type {
+            TVERSION => {
+                let msize = r.u32()?;
+                let version = r.string()?;
+                // Rread and Rreaddir need room for their header
+                if msize < IO_HEADER_LEN {
+                    return Err(EINVAL);
+                }
+                self.msize = msize.min(MAX_MSIZE);
+                self.fids.clear();
+
+                let version = if version.starts_with(VERSION_9P2000_L) {
+                    VERSION_9P2000_L
+                } else {
+                    "unknown"
+                };
+                w.u32(self.msize).string(version);
+            }
+            TATTACH => {
+                let fid = r.u32()?;
+                let qid = self.qid_of(&self.root)?;
+                self.fids.insert(
+                    fid,
+                    Fid {
+                        path: self.root.clone(),
+                        file: None,
+                    },
+                );
+                w.qid(&qid);
+            }
+            TWALK => {
+                let fid = r.u32()?;
+                let newfid = r.u32()?;
+                let nwname = r.u16()?;
+
+                let mut path = self.fid(fid)?.path.clone();
+                let mut qids = Vec::new();
+                for i in 0..nwname {
+                    let name = r.string()?;
+                    let next = self.resolve(&path, &name).and_then(|next| {
+                        let qid = self.qid_of(&next)?;
+                        Ok((next, qid))
+                    });
+
+                    match next {
+                        Ok((next, qid)) => {
+                            path = next;
+                            qids.push(qid);
+                        }
+                        Err(e) if i == 0 => return Err(e),
+                        Err(_) => break,
+                    }
+                }
+
+                // newfid is only created if all names were walked
+                if qids.len() == nwname as usize {
+                    self.fids.insert(newfid, Fid { path, file: None });
+                }
+
+                w.u16(qids.len() as u16);
+                for qid in &qids {
+                    w.qid(qid);
+                }
+            }
+            TLOPEN => {
+                let fid = r.u32()?;
+                let flags = r.u32()?;
+                let access = flags & O_ACCMODE;
+                let writable = access == O_WRONLY || access == O_RDWR;
+                if writable || flags & O_TRUNC != 0 {
+                    self.check_writable()?;
+                }
+
+                let path = self.fid(fid)?.path.clone();
+                let qid = self.qid_of(&path)?;
+                if qid.qid_type != QID_TYPE_DIR {
+                    let file = OpenOptions::new()
+                        .read(access != O_WRONLY)
+                        .write(writable)
+                        .append(flags & O_APPEND != 0)
+                        .truncate(flags & O_TRUNC != 0)
+                        .open(&path)
+                        .map_err(errno)?;
+                    self.fid(fid)?.file = Some(file);
+                }
+
+                w.qid(&qid).u32(0);
+            }
+            TLCREATE => {
+                let fid = r.u32()?;
+                let name = r.string()?;
+                let flags = r.u32()?;
+                let _mode = r.u32()?;
+                let _gid = r.u32()?;
+                self.check_writable()?;
+
+                let dir = self.fid(fid)?.path.clone();
+                let path = self.resolve(&dir, &name)?;
+                let file = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .append(flags & O_APPEND != 0)
+                    .create_new(true)
+                    .open(&path)
+                    .map_err(errno)?;
+                let qid = self.qid_of(&path)?;
+
+                let fid = self.fid(fid)?;
+                fid.path = path;
+                fid.file = Some(file);
+                w.qid(&qid).u32(0);
+            }
+            TREAD => {
+                let fid = r.u32()?;
+                let offset = r.u64()?;
+                let count = r.u32()?.min(self.msize - IO_HEADER_LEN);
+
+                let file = self.fid(fid)?.file.as_mut().ok_or(EBADF)?;
+                file.seek(SeekFrom::Start(offset)).map_err(errno)?;
+                let mut data = Vec::with_capacity(count as usize);
+                file.take(count as u64)
+                    .read_to_end(&mut data)
+                    .map_err(errno)?;
+
+                w.u32(data.len() as u32);
+                w.0.extend_from_slice(&data);
+            }
+            TWRITE => {
+                let fid = r.u32()?;
+                let offset = r.u64()?;
+                let count = r.u32()?;
+                let data = r.bytes(count as usize)?;
+                self.check_writable()?;
+
+                let file = self.fid(fid)?.file.as_mut().ok_or(EBADF)?;
+                file.seek(SeekFrom::Start(offset)).map_err(errno)?;
+                file.write_all(data).map_err(errno)?;
+                w.u32(count);
+            }
+            TCLUNK => {
+                let fid = r.u32()?;
+                self.fids.remove(&fid).ok_or(EBADF)?;
+            }
+            TREMOVE => {
+                let fid = r.u32()?;
+                let path = self.fids.remove(&fid).ok_or(EBADF)?.path;
+                self.check_writable()?;
+
+                if path.is_dir() {
+                    fs::remove_dir(&path).map_err(errno)?;
+                } else {
+                    fs::remove_file(&path).map_err(errno)?;
+                }
+            }
+            TGETATTR => {
+                let fid = r.u32()?;
+                let _request_mask = r.u64()?;
+
+                let path = self.fid(fid)?.path.clone();
+                let metadata = fs::symlink_metadata(&path).map_err(errno)?;
+                let qid = self.qid(&path, &metadata);
+                let file_type = match qid.qid_type {
+                    QID_TYPE_DIR => S_IFDIR,
+                    QID_TYPE_SYMLINK => S_IFLNK,
+                    _ => S_IFREG,
+                };
+                let (atime_sec, atime_nsec) = Self::timestamp(metadata.accessed());
+                let (mtime_sec, mtime_nsec) = Self::timestamp(metadata.modified());
+
+                w.u64(P9_GETATTR_BASIC)
+                    .qid(&qid)
+                    .u32(file_type | Self::permission_bits(&metadata))
+                    .u32(0) // uid
+                    .u32(0) // gid
+                    .u64(1) // nlink
+                    .u64(0) // rdev
+                    .u64(metadata.len())
+                    .u64(BLOCK_SIZE)
+                    .u64(metadata.len().div_ceil(512))
+                    .u64(atime_sec)
+                    .u64(atime_nsec)
+                    .u64(mtime_sec)
+                    .u64(mtime_nsec)
+                    .u64(mtime_sec) // ctime
+                    .u64(mtime_nsec)
+                    .u64(0) // btime
+                    .u64(0)
+                    .u64(0) // gen
+                    .u64(0); // data_version
+            }
+            TSETATTR => {
+                let fid = r.u32()?;
+                let valid = r.u32()?;
+                let _mode = r.u32()?;
+                let _uid = r.u32()?;
+                let _gid = r.u32()?;
+                let size = r.u64()?;
+
+                // only truncation is supported, the other attributes are ignored
+                if valid & P9_SETATTR_SIZE != 0 {
+                    self.check_writable()?;
+                    let path = self.fid(fid)?.path.clone();
+                    let file = OpenOptions::new().write(true).open(&path).map_err(errno)?;
+                    file.set_len(size).map_err(errno)?;
+                }
+            }
+            TREADDIR => {
+                let fid = r.u32()?;
+                let offset = r.u64()?;
+                let count = r.u32()?.min(self.msize - IO_HEADER_LEN) as usize;
+
+                let path = self.fid(fid)?.path.clone();
+                let mut names: Vec<String> = fs::read_dir(&path)
+                    .map_err(errno)?
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                    .collect();
+                names.sort();
+                names.insert(0, String::from(".."));
+                names.insert(0, String::from("."));
+
+                // offset is the index of the next entry
+                let mut entries = Writer::default();
+                for (i, name) in names.iter().enumerate().skip(offset as usize) {
+                    let entry_path = self.resolve(&path, name).unwrap_or(path.join(name));
+                    let qid = match self.qid_of(&entry_path) {
+                        Ok(qid) => qid,
+                        Err(_) => continue,
+                    };
+                    let dirent_type = match qid.qid_type {
+                        QID_TYPE_DIR => DT_DIR,
+                        QID_TYPE_SYMLINK => DT_LNK,
+                        _ => DT_REG,
+                    };
+
+                    let mut entry = Writer::default();
+                    entry
+                        .qid(&qid)
+                        .u64(i as u64 + 1)
+                        .u8(dirent_type)
+                        .string(name);
+                    if entries.0.len() + entry.0.len() > count {
+                        break;
+                    }
+                    entries.0.extend_from_slice(&entry.0);
+                }
+
+                w.u32(entries.0.len() as u32);
+                w.0.extend_from_slice(&entries.0);
+            }
+            TSTATFS => {
+                let _fid = r.u32()?;
+                w.u32(0x01021997) // V9FS_MAGIC
+                    .u32(BLOCK_SIZE as u32)
+                    .u64(0) // blocks
+                    .u64(0) // bfree
+                    .u64(0) // bavail
+                    .u64(0) // files
+                    .u64(0) // ffree
+                    .u64(0) // fsid
+                    .u32(255); // namelen
+            }
+            TFSYNC => {
+                let fid = r.u32()?;
+                if let Some(file) = &self.fid(fid)?.file {
+                    file.sync_all().map_err(errno)?;
+                }
+            }
+            TMKDIR => {
+                let dfid = r.u32()?;
+                let name = r.string()?;
+                self.check_writable()?;
+
+                let dir = self.fid(dfid)?.path.clone();
+                let path = self.resolve(&dir, &name)?;
+                fs::create_dir(&path).map_err(errno)?;
+                w.qid(&self.qid_of(&path)?);
+            }
+            TUNLINKAT => {
+                let dfid = r.u32()?;
+                let name = r.string()?;
+                let flags = r.u32()?;
+                self.check_writable()?;
+
+                let dir = self.fid(dfid)?.path.clone();
+                let path = self.resolve(&dir, &name)?;
+                if flags & AT_REMOVEDIR != 0 {
+                    fs::remove_dir(&path).map_err(errno)?;
+                } else {
+                    fs::remove_file(&path).map_err(errno)?;
+                }
+            }
+            TRENAMEAT => {
+                let olddirfid = r.u32()?;
+                let oldname = r.string()?;
+                let newdirfid = r.u32()?;
+                let newname = r.string()?;
+                self.check_writable()?;
+
+                let old_dir = self.fid(olddirfid)?.path.clone();
+                let new_dir = self.fid(newdirfid)?.path.clone();
+                let old_path = self.resolve(&old_dir, &oldname)?;
+                let new_path = self.resolve(&new_dir, &newname)?;
+                fs::rename(old_path, new_path).map_err(errno)?;
+            }
+            TRENAME => {
+                let fid = r.u32()?;
+                let dfid = r.u32()?;
+                let name = r.string()?;
+                self.check_writable()?;
+
+                let old_path = self.fid(fid)?.path.clone();
+                let dir = self.fid(dfid)?.path.clone();
+                let new_path = self.resolve(&dir, &name)?;
+                fs::rename(&old_path, &new_path).map_err(errno)?;
+                self.fid(fid)?.path = new_path;
+            }
+            // requests are handled synchronously, there is nothing to cancel
+            TFLUSH => {
+                let _oldtag = r.u16()?;
+            }
+            _ => return Err(EOPNOTSUPP),
+        }
+
+        Ok(())
+    }
+
+    fn handle_message(&mut self, message: &[u8]) -> Vec<u8> {
+        let mut r = Reader::new(message);
+        let header = (|| Ok::<_, u32>((r.u32()?, r.u8()?, r.u16()?)))();
+        let (msg_type, tag) = match header {
+            Ok((_, msg_type, tag)) => (msg_type, tag),
+            Err(_) => (0, 0),
+        };
+
+        let mut w = Writer::default();
+        let reply_type = match self.handle(msg_type, &mut r, &mut w) {
+            Ok(()) => msg_type + 1,
+            Err(ecode) => {
+                w = Writer::default();
+                w.u32(ecode);
+                RLERROR
+            }
+        };
+
+        let mut reply = Writer::default();
+        reply.u32(7 + w.0.len() as u32).u8(reply_type).u16(tag);
+        reply.0.extend_from_slice(&w.0);
+        reply.0
+    }
+}
+
+impl VirtioDevice for Virtio9p {
+    fn device_id(&self) -> u32 {
+        DEVICE_ID
+    }
+
+    fn device_features(&self) -> u64 {
+        VIRTIO_9P_MOUNT_TAG
+    }
+
+    fn num_queues(&self) -> usize {
+        1
+    }
+
+    // tag_len, tag
+    fn load_config(&self, offset: usize) -> u8 {
+        match offset {
+            0..=1 => (self.tag.len() as u16).to_le_bytes()[offset],
+            _ => self.tag.as_bytes().get(offset - 2).copied().unwrap_or(0),
+        }
+    }
+
+    fn store_config(&mut self, _offset: usize, _value: u8) {}
+
+    fn process_queues(&mut self, queues: &mut [Virtqueue], ram: &mut Ram) -> bool {
+        let queue = &mut queues[0];
+        if !queue.notified {
+            return false;
+        }
+
+        let mut interrupt = false;
+        while let Some(chain) = queue.pop(ram) {
+            let reply = self.handle_message(&chain.read(ram));
+            let written = chain.write(ram, &reply);
+            interrupt |= queue.push(ram, chain.head, written);
+        }
+
+        interrupt
+    }
+
+    fn reset(&mut self) {
+        self.msize = MAX_MSIZE;
+        self.fids.clear();
+    }
+}
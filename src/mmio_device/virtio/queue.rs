@@ -0,0 +1,180 @@
+use crate::ram::Ram;
+
+pub const QUEUE_NUM_MAX: u16 = 256;
+
+const DESC_LEN: u32 = 16;
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+const AVAIL_F_NO_INTERRUPT: u16 = 1;
+
+// buffers of a descriptor chain, split into device-readable and device-writable parts
+#[derive(Debug, Default)]
+pub struct DescriptorChain {
+    pub head: u16,
+    pub readable: Vec<(u32, u32)>,
+    pub writable: Vec<(u32, u32)>,
+}
+
+impl DescriptorChain {
+    pub fn readable_len(&self) -> usize {
+        self.readable.iter().map(|(_, len)| *len as usize).sum()
+    }
+
+    pub fn writable_len(&self) -> usize {
+        self.writable.iter().map(|(_, len)| *len as usize).sum()
+    }
+
+    // all device-readable bytes
+    pub fn read(&self, ram: &Ram) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.readable_len());
+        for (addr, len) in &self.readable {
+            if let Some(slice) = ram.slice(*addr, *len as usize) {
+                data.extend_from_slice(slice);
+            }
+        }
+
+        data
+    }
+
+    // fill the device-writable buffers in order, returns written bytes len
+    pub fn write(&self, ram: &mut Ram, data: &[u8]) -> u32 {
+        let mut written = 0;
+        for (addr, len) in &self.writable {
+            if written == data.len() {
+                break;
+            }
+
+            let n = (*len as usize).min(data.len() - written);
+            if let Some(slice) = ram.slice_mut(*addr, n) {
+                slice.copy_from_slice(&data[written..written + n]);
+            }
+            written += n;
+        }
+
+        written as u32
+    }
+}
+
+// split virtqueue
+#[derive(Debug, Default, Clone)]
+pub struct Virtqueue {
+    pub num: u16,
+    pub ready: bool,
+    pub desc_addr: u64,
+    pub driver_addr: u64,
+    pub device_addr: u64,
+    // QueueNotify was written since the last processing
+    pub notified: bool,
+    last_avail_idx: u16,
+}
+
+impl Virtqueue {
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    // the rings are in the 32-bit physical address space, a driver that puts them above it
+    // makes the device fail (instead of aliasing the low memory)
+    pub fn is_addressable(&self) -> bool {
+        [self.desc_addr, self.driver_addr, self.device_addr]
+            .iter()
+            .all(|addr| u32::try_from(*addr).is_ok())
+    }
+
+    // None above the 32-bit address space (e.g. a ring crossing 4 GiB)
+    fn slice(ram: &Ram, addr: u64, len: usize) -> Option<&[u8]> {
+        ram.slice(u32::try_from(addr).ok()?, len)
+    }
+
+    fn load16(ram: &Ram, addr: u64) -> Option<u16> {
+        let slice = Self::slice(ram, addr, 2)?;
+        Some(u16::from_le_bytes([slice[0], slice[1]]))
+    }
+
+    fn store16(ram: &mut Ram, addr: u64, value: u16) {
+        let Ok(addr) = u32::try_from(addr) else {
+            return;
+        };
+        if let Some(slice) = ram.slice_mut(addr, 2) {
+            slice.copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    fn store32(ram: &mut Ram, addr: u64, value: u32) {
+        let Ok(addr) = u32::try_from(addr) else {
+            return;
+        };
+        if let Some(slice) = ram.slice_mut(addr, 4) {
+            slice.copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    pub fn has_available(&self, ram: &Ram) -> bool {
+        self.ready
+            && self.num != 0
+            && Self::load16(ram, self.driver_addr + 2).is_some_and(|idx| idx != self.last_avail_idx)
+    }
+
+    // take the next available descriptor chain
+    pub fn pop(&mut self, ram: &Ram) -> Option<DescriptorChain> {
+        if !self.has_available(ram) {
+            return None;
+        }
+
+        let ring_offset = 4 + 2 * (self.last_avail_idx % self.num) as u64;
+        let head = Self::load16(ram, self.driver_addr + ring_offset)?;
+        self.last_avail_idx = self.last_avail_idx.wrapping_add(1);
+
+        let mut chain = DescriptorChain {
+            head,
+            ..Default::default()
+        };
+
+        // bounded by the queue size in case of a looping chain
+        let mut index = head;
+        for _ in 0..self.num {
+            let desc = Self::slice(
+                ram,
+                self.desc_addr + (index % self.num) as u64 * DESC_LEN as u64,
+                DESC_LEN as usize,
+            )?;
+            let addr = u64::from_le_bytes(desc[0..8].try_into().unwrap());
+            let len = u32::from_le_bytes(desc[8..12].try_into().unwrap());
+            let flags = u16::from_le_bytes(desc[12..14].try_into().unwrap());
+            let next = u16::from_le_bytes(desc[14..16].try_into().unwrap());
+
+            // buffers outside of ram (also above 4 GiB) are dropped
+            if let Some(addr) = u32::try_from(addr)
+                .ok()
+                .filter(|addr| ram.slice(*addr, len as usize).is_some())
+            {
+                if flags & DESC_F_WRITE != 0 {
+                    chain.writable.push((addr, len));
+                } else {
+                    chain.readable.push((addr, len));
+                }
+            }
+
+            if flags & DESC_F_NEXT == 0 {
+                break;
+            }
+            index = next;
+        }
+
+        Some(chain)
+    }
+
+    // return a chain to the driver, returns whether an interrupt should be raised
+    pub fn push(&mut self, ram: &mut Ram, head: u16, len: u32) -> bool {
+        let used_idx = Self::load16(ram, self.device_addr + 2).unwrap_or(0);
+        let elem_addr = self.device_addr + 4 + 8 * (used_idx % self.num) as u64;
+
+        Self::store32(ram, elem_addr, head as u32);
+        Self::store32(ram, elem_addr + 4, len);
+        Self::store16(ram, self.device_addr + 2, used_idx.wrapping_add(1));
+
+        let avail_flags = Self::load16(ram, self.driver_addr).unwrap_or(0);
+        avail_flags & AVAIL_F_NO_INTERRUPT == 0
+    }
+}
@@ -0,0 +1,101 @@
+use super::{queue::Virtqueue, VirtioDevice};
+use crate::ram::Ram;
+use std::{fs::File, io::Read};
+
+const DEVICE_ID: u32 = 4;
+
+const HOST_ENTROPY_PATH: &str = "/dev/urandom";
+
+#[derive(Debug)]
+enum EntropySource {
+    // SplitMix64, the same seed always gives the same bytes
+    Seeded { seed: u64, state: u64 },
+    Host(File),
+}
+
+#[derive(Debug)]
+pub struct VirtioRng {
+    source: EntropySource,
+}
+
+impl VirtioRng {
+    pub fn new_with_seed(seed: u64) -> Self {
+        Self {
+            source: EntropySource::Seeded { seed, state: seed },
+        }
+    }
+
+    pub fn new_with_host_entropy() -> anyhow::Result<Self> {
+        Ok(Self {
+            source: EntropySource::Host(File::open(HOST_ENTROPY_PATH)?),
+        })
+    }
+
+    fn next_u64(state: &mut u64) -> u64 {
+        *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) {
+        match &mut self.source {
+            EntropySource::Seeded { state, .. } => {
+                for chunk in buf.chunks_mut(8) {
+                    let bytes = Self::next_u64(state).to_le_bytes();
+                    chunk.copy_from_slice(&bytes[..chunk.len()]);
+                }
+            }
+            EntropySource::Host(file) => {
+                if file.read_exact(buf).is_err() {
+                    buf.fill(0);
+                }
+            }
+        }
+    }
+}
+
+impl VirtioDevice for VirtioRng {
+    fn device_id(&self) -> u32 {
+        DEVICE_ID
+    }
+
+    fn device_features(&self) -> u64 {
+        0
+    }
+
+    fn num_queues(&self) -> usize {
+        1
+    }
+
+    fn load_config(&self, _offset: usize) -> u8 {
+        0
+    }
+
+    fn store_config(&mut self, _offset: usize, _value: u8) {}
+
+    fn process_queues(&mut self, queues: &mut [Virtqueue], ram: &mut Ram) -> bool {
+        let queue = &mut queues[0];
+        if !queue.notified {
+            return false;
+        }
+
+        let mut interrupt = false;
+        while let Some(chain) = queue.pop(ram) {
+            let mut buf = vec![0; chain.writable_len()];
+            self.fill(&mut buf);
+            let written = chain.write(ram, &buf);
+            interrupt |= queue.push(ram, chain.head, written);
+        }
+
+        interrupt
+    }
+
+    // restart the sequence so that a rebooted guest sees the same bytes
+    fn reset(&mut self) {
+        if let EntropySource::Seeded { seed, state } = &mut self.source {
+            *state = *seed;
+        }
+    }
+}
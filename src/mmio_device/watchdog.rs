@@ -0,0 +1,234 @@
+use super::{MmioDeviceBase, MmioDeviceInterface, RequestFromDevice};
+use crate::{fdt::FdtNode, ram::Ram};
+use std::collections::VecDeque;
+
+// free region after the other SiFive peripherals
+const DEFAULT_BASE_ADDR: u32 = 0x1007_0000;
+const DEFAULT_MEM_BYTES_LEN: usize = 0x1000;
+pub const DEFAULT_IRQ: u32 = 16;
+// exit code when the watchdog expires in exit mode
+pub const DEFAULT_EXIT_CODE: u32 = 0xdead;
+
+const REG_WDOGCFG: usize = 0x00;
+const REG_WDOGCOUNT: usize = 0x08;
+const REG_WDOGS: usize = 0x10;
+const REG_WDOGFEED: usize = 0x18;
+const REG_WDOGKEY: usize = 0x1c;
+const REG_WDOGCMP0: usize = 0x20;
+
+const WDOGKEY: u32 = 0x0051_f15e;
+const WDOGFEED: u32 = 0x0d09_f00d;
+
+const CFG_SCALE_MASK: u32 = 0xf;
+const CFG_RSTEN: u32 = 1 << 8;
+const CFG_ZEROCMP: u32 = 1 << 9;
+const CFG_ENALWAYS: u32 = 1 << 12;
+const CFG_ENCOREAWAKE: u32 = 1 << 13;
+const CFG_IP0: u32 = 1 << 28;
+const CFG_MASK: u32 =
+    CFG_SCALE_MASK | CFG_RSTEN | CFG_ZEROCMP | CFG_ENALWAYS | CFG_ENCOREAWAKE | CFG_IP0;
+
+const COUNT_MASK: u32 = 0x7fff_ffff;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchdogAction {
+    Reset,
+    Exit(u32),
+}
+
+// compatible with the watchdog of the SiFive always-on domain (sifive,wdt0),
+// the counter runs at one tick per step
+#[derive(Debug)]
+pub struct Watchdog {
+    device_base: MmioDeviceBase,
+    irq: u32,
+    action: WatchdogAction,
+    // armed by the host at power on (cfg, cmp0)
+    boot_config: Option<(u32, u32)>,
+    cfg: u32,
+    count: u32,
+    cmp0: u32,
+    unlocked: bool,
+    requests: VecDeque<RequestFromDevice>,
+}
+
+impl Watchdog {
+    fn new(
+        device_name: String,
+        base_addr: u32,
+        used_mem_bytes_len: usize,
+        irq: u32,
+        action: WatchdogAction,
+    ) -> Self {
+        Self {
+            device_base: MmioDeviceBase {
+                device_name,
+                base_addr,
+                used_mem_bytes_len,
+            },
+            irq,
+            action,
+            boot_config: None,
+            cfg: 0,
+            count: 0,
+            cmp0: 0xffff,
+            unlocked: false,
+            requests: VecDeque::new(),
+        }
+    }
+
+    pub fn new_with_action(action: WatchdogAction) -> Self {
+        Self::new(
+            String::from("watchdog"),
+            DEFAULT_BASE_ADDR,
+            DEFAULT_MEM_BYTES_LEN,
+            DEFAULT_IRQ,
+            action,
+        )
+    }
+
+    // start counting at power on (and after every reset) with a timeout in steps,
+    // the guest can still feed or reconfigure it
+    pub fn arm(&mut self, timeout_steps: u32) {
+        let timeout_steps = timeout_steps.clamp(1, COUNT_MASK);
+        let scale = (0..=CFG_SCALE_MASK)
+            .find(|scale| timeout_steps.div_ceil(1 << scale) <= 0xffff)
+            .unwrap();
+        let cfg = scale | CFG_RSTEN | CFG_ENALWAYS;
+        let cmp0 = timeout_steps.div_ceil(1 << scale);
+
+        self.boot_config = Some((cfg, cmp0));
+        self.load_boot_config();
+    }
+
+    fn load_boot_config(&mut self) {
+        let (cfg, cmp0) = self.boot_config.unwrap_or((0, 0xffff));
+        self.cfg = cfg;
+        self.count = 0;
+        self.cmp0 = cmp0;
+        self.unlocked = false;
+    }
+
+    fn scaled_count(&self) -> u32 {
+        (self.count >> (self.cfg & CFG_SCALE_MASK)) & 0xffff
+    }
+
+    fn expire(&mut self) {
+        self.requests.push_back(RequestFromDevice::WatchdogExpired);
+        self.requests.push_back(match self.action {
+            WatchdogAction::Reset => RequestFromDevice::Reset,
+            WatchdogAction::Exit(exit_code) => RequestFromDevice::Exit(exit_code),
+        });
+    }
+}
+
+impl MmioDeviceInterface for Watchdog {
+    fn tick(&mut self, _ram: &mut Ram) {
+        if self.cfg & CFG_ENALWAYS == 0 || !self.requests.is_empty() {
+            return;
+        }
+
+        self.count = (self.count + 1) & COUNT_MASK;
+        if self.scaled_count() < self.cmp0 {
+            return;
+        }
+
+        self.cfg |= CFG_IP0;
+        if self.cfg & CFG_ZEROCMP != 0 {
+            self.count = 0;
+        }
+        if self.cfg & CFG_RSTEN != 0 {
+            self.expire();
+        }
+    }
+
+    fn poll_request(&mut self) -> Option<RequestFromDevice> {
+        self.requests.pop_front()
+    }
+
+    fn irq(&self) -> Option<u32> {
+        if self.cfg & CFG_IP0 != 0 {
+            Some(self.irq)
+        } else {
+            None
+        }
+    }
+
+    fn load8(&mut self, _bytes_offset: usize) -> u8 {
+        0
+    }
+
+    fn store8(&mut self, _bytes_offset: usize, _value: u8) {}
+
+    fn load16(&mut self, _bytes_offset: usize) -> u16 {
+        0
+    }
+
+    fn store16(&mut self, _bytes_offset: usize, _value: u16) {}
+
+    fn load32(&mut self, bytes_offset: usize) -> u32 {
+        match bytes_offset {
+            REG_WDOGCFG => self.cfg,
+            REG_WDOGCOUNT => self.count,
+            REG_WDOGS => self.scaled_count(),
+            REG_WDOGKEY => self.unlocked as u32,
+            REG_WDOGCMP0 => self.cmp0,
+            _ => 0,
+        }
+    }
+
+    // every write except to wdogkey needs the key written first and locks again
+    fn store32(&mut self, bytes_offset: usize, value: u32) {
+        if bytes_offset == REG_WDOGKEY {
+            self.unlocked = value == WDOGKEY;
+            return;
+        }
+
+        if !self.unlocked {
+            return;
+        }
+        self.unlocked = false;
+
+        match bytes_offset {
+            REG_WDOGCFG => self.cfg = value & CFG_MASK,
+            REG_WDOGCOUNT => self.count = value & COUNT_MASK,
+            REG_WDOGFEED if value == WDOGFEED => self.count = 0,
+            REG_WDOGCMP0 => self.cmp0 = value & 0xffff,
+            _ => (),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.requests.clear();
+        self.load_boot_config();
+    }
+
+    fn is_available_addr(&self, addr: u32) -> bool {
+        addr >= self.base_addr() && addr < self.base_addr() + self.used_mem_bytes_len() as u32
+    }
+
+    fn device_name(&self) -> &str {
+        &self.device_base.device_name
+    }
+
+    fn base_addr(&self) -> u32 {
+        self.device_base.base_addr
+    }
+
+    fn used_mem_bytes_len(&self) -> usize {
+        self.device_base.used_mem_bytes_len
+    }
+
+    fn fdt_nodes(&self) -> Vec<FdtNode> {
+        vec![FdtNode::new_with_addr("watchdog", self.base_addr())
+            .prop_str("compatible", "sifive,wdt0")
+            .prop_reg(self.base_addr(), self.used_mem_bytes_len())
+            .prop_u32("interrupts", self.irq)]
+    }
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self::new_with_action(WatchdogAction::Reset)
+    }
+}
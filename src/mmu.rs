@@ -0,0 +1,146 @@
+use crate::{
+    cpu::{Exception, PrivilegeMode},
+    csr::{self, Csrs},
+    ram::Memory,
+    step_log::RamWrite,
+};
+
+pub const PAGE_SIZE: u64 = 4096;
+const PTE_SIZE: u64 = 4;
+const LEVELS: usize = 2;
+
+const PTE_V: u32 = 1 << 0;
+const PTE_R: u32 = 1 << 1;
+const PTE_W: u32 = 1 << 2;
+const PTE_X: u32 = 1 << 3;
+const PTE_U: u32 = 1 << 4;
+const PTE_A: u32 = 1 << 6;
+const PTE_D: u32 = 1 << 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessType {
+    Fetch,
+    Load,
+    // stores and AMOs
+    Store,
+}
+
+impl AccessType {
+    fn page_fault(self, vaddr: u32) -> Exception {
+        match self {
+            Self::Fetch => Exception::InstructionPageFault(vaddr),
+            Self::Load => Exception::LoadPageFault(vaddr),
+            Self::Store => Exception::StorePageFault(vaddr),
+        }
+    }
+
+    pub fn access_fault(self, vaddr: u32) -> Exception {
+        match self {
+            Self::Fetch => Exception::InstructionAccessFault(vaddr),
+            Self::Load => Exception::LoadAccessFault(vaddr),
+            Self::Store => Exception::StoreAccessFault(vaddr),
+        }
+    }
+}
+
+// the privilege mode used for the permission checks (loads and stores use mstatus.MPP when MPRV is set)
+pub fn effective_mode(csrs: &Csrs, mode: PrivilegeMode, access: AccessType) -> PrivilegeMode {
+    let mstatus = csrs.load(csr::MSTATUS);
+    if mode == PrivilegeMode::Machine
+        && access != AccessType::Fetch
+        && mstatus & csr::MSTATUS_MPRV != 0
+    {
+        PrivilegeMode::from_bits(mstatus >> csr::MSTATUS_MPP_SHIFT)
+    } else {
+        mode
+    }
+}
+
+pub fn is_enabled(csrs: &Csrs, mode: PrivilegeMode) -> bool {
+    mode != PrivilegeMode::Machine && csrs.load(csr::SATP) & csr::SATP_MODE_SV32 != 0
+}
+
+// Sv32 page table walk (no TLB), the A and D bits are set by the walk and logged as ram writes
+pub fn translate(
+    csrs: &Csrs,
+    mode: PrivilegeMode,
+    vaddr: u32,
+    access: AccessType,
+    ram: &mut impl Memory,
+    ram_writes: &mut Vec<RamWrite>,
+) -> Result<u32, Exception> {
+    let mode = effective_mode(csrs, mode, access);
+    if !is_enabled(csrs, mode) {
+        return Ok(vaddr);
+    }
+
+    let mstatus = csrs.load(csr::MSTATUS);
+    let vpn = [(vaddr >> 12) & 0x3ff, vaddr >> 22];
+    let mut table = (csrs.load(csr::SATP) & csr::SATP_PPN_MASK) as u64 * PAGE_SIZE;
+
+    for level in (0..LEVELS).rev() {
+        // physical addresses are 34 bits wide, only the low 4GB can hold page tables
+        let pte_addr = table + vpn[level] as u64 * PTE_SIZE;
+        if pte_addr > u32::MAX as u64 || !ram.is_available_addr(pte_addr as u32) {
+            return Err(access.access_fault(vaddr));
+        }
+        let pte_addr = pte_addr as u32;
+        let mut pte = ram.load32(pte_addr);
+
+        if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) {
+            return Err(access.page_fault(vaddr));
+        }
+
+        let ppn = (pte >> 10) as u64;
+        if pte & (PTE_R | PTE_X) == 0 {
+            if level == 0 {
+                return Err(access.page_fault(vaddr));
+            }
+            table = ppn * PAGE_SIZE;
+            continue;
+        }
+
+        let permitted = match access {
+            AccessType::Fetch => pte & PTE_X != 0,
+            AccessType::Load => {
+                pte & PTE_R != 0 || (pte & PTE_X != 0 && mstatus & csr::MSTATUS_MXR != 0)
+            }
+            AccessType::Store => pte & PTE_W != 0,
+        };
+        let user_page = pte & PTE_U != 0;
+        let privileged = match mode {
+            PrivilegeMode::User => user_page,
+            _ => !user_page || (access != AccessType::Fetch && mstatus & csr::MSTATUS_SUM != 0),
+        };
+        // superpages must be aligned
+        let misaligned = level == 1 && ppn & 0x3ff != 0;
+        if !permitted || !privileged || misaligned {
+            return Err(access.page_fault(vaddr));
+        }
+
+        let mut flags = PTE_A;
+        if access == AccessType::Store {
+            flags |= PTE_D;
+        }
+        if pte & flags != flags {
+            pte |= flags;
+            ram.store32(pte_addr, pte);
+            for (i, byte) in pte.to_le_bytes().into_iter().enumerate() {
+                ram_writes.push(RamWrite::new(pte_addr + i as u32, byte));
+            }
+        }
+
+        let paddr = if level == 1 {
+            (ppn >> 10) << 22 | (vaddr & 0x003f_ffff) as u64
+        } else {
+            ppn << 12 | (vaddr & 0xfff) as u64
+        };
+        if paddr > u32::MAX as u64 {
+            return Err(access.access_fault(vaddr));
+        }
+
+        return Ok(paddr as u32);
+    }
+
+    unreachable!()
+}
@@ -0,0 +1,128 @@
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::mpsc::{self, Receiver, Sender},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+const PCAP_SNAPLEN: u32 = 65535;
+const PCAP_LINKTYPE_ETHERNET: u32 = 1;
+
+// host side of a network device (ethernet frames without the virtio header)
+pub trait NetBackend: Debug {
+    fn send(&mut self, frame: &[u8]);
+    fn recv(&mut self) -> Option<Vec<u8>>;
+}
+
+// discard all frames
+#[derive(Debug, Default)]
+pub struct DropBackend;
+
+impl NetBackend for DropBackend {
+    fn send(&mut self, _frame: &[u8]) {}
+
+    fn recv(&mut self) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+// send every frame back to the guest
+#[derive(Debug, Default)]
+pub struct ReflectBackend {
+    frames: VecDeque<Vec<u8>>,
+}
+
+impl NetBackend for ReflectBackend {
+    fn send(&mut self, frame: &[u8]) {
+        self.frames.push_back(frame.to_vec());
+    }
+
+    fn recv(&mut self) -> Option<Vec<u8>> {
+        self.frames.pop_front()
+    }
+}
+
+// one end of a cable between two emulators in the same process (can be moved to another thread)
+#[derive(Debug)]
+pub struct PairBackend {
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+}
+
+impl PairBackend {
+    pub fn new_pair() -> (Self, Self) {
+        let (tx_a, rx_b) = mpsc::channel();
+        let (tx_b, rx_a) = mpsc::channel();
+
+        (Self { tx: tx_a, rx: rx_a }, Self { tx: tx_b, rx: rx_b })
+    }
+}
+
+impl NetBackend for PairBackend {
+    fn send(&mut self, frame: &[u8]) {
+        // the other end may already be dropped
+        let _ = self.tx.send(frame.to_vec());
+    }
+
+    fn recv(&mut self) -> Option<Vec<u8>> {
+        self.rx.try_recv().ok()
+    }
+}
+
+// write the traffic of another backend (both directions) to a pcap file
+#[derive(Debug)]
+pub struct PcapBackend {
+    backend: Box<dyn NetBackend>,
+    writer: BufWriter<File>,
+}
+
+impl PcapBackend {
+    pub fn new<P: AsRef<Path>>(path: P, backend: Box<dyn NetBackend>) -> anyhow::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(&PCAP_MAGIC.to_le_bytes())?;
+        writer.write_all(&PCAP_VERSION_MAJOR.to_le_bytes())?;
+        writer.write_all(&PCAP_VERSION_MINOR.to_le_bytes())?;
+        writer.write_all(&0i32.to_le_bytes())?; // thiszone
+        writer.write_all(&0u32.to_le_bytes())?; // sigfigs
+        writer.write_all(&PCAP_SNAPLEN.to_le_bytes())?;
+        writer.write_all(&PCAP_LINKTYPE_ETHERNET.to_le_bytes())?;
+        writer.flush()?;
+
+        Ok(Self { backend, writer })
+    }
+
+    fn capture(&mut self, frame: &[u8]) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        let mut record = Vec::with_capacity(16 + frame.len());
+        record.extend_from_slice(&(now.as_secs() as u32).to_le_bytes());
+        record.extend_from_slice(&now.subsec_micros().to_le_bytes());
+        record.extend_from_slice(&(frame.len() as u32).to_le_bytes()); // incl_len
+        record.extend_from_slice(&(frame.len() as u32).to_le_bytes()); // orig_len
+        record.extend_from_slice(frame);
+
+        let _ = self.writer.write_all(&record);
+        let _ = self.writer.flush();
+    }
+}
+
+impl NetBackend for PcapBackend {
+    fn send(&mut self, frame: &[u8]) {
+        self.capture(frame);
+        self.backend.send(frame);
+    }
+
+    fn recv(&mut self) -> Option<Vec<u8>> {
+        let frame = self.backend.recv()?;
+        self.capture(&frame);
+        Some(frame)
+    }
+}
@@ -0,0 +1,434 @@
+use crate::{
+    cpu::{Cpu, PrivilegeMode},
+    mmio_device::{MmioDeviceInterface, RequestFromDevice},
+    ram::{Memory, Ram},
+    step_log,
+};
+use std::{
+    ops::Range,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        mpsc::{Receiver, Sender},
+        Arc,
+    },
+};
+
+// bytes of ram covered by one reservation set of lr.w
+const RESERVATION_GRANULE: usize = 64;
+
+// a version per reservation granule of the ram, every store to the granule adds 2 to it (it
+// is odd while the store is in progress), so sc.w sees any store since lr.w even if the word
+// got its old value back
+#[derive(Debug)]
+pub struct Reservations(Vec<AtomicU32>);
+
+impl Reservations {
+    pub fn new(ram: &Ram) -> Self {
+        let granules = ram.size().div_ceil(RESERVATION_GRANULE);
+        Self((0..granules).map(|_| AtomicU32::new(0)).collect())
+    }
+}
+
+// the ram shared by the hart threads, every access is an atomic access to the aligned
+// words it touches (relaxed for loads and stores, fences order them like RVWMO allows)
+#[derive(Debug, Clone, Copy)]
+pub struct SharedRam {
+    base_addr: u32,
+    ptr: *mut u8,
+    len: usize,
+    versions: *const AtomicU32,
+}
+
+// the main thread doesn't touch the ram while the hart threads use it
+unsafe impl Send for SharedRam {}
+
+impl SharedRam {
+    // only valid until the ram is used by the main thread again (or the reservations are
+    // dropped)
+    pub fn new(ram: &mut Ram, reservations: &Reservations) -> Option<Self> {
+        let ptr = ram.data.as_mut_ptr();
+        if ptr as usize & 0b11 != 0
+            || ram.data.len() & 0b11 != 0
+            || reservations.0.len() < ram.data.len().div_ceil(RESERVATION_GRANULE)
+        {
+            return None;
+        }
+
+        Some(Self {
+            base_addr: ram.base_addr,
+            ptr,
+            len: ram.data.len(),
+            versions: reservations.0.as_ptr(),
+        })
+    }
+
+    fn word(&self, addr: u32) -> &AtomicU32 {
+        let index = addr.wrapping_sub(self.base_addr) as usize & !0b11;
+        assert!(index < self.len, "0x{:08x} is outside of the ram", addr);
+        // aligned and inside the ram, it is only accessed atomically during the parallel run
+        unsafe { AtomicU32::from_ptr(self.ptr.add(index) as *mut u32) }
+    }
+
+    fn version(&self, addr: u32) -> &AtomicU32 {
+        let index = addr.wrapping_sub(self.base_addr) as usize / RESERVATION_GRANULE;
+        assert!(index < self.len.div_ceil(RESERVATION_GRANULE));
+        // one version per granule of the ram, they outlive the parallel run
+        unsafe { &*self.versions.add(index) }
+    }
+
+    // every store to a granule is done while holding its version (sc.w takes it only if it
+    // is still the version of lr.w)
+    fn store_with(&self, addr: u32, op: impl FnOnce(&AtomicU32) -> u32) -> u32 {
+        let version = self.version(addr);
+        let mut current = version.load(Ordering::Relaxed);
+        loop {
+            if current & 1 != 0 {
+                std::hint::spin_loop();
+                current = version.load(Ordering::Relaxed);
+                continue;
+            }
+            match version.compare_exchange_weak(
+                current,
+                current.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(value) => current = value,
+            }
+        }
+
+        let value = op(self.word(addr));
+        version.store(current.wrapping_add(2), Ordering::Release);
+        value
+    }
+
+    // replace the bits of mask (shifted to the byte offset of addr) in the word
+    fn store_bits(&self, addr: u32, mask: u32, value: u32) {
+        let shift = (addr & 0b11) * 8;
+        self.store_with(addr, |word| {
+            let _ = word.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |word| {
+                Some(word & !(mask << shift) | (value & mask) << shift)
+            });
+            0
+        });
+    }
+}
+
+impl Memory for SharedRam {
+    fn is_available_addr(&self, addr: u32) -> bool {
+        (addr.wrapping_sub(self.base_addr) as usize) < self.len
+    }
+
+    fn load8(&self, addr: u32) -> u8 {
+        let shift = (addr & 0b11) * 8;
+        (self.word(addr).load(Ordering::Relaxed) >> shift) as u8
+    }
+
+    fn store8(&mut self, addr: u32, value: u8) {
+        self.store_bits(addr, 0xff, value as u32);
+    }
+
+    fn load16(&self, addr: u32) -> u16 {
+        // a halfword across two words is loaded as bytes
+        if addr & 0b11 == 0b11 {
+            return self.load8(addr) as u16 | (self.load8(addr.wrapping_add(1)) as u16) << 8;
+        }
+
+        let shift = (addr & 0b11) * 8;
+        (self.word(addr).load(Ordering::Relaxed) >> shift) as u16
+    }
+
+    fn store16(&mut self, addr: u32, value: u16) {
+        if addr & 0b11 == 0b11 {
+            self.store8(addr, value as u8);
+            self.store8(addr.wrapping_add(1), (value >> 8) as u8);
+            return;
+        }
+
+        self.store_bits(addr, 0xffff, value as u32);
+    }
+
+    fn load32(&self, addr: u32) -> u32 {
+        if addr & 0b11 != 0 {
+            return (0..4).fold(0, |value, i| {
+                value | (self.load8(addr.wrapping_add(i)) as u32) << (i * 8)
+            });
+        }
+
+        self.word(addr).load(Ordering::Relaxed)
+    }
+
+    fn store32(&mut self, addr: u32, value: u32) {
+        if addr & 0b11 != 0 {
+            for i in 0..4 {
+                self.store8(addr.wrapping_add(i), (value >> (i * 8)) as u8);
+            }
+            return;
+        }
+
+        self.store_with(addr, |word| {
+            word.store(value, Ordering::Relaxed);
+            0
+        });
+    }
+
+    fn fetch_update32(&mut self, addr: u32, op: impl Fn(u32) -> u32) -> u32 {
+        self.store_with(addr, |word| {
+            match word.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |word| Some(op(word))) {
+                Ok(value) | Err(value) => value,
+            }
+        })
+    }
+
+    // the version at the time of lr.w, acquire makes the value loaded after it at least as
+    // new as the store that set the version
+    fn reservation_tag(&self, addr: u32) -> u32 {
+        if !self.is_available_addr(addr) {
+            return 0;
+        }
+
+        self.version(addr).load(Ordering::Acquire)
+    }
+
+    fn store_conditional32(&mut self, addr: u32, tag: u32, value: u32) -> bool {
+        // a store was in progress at the time of lr.w
+        if tag & 1 != 0 {
+            return false;
+        }
+
+        let version = self.version(addr);
+        if version
+            .compare_exchange(
+                tag,
+                tag.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_err()
+        {
+            return false;
+        }
+        self.word(addr).store(value, Ordering::SeqCst);
+        version.store(tag.wrapping_add(2), Ordering::Release);
+        true
+    }
+}
+
+#[derive(Debug)]
+pub enum HartMessage {
+    // accesses to the devices, done by the main thread
+    Load { hart: usize, addr: u32, len: u32 },
+    Store { addr: u32, len: u32, value: u32 },
+    // the hart has run its quantum
+    Done(Box<QuantumResult>),
+}
+
+#[derive(Debug)]
+pub struct Quantum {
+    pub cpu: Cpu,
+    pub ram: SharedRam,
+}
+
+#[derive(Debug)]
+pub struct QuantumResult {
+    pub hart: usize,
+    pub cpu: Cpu,
+    pub steps: usize,
+    pub step_logs: Vec<step_log::CpuStep>,
+    pub error: Option<anyhow::Error>,
+}
+
+// the devices as seen by a hart thread, the ranges of the devices (some of them are in
+// the ram, e.g. the debug exit port) and everything outside of the ram are forwarded to the
+// main thread
+#[derive(Debug)]
+pub struct MmioProxy {
+    hart: usize,
+    ram_base_addr: u32,
+    ram_len: usize,
+    device_ranges: Vec<Range<u64>>,
+    requests: Sender<HartMessage>,
+    replies: Receiver<u32>,
+    // set by a store, the quantum ends there so that the devices see it in time (e.g. an
+    // exit request)
+    stored: Arc<AtomicBool>,
+}
+
+impl MmioProxy {
+    pub fn new(
+        hart: usize,
+        ram: &Ram,
+        mmio_devices: &[Box<dyn MmioDeviceInterface>],
+        requests: Sender<HartMessage>,
+        replies: Receiver<u32>,
+    ) -> Self {
+        let device_ranges = mmio_devices
+            .iter()
+            .map(|device| {
+                let base_addr = device.base_addr() as u64;
+                base_addr..base_addr + device.used_mem_bytes_len() as u64
+            })
+            .collect();
+
+        Self {
+            hart,
+            ram_base_addr: ram.base_addr,
+            ram_len: ram.size(),
+            device_ranges,
+            requests,
+            replies,
+            stored: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn stored(&self) -> Arc<AtomicBool> {
+        self.stored.clone()
+    }
+
+    fn load(&mut self, bytes_offset: usize, len: u32) -> u32 {
+        let req = HartMessage::Load {
+            hart: self.hart,
+            addr: bytes_offset as u32,
+            len,
+        };
+        if self.requests.send(req).is_err() {
+            return 0;
+        }
+
+        self.replies.recv().unwrap_or(0)
+    }
+
+    fn store(&mut self, bytes_offset: usize, len: u32, value: u32) {
+        self.stored.store(true, Ordering::Relaxed);
+        let _ = self.requests.send(HartMessage::Store {
+            addr: bytes_offset as u32,
+            len,
+            value,
+        });
+    }
+}
+
+impl MmioDeviceInterface for MmioProxy {
+    fn tick(&mut self, _ram: &mut Ram) {}
+
+    fn poll_request(&mut self) -> Option<RequestFromDevice> {
+        None
+    }
+
+    fn load8(&mut self, bytes_offset: usize) -> u8 {
+        self.load(bytes_offset, 1) as u8
+    }
+
+    fn store8(&mut self, bytes_offset: usize, value: u8) {
+        self.store(bytes_offset, 1, value as u32);
+    }
+
+    fn load16(&mut self, bytes_offset: usize) -> u16 {
+        self.load(bytes_offset, 2) as u16
+    }
+
+    fn store16(&mut self, bytes_offset: usize, value: u16) {
+        self.store(bytes_offset, 2, value as u32);
+    }
+
+    fn load32(&mut self, bytes_offset: usize) -> u32 {
+        self.load(bytes_offset, 4)
+    }
+
+    fn store32(&mut self, bytes_offset: usize, value: u32) {
+        self.store(bytes_offset, 4, value);
+    }
+
+    fn reset(&mut self) {}
+
+    fn is_available_addr(&self, addr: u32) -> bool {
+        self.device_ranges
+            .iter()
+            .any(|range| range.contains(&(addr as u64)))
+            || addr.wrapping_sub(self.ram_base_addr) as usize >= self.ram_len
+    }
+
+    fn device_name(&self) -> &str {
+        "MMIO proxy"
+    }
+
+    // the offsets are the physical addresses
+    fn base_addr(&self) -> u32 {
+        0
+    }
+
+    fn used_mem_bytes_len(&self) -> usize {
+        0
+    }
+}
+
+// runs the quanta sent by the main thread until it closes the channel, a quantum ends
+// early when the main thread has to look at the hart (host calls, errors, leaving the ram) or
+// a device was written
+pub fn run_hart(
+    hart: usize,
+    proxy: MmioProxy,
+    quanta: Receiver<Quantum>,
+    results: Sender<HartMessage>,
+    quantum: usize,
+    keep_step_logs: bool,
+    print_instruction_log: bool,
+) {
+    let stored = proxy.stored();
+    let mut mmio_devices: Vec<Box<dyn MmioDeviceInterface>> = vec![Box::new(proxy)];
+
+    while let Ok(Quantum { mut cpu, mut ram }) = quanta.recv() {
+        let start_mode = cpu.mode;
+        let mut steps = 0;
+        let mut step_logs = Vec::new();
+        let mut error = None;
+
+        // a panic ends the run with an error, the main thread would wait for the quantum forever
+        let quantum_run = panic::catch_unwind(AssertUnwindSafe(|| {
+            while steps < quantum {
+                match cpu.fetch_decode_execute(&mut ram, &mut mmio_devices, print_instruction_log) {
+                    Ok(step_log) => {
+                        if keep_step_logs {
+                            step_logs.push(step_log);
+                        }
+                    }
+                    Err(e) => {
+                        error = Some(e);
+                        break;
+                    }
+                }
+                steps += 1;
+
+                if cpu.ecall_pending
+                    || cpu.semihosting_pending
+                    || stored.swap(false, Ordering::Relaxed)
+                    || (start_mode == PrivilegeMode::User && cpu.mode != PrivilegeMode::User)
+                    || (!cpu.is_translating() && !ram.is_available_addr(cpu.pc.load()))
+                {
+                    break;
+                }
+            }
+        }));
+        if let Err(payload) = quantum_run {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            error = Some(anyhow::anyhow!("Hart {} panicked: {}", hart, message));
+        }
+
+        let result = QuantumResult {
+            hart,
+            cpu,
+            steps,
+            step_logs,
+            error,
+        };
+        if results.send(HartMessage::Done(Box::new(result))).is_err() {
+            break;
+        }
+    }
+}
@@ -4,16 +4,19 @@ use crate::mmio_device::MmioDeviceInterface;
 
 pub const DEFAULT_RAM_SIZE: u32 = 1024 * 1024; // 1MB
 
-pub struct Ram(pub Vec<u8>);
+pub struct Ram {
+    pub base_addr: u32,
+    pub data: Vec<u8>,
+}
 
 impl Debug for Ram {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "Ram(size: {}):", self.0.len())?;
+        writeln!(f, "Ram(base: 0x{:x}, size: {}):", self.base_addr, self.data.len())?;
 
         // hexdump
-        let ram = self.0.as_slice();
+        let ram = self.data.as_slice();
         for i in 0..=ram.len() / 16 {
-            write!(f, "{:08x}", i * 16)?;
+            write!(f, "{:08x}", self.base_addr as usize + i * 16)?;
             let slice = &ram[i * 16..(i * 16 + 16).min(ram.len())];
             for (j, b) in slice.iter().enumerate() {
                 if j % 8 == 0 {
@@ -53,25 +56,34 @@ impl Default for Ram {
 
 impl Ram {
     pub fn new(size: u32) -> Self {
-        Self(vec![0; size as usize])
+        Self::new_with_data(vec![0; size as usize])
     }
 
     pub fn new_with_data(data: Vec<u8>) -> Self {
-        Self(data)
+        Self::new_with_base_addr(0, data)
+    }
+
+    pub fn new_with_base_addr(base_addr: u32, data: Vec<u8>) -> Self {
+        Self { base_addr, data }
+    }
+
+    fn index(&self, addr: u32) -> usize {
+        addr.wrapping_sub(self.base_addr) as usize
     }
 
     pub fn load8(&self, addr: u32) -> u8 {
-        self.0[addr as usize]
+        self.data[self.index(addr)]
     }
 
     pub fn store8(&mut self, addr: u32, value: u8) {
-        self.0[addr as usize] = value;
+        let addr = self.index(addr);
+        self.data[addr] = value;
     }
 
     pub fn load16(&self, addr: u32) -> u16 {
-        let addr = addr as usize;
-        let data1 = self.0[addr];
-        let data2 = self.0[addr + 1];
+        let addr = self.index(addr);
+        let data1 = self.data[addr];
+        let data2 = self.data[addr + 1];
 
         // little endian
         u16::from_le_bytes([data1, data2])
@@ -79,31 +91,82 @@ impl Ram {
 
     pub fn store16(&mut self, addr: u32, value: u16) {
         let bytes = value.to_le_bytes();
-        let addr = addr as usize;
-        self.0[addr] = bytes[0];
-        self.0[addr + 1] = bytes[1];
+        let addr = self.index(addr);
+        self.data[addr] = bytes[0];
+        self.data[addr + 1] = bytes[1];
     }
 
     pub fn load32(&self, addr: u32) -> u32 {
-        let addr = addr as usize;
-        let data1 = self.0[addr];
-        let data2 = self.0[addr + 1];
-        let data3 = self.0[addr + 2];
-        let data4 = self.0[addr + 3];
+        let addr = self.index(addr);
+        let data1 = self.data[addr];
+        let data2 = self.data[addr + 1];
+        let data3 = self.data[addr + 2];
+        let data4 = self.data[addr + 3];
 
         u32::from_le_bytes([data1, data2, data3, data4])
     }
 
     pub fn store32(&mut self, addr: u32, value: u32) {
         let bytes = value.to_le_bytes();
-        let addr = addr as usize;
-        self.0[addr] = bytes[0];
-        self.0[addr + 1] = bytes[1];
-        self.0[addr + 2] = bytes[2];
-        self.0[addr + 3] = bytes[3];
+        let addr = self.index(addr);
+        self.data[addr] = bytes[0];
+        self.data[addr + 1] = bytes[1];
+        self.data[addr + 2] = bytes[2];
+        self.data[addr + 3] = bytes[3];
     }
 
-    pub fn load8_with_mmio(
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_available_addr(&self, addr: u32) -> bool {
+        addr >= self.base_addr && self.index(addr) < self.size()
+    }
+
+    // for device DMA (None if the range is not in ram)
+    pub fn slice(&self, addr: u32, len: usize) -> Option<&[u8]> {
+        let start = self.range_start(addr, len)?;
+        Some(&self.data[start..start + len])
+    }
+
+    pub fn slice_mut(&mut self, addr: u32, len: usize) -> Option<&mut [u8]> {
+        let start = self.range_start(addr, len)?;
+        Some(&mut self.data[start..start + len])
+    }
+
+    fn range_start(&self, addr: u32, len: usize) -> Option<usize> {
+        if addr < self.base_addr {
+            return None;
+        }
+
+        let start = self.index(addr);
+        if start.checked_add(len)? > self.size() {
+            return None;
+        }
+
+        Some(start)
+    }
+}
+
+// the memory as seen by a hart, the ram of the emulator or the view of it shared by
+// the hart threads of the parallel mode
+pub trait Memory {
+    fn is_available_addr(&self, addr: u32) -> bool;
+    fn load8(&self, addr: u32) -> u8;
+    fn store8(&mut self, addr: u32, value: u8);
+    fn load16(&self, addr: u32) -> u16;
+    fn store16(&mut self, addr: u32, value: u16);
+    fn load32(&self, addr: u32) -> u32;
+    fn store32(&mut self, addr: u32, value: u32);
+    // read-modify-write of an aligned word as a single access, returns the old value
+    fn fetch_update32(&mut self, addr: u32, op: impl Fn(u32) -> u32) -> u32;
+    // tag of the reservation set of addr taken by lr.w, it changes with every store to the set
+    fn reservation_tag(&self, addr: u32) -> u32;
+    // store to an aligned word only if nothing was stored to its reservation set since the
+    // tag was taken
+    fn store_conditional32(&mut self, addr: u32, tag: u32, value: u32) -> bool;
+
+    fn load8_with_mmio(
         &self,
         addr: u32,
         mmio_devices: &mut Vec<Box<dyn MmioDeviceInterface>>,
@@ -118,7 +181,7 @@ impl Ram {
         return self.load8(addr);
     }
 
-    pub fn store8_with_mmio(
+    fn store8_with_mmio(
         &mut self,
         addr: u32,
         value: u8,
@@ -135,7 +198,7 @@ impl Ram {
         self.store8(addr, value);
     }
 
-    pub fn load16_with_mmio(
+    fn load16_with_mmio(
         &self,
         addr: u32,
         mmio_devices: &mut Vec<Box<dyn MmioDeviceInterface>>,
@@ -150,7 +213,7 @@ impl Ram {
         return self.load16(addr);
     }
 
-    pub fn store16_with_mmio(
+    fn store16_with_mmio(
         &mut self,
         addr: u32,
         value: u16,
@@ -167,7 +230,7 @@ impl Ram {
         self.store16(addr, value);
     }
 
-    pub fn load32_with_mmio(
+    fn load32_with_mmio(
         &self,
         addr: u32,
         mmio_devices: &mut Vec<Box<dyn MmioDeviceInterface>>,
@@ -182,7 +245,7 @@ impl Ram {
         return self.load32(addr);
     }
 
-    pub fn store32_with_mmio(
+    fn store32_with_mmio(
         &mut self,
         addr: u32,
         value: u32,
@@ -198,8 +261,51 @@ impl Ram {
 
         self.store32(addr, value);
     }
+}
 
-    pub fn size(&self) -> usize {
-        self.0.len()
+impl Memory for Ram {
+    fn is_available_addr(&self, addr: u32) -> bool {
+        Ram::is_available_addr(self, addr)
+    }
+
+    fn load8(&self, addr: u32) -> u8 {
+        Ram::load8(self, addr)
+    }
+
+    fn store8(&mut self, addr: u32, value: u8) {
+        Ram::store8(self, addr, value)
+    }
+
+    fn load16(&self, addr: u32) -> u16 {
+        Ram::load16(self, addr)
+    }
+
+    fn store16(&mut self, addr: u32, value: u16) {
+        Ram::store16(self, addr, value)
+    }
+
+    fn load32(&self, addr: u32) -> u32 {
+        Ram::load32(self, addr)
+    }
+
+    fn store32(&mut self, addr: u32, value: u32) {
+        Ram::store32(self, addr, value)
+    }
+
+    fn fetch_update32(&mut self, addr: u32, op: impl Fn(u32) -> u32) -> u32 {
+        let value = Ram::load32(self, addr);
+        Ram::store32(self, addr, op(value));
+        value
+    }
+
+    // the harts that share it take turns, a store drops the reservations of the others
+    // (Emulator::invalidate_reservations)
+    fn reservation_tag(&self, _addr: u32) -> u32 {
+        0
+    }
+
+    fn store_conditional32(&mut self, addr: u32, _tag: u32, value: u32) -> bool {
+        Ram::store32(self, addr, value);
+        true
     }
 }
@@ -0,0 +1,269 @@
+use crate::{
+    cpu::{Cpu, PrivilegeMode},
+    csr,
+    mmio_device::RequestFromDevice,
+    ram::Ram,
+    serial::SerialBackend,
+};
+
+// SBI specification v2.0
+const SPEC_VERSION: u32 = 2 << 24;
+// not taken from the implementation id registry of the specification
+const IMPL_ID: u32 = 0x4652;
+const IMPL_VERSION: u32 = 1;
+
+// legacy extensions (a0 is the only return value)
+const EXT_LEGACY_SET_TIMER: u32 = 0x00;
+const EXT_LEGACY_CONSOLE_PUTCHAR: u32 = 0x01;
+const EXT_LEGACY_CONSOLE_GETCHAR: u32 = 0x02;
+const EXT_LEGACY_CLEAR_IPI: u32 = 0x03;
+const EXT_LEGACY_SEND_IPI: u32 = 0x04;
+const EXT_LEGACY_SHUTDOWN: u32 = 0x08;
+
+const EXT_BASE: u32 = 0x10;
+const EXT_TIME: u32 = 0x5449_4d45; // "TIME"
+const EXT_IPI: u32 = 0x0073_5049; // "sPI"
+const EXT_RFENCE: u32 = 0x5246_4e43; // "RFNC"
+const EXT_HSM: u32 = 0x0048_534d; // "HSM"
+const EXT_SRST: u32 = 0x5352_5354; // "SRST"
+const EXT_DBCN: u32 = 0x4442_434e; // "DBCN"
+
+const EXTENSIONS: [u32; 7] = [
+    EXT_BASE, EXT_TIME, EXT_IPI, EXT_RFENCE, EXT_HSM, EXT_SRST, EXT_DBCN,
+];
+
+const ERR_FAILED: i32 = -1;
+const ERR_NOT_SUPPORTED: i32 = -2;
+const ERR_INVALID_PARAM: i32 = -3;
+const ERR_ALREADY_AVAILABLE: i32 = -6;
+
+const HSM_STATE_STARTED: u32 = 0;
+const HSM_STATE_STOPPED: u32 = 1;
+
+const SRST_TYPE_SHUTDOWN: u32 = 0;
+const SRST_TYPE_COLD_REBOOT: u32 = 1;
+const SRST_TYPE_WARM_REBOOT: u32 = 2;
+
+// Supervisor Binary Interface implemented by the host for a kernel started in S-mode
+// without firmware (the calls are the ecalls from S-mode, the hart ids are the indexes of the harts)
+#[derive(Debug)]
+pub struct Sbi {
+    console: Box<dyn SerialBackend>,
+}
+
+impl Sbi {
+    pub fn new(console: Box<dyn SerialBackend>) -> Self {
+        Self { console }
+    }
+
+    // a7 = extension id, a6 = function id, a0-a5 = arguments,
+    // returns the error in a0 and the value in a1
+    pub fn handle_call(
+        &mut self,
+        harts: &mut [Cpu],
+        hart: usize,
+        ram: &mut Ram,
+    ) -> Option<RequestFromDevice> {
+        let cpu = &harts[hart];
+        let eid = cpu.x_regs[17].load();
+        let fid = cpu.x_regs[16].load();
+        let args: [u32; 6] = std::array::from_fn(|i| cpu.x_regs[10 + i].load());
+
+        if eid <= EXT_LEGACY_SHUTDOWN {
+            let (value, request) = self.legacy_call(eid, args, harts, hart);
+            harts[hart].x_regs[10].store(value);
+            return request;
+        }
+
+        let mut request = None;
+        let result = match (eid, fid) {
+            (EXT_BASE, _) => base_call(fid, args, &harts[hart]),
+            (EXT_TIME, 0) => {
+                set_timer(&mut harts[hart], args[0], args[1]);
+                Ok(0)
+            }
+            (EXT_IPI, 0) => send_ipi(harts, args[0], args[1]),
+            // there is no TLB or instruction cache to flush
+            (EXT_RFENCE, 0..=6) => Ok(0),
+            (EXT_HSM, _) => hsm_call(fid, args, harts, hart),
+            (EXT_SRST, 0) => match args[0] {
+                SRST_TYPE_SHUTDOWN => {
+                    // the reset reason is 0 (none) or 1 (system failure)
+                    request = Some(RequestFromDevice::Exit((args[1] != 0) as u32));
+                    Ok(0)
+                }
+                SRST_TYPE_COLD_REBOOT | SRST_TYPE_WARM_REBOOT => {
+                    request = Some(RequestFromDevice::Reset);
+                    Ok(0)
+                }
+                _ => Err(ERR_INVALID_PARAM),
+            },
+            (EXT_DBCN, _) => self.dbcn_call(fid, args, ram),
+            _ => Err(ERR_NOT_SUPPORTED),
+        };
+
+        let (error, value) = match result {
+            Ok(value) => (0, value),
+            Err(error) => (error, 0),
+        };
+        let cpu = &mut harts[hart];
+        cpu.x_regs[10].store(error as u32);
+        cpu.x_regs[11].store(value);
+
+        request
+    }
+
+    fn legacy_call(
+        &mut self,
+        eid: u32,
+        args: [u32; 6],
+        harts: &mut [Cpu],
+        hart: usize,
+    ) -> (u32, Option<RequestFromDevice>) {
+        match eid {
+            EXT_LEGACY_SET_TIMER => set_timer(&mut harts[hart], args[0], args[1]),
+            EXT_LEGACY_CONSOLE_PUTCHAR => self.console.write(args[0] as u8),
+            EXT_LEGACY_CONSOLE_GETCHAR => {
+                let value = self.console.read().map_or(u32::MAX, |value| value as u32);
+                return (value, None);
+            }
+            EXT_LEGACY_CLEAR_IPI => set_ssip(&mut harts[hart], false),
+            // the hart mask (a virtual address) is ignored, every hart gets the ipi
+            EXT_LEGACY_SEND_IPI => {
+                for cpu in harts {
+                    set_ssip(cpu, true);
+                }
+            }
+            EXT_LEGACY_SHUTDOWN => return (0, Some(RequestFromDevice::Exit(0))),
+            // remote fences
+            _ => (),
+        }
+
+        (0, None)
+    }
+
+    // the buffers are physical addresses (base_addr_lo, base_addr_hi)
+    fn dbcn_call(&mut self, fid: u32, args: [u32; 6], ram: &mut Ram) -> Result<u32, i32> {
+        let len = args[0] as usize;
+
+        match fid {
+            0 if args[2] == 0 => {
+                let data = ram.slice(args[1], len).ok_or(ERR_INVALID_PARAM)?;
+                for value in data {
+                    self.console.write(*value);
+                }
+                Ok(len as u32)
+            }
+            1 if args[2] == 0 => {
+                let buf = ram.slice_mut(args[1], len).ok_or(ERR_INVALID_PARAM)?;
+                let mut read_len = 0;
+                while read_len < len {
+                    match self.console.read() {
+                        Some(value) => buf[read_len] = value,
+                        None => break,
+                    }
+                    read_len += 1;
+                }
+                Ok(read_len as u32)
+            }
+            0 | 1 => Err(ERR_INVALID_PARAM),
+            2 => {
+                self.console.write(args[0] as u8);
+                Ok(0)
+            }
+            _ => Err(ERR_NOT_SUPPORTED),
+        }
+    }
+}
+
+fn base_call(fid: u32, args: [u32; 6], cpu: &Cpu) -> Result<u32, i32> {
+    match fid {
+        0 => Ok(SPEC_VERSION),
+        1 => Ok(IMPL_ID),
+        2 => Ok(IMPL_VERSION),
+        3 => {
+            let eid = args[0];
+            Ok((eid <= EXT_LEGACY_SHUTDOWN || EXTENSIONS.contains(&eid)) as u32)
+        }
+        4 => Ok(cpu.csrs.load(csr::MVENDORID)),
+        5 => Ok(cpu.csrs.load(csr::MARCHID)),
+        6 => Ok(cpu.csrs.load(csr::MIMPID)),
+        _ => Err(ERR_NOT_SUPPORTED),
+    }
+}
+
+// hart_mask, hart_mask_base (all harts if the base is -1)
+fn send_ipi(harts: &mut [Cpu], hart_mask: u32, hart_mask_base: u32) -> Result<u32, i32> {
+    if hart_mask_base == u32::MAX {
+        for cpu in harts {
+            set_ssip(cpu, true);
+        }
+        return Ok(0);
+    }
+
+    let targets: Vec<usize> = (0..32)
+        .filter(|bit| hart_mask >> bit & 1 != 0)
+        .map(|bit| hart_mask_base as usize + bit)
+        .collect();
+    if targets.iter().any(|&hart| hart >= harts.len()) {
+        return Err(ERR_INVALID_PARAM);
+    }
+    for hart in targets {
+        set_ssip(&mut harts[hart], true);
+    }
+
+    Ok(0)
+}
+
+// hart_start enters S-mode at start_addr with a0 = hart id, a1 = opaque and the mmu off
+fn hsm_call(fid: u32, args: [u32; 6], harts: &mut [Cpu], hart: usize) -> Result<u32, i32> {
+    match fid {
+        0 => {
+            let cpu = harts.get_mut(args[0] as usize).ok_or(ERR_INVALID_PARAM)?;
+            if !cpu.stopped {
+                return Err(ERR_ALREADY_AVAILABLE);
+            }
+
+            cpu.stopped = false;
+            cpu.mode = PrivilegeMode::Supervisor;
+            cpu.pc.store(args[1]);
+            cpu.x_regs[10].store(args[0]); // a0
+            cpu.x_regs[11].store(args[2]); // a1
+            cpu.csrs.store(csr::SATP, 0);
+            let mstatus = cpu.csrs.load(csr::MSTATUS) & !csr::MSTATUS_SIE;
+            cpu.csrs.store(csr::MSTATUS, mstatus);
+            Ok(0)
+        }
+        // hart_stop (the last hart can't be stopped)
+        1 => {
+            if harts.iter().filter(|cpu| !cpu.stopped).count() <= 1 {
+                return Err(ERR_FAILED);
+            }
+            harts[hart].stopped = true;
+            Ok(0)
+        }
+        2 => harts
+            .get(args[0] as usize)
+            .map(|cpu| {
+                if cpu.stopped {
+                    HSM_STATE_STOPPED
+                } else {
+                    HSM_STATE_STARTED
+                }
+            })
+            .ok_or(ERR_INVALID_PARAM),
+        _ => Err(ERR_NOT_SUPPORTED),
+    }
+}
+
+// the supervisor timer interrupt follows stimecmp (see Csrs::set_time)
+fn set_timer(cpu: &mut Cpu, low: u32, high: u32) {
+    cpu.csrs.store(csr::STIMECMP, low);
+    cpu.csrs.store(csr::STIMECMPH, high);
+}
+
+fn set_ssip(cpu: &mut Cpu, pending: bool) {
+    let mip = cpu.csrs.load(csr::MIP) & !csr::MIP_SSIP;
+    cpu.csrs
+        .store(csr::MIP, if pending { mip | csr::MIP_SSIP } else { mip });
+}
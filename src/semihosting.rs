@@ -0,0 +1,420 @@
+use crate::{cpu::Cpu, mmio_device::RequestFromDevice, ram::Ram, serial::SerialBackend};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+// the magic sequence around the ebreak
+pub const SLLI_X0_X0_0X1F: u32 = 0x01f0_1013;
+pub const SRAI_X0_X0_7: u32 = 0x4070_5013;
+
+const SYS_OPEN: u32 = 0x01;
+const SYS_CLOSE: u32 = 0x02;
+const SYS_WRITEC: u32 = 0x03;
+const SYS_WRITE0: u32 = 0x04;
+const SYS_WRITE: u32 = 0x05;
+const SYS_READ: u32 = 0x06;
+const SYS_ISTTY: u32 = 0x09;
+const SYS_SEEK: u32 = 0x0a;
+const SYS_FLEN: u32 = 0x0c;
+const SYS_REMOVE: u32 = 0x0e;
+const SYS_CLOCK: u32 = 0x10;
+const SYS_TIME: u32 = 0x11;
+const SYS_ERRNO: u32 = 0x13;
+const SYS_GET_CMDLINE: u32 = 0x15;
+const SYS_HEAPINFO: u32 = 0x16;
+const SYS_EXIT: u32 = 0x18;
+const SYS_EXIT_EXTENDED: u32 = 0x20;
+
+const ADP_STOPPED_APPLICATION_EXIT: u32 = 0x20026;
+
+// special file names of SYS_OPEN
+const CONSOLE_NAME: &str = ":tt";
+const FEATURES_NAME: &str = ":semihosting-features";
+// SH_EXT_EXIT_EXTENDED | SH_EXT_STDOUT_STDERR
+const FEATURES: [u8; 5] = [b'S', b'H', b'F', b'B', 0b11];
+
+const ENOENT: u32 = 2;
+const EIO: u32 = 5;
+const EBADF: u32 = 9;
+const EACCES: u32 = 13;
+const EEXIST: u32 = 17;
+const EINVAL: u32 = 22;
+
+const FAILED: u32 = u32::MAX;
+
+// heap and stack of the program returned by SYS_HEAPINFO (0 means unknown)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeapInfo {
+    pub heap_base: u32,
+    pub heap_limit: u32,
+    pub stack_base: u32,
+    pub stack_limit: u32,
+}
+
+#[derive(Debug)]
+enum Handle {
+    // stdin, stdout and stderr of ":tt"
+    Console,
+    File(File),
+    // read position in FEATURES
+    Features(usize),
+}
+
+// ARM-style semihosting for RISC-V, the calls are `slli x0, x0, 0x1f; ebreak; srai x0, x0, 7`
+// with the operation in a0 and the parameter (usually a block of words) in a1,
+// the parameter blocks are physical addresses
+#[derive(Debug)]
+pub struct Semihosting {
+    console: Box<dyn SerialBackend>,
+    cmdline: String,
+    heap_info: HeapInfo,
+    // files are opened relative to this directory and can't leave it
+    sandbox: Option<PathBuf>,
+    handles: Vec<Option<Handle>>,
+    errno: u32,
+    start: Instant,
+}
+
+impl Semihosting {
+    pub fn new(
+        console: Box<dyn SerialBackend>,
+        cmdline: &str,
+        heap_info: HeapInfo,
+        sandbox: Option<PathBuf>,
+    ) -> Self {
+        Self {
+            console,
+            cmdline: String::from(cmdline),
+            heap_info,
+            sandbox,
+            handles: Vec::new(),
+            errno: 0,
+            start: Instant::now(),
+        }
+    }
+
+    // returns the result in a0
+    pub fn handle_call(&mut self, cpu: &mut Cpu, ram: &mut Ram) -> Option<RequestFromDevice> {
+        let op = cpu.x_regs[10].load();
+        let param = cpu.x_regs[11].load();
+
+        let result = match op {
+            SYS_EXIT => {
+                // the reason code itself on 32-bit targets
+                let exit_code = (param != ADP_STOPPED_APPLICATION_EXIT) as u32;
+                return Some(RequestFromDevice::Exit(exit_code));
+            }
+            SYS_EXIT_EXTENDED => match load_block::<2>(ram, param) {
+                Ok([ADP_STOPPED_APPLICATION_EXIT, subcode]) => {
+                    return Some(RequestFromDevice::Exit(subcode))
+                }
+                Ok(_) => return Some(RequestFromDevice::Exit(1)),
+                Err(errno) => Err(errno),
+            },
+            SYS_OPEN => self.open(param, ram),
+            SYS_CLOSE => self.close(param, ram),
+            SYS_WRITEC => ram
+                .slice(param, 1)
+                .map(|value| self.console.write(value[0]))
+                .map(|_| 0)
+                .ok_or(EINVAL),
+            SYS_WRITE0 => self.write0(param, ram),
+            SYS_WRITE => self.write(param, ram),
+            SYS_READ => self.read(param, ram),
+            SYS_ISTTY => self.istty(param, ram),
+            SYS_SEEK => self.seek(param, ram),
+            SYS_FLEN => self.flen(param, ram),
+            SYS_REMOVE => self.remove(param, ram),
+            // centiseconds since the start
+            SYS_CLOCK => Ok((self.start.elapsed().as_millis() / 10) as u32),
+            SYS_TIME => Ok(SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs() as u32)),
+            SYS_ERRNO => Ok(self.errno),
+            SYS_GET_CMDLINE => self.get_cmdline(param, ram),
+            SYS_HEAPINFO => self.heapinfo(param, ram),
+            _ => Err(EINVAL),
+        };
+
+        cpu.x_regs[10].store(match result {
+            Ok(value) => value,
+            Err(errno) => {
+                self.errno = errno;
+                FAILED
+            }
+        });
+
+        None
+    }
+
+    fn handle(&mut self, handle: u32) -> Result<&mut Handle, u32> {
+        // handles start at 1
+        (handle as usize)
+            .checked_sub(1)
+            .and_then(|i| self.handles.get_mut(i))
+            .and_then(Option::as_mut)
+            .ok_or(EBADF)
+    }
+
+    // [name, mode (fopen mode index), name length]
+    fn open(&mut self, param: u32, ram: &Ram) -> Result<u32, u32> {
+        let [name, mode, len] = load_block(ram, param)?;
+        let name = ram.slice(name, len as usize).ok_or(EINVAL)?;
+        let name = String::from_utf8_lossy(name).into_owned();
+
+        let handle = match name.as_str() {
+            CONSOLE_NAME => Handle::Console,
+            FEATURES_NAME if mode < 4 => Handle::Features(0),
+            _ => {
+                let path = self.host_path(&name)?;
+                let mut options = OpenOptions::new();
+                // r, r+, w, w+, a, a+ (each with and without b)
+                match mode / 2 {
+                    0 => options.read(true),
+                    1 => options.read(true).write(true),
+                    2 => options.write(true).create(true).truncate(true),
+                    3 => options.read(true).write(true).create(true).truncate(true),
+                    4 => options.append(true).create(true),
+                    5 => options.read(true).append(true).create(true),
+                    _ => return Err(EINVAL),
+                };
+                Handle::File(options.open(path).map_err(errno)?)
+            }
+        };
+
+        let i = match self.handles.iter().position(Option::is_none) {
+            Some(i) => i,
+            None => {
+                self.handles.push(None);
+                self.handles.len() - 1
+            }
+        };
+        self.handles[i] = Some(handle);
+
+        Ok(i as u32 + 1)
+    }
+
+    // [handle]
+    fn close(&mut self, param: u32, ram: &Ram) -> Result<u32, u32> {
+        let [handle] = load_block(ram, param)?;
+        self.handle(handle)?;
+        self.handles[handle as usize - 1] = None;
+
+        Ok(0)
+    }
+
+    fn write0(&mut self, param: u32, ram: &Ram) -> Result<u32, u32> {
+        let mut addr = param;
+        while ram.is_available_addr(addr) {
+            match ram.load8(addr) {
+                0 => return Ok(0),
+                value => self.console.write(value),
+            }
+            addr = addr.wrapping_add(1);
+        }
+
+        Err(EINVAL)
+    }
+
+    // [handle, buffer, length], returns the number of bytes that were not written
+    fn write(&mut self, param: u32, ram: &Ram) -> Result<u32, u32> {
+        let [handle, buf, len] = load_block(ram, param)?;
+        let buf = ram.slice(buf, len as usize).ok_or(EINVAL)?;
+
+        match self.handle(handle)? {
+            Handle::Console => {
+                for value in buf {
+                    self.console.write(*value);
+                }
+            }
+            Handle::File(file) => file.write_all(buf).map_err(errno)?,
+            Handle::Features(_) => return Err(EBADF),
+        }
+
+        Ok(0)
+    }
+
+    // [handle, buffer, length], returns the number of bytes that were not read
+    fn read(&mut self, param: u32, ram: &mut Ram) -> Result<u32, u32> {
+        let [handle, buf, len] = load_block(ram, param)?;
+        let buf = ram.slice_mut(buf, len as usize).ok_or(EINVAL)?;
+
+        let read_len = match self.handle(handle)? {
+            // only what has already arrived
+            Handle::Console => {
+                let mut read_len = 0;
+                while read_len < buf.len() {
+                    match self.console.read() {
+                        Some(value) => buf[read_len] = value,
+                        None => break,
+                    }
+                    read_len += 1;
+                }
+                read_len
+            }
+            Handle::File(file) => read_full(file, buf).map_err(errno)?,
+            Handle::Features(pos) => {
+                let data = &FEATURES[(*pos).min(FEATURES.len())..];
+                let read_len = data.len().min(buf.len());
+                buf[..read_len].copy_from_slice(&data[..read_len]);
+                *pos += read_len;
+                read_len
+            }
+        };
+
+        Ok(len - read_len as u32)
+    }
+
+    // [handle]
+    fn istty(&mut self, param: u32, ram: &Ram) -> Result<u32, u32> {
+        let [handle] = load_block(ram, param)?;
+        Ok(matches!(self.handle(handle)?, Handle::Console) as u32)
+    }
+
+    // [handle, absolute position]
+    fn seek(&mut self, param: u32, ram: &Ram) -> Result<u32, u32> {
+        let [handle, pos] = load_block(ram, param)?;
+        match self.handle(handle)? {
+            Handle::File(file) => {
+                file.seek(SeekFrom::Start(pos as u64)).map_err(errno)?;
+            }
+            Handle::Features(features_pos) => *features_pos = pos as usize,
+            Handle::Console => return Err(EBADF),
+        }
+
+        Ok(0)
+    }
+
+    // [handle]
+    fn flen(&mut self, param: u32, ram: &Ram) -> Result<u32, u32> {
+        let [handle] = load_block(ram, param)?;
+        match self.handle(handle)? {
+            Handle::File(file) => Ok(file.metadata().map_err(errno)?.len() as u32),
+            Handle::Features(_) => Ok(FEATURES.len() as u32),
+            Handle::Console => Err(EBADF),
+        }
+    }
+
+    // [name, name length]
+    fn remove(&mut self, param: u32, ram: &Ram) -> Result<u32, u32> {
+        let [name, len] = load_block(ram, param)?;
+        let name = ram.slice(name, len as usize).ok_or(EINVAL)?;
+        let path = self.host_path(&String::from_utf8_lossy(name))?;
+        fs::remove_file(path).map_err(errno)?;
+
+        Ok(0)
+    }
+
+    // [buffer, buffer length], the length is replaced with the length of the command line
+    fn get_cmdline(&mut self, param: u32, ram: &mut Ram) -> Result<u32, u32> {
+        let [buf, len] = load_block(ram, param)?;
+        let cmdline = format!("{}\0", self.cmdline);
+        if cmdline.len() > len as usize {
+            return Err(EINVAL);
+        }
+
+        ram.slice_mut(buf, cmdline.len())
+            .ok_or(EINVAL)?
+            .copy_from_slice(cmdline.as_bytes());
+        store_block(ram, param + 4, &[cmdline.len() as u32 - 1])?;
+
+        Ok(0)
+    }
+
+    // the parameter points to the address of a block of 4 words
+    fn heapinfo(&mut self, param: u32, ram: &mut Ram) -> Result<u32, u32> {
+        let [block] = load_block(ram, param)?;
+        let info = self.heap_info;
+        store_block(
+            ram,
+            block,
+            &[
+                info.heap_base,
+                info.heap_limit,
+                info.stack_base,
+                info.stack_limit,
+            ],
+        )?;
+
+        Ok(0)
+    }
+
+    fn host_path(&self, name: &str) -> Result<PathBuf, u32> {
+        let path = Path::new(name);
+        match &self.sandbox {
+            Some(sandbox) => {
+                let inside = path
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+                if !inside {
+                    return Err(EACCES);
+                }
+
+                // symlinks may still lead out of the sandbox, the resolved path is checked (a
+                // file that doesn't exist yet is resolved through its directory)
+                let root = sandbox.canonicalize().map_err(errno)?;
+                let joined = sandbox.join(path);
+                let resolved = match joined.canonicalize() {
+                    Ok(resolved) => resolved,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                        // a dangling symlink would be followed by a create
+                        if fs::symlink_metadata(&joined).is_ok() {
+                            return Err(EACCES);
+                        }
+                        let file_name = joined.file_name().ok_or(EACCES)?;
+                        let parent = joined.parent().ok_or(EACCES)?;
+                        parent.canonicalize().map_err(errno)?.join(file_name)
+                    }
+                    Err(e) => return Err(errno(e)),
+                };
+                if !resolved.starts_with(&root) {
+                    return Err(EACCES);
+                }
+
+                Ok(joined)
+            }
+            None => Ok(path.to_path_buf()),
+        }
+    }
+}
+
+fn load_block<const N: usize>(ram: &Ram, addr: u32) -> Result<[u32; N], u32> {
+    let bytes = ram.slice(addr, N * 4).ok_or(EINVAL)?;
+    Ok(std::array::from_fn(|i| {
+        u32::from_le_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap())
+    }))
+}
+
+fn store_block(ram: &mut Ram, addr: u32, values: &[u32]) -> Result<(), u32> {
+    let bytes = ram.slice_mut(addr, values.len() * 4).ok_or(EINVAL)?;
+    for (chunk, value) in bytes.chunks_exact_mut(4).zip(values) {
+        chunk.copy_from_slice(&value.to_le_bytes());
+    }
+
+    Ok(())
+}
+
+fn read_full(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match file.read(&mut buf[len..])? {
+            0 => break,
+            read_len => len += read_len,
+        }
+    }
+
+    Ok(len)
+}
+
+fn errno(error: io::Error) -> u32 {
+    match error.kind() {
+        io::ErrorKind::NotFound => ENOENT,
+        io::ErrorKind::PermissionDenied => EACCES,
+        io::ErrorKind::AlreadyExists => EEXIST,
+        io::ErrorKind::InvalidInput => EINVAL,
+        _ => EIO,
+    }
+}
@@ -0,0 +1,74 @@
+use std::{
+    cell::RefCell,
+    fmt::Debug,
+    io::{self, Write},
+    rc::Rc,
+};
+
+// host side of a serial device (transmitted bytes go to the sink, received bytes come from the source)
+pub trait SerialBackend: Debug {
+    fn write(&mut self, value: u8);
+    fn read(&mut self) -> Option<u8>;
+
+    fn quit_requested(&mut self) -> bool {
+        false
+    }
+}
+
+// output only, default backend
+#[derive(Debug, Default)]
+pub struct StdoutBackend;
+
+impl SerialBackend for StdoutBackend {
+    fn write(&mut self, value: u8) {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(&[value]);
+        let _ = stdout.flush();
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        None
+    }
+}
+
+// lets several devices (e.g. the UART and virtio-console) use the same host side, the
+// input goes to the handles made by new and cloned from them, output_only handles never read
+#[derive(Debug, Clone)]
+pub struct SharedBackend {
+    backend: Rc<RefCell<Box<dyn SerialBackend>>>,
+    input: bool,
+}
+
+impl SharedBackend {
+    pub fn new(backend: Box<dyn SerialBackend>) -> Self {
+        Self {
+            backend: Rc::new(RefCell::new(backend)),
+            input: true,
+        }
+    }
+
+    pub fn output_only(&self) -> Self {
+        Self {
+            backend: self.backend.clone(),
+            input: false,
+        }
+    }
+}
+
+impl SerialBackend for SharedBackend {
+    fn write(&mut self, value: u8) {
+        self.backend.borrow_mut().write(value);
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        if !self.input {
+            return None;
+        }
+
+        self.backend.borrow_mut().read()
+    }
+
+    fn quit_requested(&mut self) -> bool {
+        self.backend.borrow_mut().quit_requested()
+    }
+}
@@ -104,7 +104,12 @@ impl RamWrite {
 #[derive(Debug, Serialize)]
 #[allow(dead_code)]
 pub struct CpuStep {
+    // counts the steps of the hart
     pub step: usize,
+    // cycles of the hart at the end of the step (timing model)
+    pub cycle: u64,
+    // mhartid of the hart that executed the step
+    pub hart: u32,
     pub fetched_instruction: u32,
     pub decoded_instruction: Instruction,
     pub cpu_state: CpuStateLog,
@@ -118,11 +123,22 @@ pub struct DeviceRequest {
     pub req: RequestFromDevice,
 }
 
+// writes by bus masters other than the cpu (one entry per burst)
+#[derive(Debug, Serialize)]
+#[allow(dead_code)]
+pub struct DeviceWrite {
+    pub step: usize,
+    pub device_name: String,
+    pub ram_writes: Vec<RamWrite>,
+}
+
 #[derive(Debug, Serialize)]
 #[allow(dead_code)]
 pub struct Log {
     pub init_cpu_state: CpuStateLog,
+    pub init_ram_base_addr: u32,
     pub init_ram: Vec<u8>,
     pub steps: Vec<CpuStep>,
     pub dev_reqs: Vec<DeviceRequest>,
+    pub dev_writes: Vec<DeviceWrite>,
 }
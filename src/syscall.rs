@@ -0,0 +1,666 @@
+use crate::{cpu::Cpu, csr, mmio_device::RequestFromDevice, ram::Ram};
+use std::{
+    fs::{File, Metadata, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+pub const PAGE_SIZE: u32 = 4096;
+
+// syscall numbers of riscv32 Linux (asm-generic, 64-bit time only)
+const SYS_IOCTL: u32 = 29;
+const SYS_OPENAT: u32 = 56;
+const SYS_CLOSE: u32 = 57;
+const SYS_LLSEEK: u32 = 62;
+const SYS_READ: u32 = 63;
+const SYS_WRITE: u32 = 64;
+const SYS_READV: u32 = 65;
+const SYS_WRITEV: u32 = 66;
+// not in riscv32 Linux, used by newlib (struct kernel_stat)
+const SYS_FSTAT: u32 = 80;
+const SYS_EXIT: u32 = 93;
+const SYS_EXIT_GROUP: u32 = 94;
+const SYS_SET_TID_ADDRESS: u32 = 96;
+const SYS_RT_SIGACTION: u32 = 134;
+const SYS_RT_SIGPROCMASK: u32 = 135;
+const SYS_UNAME: u32 = 160;
+// not in riscv32 Linux, used by newlib (64-bit tv_sec)
+const SYS_GETTIMEOFDAY: u32 = 169;
+const SYS_GETPID: u32 = 172;
+const SYS_GETPPID: u32 = 173;
+const SYS_GETUID: u32 = 174;
+const SYS_GETEUID: u32 = 175;
+const SYS_GETGID: u32 = 176;
+const SYS_GETEGID: u32 = 177;
+const SYS_GETTID: u32 = 178;
+const SYS_BRK: u32 = 214;
+const SYS_MUNMAP: u32 = 215;
+const SYS_MMAP2: u32 = 222;
+const SYS_MPROTECT: u32 = 226;
+const SYS_GETRANDOM: u32 = 278;
+const SYS_STATX: u32 = 291;
+const SYS_CLOCK_GETTIME64: u32 = 403;
+
+const ENOENT: i32 = 2;
+const EIO: i32 = 5;
+const EBADF: i32 = 9;
+const ENOMEM: i32 = 12;
+const EACCES: i32 = 13;
+const EFAULT: i32 = 14;
+const EEXIST: i32 = 17;
+const EINVAL: i32 = 22;
+const ENOTTY: i32 = 25;
+const ESPIPE: i32 = 29;
+const ENOSYS: i32 = 38;
+const ENOTSUP: i32 = 95;
+
+const AT_FDCWD: u32 = -100i32 as u32;
+const AT_SYMLINK_NOFOLLOW: u32 = 0x100;
+const AT_EMPTY_PATH: u32 = 0x1000;
+
+const O_ACCMODE: u32 = 0b11;
+const O_WRONLY: u32 = 1;
+const O_RDWR: u32 = 2;
+const O_CREAT: u32 = 0o100;
+const O_EXCL: u32 = 0o200;
+const O_TRUNC: u32 = 0o1000;
+const O_APPEND: u32 = 0o2000;
+
+const SEEK_SET: u32 = 0;
+const SEEK_CUR: u32 = 1;
+const SEEK_END: u32 = 2;
+
+const MAP_FIXED: u32 = 0x10;
+const MAP_ANONYMOUS: u32 = 0x20;
+
+const S_IFCHR: u32 = 0o020000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+
+const STATX_BASIC_STATS: u32 = 0x7ff;
+const CLOCK_REALTIME: u32 = 0;
+
+// the process is alone (pid 1 without a parent)
+const PID: u32 = 1;
+const UTSNAME_FIELD_LEN: usize = 65;
+const UTSNAME: [&str; 6] = ["Linux", "frisc", "6.6.0", "#1", "riscv32", "(none)"];
+
+// auxiliary vector entries
+pub const AT_NULL: u32 = 0;
+pub const AT_PHDR: u32 = 3;
+pub const AT_PHENT: u32 = 4;
+pub const AT_PHNUM: u32 = 5;
+pub const AT_PAGESZ: u32 = 6;
+pub const AT_ENTRY: u32 = 9;
+pub const AT_UID: u32 = 11;
+pub const AT_EUID: u32 = 12;
+pub const AT_GID: u32 = 13;
+pub const AT_EGID: u32 = 14;
+pub const AT_HWCAP: u32 = 16;
+pub const AT_CLKTCK: u32 = 17;
+pub const AT_SECURE: u32 = 23;
+pub const AT_RANDOM: u32 = 25;
+
+const CLOCK_TICKS: u32 = 100;
+const RANDOM_SEED: u64 = 0x6672_6973_6321_2121;
+
+#[derive(Debug)]
+enum FileDesc {
+    Stdin,
+    Stdout,
+    Stderr,
+    File(File),
+}
+
+// Linux syscalls of a static executable running in U-mode without a kernel (the calls are the
+// ecalls from U-mode), user addresses are physical addresses and files are the host's
+#[derive(Debug)]
+pub struct Syscalls {
+    files: Vec<Option<FileDesc>>,
+    brk_start: u32,
+    brk: u32,
+    // mmap allocates downwards from the bottom of the stack
+    mmap_base: u32,
+    start: Instant,
+    // xorshift64 for AT_RANDOM and getrandom (same bytes on every run)
+    random_state: u64,
+}
+
+impl Syscalls {
+    // the heap starts at the end of the program, mmap_top is the bottom of the stack
+    pub fn new(program_end: u32, mmap_top: u32) -> Self {
+        let brk = page_align(program_end);
+
+        Self {
+            files: vec![
+                Some(FileDesc::Stdin),
+                Some(FileDesc::Stdout),
+                Some(FileDesc::Stderr),
+            ],
+            brk_start: brk,
+            brk,
+            mmap_base: mmap_top & !(PAGE_SIZE - 1),
+            start: Instant::now(),
+            random_state: RANDOM_SEED,
+        }
+    }
+
+    // the heap, the mmap area and the stack are above it, the calls access them directly
+    pub fn memory_start(&self) -> u32 {
+        self.brk_start
+    }
+
+    // the initial stack as built by the kernel (argc, argv, envp and auxv, then the strings),
+    // returns the stack pointer
+    pub fn init_stack(
+        &mut self,
+        ram: &mut Ram,
+        stack_top: u32,
+        argv: &[String],
+        envp: &[String],
+        auxv: &[(u32, u32)],
+    ) -> Option<u32> {
+        let mut sp = stack_top;
+        let mut random = [0; 16];
+        self.fill_random(&mut random);
+        let random_addr = push_bytes(ram, &mut sp, &random)?;
+
+        let mut push_strings = |strings: &[String]| -> Option<Vec<u32>> {
+            strings
+                .iter()
+                .map(|s| push_bytes(ram, &mut sp, format!("{}\0", s).as_bytes()))
+                .collect()
+        };
+        let argv_addrs = push_strings(argv)?;
+        let envp_addrs = push_strings(envp)?;
+
+        let mut words = vec![argv.len() as u32];
+        words.extend(argv_addrs);
+        words.push(0);
+        words.extend(envp_addrs);
+        words.push(0);
+        let hwcap = csr::MISA_VALUE & 0x3ff_ffff;
+        let default_auxv = [
+            (AT_PAGESZ, PAGE_SIZE),
+            (AT_HWCAP, hwcap),
+            (AT_CLKTCK, CLOCK_TICKS),
+            (AT_UID, 0),
+            (AT_EUID, 0),
+            (AT_GID, 0),
+            (AT_EGID, 0),
+            (AT_SECURE, 0),
+            (AT_RANDOM, random_addr),
+            (AT_NULL, 0),
+        ];
+        for (key, value) in auxv.iter().chain(default_auxv.iter()) {
+            words.extend([*key, *value]);
+        }
+
+        // 16 bytes aligned like the psABI requires
+        sp = sp.checked_sub(words.len() as u32 * 4)? & !0xf;
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        ram.slice_mut(sp, bytes.len())?.copy_from_slice(&bytes);
+
+        Some(sp)
+    }
+
+    // a7 = syscall number, a0-a5 = arguments, returns the result or -errno in a0
+    pub fn handle_call(&mut self, cpu: &mut Cpu, ram: &mut Ram) -> Option<RequestFromDevice> {
+        let nr = cpu.x_regs[17].load();
+        let args: [u32; 6] = std::array::from_fn(|i| cpu.x_regs[10 + i].load());
+
+        let result = match nr {
+            SYS_EXIT | SYS_EXIT_GROUP => return Some(RequestFromDevice::Exit(args[0] & 0xff)),
+            SYS_IOCTL => self.file(args[0]).and(Err(ENOTTY)),
+            SYS_OPENAT => self.openat(args, ram),
+            SYS_CLOSE => self.close(args[0]),
+            SYS_LLSEEK => self.llseek(args, ram),
+            SYS_READ => self.read(args[0], args[1], args[2], ram),
+            SYS_WRITE => self.write(args[0], args[1], args[2], ram),
+            SYS_READV => self.readv(args, ram),
+            SYS_WRITEV => self.writev(args, ram),
+            SYS_FSTAT => self.fstat(args, ram),
+            SYS_STATX => self.statx(args, ram),
+            SYS_BRK => Ok(self.set_brk(args[0], ram)),
+            SYS_MMAP2 => self.mmap(args, ram),
+            // nothing is freed or protected
+            SYS_MUNMAP | SYS_MPROTECT => Ok(0),
+            // there are no signals
+            SYS_RT_SIGACTION | SYS_RT_SIGPROCMASK => Ok(0),
+            SYS_SET_TID_ADDRESS | SYS_GETPID | SYS_GETTID => Ok(PID),
+            SYS_GETPPID | SYS_GETUID | SYS_GETEUID | SYS_GETGID | SYS_GETEGID => Ok(0),
+            SYS_UNAME => uname(args[0], ram),
+            SYS_CLOCK_GETTIME64 => self.clock_gettime(args, ram),
+            SYS_GETTIMEOFDAY => gettimeofday(args[0], ram),
+            SYS_GETRANDOM => self.getrandom(args, ram),
+            _ => Err(ENOSYS),
+        };
+
+        cpu.x_regs[10].store(match result {
+            Ok(value) => value,
+            Err(errno) => (-errno) as u32,
+        });
+
+        None
+    }
+
+    fn file(&mut self, fd: u32) -> Result<&mut FileDesc, i32> {
+        self.files
+            .get_mut(fd as usize)
+            .and_then(Option::as_mut)
+            .ok_or(EBADF)
+    }
+
+    fn openat(&mut self, args: [u32; 6], ram: &Ram) -> Result<u32, i32> {
+        let [dirfd, path, flags, ..] = args;
+        let path = read_c_string(ram, path).ok_or(EFAULT)?;
+        if dirfd != AT_FDCWD && !path.starts_with('/') {
+            return Err(ENOTSUP);
+        }
+
+        // the host only creates files opened for writing
+        if flags & O_CREAT != 0 {
+            OpenOptions::new()
+                .write(true)
+                .create(true)
+                .create_new(flags & O_EXCL != 0)
+                .truncate(false)
+                .open(&path)
+                .map_err(errno)?;
+        }
+
+        let mut options = OpenOptions::new();
+        match flags & O_ACCMODE {
+            O_WRONLY => options.write(true),
+            O_RDWR => options.read(true).write(true),
+            _ => options.read(true),
+        };
+        if flags & O_ACCMODE != 0 {
+            options.truncate(flags & O_TRUNC != 0);
+        }
+        let file = options
+            .append(flags & O_APPEND != 0)
+            .open(path)
+            .map_err(errno)?;
+
+        // the lowest free descriptor
+        let fd = match self.files.iter().position(Option::is_none) {
+            Some(fd) => fd,
+            None => {
+                self.files.push(None);
+                self.files.len() - 1
+            }
+        };
+        self.files[fd] = Some(FileDesc::File(file));
+
+        Ok(fd as u32)
+    }
+
+    fn close(&mut self, fd: u32) -> Result<u32, i32> {
+        self.file(fd)?;
+        self.files[fd as usize] = None;
+
+        Ok(0)
+    }
+
+    // (fd, offset_high, offset_low, result, whence)
+    fn llseek(&mut self, args: [u32; 6], ram: &mut Ram) -> Result<u32, i32> {
+        let [fd, high, low, result, whence, _] = args;
+        let offset = (high as u64) << 32 | low as u64;
+        let pos = match whence {
+            SEEK_SET => SeekFrom::Start(offset),
+            SEEK_CUR => SeekFrom::Current(offset as i64),
+            SEEK_END => SeekFrom::End(offset as i64),
+            _ => return Err(EINVAL),
+        };
+
+        let offset = match self.file(fd)? {
+            FileDesc::File(file) => file.seek(pos).map_err(errno)?,
+            _ => return Err(ESPIPE),
+        };
+        write_bytes(ram, result, &offset.to_le_bytes())?;
+
+        Ok(0)
+    }
+
+    fn read(&mut self, fd: u32, buf: u32, len: u32, ram: &mut Ram) -> Result<u32, i32> {
+        let buf = ram.slice_mut(buf, len as usize).ok_or(EFAULT)?;
+        let len = match self.file(fd)? {
+            FileDesc::Stdin => io::stdin().read(buf),
+            FileDesc::File(file) => file.read(buf),
+            FileDesc::Stdout | FileDesc::Stderr => return Err(EBADF),
+        }
+        .map_err(errno)?;
+
+        Ok(len as u32)
+    }
+
+    fn write(&mut self, fd: u32, buf: u32, len: u32, ram: &Ram) -> Result<u32, i32> {
+        let buf = ram.slice(buf, len as usize).ok_or(EFAULT)?;
+        match self.file(fd)? {
+            FileDesc::Stdout => {
+                let mut stdout = io::stdout();
+                stdout.write_all(buf).and_then(|_| stdout.flush())
+            }
+            FileDesc::Stderr => io::stderr().write_all(buf),
+            FileDesc::File(file) => file.write_all(buf),
+            FileDesc::Stdin => return Err(EBADF),
+        }
+        .map_err(errno)?;
+
+        Ok(len)
+    }
+
+    // (fd, iov, iovcnt), struct iovec is { base, len }
+    fn readv(&mut self, args: [u32; 6], ram: &mut Ram) -> Result<u32, i32> {
+        let mut total = 0;
+        for (base, len) in read_iovecs(ram, args[1], args[2])? {
+            let read_len = self.read(args[0], base, len, ram)?;
+            total += read_len;
+            if read_len < len {
+                break;
+            }
+        }
+
+        Ok(total)
+    }
+
+    fn writev(&mut self, args: [u32; 6], ram: &mut Ram) -> Result<u32, i32> {
+        let mut total = 0;
+        for (base, len) in read_iovecs(ram, args[1], args[2])? {
+            total += self.write(args[0], base, len, ram)?;
+        }
+
+        Ok(total)
+    }
+
+    // struct kernel_stat of newlib (128 bytes)
+    fn fstat(&mut self, args: [u32; 6], ram: &mut Ram) -> Result<u32, i32> {
+        let stat = self.fd_stat(args[0])?;
+
+        let mut buf = [0; 128];
+        put(&mut buf, 16, &stat.mode.to_le_bytes());
+        put(&mut buf, 20, &1u32.to_le_bytes()); // st_nlink
+        put(&mut buf, 48, &stat.size.to_le_bytes());
+        put(&mut buf, 56, &PAGE_SIZE.to_le_bytes());
+        put(&mut buf, 64, &stat.size.div_ceil(512).to_le_bytes());
+        for offset in [72, 88, 104] {
+            put(&mut buf, offset, &stat.mtime.to_le_bytes());
+        }
+        write_bytes(ram, args[1], &buf)?;
+
+        Ok(0)
+    }
+
+    // (dirfd, path, flags, mask, buf), struct statx (256 bytes)
+    fn statx(&mut self, args: [u32; 6], ram: &mut Ram) -> Result<u32, i32> {
+        let [dirfd, path, flags, _, buf, _] = args;
+        let path = read_c_string(ram, path).ok_or(EFAULT)?;
+        let stat = if path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+            self.fd_stat(dirfd)?
+        } else if dirfd != AT_FDCWD && !path.starts_with('/') {
+            return Err(ENOTSUP);
+        } else if flags & AT_SYMLINK_NOFOLLOW != 0 {
+            FileStat::new(&std::fs::symlink_metadata(path).map_err(errno)?)
+        } else {
+            FileStat::new(&std::fs::metadata(path).map_err(errno)?)
+        };
+
+        let mut statx = [0; 256];
+        put(&mut statx, 0, &STATX_BASIC_STATS.to_le_bytes());
+        put(&mut statx, 4, &PAGE_SIZE.to_le_bytes());
+        put(&mut statx, 16, &1u32.to_le_bytes()); // stx_nlink
+        put(&mut statx, 28, &(stat.mode as u16).to_le_bytes());
+        put(&mut statx, 40, &stat.size.to_le_bytes());
+        put(&mut statx, 48, &stat.size.div_ceil(512).to_le_bytes());
+        // atime, btime, ctime and mtime
+        for offset in [64, 80, 96, 112] {
+            put(&mut statx, offset, &stat.mtime.to_le_bytes());
+        }
+        write_bytes(ram, buf, &statx)?;
+
+        Ok(0)
+    }
+
+    fn fd_stat(&mut self, fd: u32) -> Result<FileStat, i32> {
+        match self.file(fd)? {
+            FileDesc::File(file) => Ok(FileStat::new(&file.metadata().map_err(errno)?)),
+            _ => Ok(FileStat {
+                mode: S_IFCHR | 0o620,
+                size: 0,
+                mtime: 0,
+            }),
+        }
+    }
+
+    // returns the current break, which is unchanged if the address is out of the heap
+    fn set_brk(&mut self, addr: u32, ram: &mut Ram) -> u32 {
+        if addr >= self.brk_start && addr <= self.mmap_base {
+            if addr > self.brk {
+                if let Some(heap) = ram.slice_mut(self.brk, (addr - self.brk) as usize) {
+                    heap.fill(0);
+                }
+            }
+            self.brk = addr;
+        }
+
+        self.brk
+    }
+
+    // (addr, len, prot, flags, fd, offset in pages), private mappings only (files are copied)
+    fn mmap(&mut self, args: [u32; 6], ram: &mut Ram) -> Result<u32, i32> {
+        let [addr, len, _, flags, fd, pgoff] = args;
+        if len == 0 {
+            return Err(EINVAL);
+        }
+        let len = len.checked_add(PAGE_SIZE - 1).ok_or(ENOMEM)? & !(PAGE_SIZE - 1);
+
+        let start = if flags & MAP_FIXED != 0 {
+            if addr % PAGE_SIZE != 0 {
+                return Err(EINVAL);
+            }
+            addr
+        } else {
+            let start = self.mmap_base.checked_sub(len).ok_or(ENOMEM)?;
+            if start < self.brk {
+                return Err(ENOMEM);
+            }
+            start
+        };
+
+        let region = ram.slice_mut(start, len as usize).ok_or(ENOMEM)?;
+        if flags & MAP_ANONYMOUS == 0 {
+            let file = match self.files.get_mut(fd as usize).and_then(Option::as_mut) {
+                Some(FileDesc::File(file)) => file,
+                _ => return Err(EBADF),
+            };
+            region.fill(0);
+            read_at(file, pgoff as u64 * PAGE_SIZE as u64, region).map_err(errno)?;
+        } else {
+            region.fill(0);
+        }
+
+        if flags & MAP_FIXED == 0 {
+            self.mmap_base = start;
+        }
+
+        Ok(start)
+    }
+
+    // (clock_id, struct __kernel_timespec { i64 tv_sec, i64 tv_nsec })
+    fn clock_gettime(&mut self, args: [u32; 6], ram: &mut Ram) -> Result<u32, i32> {
+        let time = match args[0] {
+            CLOCK_REALTIME => unix_time(),
+            // the monotonic and cpu time clocks start with the emulator
+            _ => self.start.elapsed(),
+        };
+
+        let mut timespec = [0; 16];
+        put(&mut timespec, 0, &time.as_secs().to_le_bytes());
+        let nanos = time.subsec_nanos() as u64;
+        put(&mut timespec, 8, &nanos.to_le_bytes());
+        write_bytes(ram, args[1], &timespec)?;
+
+        Ok(0)
+    }
+
+    // (buf, len, flags), deterministic bytes
+    fn getrandom(&mut self, args: [u32; 6], ram: &mut Ram) -> Result<u32, i32> {
+        let mut buf = vec![0; args[1] as usize];
+        self.fill_random(&mut buf);
+        write_bytes(ram, args[0], &buf)?;
+
+        Ok(args[1])
+    }
+
+    fn fill_random(&mut self, buf: &mut [u8]) {
+        for value in buf {
+            self.random_state ^= self.random_state << 13;
+            self.random_state ^= self.random_state >> 7;
+            self.random_state ^= self.random_state << 17;
+            *value = self.random_state as u8;
+        }
+    }
+}
+
+struct FileStat {
+    mode: u32,
+    size: u64,
+    // seconds since the unix epoch
+    mtime: u64,
+}
+
+impl FileStat {
+    fn new(metadata: &Metadata) -> Self {
+        let mode = if metadata.is_dir() {
+            S_IFDIR | 0o755
+        } else if metadata.permissions().readonly() {
+            S_IFREG | 0o444
+        } else {
+            S_IFREG | 0o644
+        };
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |time| time.as_secs());
+
+        Self {
+            mode,
+            size: metadata.len(),
+            mtime,
+        }
+    }
+}
+
+// struct utsname (6 fields of 65 bytes)
+fn uname(buf: u32, ram: &mut Ram) -> Result<u32, i32> {
+    let mut utsname = [0; UTSNAME_FIELD_LEN * 6];
+    for (i, field) in UTSNAME.iter().enumerate() {
+        put(&mut utsname, i * UTSNAME_FIELD_LEN, field.as_bytes());
+    }
+    write_bytes(ram, buf, &utsname)?;
+
+    Ok(0)
+}
+
+// (struct timeval { i64 tv_sec, i32 tv_usec }, timezone)
+fn gettimeofday(tv: u32, ram: &mut Ram) -> Result<u32, i32> {
+    if tv != 0 {
+        let time = unix_time();
+        let mut timeval = [0; 16];
+        put(&mut timeval, 0, &time.as_secs().to_le_bytes());
+        put(&mut timeval, 8, &time.subsec_micros().to_le_bytes());
+        write_bytes(ram, tv, &timeval)?;
+    }
+
+    Ok(0)
+}
+
+fn unix_time() -> std::time::Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+fn page_align(addr: u32) -> u32 {
+    addr.wrapping_add(PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+fn put(buf: &mut [u8], offset: usize, bytes: &[u8]) {
+    buf[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
+
+fn write_bytes(ram: &mut Ram, addr: u32, bytes: &[u8]) -> Result<(), i32> {
+    ram.slice_mut(addr, bytes.len())
+        .ok_or(EFAULT)?
+        .copy_from_slice(bytes);
+
+    Ok(())
+}
+
+fn push_bytes(ram: &mut Ram, sp: &mut u32, bytes: &[u8]) -> Option<u32> {
+    *sp = sp.checked_sub(bytes.len() as u32)?;
+    ram.slice_mut(*sp, bytes.len())?.copy_from_slice(bytes);
+
+    Some(*sp)
+}
+
+fn read_c_string(ram: &Ram, addr: u32) -> Option<String> {
+    let mut bytes = Vec::new();
+    loop {
+        let addr = addr.checked_add(bytes.len() as u32)?;
+        if !ram.is_available_addr(addr) {
+            return None;
+        }
+        match ram.load8(addr) {
+            0 => break,
+            value => bytes.push(value),
+        }
+    }
+
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn read_iovecs(ram: &Ram, iov: u32, count: u32) -> Result<Vec<(u32, u32)>, i32> {
+    let iovecs = ram
+        .slice(iov, count as usize * 8)
+        .ok_or(EFAULT)?
+        .chunks_exact(8)
+        .map(|iovec| {
+            let base = u32::from_le_bytes(iovec[..4].try_into().unwrap());
+            let len = u32::from_le_bytes(iovec[4..].try_into().unwrap());
+            (base, len)
+        })
+        .collect();
+
+    Ok(iovecs)
+}
+
+// reads until the buffer is full or the end of the file, the file position is kept
+fn read_at(file: &mut File, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+    let pos = file.stream_position()?;
+    file.seek(SeekFrom::Start(offset))?;
+
+    let mut len = 0;
+    while len < buf.len() {
+        match file.read(&mut buf[len..])? {
+            0 => break,
+            read_len => len += read_len,
+        }
+    }
+
+    file.seek(SeekFrom::Start(pos))?;
+
+    Ok(())
+}
+
+fn errno(error: io::Error) -> i32 {
+    match error.kind() {
+        io::ErrorKind::NotFound => ENOENT,
+        io::ErrorKind::PermissionDenied => EACCES,
+        io::ErrorKind::AlreadyExists => EEXIST,
+        io::ErrorKind::InvalidInput => EINVAL,
+        _ => EIO,
+    }
+}
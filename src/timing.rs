@@ -0,0 +1,125 @@
+use serde::Deserialize;
+use std::fmt::Write;
+
+use crate::{cpu::Cpu, instruction::Instruction};
+
+// cycles per instruction class of a simple in-order core, a class missing from a
+// configuration file keeps its default
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timing {
+    pub alu: u64,
+    pub mul: u64,
+    pub div: u64,
+    pub load: u64,
+    pub store: u64,
+    // lr.w, sc.w and the amos
+    pub atomic: u64,
+    pub branch: u64,
+    pub taken_branch: u64,
+    // jal and jalr
+    pub jump: u64,
+    pub csr: u64,
+    // fences, ecall, ebreak, xret, wfi and sfence.vma
+    pub system: u64,
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Self {
+            alu: 1,
+            mul: 3,
+            div: 34,
+            load: 2,
+            store: 1,
+            atomic: 4,
+            branch: 1,
+            taken_branch: 3,
+            jump: 2,
+            csr: 3,
+            system: 4,
+        }
+    }
+}
+
+impl Timing {
+    // taken is only looked at for the conditional branches
+    pub fn latency(&self, instruction: &Instruction, taken: bool) -> u64 {
+        match instruction {
+            Instruction::Mul { .. }
+            | Instruction::Mulh { .. }
+            | Instruction::Mulhsu { .. }
+            | Instruction::Mulhu { .. } => self.mul,
+            Instruction::Div { .. }
+            | Instruction::Divu { .. }
+            | Instruction::Rem { .. }
+            | Instruction::Remu { .. } => self.div,
+            Instruction::Lb { .. }
+            | Instruction::Lbu { .. }
+            | Instruction::Lh { .. }
+            | Instruction::Lhu { .. }
+            | Instruction::Lw { .. } => self.load,
+            Instruction::Sb { .. } | Instruction::Sh { .. } | Instruction::Sw { .. } => self.store,
+            Instruction::LrW { .. }
+            | Instruction::ScW { .. }
+            | Instruction::AmoswapW { .. }
+            | Instruction::AmoaddW { .. }
+            | Instruction::AmoxorW { .. }
+            | Instruction::AmoandW { .. }
+            | Instruction::AmoorW { .. }
+            | Instruction::AmominW { .. }
+            | Instruction::AmomaxW { .. }
+            | Instruction::AmominuW { .. }
+            | Instruction::AmomaxuW { .. } => self.atomic,
+            Instruction::Beq { .. }
+            | Instruction::Bne { .. }
+            | Instruction::Blt { .. }
+            | Instruction::Bge { .. }
+            | Instruction::Bltu { .. }
+            | Instruction::Bgeu { .. } => {
+                if taken {
+                    self.taken_branch
+                } else {
+                    self.branch
+                }
+            }
+            Instruction::Jal { .. } | Instruction::Jalr { .. } => self.jump,
+            Instruction::Csrrw { .. }
+            | Instruction::Csrrs { .. }
+            | Instruction::Csrrc { .. }
+            | Instruction::Csrrwi { .. }
+            | Instruction::Csrrsi { .. }
+            | Instruction::Csrrci { .. } => self.csr,
+            Instruction::Fence { .. }
+            | Instruction::FenceI
+            | Instruction::Ecall
+            | Instruction::Ebreak
+            | Instruction::Sret
+            | Instruction::Mret
+            | Instruction::Wfi
+            | Instruction::SfenceVma { .. } => self.system,
+            _ => self.alu,
+        }
+    }
+}
+
+// instructions, cycles and CPI of each hart (e.g. printed at the end of a run)
+pub fn report(harts: &[Cpu]) -> String {
+    let mut s = String::new();
+    for cpu in harts {
+        let cpi = match cpu.step {
+            0 => 0.0,
+            steps => cpu.cycle as f64 / steps as f64,
+        };
+        let _ = writeln!(
+            s,
+            "hart {}: {} instructions, {} cycles, CPI {:.3}",
+            cpu.hart_id(),
+            cpu.step,
+            cpu.cycle,
+            cpi
+        );
+    }
+
+    s
+}
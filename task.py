@@ -6,6 +6,10 @@ APPS_DIR = "apps"
 EMU_CLI_DIR = "emu-cli"
 LOG_VIEWER_DIR = "frisc-log-viewer"
 RISCV_TESTS_DIR = "riscv-tests"
+RISCOF_DIR = "riscof"
+OPENSBI_DIR = "opensbi"
+BUILDROOT_DIR = "buildroot"
+LINUX_DIR = "linux"
 
 GIT_SUBMODULE_UPDATE = "git submodule update --init --recursive"
 
@@ -49,6 +53,54 @@ def task_test():
     run_cmd("cargo test")
 
 
+def task_riscv_tests():
+    run_cmd("cargo test -p emu --test riscv_tests -- --ignored --nocapture")
+
+
+def task_riscof():
+    # requires riscof, sail_cSim (reference model) and riscv64-unknown-elf-gcc
+    run_cmd("cargo build --release -p emu")
+
+    if not os.path.isdir(f"./{RISCOF_DIR}/riscv-arch-test"):
+        run_cmd("riscof arch-test --clone", dir=f"./{RISCOF_DIR}")
+
+    run_cmd(
+        "riscof run --config=config.ini --suite=riscv-arch-test/riscv-test-suite/ --env=riscv-arch-test/riscv-test-suite/env",
+        dir=f"./{RISCOF_DIR}",
+    )
+
+
+def task_build_opensbi():
+    # requires riscv64-unknown-elf-gcc (or set CROSS_COMPILE)
+    if not os.path.isdir(f"./{OPENSBI_DIR}"):
+        run_cmd("git clone --depth 1 https://github.com/riscv-software-src/opensbi.git")
+
+    cross_compile = os.environ.get("CROSS_COMPILE", "riscv64-unknown-elf-")
+    run_cmd(
+        f"make PLATFORM=generic PLATFORM_RISCV_XLEN=32 CROSS_COMPILE={cross_compile}",
+        dir=f"./{OPENSBI_DIR}",
+    )
+
+
+def task_opensbi_test():
+    run_cmd("cargo test -p emu --test opensbi -- --ignored --nocapture")
+
+
+def task_build_linux():
+    # rv32ima Linux Image with a BusyBox initramfs (buildroot builds its own toolchain)
+    if not os.path.isdir(f"./{BUILDROOT_DIR}"):
+        run_cmd(
+            "git clone --depth 1 --branch 2024.02.x https://gitlab.com/buildroot.org/buildroot.git"
+        )
+
+    run_cmd(f"make BR2_EXTERNAL=../{LINUX_DIR} frisc_defconfig", dir=f"./{BUILDROOT_DIR}")
+    run_cmd("make", dir=f"./{BUILDROOT_DIR}")
+
+
+def task_linux_test():
+    run_cmd("cargo test --release -p emu --test linux -- --ignored --nocapture")
+
+
 def task_run_log_viewer():
     run_cmd("npm run dev", dir=f"./{LOG_VIEWER_DIR}")
 
@@ -57,6 +109,12 @@ TASKS = [
     task_build,
     task_clean,
     task_test,
+    task_riscv_tests,
+    task_riscof,
+    task_build_opensbi,
+    task_opensbi_test,
+    task_build_linux,
+    task_linux_test,
     task_run_log_viewer,
 ]
 